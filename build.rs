@@ -1,5 +1,6 @@
 use std::path::Path;
 use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 fn command_exists(cmd: &str) -> bool {
     Command::new("which")
@@ -9,11 +10,43 @@ fn command_exists(cmd: &str) -> bool {
         .unwrap_or(false)
 }
 
+/// Embed build metadata read by `GET /api/version`
+fn emit_build_info() {
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs");
+
+    let git_commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=DEV_PROXY_GIT_COMMIT={}", git_commit);
+
+    let build_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    println!("cargo:rustc-env=DEV_PROXY_BUILD_TIMESTAMP={}", build_time);
+
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|name| name.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=DEV_PROXY_FEATURES={}", features.join(","));
+}
+
 fn main() {
     println!("cargo:rerun-if-changed=../ui/src");
     println!("cargo:rerun-if-changed=../ui/static");
     println!("cargo:rerun-if-changed=../ui/package.json");
 
+    emit_build_info();
+
     // Check if npm is installed
     if !command_exists("npm") {
         eprintln!("\n❌ ERROR: npm is not installed or not in PATH");
@@ -0,0 +1,303 @@
+use crate::config::ProxyConfig;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Per-upstream circuit breakers. After `failure_threshold` consecutive 5xx responses or
+/// connection errors within `window_ms`, an upstream's circuit opens and its requests are
+/// answered immediately until `cooldown_ms` has passed. Then one probe request goes
+/// through: success closes the circuit, failure opens it for another cooldown.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    settings: CircuitSettings,
+    // One per configured upstream; the set is fixed at startup
+    circuits: Arc<HashMap<String, Circuit>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitSettings {
+    // None leaves every circuit closed
+    pub failure_threshold: Option<u32>,
+    pub window_ms: u64,
+    pub cooldown_ms: u64,
+    pub open_status: u16,
+}
+
+#[derive(Default)]
+struct Circuit {
+    consecutive_failures: AtomicU32,
+    // Epoch millis of the first failure in the current run
+    window_started_ms: AtomicU64,
+    // Epoch millis the circuit opened at, 0 while closed
+    opened_at_ms: AtomicU64,
+    // Epoch millis the half-open probe was let through at, 0 when none is in flight
+    probe_started_ms: AtomicU64,
+}
+
+/// A probe that hasn't reported back by now is given up on, so a request that never
+/// finishes (a client that disconnected, say) can't hold the circuit open forever
+const PROBE_TIMEOUT_MS: u64 = 30_000;
+
+impl Circuit {
+    fn probing(&self, now: u64) -> bool {
+        let started = self.probe_started_ms.load(Ordering::Acquire);
+        started != 0 && now.saturating_sub(started) < PROBE_TIMEOUT_MS
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// Body of `GET /api/circuit-breaker`
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitBreakerStatus {
+    #[serde(flatten)]
+    pub settings: CircuitSettings,
+    pub upstreams: Vec<UpstreamCircuitStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamCircuitStatus {
+    pub upstream: String,
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    pub opened_at: Option<chrono::DateTime<chrono::Utc>>,
+    // Until the next probe is let through
+    pub retry_in_ms: Option<u64>,
+}
+
+fn now_ms() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &ProxyConfig) -> Self {
+        let circuits = config
+            .upstream_urls
+            .iter()
+            .chain(config.routes.iter().map(|route| &route.upstream))
+            .map(|upstream| (upstream.clone(), Circuit::default()))
+            .collect();
+
+        Self {
+            settings: CircuitSettings {
+                failure_threshold: config.circuit_breaker_failures,
+                window_ms: config.circuit_breaker_window_ms,
+                cooldown_ms: config.circuit_breaker_cooldown_ms,
+                open_status: config.circuit_breaker_status,
+            },
+            circuits: Arc::new(circuits),
+        }
+    }
+
+    pub fn open_status(&self) -> u16 {
+        self.settings.open_status
+    }
+
+    /// Whether a request may go to `upstream`. Once the cooldown is over this lets
+    /// exactly one probe through until its outcome is recorded or it times out.
+    pub fn allow(&self, upstream: &str) -> bool {
+        let Some(circuit) = self.circuit(upstream) else {
+            return true;
+        };
+        let now = now_ms();
+        match self.state(circuit, now) {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            // No live probe here, but an expired one may still need replacing
+            CircuitState::HalfOpen => {
+                let started = circuit.probe_started_ms.load(Ordering::Acquire);
+                circuit
+                    .probe_started_ms
+                    .compare_exchange(started, now, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+            }
+        }
+    }
+
+    /// Milliseconds until `upstream` lets a probe through, 0 when it already would
+    pub fn retry_in_ms(&self, upstream: &str) -> u64 {
+        self.circuit(upstream)
+            .map(|circuit| self.remaining_cooldown_ms(circuit, now_ms()))
+            .unwrap_or(0)
+    }
+
+    pub fn record_success(&self, upstream: &str) {
+        let Some(circuit) = self.circuit(upstream) else {
+            return;
+        };
+        circuit.consecutive_failures.store(0, Ordering::Relaxed);
+        circuit.opened_at_ms.store(0, Ordering::Relaxed);
+        circuit.probe_started_ms.store(0, Ordering::Release);
+    }
+
+    /// Count a 5xx response or connection error from `upstream`
+    pub fn record_failure(&self, upstream: &str) {
+        let (Some(threshold), Some(circuit)) =
+            (self.settings.failure_threshold, self.circuit(upstream))
+        else {
+            return;
+        };
+        let now = now_ms();
+
+        // A failed probe opens the circuit for another cooldown
+        if circuit.probe_started_ms.swap(0, Ordering::AcqRel) != 0 {
+            circuit.opened_at_ms.store(now, Ordering::Relaxed);
+            return;
+        }
+
+        let window_started = circuit.window_started_ms.load(Ordering::Relaxed);
+        let failures = if now.saturating_sub(window_started) > self.settings.window_ms {
+            circuit.window_started_ms.store(now, Ordering::Relaxed);
+            circuit.consecutive_failures.store(1, Ordering::Relaxed);
+            1
+        } else {
+            circuit.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1
+        };
+
+        if failures >= threshold && circuit.opened_at_ms.load(Ordering::Relaxed) == 0 {
+            circuit.opened_at_ms.store(now, Ordering::Relaxed);
+        }
+    }
+
+    pub fn status(&self) -> CircuitBreakerStatus {
+        let now = now_ms();
+        let mut upstreams: Vec<_> = self
+            .circuits
+            .iter()
+            .map(|(upstream, circuit)| {
+                let opened_at_ms = circuit.opened_at_ms.load(Ordering::Relaxed);
+                let state = if circuit.probing(now) {
+                    CircuitState::HalfOpen
+                } else {
+                    self.state(circuit, now)
+                };
+                UpstreamCircuitStatus {
+                    upstream: upstream.clone(),
+                    state,
+                    consecutive_failures: circuit.consecutive_failures.load(Ordering::Relaxed),
+                    opened_at: (opened_at_ms > 0)
+                        .then(|| chrono::DateTime::from_timestamp_millis(opened_at_ms as i64))
+                        .flatten(),
+                    retry_in_ms: (state == CircuitState::Open)
+                        .then(|| self.remaining_cooldown_ms(circuit, now)),
+                }
+            })
+            .collect();
+        upstreams.sort_by(|a, b| a.upstream.cmp(&b.upstream));
+
+        CircuitBreakerStatus {
+            settings: self.settings.clone(),
+            upstreams,
+        }
+    }
+
+    fn circuit(&self, upstream: &str) -> Option<&Circuit> {
+        self.settings.failure_threshold?;
+        self.circuits.get(upstream)
+    }
+
+    fn state(&self, circuit: &Circuit, now: u64) -> CircuitState {
+        if circuit.opened_at_ms.load(Ordering::Relaxed) == 0 {
+            CircuitState::Closed
+        } else if self.remaining_cooldown_ms(circuit, now) > 0 || circuit.probing(now) {
+            CircuitState::Open
+        } else {
+            CircuitState::HalfOpen
+        }
+    }
+
+    fn remaining_cooldown_ms(&self, circuit: &Circuit, now: u64) -> u64 {
+        let opened_at = circuit.opened_at_ms.load(Ordering::Relaxed);
+        if opened_at == 0 {
+            return 0;
+        }
+        (opened_at + self.settings.cooldown_ms).saturating_sub(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UPSTREAM: &str = "http://localhost:8000";
+
+    fn breaker(cooldown_ms: u64) -> CircuitBreaker {
+        CircuitBreaker {
+            settings: CircuitSettings {
+                failure_threshold: Some(2),
+                window_ms: 60_000,
+                cooldown_ms,
+                open_status: 503,
+            },
+            circuits: Arc::new(HashMap::from([(UPSTREAM.to_string(), Circuit::default())])),
+        }
+    }
+
+    fn state(breaker: &CircuitBreaker) -> CircuitState {
+        breaker.status().upstreams[0].state
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = breaker(60_000);
+        breaker.record_failure(UPSTREAM);
+        assert!(breaker.allow(UPSTREAM));
+        breaker.record_failure(UPSTREAM);
+        assert!(!breaker.allow(UPSTREAM));
+        assert_eq!(state(&breaker), CircuitState::Open);
+    }
+
+    #[test]
+    fn lets_one_probe_through_after_the_cooldown() {
+        let breaker = breaker(0);
+        breaker.record_failure(UPSTREAM);
+        breaker.record_failure(UPSTREAM);
+        assert!(breaker.allow(UPSTREAM));
+        assert!(!breaker.allow(UPSTREAM));
+        assert_eq!(state(&breaker), CircuitState::HalfOpen);
+
+        breaker.record_success(UPSTREAM);
+        assert_eq!(state(&breaker), CircuitState::Closed);
+        assert!(breaker.allow(UPSTREAM));
+    }
+
+    #[test]
+    fn failed_probe_reopens_the_circuit() {
+        let breaker = breaker(60_000);
+        breaker.record_failure(UPSTREAM);
+        breaker.record_failure(UPSTREAM);
+        let circuit = &breaker.circuits[UPSTREAM];
+        // Pretend the cooldown ran out and a probe went through
+        circuit.opened_at_ms.store(1, Ordering::Relaxed);
+        assert!(breaker.allow(UPSTREAM));
+
+        breaker.record_failure(UPSTREAM);
+        assert!(!breaker.allow(UPSTREAM));
+        assert_eq!(state(&breaker), CircuitState::Open);
+    }
+
+    #[test]
+    fn abandoned_probe_expires() {
+        let breaker = breaker(0);
+        breaker.record_failure(UPSTREAM);
+        breaker.record_failure(UPSTREAM);
+        assert!(breaker.allow(UPSTREAM));
+        assert!(!breaker.allow(UPSTREAM));
+
+        // The probe never reported back
+        let circuit = &breaker.circuits[UPSTREAM];
+        circuit
+            .probe_started_ms
+            .store(now_ms() - PROBE_TIMEOUT_MS - 1, Ordering::Release);
+        assert_eq!(state(&breaker), CircuitState::HalfOpen);
+        assert!(breaker.allow(UPSTREAM));
+        assert!(!breaker.allow(UPSTREAM));
+    }
+}
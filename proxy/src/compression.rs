@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+/// A `Content-Encoding` the HTTP layer can undo, so body modifiers and `--replace` see
+/// the text instead of compressed bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Gzip,
+    Deflate,
+    Brotli,
+}
+
+// Brotli buffer size, quality and window; quality 5 keeps re-compression cheap
+const BROTLI_BUFFER: usize = 4096;
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+
+// Cap on a decompressed body when --max-body-bytes is 0, so a small compressed body
+// can't expand without bound
+pub const MAX_DECODED_BYTES: usize = 64 * 1024 * 1024;
+
+impl ContentEncoding {
+    /// The encoding named by the `Content-Encoding` header, if it's a single one we support.
+    /// Stacked encodings such as `gzip, br` are left alone.
+    pub fn from_headers(headers: &HashMap<String, String>) -> Option<Self> {
+        let value = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-encoding"))
+            .map(|(_, value)| value.trim().to_ascii_lowercase())?;
+        match value.as_str() {
+            "gzip" | "x-gzip" => Some(ContentEncoding::Gzip),
+            "deflate" => Some(ContentEncoding::Deflate),
+            "br" => Some(ContentEncoding::Brotli),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+            ContentEncoding::Brotli => "br",
+        }
+    }
+
+    /// Decompress a body, None if it isn't valid for this encoding or would come to more
+    /// than `limit` bytes
+    pub fn decode(&self, body: &[u8], limit: usize) -> Option<Vec<u8>> {
+        // One byte past the limit tells a body that fits exactly from one that doesn't
+        let take = limit as u64 + 1;
+        let mut decoded = Vec::new();
+        let result = match self {
+            ContentEncoding::Gzip => flate2::read::MultiGzDecoder::new(body)
+                .take(take)
+                .read_to_end(&mut decoded),
+            // `deflate` should be zlib-wrapped, but some servers send a raw stream
+            ContentEncoding::Deflate => flate2::read::ZlibDecoder::new(body)
+                .take(take)
+                .read_to_end(&mut decoded)
+                .or_else(|_| {
+                    decoded.clear();
+                    flate2::read::DeflateDecoder::new(body)
+                        .take(take)
+                        .read_to_end(&mut decoded)
+                }),
+            ContentEncoding::Brotli => brotli::Decompressor::new(body, BROTLI_BUFFER)
+                .take(take)
+                .read_to_end(&mut decoded),
+        };
+        result
+            .ok()
+            .filter(|_| decoded.len() <= limit)
+            .map(|_| decoded)
+    }
+
+    pub fn encode(&self, body: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            ContentEncoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+            ContentEncoding::Deflate => {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+            ContentEncoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(
+                    Vec::new(),
+                    BROTLI_BUFFER,
+                    BROTLI_QUALITY,
+                    BROTLI_WINDOW,
+                );
+                encoder.write_all(body)?;
+                encoder.flush()?;
+                Ok(encoder.into_inner())
+            }
+        }
+    }
+}
+
+/// A compressed body unpacked for rewriting. `finish` compresses it again, or hands back
+/// the original bytes when nothing changed.
+pub struct DecodedBody {
+    encoding: ContentEncoding,
+    original: Vec<u8>,
+    decoded: Vec<u8>,
+}
+
+impl DecodedBody {
+    /// Replace `body` with its decompressed bytes; None when it isn't in an encoding we
+    /// can undo or decompresses to more than `limit` bytes
+    pub fn open(
+        headers: &HashMap<String, String>,
+        body: &mut Vec<u8>,
+        limit: usize,
+    ) -> Option<Self> {
+        let encoding = ContentEncoding::from_headers(headers)?;
+        let decoded = encoding.decode(body, limit)?;
+        let original = std::mem::replace(body, decoded.clone());
+        Some(Self {
+            encoding,
+            original,
+            decoded,
+        })
+    }
+
+    pub fn encoding(&self) -> ContentEncoding {
+        self.encoding
+    }
+
+    /// Put `body` back into the original encoding; returns whether it had changed and
+    /// was compressed again
+    pub fn finish(self, body: &mut Vec<u8>) -> std::io::Result<bool> {
+        if *body == self.decoded {
+            *body = self.original;
+            return Ok(false);
+        }
+        *body = self.encoding.encode(body)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &[u8] = b"{\"message\":\"hello from upstream\"}";
+
+    fn encoded(encoding: ContentEncoding) -> (HashMap<String, String>, Vec<u8>) {
+        let headers =
+            HashMap::from([("Content-Encoding".to_string(), encoding.name().to_string())]);
+        (headers, encoding.encode(TEXT).unwrap())
+    }
+
+    const ENCODINGS: [ContentEncoding; 3] = [
+        ContentEncoding::Gzip,
+        ContentEncoding::Deflate,
+        ContentEncoding::Brotli,
+    ];
+
+    #[test]
+    fn untouched_bodies_go_back_byte_identical() {
+        for encoding in ENCODINGS {
+            let (headers, original) = encoded(encoding);
+            let mut body = original.clone();
+            let decoded = DecodedBody::open(&headers, &mut body, MAX_DECODED_BYTES).unwrap();
+            assert_eq!(body, TEXT);
+            assert!(!decoded.finish(&mut body).unwrap());
+            assert_eq!(body, original, "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn modified_bodies_are_compressed_again() {
+        for encoding in ENCODINGS {
+            let (headers, mut body) = encoded(encoding);
+            let decoded = DecodedBody::open(&headers, &mut body, MAX_DECODED_BYTES).unwrap();
+            body = String::from_utf8(body)
+                .unwrap()
+                .replace("upstream", "dev-proxy")
+                .into_bytes();
+            assert!(decoded.finish(&mut body).unwrap());
+            assert_eq!(
+                encoding.decode(&body, MAX_DECODED_BYTES).unwrap(),
+                b"{\"message\":\"hello from dev-proxy\"}",
+                "{:?}",
+                encoding
+            );
+        }
+    }
+
+    #[test]
+    fn raw_deflate_streams_are_decoded() {
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(TEXT).unwrap();
+        let raw = encoder.finish().unwrap();
+        assert_eq!(
+            ContentEncoding::Deflate
+                .decode(&raw, MAX_DECODED_BYTES)
+                .unwrap(),
+            TEXT
+        );
+    }
+
+    #[test]
+    fn leaves_unknown_or_invalid_bodies_alone() {
+        let mut body = b"not gzip".to_vec();
+        let gzip = HashMap::from([("content-encoding".to_string(), "gzip".to_string())]);
+        assert!(DecodedBody::open(&gzip, &mut body, MAX_DECODED_BYTES).is_none());
+        assert_eq!(body, b"not gzip");
+
+        let stacked = HashMap::from([("content-encoding".to_string(), "gzip, br".to_string())]);
+        assert!(DecodedBody::open(&stacked, &mut body, MAX_DECODED_BYTES).is_none());
+        assert!(DecodedBody::open(&HashMap::new(), &mut body, MAX_DECODED_BYTES).is_none());
+    }
+
+    #[test]
+    fn bodies_past_the_limit_are_not_decoded() {
+        for encoding in ENCODINGS {
+            let (headers, original) = encoded(encoding);
+            let mut body = original.clone();
+            assert!(DecodedBody::open(&headers, &mut body, TEXT.len() - 1).is_none());
+            assert_eq!(body, original, "{:?}", encoding);
+            assert!(DecodedBody::open(&headers, &mut body, TEXT.len()).is_some());
+        }
+    }
+}
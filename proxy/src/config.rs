@@ -1,9 +1,427 @@
+use crate::compression::{ContentEncoding, MAX_DECODED_BYTES};
+use crate::matcher::PassthroughPattern;
+use crate::recorder::RecordFilterRule;
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProxyConfig {
     pub proxy_port: u16,
     pub ui_port: u16,
-    pub upstream_url: String,
+    // Requests are spread across these round-robin; never empty
+    pub upstream_urls: Vec<String>,
     pub recording_enabled: bool,
+    pub verbose: bool,
+    // HTTP/1 connection handling for client connections on the proxy port
+    pub keep_alive: bool,
+    pub max_requests_per_connection: Option<usize>,
+    pub header_read_timeout_ms: Option<u64>,
+    pub pipeline_flush: bool,
+    // Answer matching If-None-Match requests with 304 from remembered upstream ETags
+    pub etag_cache: bool,
+    // Paths that always go straight to upstream, skipping every rule
+    pub passthrough: Vec<PassthroughPattern>,
+    // Lowercased request header whose value is stored as the recording's correlation id
+    pub correlation_header: Option<String>,
+    // Shed load with 503 + Retry-After once this many requests are in flight
+    pub max_inflight: Option<usize>,
+    // UI server connection limits in milliseconds, 0 disables
+    pub ui_header_read_timeout_ms: u64,
+    pub ui_idle_timeout_ms: u64,
+    // HTTP Basic Auth credentials for the UI server; both or neither
+    pub ui_user: Option<String>,
+    pub ui_pass: Option<String>,
+    // CORS for the UI server's API; no origins means `*` without credentials
+    pub ui_cors_origins: Vec<String>,
+    pub ui_cors_methods: String,
+    pub ui_cors_headers: Option<String>,
+    // Headers left out of stored recordings; proxied traffic keeps them
+    pub strip_recorded_headers: Vec<String>,
+    // Find/replace applied to every proxied text response body, after modifier rules
+    pub replacements: Vec<BodyReplacement>,
+    // Add X-Forwarded-Proto/Host/For to requests sent upstream
+    pub forwarded_headers: bool,
+    // Attach a decision trace to every response, not only those asking for one
+    pub explain: bool,
+    // JSONL file recordings are loaded from and appended to
+    pub persist_file: Option<PathBuf>,
+    // Recordings kept in memory before the oldest are evicted; 0 keeps everything
+    pub max_recordings: usize,
+    // Seed for weighted mock responses, so a run can be reproduced
+    pub mock_seed: Option<u64>,
+    // Path prefixes sent to their own upstream instead of `upstream_urls`
+    pub routes: Vec<UpstreamRoute>,
+    // YAML rule definitions loaded at startup and again on SIGHUP
+    pub rules_file: Option<PathBuf>,
+    // Bytes of each request/response body kept on a recording; 0 keeps whole bodies
+    pub max_body_bytes: usize,
+    // Consecutive upstream failures that open its circuit; None disables the breaker
+    pub circuit_breaker_failures: Option<u32>,
+    pub circuit_breaker_window_ms: u64,
+    pub circuit_breaker_cooldown_ms: u64,
+    // Status sent while a circuit is open
+    pub circuit_breaker_status: u16,
+    // Give up on an upstream request after this long and answer 504
+    pub upstream_timeout_ms: Option<u64>,
+    // Connection pool of the shared upstream client
+    pub pool_max_idle_per_host: usize,
+    pub pool_idle_timeout_ms: u64,
+    // HTTP version Pingora speaks to upstreams
+    pub upstream_protocol: UpstreamProtocol,
+    // Group numeric and UUID path segments as `{id}` in analytics
+    pub normalize_paths: bool,
+    // PEM certificate chain and private key; with both set the proxy port speaks HTTPS
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    // Skip certificate verification for HTTPS upstreams, or trust an extra PEM root CA
+    pub insecure_upstream: bool,
+    pub upstream_ca: Option<PathBuf>,
+    // How long Ctrl-C waits for in-flight requests before exiting
+    pub shutdown_grace_ms: u64,
+    pub log_format: LogFormat,
+    // File every request summary is also appended to
+    pub access_log: Option<PathBuf>,
+    // Which requests are recorded, until a rules file's `record_filter` replaces them
+    pub record_filters: Vec<RecordFilterRule>,
+    // Serve repeated requests from their recording; with match_body the body must match too
+    pub auto_mock: bool,
+    pub auto_mock_match_body: bool,
+}
+
+/// `--replace FIND=REPLACEMENT`, split at the first `=`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BodyReplacement {
+    pub find: String,
+    pub replace: String,
+}
+
+impl FromStr for BodyReplacement {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once('=') {
+            Some((find, replace)) if !find.is_empty() => Ok(BodyReplacement {
+                find: find.to_string(),
+                replace: replace.to_string(),
+            }),
+            _ => Err(format!(
+                "Invalid replacement '{}', expected FIND=REPLACEMENT",
+                value
+            )),
+        }
+    }
+}
+
+/// `--route PREFIX=UPSTREAM`, split at the first `=`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamRoute {
+    pub prefix: String,
+    pub upstream: String,
+}
+
+impl UpstreamRoute {
+    /// Whether `path` is under this prefix as a whole segment: `/api` covers `/api`,
+    /// `/api/users` and `/api?x=1` but not `/apiary`
+    pub fn matches(&self, path: &str) -> bool {
+        path.strip_prefix(self.prefix.as_str()).is_some_and(|rest| {
+            rest.is_empty()
+                || self.prefix.ends_with('/')
+                || rest.starts_with('/')
+                || rest.starts_with('?')
+        })
+    }
+
+    /// The route with the longest prefix matching `path`
+    pub fn longest_match<'a>(routes: &'a [UpstreamRoute], path: &str) -> Option<&'a UpstreamRoute> {
+        routes
+            .iter()
+            .filter(|route| route.matches(path))
+            .max_by_key(|route| route.prefix.len())
+    }
+}
+
+impl FromStr for UpstreamRoute {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once('=') {
+            Some((prefix, upstream)) if prefix.starts_with('/') => {
+                url::Url::parse(upstream)
+                    .map_err(|e| format!("Invalid upstream '{}' in route: {}", upstream, e))?;
+                Ok(UpstreamRoute {
+                    prefix: prefix.to_string(),
+                    upstream: upstream.to_string(),
+                })
+            }
+            _ => Err(format!(
+                "Invalid route '{}', expected /PREFIX=UPSTREAM",
+                value
+            )),
+        }
+    }
+}
+
+/// `--upstream-protocol`: `http1`, `http2` (prior knowledge on plain HTTP, which gRPC
+/// servers expect) or `auto` (negotiated over TLS via ALPN, HTTP/1.1 otherwise)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamProtocol {
+    #[default]
+    Http1,
+    Http2,
+    Auto,
+}
+
+impl FromStr for UpstreamProtocol {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "http1" | "h1" => Ok(UpstreamProtocol::Http1),
+            "http2" | "h2" => Ok(UpstreamProtocol::Http2),
+            "auto" => Ok(UpstreamProtocol::Auto),
+            _ => Err(format!(
+                "Invalid upstream protocol '{}', expected http1, http2 or auto",
+                value
+            )),
+        }
+    }
+}
+
+/// `--log-format`: human-readable request lines, or one JSON object per line for log
+/// aggregators
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "Invalid log format '{}', expected text or json",
+                value
+            )),
+        }
+    }
+}
+
+impl ProxyConfig {
+    /// Scheme clients reach the proxy port with
+    pub fn scheme(&self) -> &'static str {
+        if self.tls_cert.is_some() && self.tls_key.is_some() {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    pub fn is_passthrough(&self, path: &str) -> bool {
+        self.passthrough.iter().any(|pattern| pattern.matches(path))
+    }
+
+    /// Client the HTTP layer forwards requests and the UI sends replays with, built once
+    /// and shared so connections are pooled
+    pub fn upstream_client(&self) -> anyhow::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(Duration::from_millis(self.pool_idle_timeout_ms))
+            .danger_accept_invalid_certs(self.insecure_upstream);
+        if let Some(timeout_ms) = self.upstream_timeout_ms {
+            builder = builder.timeout(Duration::from_millis(timeout_ms));
+        }
+        if let Some(ref path) = self.upstream_ca {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("reading --upstream-ca {}", path.display()))?;
+            for certificate in reqwest::Certificate::from_pem_bundle(&pem)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        Ok(builder.build()?)
+    }
+
+    /// `--ui-user` and `--ui-pass`, when the UI server requires Basic Auth
+    pub fn ui_credentials(&self) -> Option<(&str, &str)> {
+        Some((self.ui_user.as_deref()?, self.ui_pass.as_deref()?))
+    }
+
+    /// Upstream of the `--route` with the longest prefix of `path`, if any
+    pub fn route_for(&self, path: &str) -> Option<&str> {
+        UpstreamRoute::longest_match(&self.routes, path).map(|route| route.upstream.as_str())
+    }
+
+    /// Most bytes a compressed response body may expand to and still be decoded for
+    /// rewriting: `--max-body-bytes`, or `MAX_DECODED_BYTES` when that is 0
+    pub fn max_decoded_bytes(&self) -> usize {
+        match self.max_body_bytes {
+            0 => MAX_DECODED_BYTES,
+            max => max,
+        }
+    }
+
+    /// Whether `--replace` pairs would be applied to a body with these headers, once any
+    /// gzip, deflate or brotli encoding has been undone
+    pub fn replaces_body(&self, headers: &HashMap<String, String>) -> bool {
+        !self.replacements.is_empty()
+            && is_textual(headers)
+            && (!is_encoded(headers) || ContentEncoding::from_headers(headers).is_some())
+    }
+
+    /// Apply the `--replace` pairs to a response body. Only uncompressed text bodies are
+    /// touched; returns whether anything changed.
+    pub fn apply_replacements(
+        &self,
+        headers: &HashMap<String, String>,
+        body: &mut Vec<u8>,
+    ) -> bool {
+        if self.replacements.is_empty() || !is_textual(headers) || is_encoded(headers) {
+            return false;
+        }
+        let Ok(text) = std::str::from_utf8(body) else {
+            return false;
+        };
+
+        let mut replaced = text.to_string();
+        for replacement in &self.replacements {
+            replaced = replaced.replace(&replacement.find, &replacement.replace);
+        }
+        if replaced.as_bytes() == body.as_slice() {
+            return false;
+        }
+        *body = replaced.into_bytes();
+        true
+    }
+
+    /// Drop the `--strip-recorded-header` headers from a header map about to be stored
+    pub fn strip_recorded(&self, headers: &mut HashMap<String, String>) {
+        if self.strip_recorded_headers.is_empty() {
+            return;
+        }
+        headers.retain(|name, _| {
+            !self
+                .strip_recorded_headers
+                .iter()
+                .any(|strip| strip.eq_ignore_ascii_case(name))
+        });
+    }
+}
+
+/// gRPC and gRPC-Web bodies are length-prefixed frames, so rewriting them corrupts them
+pub fn is_grpc(headers: &HashMap<String, String>) -> bool {
+    headers.iter().any(|(name, value)| {
+        name.eq_ignore_ascii_case("content-type")
+            && value.to_ascii_lowercase().starts_with("application/grpc")
+    })
+}
+
+fn header_value(headers: &HashMap<String, String>, wanted: &str) -> Option<String> {
+    headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+        .map(|(_, value)| value.to_ascii_lowercase())
+}
+
+/// Textual content type, so the decoded body can be edited as a string
+fn is_textual(headers: &HashMap<String, String>) -> bool {
+    header_value(headers, "content-type").is_some_and(|content_type| {
+        content_type.starts_with("text/")
+            || ["json", "xml", "javascript", "x-www-form-urlencoded"]
+                .iter()
+                .any(|kind| content_type.contains(kind))
+    })
+}
+
+fn is_encoded(headers: &HashMap<String, String>) -> bool {
+    header_value(headers, "content-encoding").is_some_and(|encoding| encoding != "identity")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn routes(specs: &[&str]) -> Vec<UpstreamRoute> {
+        specs.iter().map(|spec| spec.parse().unwrap()).collect()
+    }
+
+    fn upstream_for<'a>(routes: &'a [UpstreamRoute], path: &str) -> Option<&'a str> {
+        UpstreamRoute::longest_match(routes, path).map(|route| route.upstream.as_str())
+    }
+
+    #[test]
+    fn prefix_matches_whole_segments() {
+        let routes = routes(&["/api=http://localhost:8000"]);
+        assert_eq!(upstream_for(&routes, "/api"), Some("http://localhost:8000"));
+        assert_eq!(
+            upstream_for(&routes, "/api/users"),
+            Some("http://localhost:8000")
+        );
+        assert_eq!(
+            upstream_for(&routes, "/api?page=2"),
+            Some("http://localhost:8000")
+        );
+        assert_eq!(upstream_for(&routes, "/apiary"), None);
+        assert_eq!(upstream_for(&routes, "/api-docs"), None);
+        assert_eq!(upstream_for(&routes, "/other"), None);
+    }
+
+    #[test]
+    fn trailing_slash_prefix_matches_anything_below() {
+        let routes = routes(&["/static/=http://localhost:9000"]);
+        assert_eq!(
+            upstream_for(&routes, "/static/app.js"),
+            Some("http://localhost:9000")
+        );
+        assert_eq!(upstream_for(&routes, "/static"), None);
+    }
+
+    #[test]
+    fn root_prefix_matches_everything() {
+        let routes = routes(&["/=http://localhost:8000"]);
+        assert_eq!(
+            upstream_for(&routes, "/anything"),
+            Some("http://localhost:8000")
+        );
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let routes = routes(&[
+            "/api=http://localhost:8000",
+            "/api/v2=http://localhost:8002",
+        ]);
+        assert_eq!(
+            upstream_for(&routes, "/api/v2/users"),
+            Some("http://localhost:8002")
+        );
+        assert_eq!(
+            upstream_for(&routes, "/api/v1/users"),
+            Some("http://localhost:8000")
+        );
+        assert_eq!(
+            upstream_for(&routes, "/api/v20"),
+            Some("http://localhost:8000")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_routes() {
+        assert!(
+            "api=http://localhost:8000"
+                .parse::<UpstreamRoute>()
+                .is_err()
+        );
+        assert!("/api=not a url".parse::<UpstreamRoute>().is_err());
+        assert!("/api".parse::<UpstreamRoute>().is_err());
+    }
 }
@@ -0,0 +1,143 @@
+use crate::config::ProxyConfig;
+use crate::http_layer::{ResponseBody, full};
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, StatusCode, header};
+
+// How long browsers may cache a preflight answer
+const PREFLIGHT_MAX_AGE_SECS: &str = "600";
+
+/// `Access-Control-*` headers for the UI server's API. Without `--ui-cors-origin`, or with
+/// `--ui-cors-origin '*'`, any origin may read responses (`*`, no credentials); with a list
+/// only those origins may, and they may send credentials such as Basic Auth.
+pub struct CorsPolicy {
+    // Empty when any origin is allowed
+    origins: Vec<String>,
+    methods: HeaderValue,
+    // None echoes whatever the preflight asks for
+    headers: Option<HeaderValue>,
+}
+
+/// `--ui-cors-origin` values as compared against `Origin`. A wildcard can't go with
+/// credentials, so it opens the API the same way as giving no origins.
+fn allowed_origins(origins: &[String]) -> Vec<String> {
+    if origins.iter().any(|origin| origin == "*") {
+        return Vec::new();
+    }
+    origins
+        .iter()
+        .map(|origin| origin.trim_end_matches('/').to_string())
+        .collect()
+}
+
+impl CorsPolicy {
+    pub fn from_config(config: &ProxyConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            origins: allowed_origins(&config.ui_cors_origins),
+            methods: HeaderValue::from_str(&config.ui_cors_methods)?,
+            headers: config
+                .ui_cors_headers
+                .as_deref()
+                .map(HeaderValue::from_str)
+                .transpose()?,
+        })
+    }
+
+    /// Value for `Access-Control-Allow-Origin`, None if this origin isn't allowed
+    fn allowed_origin(&self, origin: Option<&HeaderValue>) -> Option<HeaderValue> {
+        if self.origins.is_empty() {
+            return Some(HeaderValue::from_static("*"));
+        }
+        let origin = origin?;
+        let value = origin.to_str().ok()?;
+        self.origins
+            .iter()
+            .any(|allowed| allowed == value)
+            .then(|| origin.clone())
+    }
+
+    /// Add the headers for a response to a request from `origin`
+    pub fn apply(&self, origin: Option<&HeaderValue>, headers: &mut HeaderMap) {
+        if !self.origins.is_empty() {
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        }
+        let Some(allowed) = self.allowed_origin(origin) else {
+            return;
+        };
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allowed);
+        if !self.origins.is_empty() {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+
+    /// Answer to an `OPTIONS` preflight; `apply` adds the origin headers afterwards
+    pub fn preflight(&self, request: &HeaderMap) -> hyper::Response<ResponseBody> {
+        let mut response = hyper::Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, self.methods.clone())
+            .header(header::ACCESS_CONTROL_MAX_AGE, PREFLIGHT_MAX_AGE_SECS)
+            .body(full(Bytes::new()))
+            .unwrap();
+        let allow_headers = self
+            .headers
+            .clone()
+            .or_else(|| request.get(header::ACCESS_CONTROL_REQUEST_HEADERS).cloned());
+        if let Some(allow_headers) = allow_headers {
+            response
+                .headers_mut()
+                .insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(origins: &[&str]) -> CorsPolicy {
+        let origins: Vec<String> = origins.iter().map(|origin| origin.to_string()).collect();
+        CorsPolicy {
+            origins: allowed_origins(&origins),
+            methods: HeaderValue::from_static("GET, POST"),
+            headers: None,
+        }
+    }
+
+    fn applied(policy: &CorsPolicy, origin: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        policy.apply(Some(&HeaderValue::from_static(origin)), &mut headers);
+        headers
+    }
+
+    #[test]
+    fn open_policy_sends_a_wildcard_without_credentials() {
+        for policy in [policy(&[]), policy(&["*"])] {
+            let headers = applied(&policy, "https://evil.example");
+            assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+            assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+        }
+    }
+
+    #[test]
+    fn listed_origins_are_echoed_with_credentials() {
+        let policy = policy(&["http://localhost:5173/"]);
+        let headers = applied(&policy, "http://localhost:5173");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "http://localhost:5173"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::VARY], "Origin");
+    }
+
+    #[test]
+    fn other_origins_get_no_allow_headers() {
+        let policy = policy(&["http://localhost:5173"]);
+        let headers = applied(&policy, "https://evil.example");
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+    }
+}
@@ -0,0 +1,111 @@
+use parking_lot::RwLock;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+// Entries kept when --max-recordings is 0
+const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+/// Remembers the last ETag seen from upstream per method + URL so conditional
+/// requests can be answered with 304 without contacting the upstream. Holds at most
+/// `max_entries`, forgetting the least recently stored first.
+#[derive(Clone)]
+pub struct EtagCache {
+    inner: Arc<RwLock<Etags>>,
+    max_entries: usize,
+}
+
+#[derive(Default)]
+struct Etags {
+    // Cache key -> (ETag, when it was stored)
+    by_key: HashMap<String, (String, u64)>,
+    // When each entry was stored -> its key, oldest first
+    by_age: BTreeMap<u64, String>,
+    next_stamp: u64,
+}
+
+impl EtagCache {
+    /// A cache holding up to `max_recordings` ETags, or 10,000 when that is 0
+    pub fn new(max_recordings: usize) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Etags::default())),
+            max_entries: match max_recordings {
+                0 => DEFAULT_MAX_ENTRIES,
+                max => max,
+            },
+        }
+    }
+
+    /// Store the ETag of an upstream response, or forget it when the response has none
+    pub fn store(&self, method: &str, url: &str, etag: Option<&str>) {
+        let key = cache_key(method, url);
+        let mut etags = self.inner.write();
+        if let Some((_, stamp)) = etags.by_key.remove(&key) {
+            etags.by_age.remove(&stamp);
+        }
+        let Some(etag) = etag else {
+            return;
+        };
+
+        let stamp = etags.next_stamp;
+        etags.next_stamp += 1;
+        etags.by_age.insert(stamp, key.clone());
+        etags.by_key.insert(key, (etag.to_string(), stamp));
+        while etags.by_key.len() > self.max_entries {
+            let Some((_, oldest)) = etags.by_age.pop_first() else {
+                break;
+            };
+            etags.by_key.remove(&oldest);
+        }
+    }
+
+    /// Return the stored ETag if the `If-None-Match` value matches it
+    pub fn matching_etag(&self, method: &str, url: &str, if_none_match: &str) -> Option<String> {
+        let etags = self.inner.read();
+        let (stored, _) = etags.by_key.get(&cache_key(method, url))?;
+
+        // If-None-Match uses weak comparison: "W/" prefixes are ignored
+        let matched = if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|candidate| candidate == "*" || strip_weak(candidate) == strip_weak(stored));
+
+        matched.then(|| stored.clone())
+    }
+}
+
+fn cache_key(method: &str, url: &str) -> String {
+    format!("{} {}", method, url)
+}
+
+fn strip_weak(etag: &str) -> &str {
+    etag.strip_prefix("W/").unwrap_or(etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oldest_entries_are_evicted_past_the_cap() {
+        let cache = EtagCache::new(2);
+        cache.store("GET", "/a", Some("\"a\""));
+        cache.store("GET", "/b", Some("\"b\""));
+        // Storing /a again makes /b the oldest
+        cache.store("GET", "/a", Some("\"a2\""));
+        cache.store("GET", "/c", Some("\"c\""));
+
+        assert_eq!(
+            cache.matching_etag("GET", "/a", "\"a2\""),
+            Some("\"a2\"".into())
+        );
+        assert_eq!(cache.matching_etag("GET", "/b", "*"), None);
+        assert_eq!(
+            cache.matching_etag("GET", "/c", "W/\"c\""),
+            Some("\"c\"".into())
+        );
+
+        cache.store("GET", "/c", None);
+        assert_eq!(cache.matching_etag("GET", "/c", "*"), None);
+        assert_eq!(cache.inner.read().by_age.len(), 1);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::http_layer::HttpLayerState;
+use crate::logger::RequestLog;
+use crate::matcher::{MatchType, unmet_header_condition, unmet_query_condition};
+use crate::schedule::ActiveWindow;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Request header that asks for a decision trace on the response
+pub const EXPLAIN_HEADER: &str = "x-devproxy-explain";
+
+/// How one rule was evaluated against a request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleTrace {
+    pub rule_id: String,
+    pub name: String,
+    pub priority: i32,
+    pub matched: bool,
+    pub reason: String,
+}
+
+/// Trace of every decision point `handle_request` went through for one request. Rules
+/// are listed highest priority first, the same order they're tried in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Explanation {
+    /// passthrough, rate_limited, mocked, cache_hit, auto_mocked or proxied
+    pub outcome: String,
+    pub passthrough: bool,
+    pub rate_limits: Vec<RuleTrace>,
+    pub mocks: Vec<RuleTrace>,
+    /// Mock rule that produced the response (the merged base or the default rule included)
+    pub mock_rule: Option<String>,
+    pub modifiers: Vec<RuleTrace>,
+    pub latency: Vec<RuleTrace>,
+}
+
+/// Matching criteria shared by every rule type, for explaining why a rule was skipped
+pub struct RuleCheck<'a> {
+    pub id: &'a str,
+    pub name: &'a str,
+    pub priority: i32,
+    pub enabled: bool,
+    pub schedule: &'a ActiveWindow,
+    pub method: Option<&'a str>,
+    pub url_pattern: &'a str,
+    pub url_match_type: &'a MatchType,
+    pub match_query: bool,
+}
+
+impl RuleCheck<'_> {
+    /// Evaluate the rule the same way the managers do, reporting the first failed check
+    pub fn trace(&self, method: &str, url: &str) -> RuleTrace {
+        let failure = if !self.enabled {
+            Some("rule is disabled".to_string())
+        } else if !self.schedule.is_active() {
+            Some("outside the rule's active window".to_string())
+        } else if let Some(expected) = self.method.filter(|expected| *expected != method) {
+            Some(format!("method {} is not {}", method, expected))
+        } else if !self
+            .url_match_type
+            .matches_url(self.url_pattern, url, self.match_query)
+        {
+            Some(format!(
+                "url doesn't match {:?} pattern {}",
+                self.url_match_type, self.url_pattern
+            ))
+        } else {
+            None
+        };
+
+        RuleTrace {
+            rule_id: self.id.to_string(),
+            name: self.name.to_string(),
+            priority: self.priority,
+            matched: failure.is_none(),
+            reason: failure.unwrap_or_else(|| "matched".to_string()),
+        }
+    }
+}
+
+/// Why a rule's query parameter or header conditions fail for a request, if they do
+pub fn unmet_conditions(
+    query_matches: &[(String, String)],
+    header_matches: &[(String, String)],
+    url: &str,
+    headers: &HashMap<String, String>,
+) -> Option<String> {
+    if let Some((name, value)) = unmet_query_condition(query_matches, url) {
+        return Some(format!("query parameter {}={} not present", name, value));
+    }
+    unmet_header_condition(header_matches, headers)
+        .map(|(name, value)| format!("header {}: {} not present", name, value))
+}
+
+/// Whether a trace was asked for, by `--explain` or the `X-DevProxy-Explain: true` header
+pub fn requested(state: &HttpLayerState, headers: &http::HeaderMap) -> bool {
+    state.config.explain
+        || headers
+            .get(EXPLAIN_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.eq_ignore_ascii_case("true"))
+}
+
+/// Re-run the matchers for a handled request and describe what each one decided
+pub fn explain(state: &HttpLayerState, log: &RequestLog, status: u16) -> Explanation {
+    Explanation {
+        outcome: outcome(log).to_string(),
+        passthrough: log.passthrough,
+        rate_limits: state.rate_limiter.explain(&log.method, &log.url),
+        mocks: state.mock_manager.explain(
+            &log.method,
+            &log.url,
+            &log.request_headers,
+            log.request_body.as_deref(),
+        ),
+        mock_rule: log.mock_rule.clone(),
+        modifiers: state.response_modifier.explain(
+            &log.method,
+            &log.url,
+            &log.request_headers,
+            Some(status),
+        ),
+        latency: state.latency_injector.explain(&log.method, &log.url),
+    }
+}
+
+/// What answered the request, as reported in `Explanation::outcome`
+fn outcome(log: &RequestLog) -> &'static str {
+    if log.passthrough {
+        "passthrough"
+    } else if log.rate_limited {
+        "rate_limited"
+    } else if log.mocked {
+        "mocked"
+    } else if log.cache_hit {
+        "cache_hit"
+    } else if log.auto_mock.is_some() {
+        "auto_mocked"
+    } else {
+        "proxied"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_mocked_requests_are_not_reported_as_rule_mocks() {
+        let mut log = RequestLog::new("id".to_string(), "GET", "/api/items");
+        assert_eq!(outcome(&log), "proxied");
+
+        log.auto_mock = Some("recording".to_string());
+        assert_eq!(outcome(&log), "auto_mocked");
+
+        log.mocked = true;
+        assert_eq!(outcome(&log), "mocked");
+    }
+}
@@ -0,0 +1,251 @@
+use parking_lot::RwLock;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub use crate::matcher::MatchType;
+use crate::matcher::default_match_query;
+use crate::schedule::ActiveWindow;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaultRule {
+    pub id: String,
+    pub name: String,
+    pub enabled: bool,
+    pub priority: i32,
+    pub match_request: FaultMatch,
+    pub fault: FaultType,
+    pub probability: f64, // 0.0 to 1.0
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateFaultRule {
+    pub name: String,
+    pub enabled: bool,
+    pub priority: i32,
+    pub match_request: FaultMatch,
+    pub fault: FaultType,
+    pub probability: f64,
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateFaultRule {
+    pub id: String,
+    pub name: String,
+    pub enabled: bool,
+    pub priority: i32,
+    pub match_request: FaultMatch,
+    pub fault: FaultType,
+    pub probability: f64,
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaultMatch {
+    pub method: Option<String>,
+    pub url_pattern: String,
+    pub url_match_type: MatchType,
+    // Match url_pattern against the whole URL; false leaves the query string out
+    #[serde(default = "default_match_query")]
+    pub match_query: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FaultType {
+    // Close the client connection without sending a response
+    ConnectionReset,
+    // Announce the full Content-Length, send `keep_bytes` of the body (half by default),
+    // then close the connection
+    TruncatedBody {
+        #[serde(default)]
+        keep_bytes: Option<usize>,
+    },
+    // Start a chunked response and close the connection before the terminating chunk
+    MalformedChunked,
+}
+
+impl From<CreateFaultRule> for FaultRule {
+    /// A new rule with a fresh id
+    fn from(create_rule: CreateFaultRule) -> Self {
+        FaultRule {
+            id: Uuid::new_v4().to_string(),
+            name: create_rule.name,
+            enabled: create_rule.enabled,
+            priority: create_rule.priority,
+            match_request: create_rule.match_request,
+            fault: create_rule.fault,
+            probability: create_rule.probability,
+            schedule: create_rule.schedule.resolve(),
+            created_at: chrono::Utc::now(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct FaultInjector {
+    rules: Arc<RwLock<HashMap<String, FaultRule>>>,
+    stats: Arc<RwLock<FaultStats>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FaultStats {
+    pub total_injections: u64,
+    pub by_rule: HashMap<String, FaultRuleStats>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaultRuleStats {
+    pub rule_id: String,
+    pub rule_name: String,
+    pub hits: u64,
+}
+
+impl FaultInjector {
+    pub fn new() -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(HashMap::new())),
+            stats: Arc::new(RwLock::new(FaultStats::default())),
+        }
+    }
+
+    pub fn add_rule(&self, create_rule: CreateFaultRule) -> String {
+        let rule = FaultRule::from(create_rule);
+        let id = rule.id.clone();
+
+        let mut rules = self.rules.write();
+        rules.insert(id.clone(), rule);
+
+        id
+    }
+
+    pub fn update_rule(&self, update_rule: UpdateFaultRule) -> bool {
+        let mut rules = self.rules.write();
+        if let Some(existing) = rules.get(&update_rule.id) {
+            let rule = FaultRule {
+                id: update_rule.id.clone(),
+                name: update_rule.name,
+                enabled: update_rule.enabled,
+                priority: update_rule.priority,
+                match_request: update_rule.match_request,
+                fault: update_rule.fault,
+                probability: update_rule.probability,
+                schedule: update_rule.schedule.resolve(),
+                created_at: existing.created_at,
+            };
+            rules.insert(update_rule.id, rule);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn delete_rule(&self, id: &str) -> bool {
+        let mut rules = self.rules.write();
+        rules.remove(id).is_some()
+    }
+
+    pub fn get_rule(&self, id: &str) -> Option<FaultRule> {
+        let rules = self.rules.read();
+        rules.get(id).cloned()
+    }
+
+    pub fn get_all_rules(&self) -> Vec<FaultRule> {
+        let rules = self.rules.read();
+        let mut all_rules: Vec<_> = rules.values().cloned().collect();
+        all_rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+        all_rules
+    }
+
+    pub fn toggle_rule(&self, id: &str) -> bool {
+        let mut rules = self.rules.write();
+        if let Some(rule) = rules.get_mut(id) {
+            rule.enabled = !rule.enabled;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn clear_all(&self) {
+        let mut rules = self.rules.write();
+        let mut stats = self.stats.write();
+        rules.clear();
+        *stats = FaultStats::default();
+    }
+
+    /// The fault to inject into this request, if the highest priority matching rule
+    /// fires. Each fired fault is counted in the stats.
+    pub fn roll(&self, method: &str, url: &str) -> Option<FaultType> {
+        let rule = {
+            let rules = self.rules.read();
+            rules
+                .values()
+                .filter(|rule| {
+                    rule.enabled && rule.schedule.is_active() && self.matches(rule, method, url)
+                })
+                .max_by_key(|rule| rule.priority)
+                .cloned()?
+        };
+
+        let mut rng = rand::thread_rng();
+        if rng.gen_range(0.0..1.0) >= rule.probability {
+            return None;
+        }
+
+        self.record_fault(&rule.id, &rule.name);
+        Some(rule.fault)
+    }
+
+    fn matches(&self, rule: &FaultRule, method: &str, url: &str) -> bool {
+        // Check method
+        if rule
+            .match_request
+            .method
+            .as_ref()
+            .is_some_and(|rule_method| rule_method != method)
+        {
+            return false;
+        }
+
+        // Check URL pattern
+        rule.match_request.url_match_type.matches_url(
+            &rule.match_request.url_pattern,
+            url,
+            rule.match_request.match_query,
+        )
+    }
+
+    fn record_fault(&self, rule_id: &str, rule_name: &str) {
+        let mut stats = self.stats.write();
+        stats.total_injections += 1;
+
+        let rule_stats = stats
+            .by_rule
+            .entry(rule_id.to_string())
+            .or_insert(FaultRuleStats {
+                rule_id: rule_id.to_string(),
+                rule_name: rule_name.to_string(),
+                hits: 0,
+            });
+        rule_stats.hits += 1;
+    }
+
+    pub fn get_stats(&self) -> FaultStats {
+        let stats = self.stats.read();
+        stats.clone()
+    }
+
+    pub fn reset_stats(&self) {
+        let mut stats = self.stats.write();
+        *stats = FaultStats::default();
+    }
+}
@@ -0,0 +1,73 @@
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// gRPC-Web framing for mock responses.
+///
+/// A gRPC-Web response body is a sequence of frames, each a 1-byte flag followed by a
+/// 4-byte big-endian length and the payload:
+///
+/// - data frame (flag `0x00`): the serialized message
+/// - trailer frame (flag `0x80`): HTTP/1-style header lines, e.g. `grpc-status:0\r\n`
+///
+/// The HTTP status stays 200 for gRPC errors; failures are reported through `grpc-status`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrpcWebResponse {
+    pub format: GrpcWebFormat,
+    #[serde(default)]
+    pub grpc_status: u32,
+    #[serde(default)]
+    pub grpc_message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GrpcWebFormat {
+    // Mock body is the base64-encoded protobuf message
+    Proto,
+    // Mock body is the JSON message, sent as-is
+    Json,
+}
+
+const DATA_FRAME: u8 = 0x00;
+const TRAILER_FRAME: u8 = 0x80;
+
+impl GrpcWebResponse {
+    pub fn content_type(&self) -> &'static str {
+        match self.format {
+            GrpcWebFormat::Proto => "application/grpc-web+proto",
+            GrpcWebFormat::Json => "application/grpc-web+json",
+        }
+    }
+
+    /// Frame the mock body as a gRPC-Web response. An empty body sends only the trailers,
+    /// which is how servers usually answer with an error status.
+    pub fn encode(&self, body: &str) -> Result<Vec<u8>, String> {
+        let message = match self.format {
+            GrpcWebFormat::Proto => base64::engine::general_purpose::STANDARD
+                .decode(body.trim())
+                .map_err(|e| format!("Invalid base64 gRPC message: {}", e))?,
+            GrpcWebFormat::Json => body.as_bytes().to_vec(),
+        };
+
+        let mut trailers = format!("grpc-status:{}\r\n", self.grpc_status);
+        if let Some(ref grpc_message) = self.grpc_message {
+            trailers.push_str(&format!(
+                "grpc-message:{}\r\n",
+                urlencoding::encode(grpc_message)
+            ));
+        }
+
+        let mut framed = Vec::with_capacity(message.len() + trailers.len() + 10);
+        if !message.is_empty() {
+            push_frame(&mut framed, DATA_FRAME, &message);
+        }
+        push_frame(&mut framed, TRAILER_FRAME, trailers.as_bytes());
+        Ok(framed)
+    }
+}
+
+fn push_frame(out: &mut Vec<u8>, flag: u8, payload: &[u8]) {
+    out.push(flag);
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    out.extend_from_slice(payload);
+}
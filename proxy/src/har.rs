@@ -0,0 +1,300 @@
+use crate::matcher::MatchType;
+use crate::mock::{CreateMockRule, MockResponse};
+use crate::schedule::ActiveWindow;
+use crate::storage::RecordedRequest;
+use base64::Engine;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+
+// Describe the captured transfer rather than the content; the mock is served unencoded
+const SKIPPED_MOCK_HEADERS: &[&str] = &[
+    "content-length",
+    "content-encoding",
+    "transfer-encoding",
+    "connection",
+    "keep-alive",
+];
+
+/// Serialize recordings as a HAR 1.2 document, oldest entry first. Recordings only keep
+/// the path, so request URLs are rebuilt from the recorded Host header.
+pub fn to_har(recordings: &[RecordedRequest]) -> Value {
+    let mut recordings: Vec<&RecordedRequest> = recordings.iter().collect();
+    recordings.sort_by_key(|recording| recording.timestamp);
+
+    json!({
+        "log": {
+            "version": "1.2",
+            "creator": {
+                "name": "dev-proxy",
+                "version": crate::version::build_info().version,
+            },
+            "pages": [],
+            "entries": recordings.into_iter().map(entry).collect::<Vec<_>>(),
+        }
+    })
+}
+
+fn entry(recording: &RecordedRequest) -> Value {
+    let url = absolute_url(recording);
+    let query_string: Vec<Value> = url::Url::parse(&url)
+        .map(|url| {
+            url.query_pairs()
+                .map(|(name, value)| json!({ "name": name, "value": value }))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut request = json!({
+        "method": recording.method,
+        "url": url,
+        "httpVersion": "HTTP/1.1",
+        "cookies": [],
+        "headers": headers(&recording.headers),
+        "queryString": query_string,
+        "headersSize": -1,
+        "bodySize": recording.body.as_ref().map_or(0, |body| body.len()),
+    });
+    if let Some(ref body) = recording.body {
+        let mut post_data = content(body);
+        post_data["mimeType"] = json!(mime_type(&recording.headers));
+        request["postData"] = post_data;
+    }
+
+    let response = match recording.response {
+        Some(ref response) => {
+            let body = response.body.as_deref().unwrap_or_default();
+            let mut content = content(body);
+            content["size"] = json!(body.len());
+            content["mimeType"] = json!(mime_type(&response.headers));
+            json!({
+                "status": response.status,
+                "statusText": http::StatusCode::from_u16(response.status)
+                    .ok()
+                    .and_then(|status| status.canonical_reason())
+                    .unwrap_or_default(),
+                "httpVersion": "HTTP/1.1",
+                "cookies": [],
+                "headers": headers(&response.headers),
+                "content": content,
+                "redirectURL": header(&response.headers, "location").unwrap_or_default(),
+                "headersSize": -1,
+                "bodySize": body.len(),
+            })
+        }
+        // Still in flight or never answered; HAR uses status 0 for that
+        None => json!({
+            "status": 0,
+            "statusText": "",
+            "httpVersion": "HTTP/1.1",
+            "cookies": [],
+            "headers": [],
+            "content": { "size": 0, "mimeType": "x-unknown" },
+            "redirectURL": "",
+            "headersSize": -1,
+            "bodySize": -1,
+        }),
+    };
+
+    // Only the total is measured, so it's all attributed to waiting on the server
+    let duration_ms = recording.duration_ms.unwrap_or(0);
+    json!({
+        "startedDateTime": recording.timestamp.to_rfc3339(),
+        "time": duration_ms,
+        "request": request,
+        "response": response,
+        "cache": {},
+        "timings": {
+            "send": 0,
+            "wait": duration_ms,
+            "receive": 0,
+        },
+    })
+}
+
+fn absolute_url(recording: &RecordedRequest) -> String {
+    if recording.url.starts_with("http://") || recording.url.starts_with("https://") {
+        return recording.url.clone();
+    }
+    let host = header(&recording.headers, "host").unwrap_or("localhost");
+    format!("http://{}{}", host, recording.url)
+}
+
+fn headers(headers: &HashMap<String, String>) -> Vec<Value> {
+    let mut headers: Vec<_> = headers.iter().collect();
+    headers.sort();
+    headers
+        .into_iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect()
+}
+
+fn header<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn mime_type(headers: &HashMap<String, String>) -> &str {
+    header(headers, "content-type").unwrap_or("application/octet-stream")
+}
+
+/// Body as HAR content text, base64-encoded when it isn't valid UTF-8
+fn content(body: &[u8]) -> Value {
+    match std::str::from_utf8(body) {
+        Ok(text) => json!({ "text": text }),
+        Err(_) => json!({
+            "text": base64::engine::general_purpose::STANDARD.encode(body),
+            "encoding": "base64",
+        }),
+    }
+}
+
+/// Turn the entries of a HAR document into exact-match mock rules, one per method, path
+/// and query. The query becomes `query_matches` conditions rather than part of the
+/// pattern, so parameter order doesn't matter, and a rule with more conditions gets a
+/// higher priority than one for the bare path. When several entries share a key, the
+/// last entry wins. Base64 bodies are decoded; mock bodies are text, so binary content
+/// is converted lossily.
+pub fn to_mock_rules(har: &Value) -> Result<Vec<CreateMockRule>, String> {
+    let entries = har["log"]["entries"]
+        .as_array()
+        .ok_or("Not a HAR file: missing log.entries")?;
+
+    let mut rules = Vec::new();
+    let mut seen = HashSet::new();
+    for (index, entry) in entries.iter().enumerate().rev() {
+        let rule = mock_rule(entry).map_err(|e| format!("entry {}: {}", index, e))?;
+        let mut query = rule.query_matches.clone();
+        query.sort();
+        if seen.insert((rule.method.clone(), rule.url_pattern.clone(), query)) {
+            rules.push(rule);
+        }
+    }
+    rules.reverse();
+    Ok(rules)
+}
+
+fn mock_rule(entry: &Value) -> Result<CreateMockRule, String> {
+    let request = &entry["request"];
+    let method = request["method"]
+        .as_str()
+        .ok_or("missing request.method")?
+        .to_uppercase();
+    let url = request["url"].as_str().ok_or("missing request.url")?;
+    let url = url::Url::parse(url).map_err(|e| format!("invalid url '{}': {}", url, e))?;
+    let path = url.path().to_string();
+    let query_matches: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+
+    let response = &entry["response"];
+    let status = response["status"]
+        .as_u64()
+        .and_then(|status| u16::try_from(status).ok())
+        .filter(|status| *status > 0)
+        .ok_or("missing response.status")?;
+
+    let mut headers = HashMap::new();
+    for header in response["headers"].as_array().into_iter().flatten() {
+        let (Some(name), Some(value)) = (header["name"].as_str(), header["value"].as_str()) else {
+            continue;
+        };
+        let name = name.to_ascii_lowercase();
+        if !SKIPPED_MOCK_HEADERS.contains(&name.as_str()) {
+            headers.insert(name, value.to_string());
+        }
+    }
+
+    let content = &response["content"];
+    let text = content["text"].as_str().unwrap_or_default();
+    let body = if content["encoding"].as_str() == Some("base64") {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(text)
+            .map_err(|e| format!("invalid base64 body: {}", e))?;
+        String::from_utf8_lossy(&bytes).into_owned()
+    } else {
+        text.to_string()
+    };
+
+    let name = match url.query() {
+        Some(query) => format!("{} {}?{}", method, path, query),
+        None => format!("{} {}", method, path),
+    };
+
+    Ok(CreateMockRule {
+        name,
+        enabled: true,
+        priority: query_matches.len() as i32,
+        method: Some(method),
+        url_pattern: path,
+        url_match_type: MatchType::Exact,
+        match_query: false,
+        body_pattern: None,
+        body_match_type: None,
+        normalize_json_body: false,
+        query_matches,
+        header_matches: Vec::new(),
+        response: MockResponse {
+            status,
+            headers,
+            body,
+            grpc_web: None,
+            body_url: None,
+            chunked: None,
+            templated: false,
+        },
+        content_responses: Vec::new(),
+        variants: Vec::new(),
+        hash_by: None,
+        responses: Vec::new(),
+        sequence: None,
+        fail_first: None,
+        delay_ms: None,
+        delay_jitter_ms: None,
+        merge_matching: false,
+        schedule: ActiveWindow::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(url: &str, text: &str) -> Value {
+        json!({
+            "request": { "method": "get", "url": url },
+            "response": { "status": 200, "headers": [], "content": { "text": text } },
+        })
+    }
+
+    #[test]
+    fn query_becomes_conditions_not_pattern() {
+        let har = json!({ "log": { "entries": [
+            entry("http://api.test/users?page=2&sort=name", "a"),
+            entry("http://api.test/users?sort=name&page=2", "b"),
+            entry("http://api.test/users", "c"),
+        ] } });
+        let rules = to_mock_rules(&har).unwrap();
+
+        assert_eq!(rules.len(), 2);
+        let with_query = &rules[0];
+        assert_eq!(with_query.url_pattern, "/users");
+        assert!(!with_query.match_query);
+        assert_eq!(with_query.response.body, "b");
+        assert_eq!(with_query.priority, 2);
+        assert_eq!(
+            with_query.query_matches,
+            vec![
+                ("sort".to_string(), "name".to_string()),
+                ("page".to_string(), "2".to_string()),
+            ]
+        );
+
+        let bare = &rules[1];
+        assert_eq!(bare.url_pattern, "/users");
+        assert!(bare.query_matches.is_empty());
+        assert_eq!(bare.priority, 0);
+    }
+}
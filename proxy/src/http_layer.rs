@@ -1,87 +1,535 @@
-use crate::mock::MockManager;
+use crate::compression::DecodedBody;
+use crate::config::{ProxyConfig, is_grpc};
+use crate::etag_cache::EtagCache;
+use crate::explain;
+use crate::fault_injector::{FaultInjector, FaultType};
+use crate::logger::{RequestLog, RequestLogger};
+use crate::metrics::Metrics;
+use crate::mock::{ChunkedDelivery, MockManager, MockRequest, MockResponse};
 use crate::modifier::ResponseModifier;
 use crate::rate_limiter::RateLimiter;
+use crate::recorder::REQUEST_ID_HEADER;
+use crate::recorder::RecordFilter;
 
-use crate::latency_injector::{ApplyTo, LatencyInjector};
+use crate::latency_injector::{ApplyTo, GLOBAL_DELAY_ID, LatencyInjector};
 
+use crate::shutdown::Shutdown;
 use crate::storage::Storage;
+use crate::websocket;
 use anyhow::Result;
 use bytes::Bytes;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
 use hyper::{Request, Response, StatusCode};
 use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
 use tokio::net::TcpListener;
 
-pub async fn start_http_layer(
-    port: u16,
-    pingora_port: u16,
-    storage: Storage,
-    mock_manager: MockManager,
-    response_modifier: ResponseModifier,
-    rate_limiter: RateLimiter,
-    latency_injector: LatencyInjector,
-) -> Result<()> {
+/// Error that aborts a response body, closing the connection mid-response
+pub type BodyError = std::io::Error;
+
+/// Body of every response sent by the HTTP layer; usually complete, streamed for chunked mocks
+pub type ResponseBody = UnsyncBoxBody<Bytes, BodyError>;
+
+pub fn full(body: impl Into<Bytes>) -> ResponseBody {
+    http_body_util::Full::new(body.into())
+        .map_err(|never| match never {})
+        .boxed_unsync()
+}
+
+/// Remote address of the TCP connection a request arrived on
+#[derive(Clone, Copy)]
+struct ClientAddr(SocketAddr);
+
+/// Marks a response whose connection should be dropped instead of answered
+#[derive(Clone, Copy)]
+struct DropConnection;
+
+/// Stream `body` in `chunk_size` pieces, pausing `chunk_delay_ms` between them. The length
+/// isn't known up front, so hyper sends it with `Transfer-Encoding: chunked`.
+fn chunked_body(body: Bytes, chunking: ChunkedDelivery) -> ResponseBody {
+    let chunk_size = chunking.chunk_size.max(1);
+    let delay = Duration::from_millis(chunking.chunk_delay_ms);
+
+    let chunks = futures_util::stream::unfold((body, true), move |(mut rest, first)| async move {
+        if rest.is_empty() {
+            return None;
+        }
+        if !first && !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        let chunk = rest.split_to(chunk_size.min(rest.len()));
+        Some((Ok(Frame::data(chunk)), (rest, false)))
+    });
+    StreamBody::new(chunks).boxed_unsync()
+}
+
+/// Shared state handed to every request served by the HTTP layer
+pub struct HttpLayerState {
+    pub config: ProxyConfig,
+    pub storage: Storage,
+    pub mock_manager: MockManager,
+    pub response_modifier: ResponseModifier,
+    pub rate_limiter: RateLimiter,
+    pub latency_injector: LatencyInjector,
+    pub fault_injector: FaultInjector,
+    pub etag_cache: EtagCache,
+    pub metrics: Metrics,
+    pub logger: RequestLogger,
+    // Forwards requests to the Pingora layer; one pool for every request
+    pub client: reqwest::Client,
+    // TLS for wss:// upstreams, see `websocket::upstream_connector`
+    pub ws_connector: tokio_tungstenite::Connector,
+    // Round-robin position in `config.upstream_urls`, shared with the Pingora layer
+    pub next_upstream: Arc<AtomicUsize>,
+    // Requests currently being served, bodies included, for --max-inflight load shedding
+    // and shutdown
+    pub inflight: Arc<AtomicUsize>,
+    pub shutdown: Shutdown,
+    // Whether recording is on right now, shared with the recorder and the UI
+    pub recording: Arc<AtomicBool>,
+    // --record-filter rules, shared with the recorder
+    pub record_filter: RecordFilter,
+    // Answer repeated requests from their recording instead of upstream; the UI can toggle it
+    pub auto_mock: Arc<AtomicBool>,
+}
+
+impl HttpLayerState {
+    /// Whether a request to `url` handled here (mocked, cached or a WebSocket) is stored
+    pub fn should_record(&self, url: &str) -> bool {
+        self.recording.load(Ordering::Relaxed) && self.record_filter.allows(url)
+    }
+}
+
+/// Seconds clients are asked to wait when a request is shed
+const SHED_RETRY_AFTER_SECS: u64 = 1;
+
+/// Names the recording an `--auto-mock` response was served from
+const AUTO_MOCK_HEADER: &str = "x-dev-proxy-auto-mock";
+
+/// How often the in-flight count is checked while draining on shutdown
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Holds one in-flight slot and releases it when the request finishes
+struct InflightGuard(Arc<AtomicUsize>);
+
+impl InflightGuard {
+    /// Take a slot, or None when `max` requests are already in flight
+    fn acquire(inflight: &Arc<AtomicUsize>, max: Option<usize>) -> Option<Self> {
+        let previous = inflight.fetch_add(1, Ordering::AcqRel);
+        if max.is_some_and(|max| previous >= max) {
+            inflight.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+        Some(Self(inflight.clone()))
+    }
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+pub async fn start_http_layer(port: u16, state: HttpLayerState) -> Result<()> {
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let tls = tls_acceptor(&state.config)?;
     let listener = TcpListener::bind(addr).await?;
 
-    println!("HTTP layer listening on {}", addr);
+    println!(
+        "HTTP layer listening on {}{}",
+        addr,
+        if tls.is_some() { " (TLS)" } else { "" }
+    );
 
-    let storage = Arc::new(storage);
-    let mock_manager = Arc::new(mock_manager);
-    let response_modifier = Arc::new(response_modifier);
-    let rate_limiter = Arc::new(rate_limiter);
-    let latency_injector = Arc::new(latency_injector);
+    let state = Arc::new(state);
 
     loop {
-        let (stream, _) = listener.accept().await?;
-        let storage = storage.clone();
-        let mock_manager = mock_manager.clone();
-        let response_modifier = response_modifier.clone();
-        let rate_limiter = rate_limiter.clone();
-        let latency_injector = latency_injector.clone();
+        let (stream, peer_addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = state.shutdown.draining() => break,
+        };
+        let state = state.clone();
+        let tls = tls.clone();
 
+        // The handshake runs in the connection's task, so a slow client can't stall accepts
         tokio::spawn(async move {
-            let io = hyper_util::rt::TokioIo::new(stream);
-
-            let service = hyper::service::service_fn(move |req| {
-                let storage = storage.clone();
-                let mock_manager = mock_manager.clone();
-                let response_modifier = response_modifier.clone();
-                let rate_limiter = rate_limiter.clone();
-                let latency_injector = latency_injector.clone();
-                handle_request(
-                    req,
-                    pingora_port,
-                    storage,
-                    mock_manager,
-                    response_modifier,
-                    rate_limiter,
-                    latency_injector,
-                )
-            });
+            match tls {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => serve_connection(stream, state, peer_addr).await,
+                    Err(e) => eprintln!("TLS handshake with {} failed: {}", peer_addr, e),
+                },
+                None => serve_connection(stream, state, peer_addr).await,
+            }
+        });
+    }
 
-            if let Err(e) = hyper::server::conn::http1::Builder::new()
-                .serve_connection(io, service)
-                .await
+    drop(listener);
+    drain(
+        &state.inflight,
+        Duration::from_millis(state.config.shutdown_grace_ms),
+    )
+    .await;
+    Ok(())
+}
+
+/// Wait for requests still being served, response bodies included, for at most `grace`
+/// (`--shutdown-grace-ms`)
+async fn drain(inflight: &AtomicUsize, grace: Duration) {
+    let deadline = tokio::time::Instant::now() + grace;
+    let count = inflight.load(Ordering::Acquire);
+    if count > 0 {
+        println!(
+            "   Waiting up to {}ms for {} in-flight request(s)...",
+            grace.as_millis(),
+            count
+        );
+    }
+    loop {
+        let count = inflight.load(Ordering::Acquire);
+        if count == 0 {
+            return;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            eprintln!(
+                "⚠️  Grace period over, dropping {} in-flight request(s)",
+                count
+            );
+            return;
+        }
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+}
+
+/// TLS acceptor for `--tls-cert`/`--tls-key`, None to serve plain HTTP
+fn tls_acceptor(config: &ProxyConfig) -> Result<Option<tokio_rustls::TlsAcceptor>> {
+    use tokio_rustls::rustls::pki_types::pem::PemObject;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    let (Some(cert_path), Some(key_path)) = (&config.tls_cert, &config.tls_key) else {
+        return Ok(None);
+    };
+
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to read TLS certificate {}: {}",
+                cert_path.display(),
+                e
+            )
+        })?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| anyhow::anyhow!("Failed to read TLS key {}: {}", key_path.display(), e))?;
+
+    // Pick the provider explicitly so another dependency enabling a second one can't
+    // make the default ambiguous
+    let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+    let mut server_config = tokio_rustls::rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    // Client connections are served over HTTP/1 only
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Some(tokio_rustls::TlsAcceptor::from(Arc::new(
+        server_config,
+    ))))
+}
+
+/// Serve HTTP/1 requests from one client connection, plain or TLS, until it closes
+async fn serve_connection<S>(stream: S, state: Arc<HttpLayerState>, peer_addr: SocketAddr)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let io = hyper_util::rt::TokioIo::new(stream);
+    let builder = http1_builder(&state.config);
+    let served = Arc::new(AtomicUsize::new(0));
+    let shutdown = state.shutdown.clone();
+
+    let service = hyper::service::service_fn(move |req| {
+        let state = state.clone();
+        let served = served.clone();
+        async move {
+            let Ok(mut response) = handle_request(req, state.clone(), peer_addr).await;
+            if response.extensions().get::<DropConnection>().is_some() {
+                return Err(BodyError::other("connection reset by fault injection"));
+            }
+
+            // Ask the client to close once the per-connection budget is used up
+            let count = served.fetch_add(1, Ordering::Relaxed) + 1;
+            if state
+                .config
+                .max_requests_per_connection
+                .is_some_and(|max| count >= max)
             {
-                eprintln!("HTTP layer connection error: {}", e);
+                response.headers_mut().insert(
+                    http::header::CONNECTION,
+                    http::HeaderValue::from_static("close"),
+                );
             }
-        });
+
+            Ok::<_, BodyError>(response)
+        }
+    });
+
+    let connection = builder.serve_connection(io, service).with_upgrades();
+    tokio::pin!(connection);
+    let result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = shutdown.draining() => {
+            // Finish the request in progress, then close rather than keep the connection alive
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("HTTP layer connection error: {}", e);
     }
 }
 
+/// Build the HTTP/1 server options for client connections from the CLI settings
+fn http1_builder(config: &ProxyConfig) -> hyper::server::conn::http1::Builder {
+    let mut builder = hyper::server::conn::http1::Builder::new();
+    builder
+        .keep_alive(config.keep_alive)
+        .pipeline_flush(config.pipeline_flush);
+
+    if let Some(timeout_ms) = config.header_read_timeout_ms {
+        builder
+            .timer(hyper_util::rt::TokioTimer::new())
+            .header_read_timeout(Duration::from_millis(timeout_ms));
+    }
+
+    builder
+}
+
 async fn handle_request(
+    mut req: Request<hyper::body::Incoming>,
+    state: Arc<HttpLayerState>,
+    peer_addr: SocketAddr,
+) -> Result<Response<ResponseBody>, Infallible> {
+    let start = std::time::Instant::now();
+    // Travels with the request so `extract_client_ip` can fall back to it
+    req.extensions_mut().insert(ClientAddr(peer_addr));
+    // Shared with the Pingora layer so both refer to the same recording and log id
+    let mut log = RequestLog::new(
+        uuid::Uuid::new_v4().to_string(),
+        req.method().as_str(),
+        &req.uri().to_string(),
+    );
+
+    let explain = explain::requested(&state, req.headers());
+
+    let slot = InflightGuard::acquire(&state.inflight, state.config.max_inflight);
+    let response = match slot {
+        Some(_) if websocket::is_upgrade_request(&req) => {
+            websocket::proxy_websocket(req, state.clone(), &log)
+                .await
+                .unwrap_or_else(|e| bad_gateway(&log.id, e))
+        }
+        Some(_) => {
+            let mut response = route_request(req, &state, &mut log).await?;
+            apply_global_delay(&state, &mut log).await;
+            if explain {
+                attach_explanation(&state, &log, &mut response);
+            }
+            let fault = if log.passthrough {
+                None
+            } else {
+                state.fault_injector.roll(&log.method, &log.url)
+            };
+            match fault {
+                Some(fault) => {
+                    state
+                        .logger
+                        .detail(&log.id, &format!("fault injected: {:?}", fault));
+                    inject_fault(response, &fault).await
+                }
+                None => response,
+            }
+        }
+        None => {
+            state.storage.record_shed_request();
+            Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header(http::header::RETRY_AFTER, SHED_RETRY_AFTER_SECS)
+                .body(full(Bytes::from("Proxy overloaded, retry later")))
+                .unwrap()
+        }
+    };
+
+    log.status = response.status().as_u16();
+    log.duration_ms = start.elapsed().as_millis() as u64;
+    state.logger.log_request(&log);
+    state.metrics.record_request(log.status, log.duration_ms);
+
+    Ok(match slot {
+        Some(slot) => hold_until_sent(response, slot),
+        None => response,
+    })
+}
+
+/// Keep `guard` alive until the response body is fully sent or dropped, so streaming
+/// and chunked bodies still count as in flight
+fn hold_until_sent<G: Send + 'static>(
+    response: Response<ResponseBody>,
+    guard: G,
+) -> Response<ResponseBody> {
+    response.map(|body| {
+        body.map_frame(move |frame| {
+            let _ = &guard;
+            frame
+        })
+        .boxed_unsync()
+    })
+}
+
+/// Response recorded for an earlier identical request, replayed by `--auto-mock`
+fn auto_mock_response(
+    recording_id: &str,
+    recorded: crate::storage::RecordedResponse,
+) -> Response<ResponseBody> {
+    let mut response = Response::builder()
+        .status(StatusCode::from_u16(recorded.status).unwrap_or(StatusCode::OK))
+        .header(AUTO_MOCK_HEADER, recording_id);
+    for (key, value) in &recorded.headers {
+        if MockResponse::is_framing_header(key) {
+            continue;
+        }
+        response = response.header(key.as_str(), value.as_str());
+    }
+    let mut body = recorded.body.unwrap_or_default();
+    // Recordings keep bodies as UTF-8; send them back in the charset upstream used
+    if let Some(encoding) = recorded
+        .original_charset
+        .as_deref()
+        .and_then(|charset| encoding_rs::Encoding::for_label(charset.as_bytes()))
+    {
+        let text = String::from_utf8_lossy(&body);
+        body = encoding.encode(&text).0.into_owned();
+    }
+    response
+        .body(full(Bytes::from(body)))
+        .unwrap_or_else(|_| Response::new(full(Bytes::new())))
+}
+
+/// Break a finished response the way `fault` describes. A truncated or malformed body
+/// ends in an error, which makes hyper close the connection mid-response.
+async fn inject_fault(
+    response: Response<ResponseBody>,
+    fault: &FaultType,
+) -> Response<ResponseBody> {
+    let (mut parts, body) = response.into_parts();
+    if let FaultType::ConnectionReset = fault {
+        parts.extensions.insert(DropConnection);
+        return Response::from_parts(parts, full(Bytes::new()));
+    }
+
+    let body = body
+        .collect()
+        .await
+        .map(|collected| collected.to_bytes())
+        .unwrap_or_default();
+    parts.headers.remove(http::header::CONTENT_LENGTH);
+    parts.headers.remove(http::header::TRANSFER_ENCODING);
+
+    let keep = match fault {
+        FaultType::TruncatedBody { keep_bytes } => {
+            // Promise the whole body so the client notices the missing bytes
+            parts
+                .headers
+                .insert(http::header::CONTENT_LENGTH, body.len().into());
+            keep_bytes.unwrap_or(body.len() / 2).min(body.len())
+        }
+        // No length, so hyper sends chunked and never gets to the final chunk
+        _ => body.len() / 2,
+    };
+
+    let frames = futures_util::stream::iter([
+        Ok(Frame::data(body.slice(..keep))),
+        Err(BodyError::other("response aborted by fault injection")),
+    ]);
+    Response::from_parts(parts, StreamBody::new(frames).boxed_unsync())
+}
+
+/// Add the decision trace to the response as `X-DevProxy-Explain` and to the recording
+fn attach_explanation(
+    state: &HttpLayerState,
+    log: &RequestLog,
+    response: &mut Response<ResponseBody>,
+) {
+    let explanation = explain::explain(state, log, response.status().as_u16());
+    let json = serde_json::to_vec(&explanation).unwrap_or_default();
+    if let Ok(value) = http::HeaderValue::from_bytes(&json) {
+        response
+            .headers_mut()
+            .insert(explain::EXPLAIN_HEADER, value);
+    }
+    state.storage.set_explanation(&log.id, explanation);
+}
+
+/// Response-side latency rules, then attribute all latency injected so far to the
+/// request's recording
+async fn apply_response_latency(state: &HttpLayerState, log: &mut RequestLog) {
+    if let Some(delay_ms) = state
+        .latency_injector
+        .apply_delay(&log.method, &log.url, ApplyTo::Response)
+        .await
+    {
+        state
+            .logger
+            .detail(&log.id, &format!("response latency {}ms", delay_ms));
+        log.latency_ms += delay_ms;
+    }
+
+    if log.latency_ms > 0 {
+        state
+            .storage
+            .record_injected_latency(&log.id, log.latency_ms);
+    }
+}
+
+/// Flat `--global-delay-ms` on top of any rule latency; passthrough paths are exempt
+async fn apply_global_delay(state: &HttpLayerState, log: &mut RequestLog) {
+    let delay_ms = state.latency_injector.global_delay_ms();
+    if delay_ms == 0 || log.passthrough {
+        return;
+    }
+
+    tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+    state
+        .latency_injector
+        .record_delay(GLOBAL_DELAY_ID, "Global delay", delay_ms);
+    state.storage.record_injected_latency(&log.id, delay_ms);
+    state
+        .logger
+        .detail(&log.id, &format!("global delay {}ms", delay_ms));
+    log.latency_ms += delay_ms;
+}
+
+async fn route_request(
     req: Request<hyper::body::Incoming>,
-    pingora_port: u16,
-    storage: Arc<Storage>,
-    mock_manager: Arc<MockManager>,
-    response_modifier: Arc<ResponseModifier>,
-    rate_limiter: Arc<RateLimiter>,
-    latency_injector: Arc<LatencyInjector>,
-) -> Result<Response<http_body_util::Full<Bytes>>, Infallible> {
-    let method = req.method().as_str().to_string();
-    let uri = req.uri().to_string();
+    state: &HttpLayerState,
+    log: &mut RequestLog,
+) -> Result<Response<ResponseBody>, Infallible> {
+    let method = log.method.clone();
+    let uri = log.url.clone();
+    let request_id = log.id.clone();
+
+    // Passthrough paths skip every rule and go straight to upstream
+    if state.config.is_passthrough(req.uri().path()) {
+        log.passthrough = true;
+        let proxied = match buffer_request(req, state, log).await {
+            Ok(req) => proxy_to_pingora(req, state, log).await,
+            Err(e) => Err(e),
+        };
+        return Ok(proxied.unwrap_or_else(|e| bad_gateway(&request_id, e)));
+    }
 
     let client_key = extract_client_ip(&req);
 
@@ -92,25 +540,35 @@ async fn handle_request(
             headers_map.insert(name.to_string(), value_str.to_string());
         }
     }
+    let correlation_id = state
+        .config
+        .correlation_header
+        .as_ref()
+        .and_then(|name| headers_map.get(name).cloned());
 
-    if let Some(delay_ms) = latency_injector
+    if let Some(delay_ms) = state
+        .latency_injector
         .apply_delay(&method, &uri, ApplyTo::Request)
         .await
     {
-        println!("{} {} [REQUEST LATENCY: {}ms]", method, uri, delay_ms);
+        state
+            .logger
+            .detail(&request_id, &format!("request latency {}ms", delay_ms));
+        log.latency_ms += delay_ms;
     }
 
     // Check rate limit FIRST
-    if let Some((rule, info)) =
-        rate_limiter.check_rate_limit(&method, &uri, &client_key, &headers_map)
+    if let Some((limited, info)) =
+        state
+            .rate_limiter
+            .check_rate_limit(&method, &uri, &client_key, &headers_map)
     {
         // Add delay if specified
-        if let Some(delay_ms) = rule.response.delay_ms {
+        if let Some(delay_ms) = limited.delay_ms {
             tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
         }
 
-        let status =
-            StatusCode::from_u16(rule.response.status).unwrap_or(StatusCode::TOO_MANY_REQUESTS);
+        let status = StatusCode::from_u16(limited.status).unwrap_or(StatusCode::TOO_MANY_REQUESTS);
         let mut response = Response::builder().status(status);
 
         // Add rate limit headers
@@ -119,116 +577,430 @@ async fn handle_request(
             .header("X-RateLimit-Remaining", info.remaining.to_string())
             .header("X-RateLimit-Reset", info.reset_in_seconds.to_string());
 
-        if let Some(retry_after) = info.retry_after {
+        // A tier can set its own Retry-After, e.g. a longer block for abusive clients
+        let custom_retry_after = limited
+            .headers
+            .keys()
+            .any(|key| key.eq_ignore_ascii_case("retry-after"));
+        if let Some(retry_after) = info.retry_after.filter(|_| !custom_retry_after) {
             response = response.header("Retry-After", retry_after.to_string());
         }
 
         // Add custom headers
-        for (key, value) in &rule.response.headers {
+        for (key, value) in &limited.headers {
             response = response.header(key.as_str(), value.as_str());
         }
 
         // Add default content-type if not present
-        if !rule.response.headers.contains_key("content-type") {
+        if !limited.headers.contains_key("content-type") {
             response = response.header("content-type", "application/json");
         }
 
-        let body = Bytes::from(rule.response.body.clone());
+        let body = Bytes::from(limited.body);
+        log.rate_limited = true;
 
-        println!(
-            "{} {} - {} [RATE LIMITED]",
-            method, uri, rule.response.status
-        );
+        return Ok(response.body(full(body)).unwrap());
+    }
+
+    // Mock rules can match on the body, so read it before looking for one
+    let mut req = match buffer_request(req, state, log).await {
+        Ok(req) => req,
+        Err(e) => return Ok(bad_gateway(&request_id, e)),
+    };
 
-        return Ok(response.body(http_body_util::Full::new(body)).unwrap());
+    // Modifier rules can point the request at a different upstream path. Mocks and the
+    // ETag cache stand in for that upstream, so they match on the rewritten URI too.
+    if let Some(rewritten) =
+        state
+            .response_modifier
+            .rewrite_url(&method, &uri, &headers_map, req.body().as_ref())
+    {
+        match rewritten.parse::<hyper::Uri>() {
+            Ok(rewritten_uri) => {
+                log::debug!("Rewrote request path {} -> {}", uri, rewritten);
+                *req.uri_mut() = rewritten_uri;
+            }
+            Err(e) => log::warn!(
+                "Ignoring URL rewrite of {} to invalid URL {}: {}",
+                uri,
+                rewritten,
+                e
+            ),
+        }
     }
+    let upstream_uri = req.uri().to_string();
 
     // Check for mock rule
-    if let Some(mock_rule) = mock_manager.find_matching_rule(&method, &uri) {
+    if let Some(mock_rule) = state.mock_manager.find_matching_rule(
+        &method,
+        &upstream_uri,
+        &headers_map,
+        Some(req.body().as_ref()),
+    ) {
         // Add delay if specified
-        if let Some(delay_ms) = mock_rule.delay_ms {
+        if let Some(delay_ms) = mock_rule.effective_delay_ms() {
             tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
         }
 
-        // Build mock response
-        let status = StatusCode::from_u16(mock_rule.response.status).unwrap_or(StatusCode::OK);
+        // Build mock response, negotiated against the Accept header
+        let mock_request = MockRequest {
+            method: &method,
+            path: req.uri().path(),
+            headers: &headers_map,
+            query: req.uri().query(),
+            client_ip: Some(&client_key),
+        };
+        let (mock_response, body) = state
+            .mock_manager
+            .resolve_body(
+                state
+                    .mock_manager
+                    .select_response(&mock_rule, &mock_request),
+            )
+            .await
+            .render(&mock_request)
+            .encode();
+        let status = StatusCode::from_u16(mock_response.status).unwrap_or(StatusCode::OK);
 
         let mut response = Response::builder().status(status);
 
         // Add headers
-        for (key, value) in &mock_rule.response.headers {
+        for (key, value) in &mock_response.headers {
+            if MockResponse::is_framing_header(key) {
+                continue;
+            }
             response = response.header(key.as_str(), value.as_str());
         }
 
         // Add default content-type if not present
-        if !mock_rule.response.headers.contains_key("content-type") {
+        if !mock_response.headers.contains_key("content-type") {
             response = response.header("content-type", "application/json");
         }
 
-        let body = Bytes::from(mock_rule.response.body.clone());
+        let body = Bytes::from(body);
+        log.mocked = true;
+        log.mock_rule = Some(mock_rule.id.clone());
 
-        println!(
-            "{} {} - {} [MOCKED]",
-            method, uri, mock_rule.response.status
+        state.rate_limiter.record_response(
+            &method,
+            &uri,
+            &client_key,
+            &headers_map,
+            mock_response.status,
         );
 
         // Record the mock
         let start = std::time::Instant::now();
-        let id = uuid::Uuid::new_v4().to_string();
-
-        storage.recordings.write().insert(
-            id.clone(),
-            crate::storage::RecordedRequest {
-                id: id.clone(),
-                timestamp: chrono::Utc::now(),
-                method: method.clone(),
-                url: uri.clone(),
-                headers: std::collections::HashMap::new(),
-                body: None,
-                response: Some(crate::storage::RecordedResponse {
-                    status: mock_rule.response.status,
-                    headers: mock_rule.response.headers.clone(),
-                    body: Some(body.to_vec()),
-                }),
-                duration_ms: Some(start.elapsed().as_millis() as u64),
-            },
-        );
+        let mut recorded_headers = mock_response.headers.clone();
+        state.config.strip_recorded(&mut recorded_headers);
 
-        if let Some(delay_ms) = latency_injector
-            .apply_delay(&method, &uri, ApplyTo::Response)
-            .await
-        {
-            println!("{} {} [RESPONSE LATENCY: {}ms]", method, uri, delay_ms);
+        if state.should_record(&uri) {
+            state
+                .storage
+                .insert_completed(crate::storage::RecordedRequest {
+                    id: request_id.clone(),
+                    timestamp: chrono::Utc::now(),
+                    method: method.clone(),
+                    url: uri.clone(),
+                    headers: std::collections::HashMap::new(),
+                    body: None,
+                    response: Some(crate::storage::RecordedResponse {
+                        status: mock_response.status,
+                        headers: recorded_headers,
+                        body: Some(body.to_vec()),
+                        original_charset: None,
+                        body_truncated: false,
+                        body_size: body.len(),
+                    }),
+                    duration_ms: Some(start.elapsed().as_millis() as u64),
+                    original_charset: None,
+                    body_truncated: false,
+                    injected_latency_ms: None,
+                    cache_hit: false,
+                    replay: None,
+                    correlation_id: correlation_id.clone(),
+                    explanation: None,
+                    origin: None,
+                });
+        }
+
+        apply_response_latency(state, log).await;
+
+        let body = match mock_response.chunking() {
+            Some(chunking) => chunked_body(body, chunking),
+            None => full(body),
+        };
+        return Ok(response.body(body).unwrap());
+    }
+
+    // Conditional GET against a known ETag - answer 304 without hitting upstream
+    if let Some(etag) = headers_map
+        .get("if-none-match")
+        .filter(|_| state.config.etag_cache)
+        .and_then(|value| {
+            state
+                .etag_cache
+                .matching_etag(&method, &upstream_uri, value)
+        })
+    {
+        log.cache_hit = true;
+
+        if state.should_record(&uri) {
+            state
+                .storage
+                .insert_completed(crate::storage::RecordedRequest {
+                    id: request_id.clone(),
+                    timestamp: chrono::Utc::now(),
+                    method: method.clone(),
+                    url: uri.clone(),
+                    headers: {
+                        let mut recorded_headers = headers_map.clone();
+                        state.config.strip_recorded(&mut recorded_headers);
+                        recorded_headers
+                    },
+                    body: None,
+                    response: Some(crate::storage::RecordedResponse {
+                        status: StatusCode::NOT_MODIFIED.as_u16(),
+                        headers: std::collections::HashMap::from([(
+                            "etag".to_string(),
+                            etag.clone(),
+                        )]),
+                        body: None,
+                        original_charset: None,
+                        body_truncated: false,
+                        body_size: 0,
+                    }),
+                    duration_ms: Some(0),
+                    original_charset: None,
+                    body_truncated: false,
+                    injected_latency_ms: None,
+                    cache_hit: true,
+                    replay: None,
+                    correlation_id: correlation_id.clone(),
+                    explanation: None,
+                    origin: None,
+                });
+        }
+
+        if log.latency_ms > 0 {
+            state
+                .storage
+                .record_injected_latency(&request_id, log.latency_ms);
         }
 
-        return Ok(response.body(http_body_util::Full::new(body)).unwrap());
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header("etag", etag)
+            .body(full(Bytes::new()))
+            .unwrap());
+    }
+
+    // Seen this request before - serve what upstream answered then. Keyed on the
+    // rewritten URI, which is what the proxied request was recorded under.
+    if state.auto_mock.load(Ordering::Relaxed) {
+        let body = state
+            .config
+            .auto_mock_match_body
+            .then(|| req.body().as_ref());
+        let recorded = state
+            .storage
+            .find_completed(&method, &upstream_uri, body)
+            .and_then(|recording| Some((recording.id, recording.response?)));
+        if let Some((recording_id, recorded)) = recorded {
+            log.auto_mock = Some(recording_id.clone());
+            state.logger.detail(
+                &request_id,
+                &format!("auto-mock from recording {}", recording_id),
+            );
+            state.rate_limiter.record_response(
+                &method,
+                &uri,
+                &client_key,
+                &headers_map,
+                recorded.status,
+            );
+
+            let response = auto_mock_response(&recording_id, recorded.clone());
+            if state.should_record(&uri) {
+                state
+                    .storage
+                    .insert_completed(crate::storage::RecordedRequest {
+                        id: request_id.clone(),
+                        timestamp: chrono::Utc::now(),
+                        method: method.clone(),
+                        url: uri.clone(),
+                        headers: std::collections::HashMap::new(),
+                        body: None,
+                        response: Some(recorded),
+                        duration_ms: Some(0),
+                        original_charset: None,
+                        body_truncated: false,
+                        injected_latency_ms: None,
+                        cache_hit: false,
+                        replay: None,
+                        correlation_id: correlation_id.clone(),
+                        explanation: None,
+                        origin: Some(crate::storage::ResponseOrigin::AutoMock { recording_id }),
+                    });
+            }
+
+            apply_response_latency(state, log).await;
+            return Ok(response);
+        }
     }
 
     // No mock - proxy to Pingora
-    match proxy_to_pingora(req, pingora_port, &method, &uri, response_modifier).await {
+    match proxy_to_pingora(req, state, log).await {
         Ok(resp) => {
-            // Apply response latency after proxying
-            if let Some(delay_ms) = latency_injector
-                .apply_delay(&method, &uri, ApplyTo::Response)
-                .await
-            {
-                println!("{} {} [RESPONSE LATENCY: {}ms]", method, uri, delay_ms);
-            }
+            state.rate_limiter.record_response(
+                &method,
+                &uri,
+                &client_key,
+                &headers_map,
+                resp.status().as_u16(),
+            );
+
+            apply_response_latency(state, log).await;
             Ok(resp)
         }
-        Err(e) => {
-            eprintln!("Proxy error: {}", e);
-            Ok(Response::builder()
-                .status(StatusCode::BAD_GATEWAY)
-                .body(http_body_util::Full::new(Bytes::from("Bad Gateway")))
-                .unwrap())
+        Err(e) => Ok(bad_gateway(&request_id, e)),
+    }
+}
+
+fn bad_gateway(request_id: &str, error: anyhow::Error) -> Response<ResponseBody> {
+    eprintln!("Proxy error [{}]: {}", request_id, error);
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .body(full(Bytes::from("Bad Gateway")))
+        .unwrap()
+}
+
+/// Whether an upstream response can be streamed to the client as it arrives instead of
+/// buffered: nothing is going to rewrite its body, and it's an event stream, a binary
+/// download, or of unknown length
+fn streams_through(
+    state: &HttpLayerState,
+    log: &RequestLog,
+    status: u16,
+    headers: &std::collections::HashMap<String, String>,
+) -> bool {
+    let rewritten = !log.passthrough
+        && !is_grpc(headers)
+        && (state.config.replaces_body(headers)
+            || state.response_modifier.has_response_rules(
+                &log.method,
+                &log.url,
+                &log.request_headers,
+                status,
+            ));
+    if rewritten {
+        return false;
+    }
+
+    let header = |wanted: &str| {
+        headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+            .map(|(_, value)| value.to_ascii_lowercase())
+    };
+    header("content-length").is_none()
+        || header("content-type").is_some_and(|content_type| {
+            content_type.starts_with("text/event-stream")
+                || content_type.starts_with("application/octet-stream")
+        })
+}
+
+/// Relay an upstream response chunk by chunk. A failed read aborts the body, which
+/// closes the client connection.
+fn streamed_response(response: reqwest::Response) -> Response<ResponseBody> {
+    let mut builder = Response::builder().status(response.status());
+    for (name, value) in response.headers() {
+        // hyper frames the body itself
+        if name != http::header::TRANSFER_ENCODING {
+            builder = builder.header(name, value);
         }
     }
+
+    let chunks = futures_util::stream::unfold(Some(response), |response| async move {
+        let mut response = response?;
+        match response.chunk().await {
+            Ok(Some(chunk)) => Some((Ok(Frame::data(chunk)), Some(response))),
+            Ok(None) => None,
+            Err(e) => Some((Err(BodyError::other(e)), None)),
+        }
+    });
+    builder
+        .body(StreamBody::new(chunks).boxed_unsync())
+        .unwrap()
+}
+
+/// Answer 504 for an upstream that didn't respond within --timeout, and record it as such
+fn gateway_timeout(
+    state: &HttpLayerState,
+    request_id: &str,
+    elapsed: Duration,
+) -> Response<ResponseBody> {
+    eprintln!(
+        "Upstream timeout [{}] after {}ms",
+        request_id,
+        elapsed.as_millis()
+    );
+    state.storage.update_response(
+        request_id,
+        crate::storage::RecordedResponse {
+            status: StatusCode::GATEWAY_TIMEOUT.as_u16(),
+            headers: std::collections::HashMap::new(),
+            body: None,
+            original_charset: None,
+            body_truncated: false,
+            body_size: 0,
+        },
+        elapsed.as_millis() as u64,
+    );
+    Response::builder()
+        .status(StatusCode::GATEWAY_TIMEOUT)
+        .body(full(Bytes::from("Gateway Timeout")))
+        .unwrap()
+}
+
+const FORWARDED_HEADERS: &[&str] = &["x-forwarded-proto", "x-forwarded-host", "x-forwarded-for"];
+
+/// X-Forwarded-* headers describing the client's original request. Proto and Host set by a
+/// proxy in front of us are kept; the client IP is appended to the X-Forwarded-For chain
+/// unless it's already the last hop.
+fn forwarded_headers(
+    headers: &http::HeaderMap,
+    client_ip: &str,
+    scheme: &str,
+) -> Vec<(&'static str, String)> {
+    let existing = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+
+    let proto = existing("x-forwarded-proto").unwrap_or_else(|| scheme.to_string());
+    let host = existing("x-forwarded-host").or_else(|| existing("host"));
+    let forwarded_for = match existing("x-forwarded-for") {
+        Some(chain) if chain.rsplit(',').next().map(str::trim) == Some(client_ip) => chain,
+        Some(chain) => format!("{}, {}", chain, client_ip),
+        None => client_ip.to_string(),
+    };
+
+    let mut forwarded = vec![
+        ("x-forwarded-proto", proto),
+        ("x-forwarded-for", forwarded_for),
+    ];
+    if let Some(host) = host {
+        forwarded.push(("x-forwarded-host", host));
+    }
+    forwarded
 }
 
 /// Extract client IP address from request headers or connection
 /// Follows the X-Forwarded-For chain for proxy environments
-fn extract_client_ip(req: &Request<hyper::body::Incoming>) -> String {
+fn extract_client_ip<B>(req: &Request<B>) -> String {
     // Try X-Forwarded-For header first (standard for proxies)
     if let Some(xff) = req.headers().get("x-forwarded-for") {
         if let Ok(xff_str) = xff.to_str() {
@@ -273,33 +1045,135 @@ fn extract_client_ip(req: &Request<hyper::body::Incoming>) -> String {
         }
     }
 
-    // Fallback to connection remote address (not available in Hyper without additional setup)
-    // For development/testing, return localhost
-    "127.0.0.1".to_string()
+    // Fall back to the address of the connection itself. IPv4 clients of a dual-stack
+    // socket show up as IPv4-mapped IPv6; IPv6 addresses are written without brackets,
+    // like the header values above.
+    req.extensions()
+        .get::<ClientAddr>()
+        .map(|ClientAddr(addr)| addr.ip().to_canonical().to_string())
+        .unwrap_or_else(|| "127.0.0.1".to_string())
 }
 
-async fn proxy_to_pingora(
-    req: Request<hyper::body::Incoming>,
-    pingora_port: u16,
-    method: &str,
-    url: &str,
-    response_modifier: Arc<ResponseModifier>,
-) -> Result<Response<http_body_util::Full<Bytes>>> {
+/// Read the client's request body. With a throttle rate, reads are paced so the
+/// body arrives no faster than `bytes_per_second`, back-pressuring the uploader.
+async fn read_request_body(
+    body: hyper::body::Incoming,
+    bytes_per_second: Option<u64>,
+) -> Result<Bytes> {
     use http_body_util::BodyExt;
 
+    let Some(rate) = bytes_per_second else {
+        return Ok(body.collect().await?.to_bytes());
+    };
+
+    let start = std::time::Instant::now();
+    let mut body = body;
+    let mut buffer = Vec::new();
+
+    while let Some(frame) = body.frame().await {
+        if let Ok(data) = frame?.into_data() {
+            buffer.extend_from_slice(&data);
+
+            // Hold off the next read until the bytes so far fit the target rate
+            let target = Duration::from_secs_f64(buffer.len() as f64 / rate as f64);
+            if let Some(wait) = target.checked_sub(start.elapsed()) {
+                tokio::time::sleep(wait).await;
+            }
+        }
+    }
+
+    Ok(Bytes::from(buffer))
+}
+
+/// Read the whole request body, paced by any request throttle rule (passthrough paths
+/// aren't throttled), and keep it and the headers on the log for modifier matching and
+/// decision traces
+async fn buffer_request(
+    req: Request<hyper::body::Incoming>,
+    state: &HttpLayerState,
+    log: &mut RequestLog,
+) -> Result<Request<Bytes>> {
     let (parts, body) = req.into_parts();
-    let body_bytes = body.collect().await?.to_bytes();
 
-    let uri = format!("http://127.0.0.1:{}{}", pingora_port, parts.uri);
+    let throttle = if log.passthrough {
+        None
+    } else {
+        state
+            .latency_injector
+            .find_request_throttle(&log.method, &log.url)
+    };
+    let read_start = std::time::Instant::now();
+    let body_bytes = read_request_body(body, throttle.as_ref().map(|(_, rate)| *rate)).await?;
+    let throttled_ms = throttle.map(|(rule, _)| {
+        let elapsed_ms = read_start.elapsed().as_millis() as u64;
+        state
+            .latency_injector
+            .record_delay(&rule.id, &rule.name, elapsed_ms);
+        state
+            .logger
+            .detail(&log.id, &format!("request body throttled {}ms", elapsed_ms));
+        elapsed_ms
+    });
+    log.latency_ms += throttled_ms.unwrap_or(0);
+    log.request_headers = parts
+        .headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    log.request_body = Some(body_bytes.clone());
+
+    Ok(Request::from_parts(parts, body_bytes))
+}
+
+async fn proxy_to_pingora(
+    req: Request<Bytes>,
+    state: &HttpLayerState,
+    log: &mut RequestLog,
+) -> Result<Response<ResponseBody>> {
+    let method = &log.method.clone();
+    let url = &log.url.clone();
+    let request_id = &log.id.clone();
+    let client_ip = extract_client_ip(&req);
+
+    let (mut parts, mut body_bytes) = req.into_parts();
+
+    // Request-side modifier rules, e.g. injecting an Authorization header
+    if !log.passthrough {
+        let mut body = body_bytes.to_vec();
+        if state.response_modifier.apply_request_modifications(
+            method,
+            url,
+            &mut parts.headers,
+            &mut body,
+        ) {
+            // Sized again by the client from the new body
+            parts.headers.remove(hyper::header::CONTENT_LENGTH);
+            body_bytes = Bytes::from(body);
+        }
+    }
+
+    let uri = format!("http://127.0.0.1:{}{}", state.config.proxy_port, parts.uri);
 
-    let client = reqwest::Client::new();
-    let mut request = client.request(parts.method.clone(), &uri);
+    let mut request = state.client.request(parts.method.clone(), &uri);
 
     for (name, value) in parts.headers.iter() {
-        if name.as_str().to_lowercase() != "host" {
-            if let Ok(value_str) = value.to_str() {
-                request = request.header(name.as_str(), value_str);
-            }
+        // Replaced below by the merged values
+        if state.config.forwarded_headers && FORWARDED_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        if name.as_str().to_lowercase() != "host"
+            && name.as_str() != REQUEST_ID_HEADER
+            && name.as_str() != explain::EXPLAIN_HEADER
+            && let Ok(value_str) = value.to_str()
+        {
+            request = request.header(name.as_str(), value_str);
+        }
+    }
+    request = request.header(REQUEST_ID_HEADER, request_id);
+
+    if state.config.forwarded_headers {
+        for (name, value) in forwarded_headers(&parts.headers, &client_ip, state.config.scheme()) {
+            request = request.header(name, value);
         }
     }
 
@@ -307,8 +1181,15 @@ async fn proxy_to_pingora(
         request = request.body(body_bytes.to_vec());
     }
 
-    let response = request.send().await?;
-
+    // --timeout covers the whole exchange, body included
+    let sent_at = std::time::Instant::now();
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) if e.is_timeout() => {
+            return Ok(gateway_timeout(state, request_id, sent_at.elapsed()));
+        }
+        Err(e) => return Err(e.into()),
+    };
     let status = response.status();
     let status_u16 = status.as_u16();
 
@@ -319,13 +1200,87 @@ async fn proxy_to_pingora(
         }
     }
 
-    let response_bytes = response.bytes().await?;
+    // Decided before reading the body, so SSE and large downloads aren't held in memory
+    if streams_through(state, log, status_u16, &header_map) {
+        if state.config.etag_cache && method == "GET" && status_u16 == 200 {
+            state
+                .etag_cache
+                .store(method, url, header_map.get("etag").map(String::as_str));
+        }
+        return Ok(streamed_response(response));
+    }
+
+    let response_bytes = match response.bytes().await {
+        Ok(bytes) => bytes,
+        Err(e) if e.is_timeout() => {
+            return Ok(gateway_timeout(state, request_id, sent_at.elapsed()));
+        }
+        Err(e) => return Err(e.into()),
+    };
+
     let mut response_vec = response_bytes.to_vec();
+    let grpc = is_grpc(&header_map);
 
-    // Apply response modifications BEFORE building response
-    let modified_status = response_modifier
-        .apply_modifications(method, url, status_u16, &mut header_map, &mut response_vec)
-        .await;
+    // Decompress gzip/deflate/brotli bodies so modifiers and --replace see the text. The
+    // original bytes are kept to send back if nothing ends up changing.
+    let compressed = if log.passthrough || grpc {
+        None
+    } else {
+        DecodedBody::open(
+            &header_map,
+            &mut response_vec,
+            state.config.max_decoded_bytes(),
+        )
+    };
+    if compressed.is_some() {
+        header_map.remove("content-encoding");
+    }
+
+    // Apply response modifications BEFORE building response. gRPC bodies are framed, so
+    // they pass through untouched.
+    let modified_status = if log.passthrough || grpc {
+        status_u16
+    } else {
+        state
+            .response_modifier
+            .apply_modifications(
+                method,
+                url,
+                &log.request_headers,
+                status_u16,
+                &mut header_map,
+                &mut response_vec,
+            )
+            .await
+    };
+
+    // Global --replace pairs go after the rule-based modifiers
+    if !log.passthrough
+        && state
+            .config
+            .apply_replacements(&header_map, &mut response_vec)
+    {
+        state.logger.detail(request_id, "body replacements applied");
+    }
+
+    // Untouched bodies go back byte-identical, changed ones are compressed again
+    if let Some(compressed) = compressed {
+        let encoding = compressed.encoding();
+        if compressed.finish(&mut response_vec)? {
+            state.logger.detail(
+                request_id,
+                &format!("modified body re-encoded as {}", encoding.name()),
+            );
+        }
+        header_map.insert("content-encoding".to_string(), encoding.name().to_string());
+    }
+
+    // Remember the ETag the client will see for later conditional requests
+    if state.config.etag_cache && method == "GET" && modified_status == 200 {
+        state
+            .etag_cache
+            .store(method, url, header_map.get("etag").map(String::as_str));
+    }
 
     // **FIX: Update Content-Length after modification**
     header_map.insert("content-length".to_string(), response_vec.len().to_string());
@@ -345,5 +1300,81 @@ async fn proxy_to_pingora(
         }
     }
 
-    Ok(builder.body(http_body_util::Full::new(Bytes::from(response_vec)))?)
+    Ok(builder.body(full(Bytes::from(response_vec)))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn streamed_body_arrives_before_upstream_finishes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (finish, finished) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await;
+            socket
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\ncontent-type: application/octet-stream\r\n\
+                      transfer-encoding: chunked\r\n\r\n10\r\n0123456789abcdef\r\n",
+                )
+                .await
+                .unwrap();
+            // The body only ends once the client has seen its first chunk
+            let _ = finished.await;
+            socket.write_all(b"0\r\n\r\n").await.unwrap();
+        });
+
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let upstream = client
+            .get(format!("http://{}/download", addr))
+            .send()
+            .await
+            .unwrap();
+        let mut body = streamed_response(upstream).into_body();
+
+        let first = tokio::time::timeout(Duration::from_secs(5), body.frame())
+            .await
+            .expect("first chunk should arrive while the upstream body is still open")
+            .unwrap()
+            .unwrap()
+            .into_data()
+            .unwrap();
+        assert_eq!(&first[..], b"0123456789abcdef");
+
+        finish.send(()).unwrap();
+        assert!(body.frame().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_unfinished_streaming_body() {
+        let inflight = Arc::new(AtomicUsize::new(0));
+        let chunking = ChunkedDelivery {
+            chunk_size: 1,
+            chunk_delay_ms: 50,
+        };
+        let slot = InflightGuard::acquire(&inflight, None).unwrap();
+        let response = Response::new(chunked_body(Bytes::from_static(b"abcd"), chunking.clone()));
+        let response = hold_until_sent(response, slot);
+        // The handler has returned but the body is still streaming
+        assert_eq!(inflight.load(Ordering::Acquire), 1);
+
+        let started = std::time::Instant::now();
+        let client = tokio::spawn(response.into_body().collect());
+        drain(&inflight, Duration::from_secs(5)).await;
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert_eq!(inflight.load(Ordering::Acquire), 0);
+        let body = client.await.unwrap().unwrap().to_bytes();
+        assert_eq!(&body[..], b"abcd");
+
+        // A client hanging up mid-body frees the slot too
+        let slot = InflightGuard::acquire(&inflight, None).unwrap();
+        let response = Response::new(chunked_body(Bytes::from_static(b"abcd"), chunking));
+        drop(hold_until_sent(response, slot));
+        assert_eq!(inflight.load(Ordering::Acquire), 0);
+    }
 }
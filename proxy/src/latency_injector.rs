@@ -1,11 +1,17 @@
 use parking_lot::RwLock;
 use rand::Rng;
-use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+use crate::explain::{RuleCheck, RuleTrace};
+pub use crate::matcher::MatchType;
+use crate::matcher::{default_match_query, reorder_priorities};
+use crate::schedule::ActiveWindow;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LatencyRule {
     pub id: String,
@@ -14,7 +20,12 @@ pub struct LatencyRule {
     pub priority: i32,
     pub match_request: LatencyMatch,
     pub delay: DelayConfig,
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    // When the rule was last enabled; a `ramp` delay grows from here
+    #[serde(skip, default = "Instant::now")]
+    pub started_at: Instant,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +35,8 @@ pub struct CreateLatencyRule {
     pub priority: i32,
     pub match_request: LatencyMatch,
     pub delay: DelayConfig,
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +47,8 @@ pub struct UpdateLatencyRule {
     pub priority: i32,
     pub match_request: LatencyMatch,
     pub delay: DelayConfig,
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,19 +56,12 @@ pub struct LatencyMatch {
     pub method: Option<String>,
     pub url_pattern: String,
     pub url_match_type: MatchType,
+    // Match url_pattern against the whole URL; false leaves the query string out
+    #[serde(default = "default_match_query")]
+    pub match_query: bool,
     pub apply_to: ApplyTo,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum MatchType {
-    Exact,
-    Contains,
-    Regex,
-    StartsWith,
-    EndsWith,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ApplyTo {
@@ -62,6 +70,9 @@ pub enum ApplyTo {
     Both,     // Delay both request and response
 }
 
+/// Cap on delays drawn from unbounded distributions, so an outlier can't hang the proxy
+const MAX_SAMPLED_DELAY_MS: u64 = 60_000;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum DelayConfig {
@@ -81,10 +92,27 @@ pub enum DelayConfig {
         spike_delay_ms: u64,
         spike_probability: f64, // 0.0 to 1.0
     },
+    // Grows linearly from `start_ms` to `end_ms` over `duration_secs` after the rule is
+    // enabled, then holds at `end_ms`
+    Ramp {
+        start_ms: u64,
+        end_ms: u64,
+        duration_secs: u64,
+    },
+    // Heavy tail: usually close to `scale_ms`, occasionally far above it
+    Pareto {
+        scale_ms: u64,
+        shape: f64,
+    },
+    // Drip-feed the request body from the client instead of delaying once (request side only)
+    Throttle {
+        bytes_per_second: u64,
+    },
 }
 
 impl DelayConfig {
-    pub fn calculate_delay(&self) -> u64 {
+    /// A delay for a rule that has been enabled for `active_for`
+    pub fn calculate_delay(&self, active_for: Duration) -> u64 {
         match self {
             DelayConfig::Fixed { delay_ms } => *delay_ms,
 
@@ -122,6 +150,51 @@ impl DelayConfig {
                     *base_delay_ms
                 }
             }
+
+            DelayConfig::Ramp {
+                start_ms,
+                end_ms,
+                duration_secs,
+            } => {
+                let duration = Duration::from_secs(*duration_secs);
+                if active_for >= duration {
+                    return *end_ms;
+                }
+                let progress = active_for.as_secs_f64() / duration.as_secs_f64();
+                let delay = *start_ms as f64 + (*end_ms as f64 - *start_ms as f64) * progress;
+                delay.max(0.0) as u64
+            }
+
+            DelayConfig::Pareto { scale_ms, shape } => {
+                if *shape <= 0.0 {
+                    return *scale_ms;
+                }
+                // Inverse CDF; 1 - u keeps the sample in (0, 1] so it never divides by zero
+                let mut rng = rand::thread_rng();
+                let u: f64 = 1.0 - rng.gen_range(0.0..1.0);
+                let value = *scale_ms as f64 / u.powf(1.0 / *shape);
+                value.min(MAX_SAMPLED_DELAY_MS as f64) as u64
+            }
+
+            // Paced while reading the body, see `find_request_throttle`
+            DelayConfig::Throttle { .. } => 0,
+        }
+    }
+}
+
+impl From<CreateLatencyRule> for LatencyRule {
+    /// A new rule with a fresh id
+    fn from(create_rule: CreateLatencyRule) -> Self {
+        LatencyRule {
+            id: Uuid::new_v4().to_string(),
+            name: create_rule.name,
+            enabled: create_rule.enabled,
+            priority: create_rule.priority,
+            match_request: create_rule.match_request,
+            delay: create_rule.delay,
+            schedule: create_rule.schedule.resolve(),
+            created_at: chrono::Utc::now(),
+            started_at: Instant::now(),
         }
     }
 }
@@ -130,6 +203,17 @@ impl DelayConfig {
 pub struct LatencyInjector {
     rules: Arc<RwLock<HashMap<String, LatencyRule>>>,
     stats: Arc<RwLock<LatencyStats>>,
+    // Flat delay added to every response on top of rules, 0 when off
+    global_delay_ms: Arc<AtomicU64>,
+}
+
+/// Stats id under which the global delay is reported
+pub const GLOBAL_DELAY_ID: &str = "global";
+
+/// Body of `GET`/`PUT /api/global-delay`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GlobalDelay {
+    pub delay_ms: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -149,6 +233,9 @@ pub struct RuleStats {
     pub hits: u64,
     pub total_delay_ms: u64,
     pub avg_delay_ms: u64,
+    // What a `ramp` rule would delay right now
+    #[serde(default)]
+    pub current_delay_ms: Option<u64>,
 }
 
 impl Default for LatencyStats {
@@ -169,21 +256,21 @@ impl LatencyInjector {
         Self {
             rules: Arc::new(RwLock::new(HashMap::new())),
             stats: Arc::new(RwLock::new(LatencyStats::default())),
+            global_delay_ms: Arc::new(AtomicU64::new(0)),
         }
     }
 
-    pub fn add_rule(&self, create_rule: CreateLatencyRule) -> String {
-        let id = Uuid::new_v4().to_string();
+    pub fn global_delay_ms(&self) -> u64 {
+        self.global_delay_ms.load(Ordering::Relaxed)
+    }
 
-        let rule = LatencyRule {
-            id: id.clone(),
-            name: create_rule.name,
-            enabled: create_rule.enabled,
-            priority: create_rule.priority,
-            match_request: create_rule.match_request,
-            delay: create_rule.delay,
-            created_at: chrono::Utc::now(),
-        };
+    pub fn set_global_delay_ms(&self, delay_ms: u64) {
+        self.global_delay_ms.store(delay_ms, Ordering::Relaxed);
+    }
+
+    pub fn add_rule(&self, create_rule: CreateLatencyRule) -> String {
+        let rule = LatencyRule::from(create_rule);
+        let id = rule.id.clone();
 
         let mut rules = self.rules.write();
         rules.insert(id.clone(), rule);
@@ -201,7 +288,14 @@ impl LatencyInjector {
                 priority: update_rule.priority,
                 match_request: update_rule.match_request,
                 delay: update_rule.delay,
+                schedule: update_rule.schedule.resolve(),
                 created_at: existing.created_at,
+                // Re-enabling restarts a ramp, like a toggle does
+                started_at: if existing.enabled || !update_rule.enabled {
+                    existing.started_at
+                } else {
+                    Instant::now()
+                },
             };
             rules.insert(update_rule.id, rule);
             true
@@ -227,16 +321,59 @@ impl LatencyInjector {
         all_rules
     }
 
+    /// How every rule was evaluated against a request, highest priority first
+    pub fn explain(&self, method: &str, url: &str) -> Vec<RuleTrace> {
+        let rules = self.rules.read();
+        let mut all_rules: Vec<&LatencyRule> = rules.values().collect();
+        all_rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+        all_rules
+            .into_iter()
+            .map(|rule| {
+                RuleCheck {
+                    id: &rule.id,
+                    name: &rule.name,
+                    priority: rule.priority,
+                    enabled: rule.enabled,
+                    schedule: &rule.schedule,
+                    method: rule.match_request.method.as_deref(),
+                    url_pattern: &rule.match_request.url_pattern,
+                    url_match_type: &rule.match_request.url_match_type,
+                    match_query: rule.match_request.match_query,
+                }
+                .trace(method, url)
+            })
+            .collect()
+    }
+
+    /// Reassign priorities so rules follow the order of `ids`, see `reorder_priorities`
+    pub fn reorder(&self, ids: Vec<String>) {
+        reorder_priorities(&mut self.rules.write(), ids, |rule| &mut rule.priority);
+    }
+
     pub fn toggle_rule(&self, id: &str) -> bool {
         let mut rules = self.rules.write();
         if let Some(rule) = rules.get_mut(id) {
             rule.enabled = !rule.enabled;
+            if rule.enabled {
+                rule.started_at = Instant::now();
+            }
             true
         } else {
             false
         }
     }
 
+    /// Replace every rule and the collected stats, e.g. when restoring a snapshot
+    pub fn replace_all(&self, new_rules: Vec<LatencyRule>, new_stats: LatencyStats) {
+        let mut rules = self.rules.write();
+        let mut stats = self.stats.write();
+        *rules = new_rules
+            .into_iter()
+            .map(|rule| (rule.id.clone(), rule))
+            .collect();
+        *stats = new_stats;
+    }
+
     pub fn clear_all(&self) {
         let mut rules = self.rules.write();
         let mut stats = self.stats.write();
@@ -256,6 +393,8 @@ impl LatencyInjector {
             .values()
             .filter(|rule| {
                 rule.enabled
+                    && rule.schedule.is_active()
+                    && !matches!(rule.delay, DelayConfig::Throttle { .. })
                     && self.matches(rule, method, url)
                     && self.applies_to(&rule.match_request.apply_to, &apply_to)
             })
@@ -263,6 +402,28 @@ impl LatencyInjector {
             .cloned()
     }
 
+    /// Highest priority request-side throttle rule for this request, with its byte rate
+    pub fn find_request_throttle(&self, method: &str, url: &str) -> Option<(LatencyRule, u64)> {
+        let rules = self.rules.read();
+
+        rules
+            .values()
+            .filter(|rule| {
+                rule.enabled
+                    && rule.schedule.is_active()
+                    && self.matches(rule, method, url)
+                    && self.applies_to(&rule.match_request.apply_to, &ApplyTo::Request)
+            })
+            .filter_map(|rule| match rule.delay {
+                DelayConfig::Throttle { bytes_per_second } if bytes_per_second > 0 => {
+                    Some((rule, bytes_per_second))
+                }
+                _ => None,
+            })
+            .max_by_key(|(rule, _)| rule.priority)
+            .map(|(rule, bytes_per_second)| (rule.clone(), bytes_per_second))
+    }
+
     fn matches(&self, rule: &LatencyRule, method: &str, url: &str) -> bool {
         // Check method
         if let Some(ref rule_method) = rule.match_request.method {
@@ -272,19 +433,11 @@ impl LatencyInjector {
         }
 
         // Check URL pattern
-        match rule.match_request.url_match_type {
-            MatchType::Exact => url == rule.match_request.url_pattern,
-            MatchType::Contains => url.contains(&rule.match_request.url_pattern),
-            MatchType::StartsWith => url.starts_with(&rule.match_request.url_pattern),
-            MatchType::EndsWith => url.ends_with(&rule.match_request.url_pattern),
-            MatchType::Regex => {
-                if let Ok(re) = Regex::new(&rule.match_request.url_pattern) {
-                    re.is_match(url)
-                } else {
-                    false
-                }
-            }
-        }
+        rule.match_request.url_match_type.matches_url(
+            &rule.match_request.url_pattern,
+            url,
+            rule.match_request.match_query,
+        )
     }
 
     fn applies_to(&self, rule_apply: &ApplyTo, current_apply: &ApplyTo) -> bool {
@@ -297,7 +450,7 @@ impl LatencyInjector {
 
     pub async fn apply_delay(&self, method: &str, url: &str, apply_to: ApplyTo) -> Option<u64> {
         if let Some(rule) = self.find_matching_rule(method, url, apply_to) {
-            let delay_ms = rule.delay.calculate_delay();
+            let delay_ms = rule.delay.calculate_delay(rule.started_at.elapsed());
 
             if delay_ms > 0 {
                 tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
@@ -308,7 +461,7 @@ impl LatencyInjector {
         None
     }
 
-    fn record_delay(&self, rule_id: &str, rule_name: &str, delay_ms: u64) {
+    pub fn record_delay(&self, rule_id: &str, rule_name: &str, delay_ms: u64) {
         let mut stats = self.stats.write();
 
         // Update global stats
@@ -328,6 +481,7 @@ impl LatencyInjector {
                 hits: 0,
                 total_delay_ms: 0,
                 avg_delay_ms: 0,
+                current_delay_ms: None,
             });
 
         rule_stats.hits += 1;
@@ -340,8 +494,77 @@ impl LatencyInjector {
         stats.clone()
     }
 
+    /// Stats plus the delay each `ramp` rule would apply right now, hit yet or not
+    pub fn live_stats(&self) -> LatencyStats {
+        let mut stats = self.get_stats();
+        for rule in self.rules.read().values() {
+            if !matches!(rule.delay, DelayConfig::Ramp { .. }) {
+                continue;
+            }
+            let rule_stats = stats
+                .by_rule
+                .entry(rule.id.clone())
+                .or_insert_with(|| RuleStats {
+                    rule_id: rule.id.clone(),
+                    rule_name: rule.name.clone(),
+                    hits: 0,
+                    total_delay_ms: 0,
+                    avg_delay_ms: 0,
+                    current_delay_ms: None,
+                });
+            rule_stats.current_delay_ms =
+                Some(rule.delay.calculate_delay(rule.started_at.elapsed()));
+        }
+        stats
+    }
+
     pub fn reset_stats(&self) {
         let mut stats = self.stats.write();
         *stats = LatencyStats::default();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pareto_mean_matches_theory() {
+        // Mean of Pareto(scale, shape) is shape * scale / (shape - 1) for shape > 1
+        let (scale_ms, shape) = (100, 3.0);
+        let config = DelayConfig::Pareto { scale_ms, shape };
+        let draws = 200_000;
+        let total: u64 = (0..draws)
+            .map(|_| config.calculate_delay(Duration::ZERO))
+            .sum();
+        let mean = total as f64 / draws as f64;
+        let expected = shape * scale_ms as f64 / (shape - 1.0);
+        assert!(
+            (mean - expected).abs() < expected * 0.03,
+            "mean {} too far from {}",
+            mean,
+            expected
+        );
+    }
+
+    #[test]
+    fn pareto_never_goes_below_scale_or_above_the_cap() {
+        let config = DelayConfig::Pareto {
+            scale_ms: 50,
+            shape: 0.05,
+        };
+        for _ in 0..10_000 {
+            let delay = config.calculate_delay(Duration::ZERO);
+            assert!((50..=MAX_SAMPLED_DELAY_MS).contains(&delay));
+        }
+    }
+
+    #[test]
+    fn pareto_with_non_positive_shape_is_the_scale() {
+        let config = DelayConfig::Pareto {
+            scale_ms: 80,
+            shape: 0.0,
+        };
+        assert_eq!(config.calculate_delay(Duration::ZERO), 80);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::config::LogFormat;
+use bytes::Bytes;
+use parking_lot::Mutex;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+/// `log` target request summaries and details are sent to with `--log-format json`
+const ACCESS_TARGET: &str = "access";
+
+/// Set up `env_logger`. With `--log-format json` every record becomes one JSON line:
+/// request logs as they are, anything else (Pingora's own logs included) wrapped with
+/// its level and target. Request logs are enabled whatever `RUST_LOG` says.
+pub fn init(format: LogFormat) {
+    match format {
+        LogFormat::Text => env_logger::init(),
+        LogFormat::Json => {
+            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+                .filter_module(ACCESS_TARGET, log::LevelFilter::Info)
+                .format(|buf, record| {
+                    if record.target() == ACCESS_TARGET {
+                        return writeln!(buf, "{}", record.args());
+                    }
+                    let line = json!({
+                        "timestamp": chrono::Utc::now().to_rfc3339(),
+                        "level": record.level().as_str(),
+                        "target": record.target(),
+                        "message": record.args().to_string(),
+                    });
+                    writeln!(buf, "{}", line)
+                })
+                .init();
+        }
+    }
+}
+
+/// Summary of one client request, logged once when the response is ready
+#[derive(Debug, Clone)]
+pub struct RequestLog {
+    pub id: String,
+    pub method: String,
+    pub url: String,
+    pub status: u16,
+    pub duration_ms: u64,
+    pub mocked: bool,
+    pub rate_limited: bool,
+    pub latency_ms: u64,
+    pub cache_hit: bool,
+    pub passthrough: bool,
+    // Request headers and buffered body, once the HTTP layer has read them
+    pub request_headers: HashMap<String, String>,
+    pub request_body: Option<Bytes>,
+    // Mock rule that produced the response
+    pub mock_rule: Option<String>,
+    // Recording `--auto-mock` served the response from
+    pub auto_mock: Option<String>,
+}
+
+impl RequestLog {
+    pub fn new(id: String, method: &str, url: &str) -> Self {
+        Self {
+            id,
+            method: method.to_string(),
+            url: url.to_string(),
+            status: 0,
+            duration_ms: 0,
+            mocked: false,
+            rate_limited: false,
+            latency_ms: 0,
+            cache_hit: false,
+            passthrough: false,
+            request_headers: HashMap::new(),
+            request_body: None,
+            mock_rule: None,
+            auto_mock: None,
+        }
+    }
+}
+
+/// How often buffered `--access-log` lines are written out
+const ACCESS_LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `--access-log` file, appended one line per completed request. Writes are buffered and
+/// flushed every second and on shutdown; SIGHUP reopens the file so it can be rotated.
+pub struct AccessLog {
+    path: PathBuf,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl AccessLog {
+    pub fn open(path: PathBuf) -> std::io::Result<Arc<Self>> {
+        let writer = Mutex::new(BufWriter::new(open_append(&path)?));
+        let access_log = Arc::new(Self { path, writer });
+
+        let weak = Arc::downgrade(&access_log);
+        std::thread::spawn(move || flush_periodically(weak));
+        reopen_on_sighup(access_log.clone());
+        Ok(access_log)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_line(&self, line: &str) {
+        if let Err(e) = writeln!(self.writer.lock(), "{}", line) {
+            eprintln!("Failed to write to {}: {}", self.path.display(), e);
+        }
+    }
+
+    pub fn flush(&self) {
+        if let Err(e) = self.writer.lock().flush() {
+            eprintln!("Failed to flush {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Continue in a fresh file at the same path, e.g. after logrotate moved the old one
+    fn reopen(&self) -> std::io::Result<()> {
+        let file = open_append(&self.path)?;
+        let mut writer = self.writer.lock();
+        writer.flush()?;
+        *writer = BufWriter::new(file);
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn flush_periodically(access_log: Weak<AccessLog>) {
+    loop {
+        std::thread::sleep(ACCESS_LOG_FLUSH_INTERVAL);
+        match access_log.upgrade() {
+            Some(access_log) => access_log.flush(),
+            None => return,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn reopen_on_sighup(access_log: Arc<AccessLog>) {
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        rt.block_on(async {
+            use tokio::signal::unix::{SignalKind, signal};
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(e) => {
+                    eprintln!("Can't listen for SIGHUP, access log won't reopen: {}", e);
+                    return;
+                }
+            };
+            while hangups.recv().await.is_some() {
+                if let Err(e) = access_log.reopen() {
+                    eprintln!(
+                        "Failed to reopen {}, still writing to the old file: {}",
+                        access_log.path.display(),
+                        e
+                    );
+                }
+            }
+        });
+    });
+}
+
+#[cfg(not(unix))]
+fn reopen_on_sighup(_access_log: Arc<AccessLog>) {}
+
+/// Request logger shared by the HTTP layer and the Pingora proxy.
+/// Each client request produces exactly one summary line; per-layer
+/// details are only printed in verbose mode, tagged with the same id.
+#[derive(Clone)]
+pub struct RequestLogger {
+    verbose: bool,
+    format: LogFormat,
+    // Also gets each summary line, with --access-log
+    access_log: Option<Arc<AccessLog>>,
+}
+
+impl RequestLogger {
+    pub fn new(verbose: bool, format: LogFormat) -> Self {
+        Self {
+            verbose,
+            format,
+            access_log: None,
+        }
+    }
+
+    pub fn with_access_log(mut self, access_log: Arc<AccessLog>) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    pub fn log_request(&self, entry: &RequestLog) {
+        let line = match self.format {
+            LogFormat::Text => text_line(entry),
+            LogFormat::Json => json_line(entry),
+        };
+        if let Some(ref access_log) = self.access_log {
+            match self.format {
+                // The console line has no timestamp, but a file read later needs one
+                LogFormat::Text => {
+                    access_log.write_line(&format!("{} {}", chrono::Utc::now().to_rfc3339(), line))
+                }
+                LogFormat::Json => access_log.write_line(&line),
+            }
+        }
+        match self.format {
+            LogFormat::Text => println!("{}", line),
+            LogFormat::Json => log::info!(target: ACCESS_TARGET, "{}", line),
+        }
+    }
+
+    /// Write out buffered access log lines
+    pub fn flush(&self) {
+        if let Some(ref access_log) = self.access_log {
+            access_log.flush();
+        }
+    }
+
+    pub fn detail(&self, id: &str, message: &str) {
+        if !self.verbose {
+            return;
+        }
+        match self.format {
+            LogFormat::Text => println!("  [{}] {}", short_id(id), message),
+            LogFormat::Json => {
+                let line = json!({
+                    "timestamp": chrono::Utc::now().to_rfc3339(),
+                    "id": id,
+                    "detail": message,
+                });
+                log::info!(target: ACCESS_TARGET, "{}", line);
+            }
+        }
+    }
+}
+
+/// `GET /api/users - 200 (12ms) [MOCKED] id=1a2b3c4d`
+fn text_line(entry: &RequestLog) -> String {
+    let mut line = format!(
+        "{} {} - {} ({}ms)",
+        entry.method, entry.url, entry.status, entry.duration_ms
+    );
+
+    if entry.passthrough {
+        line.push_str(" [PASSTHROUGH]");
+    }
+    if entry.rate_limited {
+        line.push_str(" [RATE LIMITED]");
+    }
+    if entry.mocked {
+        line.push_str(" [MOCKED]");
+    }
+    if entry.cache_hit {
+        line.push_str(" [CACHE HIT]");
+    }
+    if entry.auto_mock.is_some() {
+        line.push_str(" [AUTO-MOCK]");
+    }
+    if entry.latency_ms > 0 {
+        line.push_str(&format!(" [LATENCY: {}ms]", entry.latency_ms));
+    }
+
+    format!("{} id={}", line, short_id(&entry.id))
+}
+
+fn json_line(entry: &RequestLog) -> String {
+    json!({
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "id": entry.id,
+        "method": entry.method,
+        "url": entry.url,
+        "status": entry.status,
+        "duration_ms": entry.duration_ms,
+        "mocked": entry.mocked,
+        "rate_limited": entry.rate_limited,
+        "latency_ms": entry.latency_ms,
+        "cache_hit": entry.cache_hit,
+        "passthrough": entry.passthrough,
+        "mock_rule": entry.mock_rule,
+        "auto_mock": entry.auto_mock,
+    })
+    .to_string()
+}
+
+fn short_id(id: &str) -> &str {
+    id.get(..8).unwrap_or(id)
+}
@@ -1,22 +1,56 @@
 use anyhow::Result;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::thread;
 use structopt::StructOpt;
 
+mod circuit_breaker;
+mod compression;
 mod config;
+mod cors;
+mod etag_cache;
+mod explain;
+mod fault_injector;
+mod grpc_web;
+mod har;
 mod http_layer;
 mod latency_injector;
+mod logger;
+mod matcher;
+mod metrics;
 mod mock;
 mod modifier;
+mod openapi;
 mod proxy;
 mod rate_limiter;
 mod recorder;
+mod rules_config;
+mod schedule;
+mod schema;
+mod shutdown;
+mod snapshot;
 mod storage;
 mod ui;
+mod version;
+mod websocket;
 
-use config::ProxyConfig;
+use circuit_breaker::CircuitBreaker;
+use config::{BodyReplacement, LogFormat, ProxyConfig, UpstreamProtocol, UpstreamRoute};
+use etag_cache::EtagCache;
+use fault_injector::FaultInjector;
+use http_layer::HttpLayerState;
 use latency_injector::LatencyInjector;
+use logger::{AccessLog, RequestLogger};
+use matcher::PassthroughPattern;
+use metrics::Metrics;
 use modifier::ResponseModifier;
 use rate_limiter::RateLimiter;
+use recorder::{RecordFilter, RecordFilterRule, Recorder};
+use rules_config::{RuleManagers, RulesConfig};
+use shutdown::Shutdown;
+use snapshot::SnapshotStore;
+use ui::UiState;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "dev-proxy", about = "Development proxy with traffic recording")]
@@ -30,29 +64,292 @@ struct Opt {
     #[structopt(long, default_value = "9090")]
     internal_port: u16,
 
-    #[structopt(short, long, default_value = "http://localhost:8000")]
-    upstream: String,
+    /// Upstream to proxy to. Repeatable; requests are spread round-robin and a
+    /// connection failure falls through to the next upstream.
+    #[structopt(
+        short,
+        long = "upstream",
+        number_of_values = 1,
+        default_value = "http://localhost:8000"
+    )]
+    upstream: Vec<String>,
 
     #[structopt(short, long)]
     record: bool,
+
+    /// Also log per-layer details (latency phases, internal proxy hop)
+    #[structopt(short, long)]
+    verbose: bool,
+
+    /// Request log format: text, or json for one object per line (written through the
+    /// logger to stderr)
+    #[structopt(long, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Also append each request line to this file, in --log-format. SIGHUP reopens it.
+    #[structopt(long, parse(from_os_str))]
+    access_log: Option<PathBuf>,
+
+    /// Disable HTTP keep-alive on client connections (responses get `Connection: close`)
+    #[structopt(long)]
+    no_keepalive: bool,
+
+    /// Close a client connection after this many requests
+    #[structopt(long)]
+    max_requests_per_connection: Option<usize>,
+
+    /// Drop client connections that don't send complete headers within this time
+    #[structopt(long)]
+    header_read_timeout_ms: Option<u64>,
+
+    /// Aggregate pipelined responses into fewer socket writes
+    #[structopt(long)]
+    pipeline_flush: bool,
+
+    /// Answer If-None-Match requests with 304 when they match a stored upstream ETag
+    #[structopt(long)]
+    etag_cache: bool,
+
+    /// Always send matching paths to upstream, bypassing mocks, modifiers, rate limits and
+    /// latency rules. A path prefix, or `regex:<pattern>`. Repeatable.
+    #[structopt(long = "passthrough", number_of_values = 1)]
+    passthrough: Vec<PassthroughPattern>,
+
+    /// Only record matching requests, as ACTION:MATCH_TYPE:PATTERN, e.g.
+    /// `include:startswith:/api/` or `exclude:contains:/health`. A request is recorded when
+    /// it matches an include (or there are none) and no exclude. Repeatable.
+    #[structopt(long = "record-filter", number_of_values = 1)]
+    record_filters: Vec<RecordFilterRule>,
+
+    /// Proxy and record the first request for each method + URL, then answer repeats from
+    /// that recording without calling upstream. Turns on recording.
+    #[structopt(long)]
+    auto_mock: bool,
+
+    /// With --auto-mock, only reuse a recording whose request body is identical too
+    #[structopt(long)]
+    auto_mock_match_body: bool,
+
+    /// Request header (e.g. X-Correlation-Id) stored on recordings as their correlation id
+    #[structopt(long)]
+    correlation_header: Option<String>,
+
+    /// Answer new requests with 503 and Retry-After while this many are already in flight
+    #[structopt(long)]
+    max_inflight: Option<usize>,
+
+    /// Drop UI connections that don't send complete headers within this time (0 disables)
+    #[structopt(long, default_value = "30000")]
+    ui_header_read_timeout_ms: u64,
+
+    /// Drop UI connections with no request in progress for this long (0 disables)
+    #[structopt(long, default_value = "120000")]
+    ui_idle_timeout_ms: u64,
+
+    /// Require HTTP Basic Auth with this user name for the UI and API (needs --ui-pass)
+    #[structopt(long, requires = "ui-pass")]
+    ui_user: Option<String>,
+
+    /// Password for --ui-user
+    #[structopt(long, requires = "ui-user")]
+    ui_pass: Option<String>,
+
+    /// Let this origin call the API with credentials (e.g. http://localhost:5173); without
+    /// it any origin may, without credentials. Repeatable, `*` allows every origin.
+    #[structopt(long = "ui-cors-origin", number_of_values = 1)]
+    ui_cors_origins: Vec<String>,
+
+    /// Methods allowed in answers to API preflight requests
+    #[structopt(long, default_value = "GET, POST, PUT, PATCH, DELETE, OPTIONS")]
+    ui_cors_methods: String,
+
+    /// Headers allowed in answers to API preflight requests; by default the requested ones
+    #[structopt(long)]
+    ui_cors_headers: Option<String>,
+
+    /// Leave this header out of stored recordings (e.g. Cookie). Repeatable.
+    #[structopt(long = "strip-recorded-header", number_of_values = 1)]
+    strip_recorded_headers: Vec<String>,
+
+    /// Flat delay added to every response on top of latency rules (adjustable via the API)
+    #[structopt(long, default_value = "0")]
+    global_delay_ms: u64,
+
+    /// Keep at most this many bytes of each recorded request and response body; the rest
+    /// still streams through, and the recording is marked truncated. 0 keeps whole bodies.
+    #[structopt(long, default_value = "1048576")]
+    max_body_bytes: usize,
+
+    /// Load mock, modifier, rate limit and latency rules from this YAML file at startup,
+    /// and re-read it on SIGHUP
+    #[structopt(long, parse(from_os_str))]
+    rules_file: Option<PathBuf>,
+
+    /// Open an upstream's circuit after this many consecutive 5xx responses or connection
+    /// errors, answering its requests immediately until the cooldown ends
+    #[structopt(long)]
+    circuit_breaker_failures: Option<u32>,
+
+    /// Failures further apart than this don't count as consecutive
+    #[structopt(long, default_value = "10000")]
+    circuit_breaker_window_ms: u64,
+
+    /// How long an open circuit rejects requests before letting a probe through
+    #[structopt(long, default_value = "30000")]
+    circuit_breaker_cooldown_ms: u64,
+
+    /// Status sent for requests rejected by an open circuit
+    #[structopt(long, default_value = "503")]
+    circuit_breaker_status: u16,
+
+    /// Give up on upstream requests after this many milliseconds and answer 504
+    #[structopt(long = "timeout")]
+    timeout_ms: Option<u64>,
+
+    /// HTTP version spoken to upstreams: http1, http2 (e.g. gRPC servers) or auto
+    #[structopt(long, default_value = "http1")]
+    upstream_protocol: UpstreamProtocol,
+
+    /// Idle connections kept open per upstream host by the shared client
+    #[structopt(long, default_value = "32")]
+    pool_max_idle_per_host: usize,
+
+    /// Close pooled upstream connections idle for this long
+    #[structopt(long, default_value = "90000")]
+    pool_idle_timeout_ms: u64,
+
+    /// Serve the proxy port over HTTPS with this PEM certificate chain (needs --tls-key)
+    #[structopt(long, parse(from_os_str), requires = "tls-key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[structopt(long, parse(from_os_str), requires = "tls-cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Accept any certificate from HTTPS upstreams, e.g. self-signed ones. Never use this
+    /// against hosts you don't control.
+    #[structopt(long)]
+    insecure_upstream: bool,
+
+    /// Also trust this PEM root CA (or bundle) when connecting to HTTPS upstreams
+    #[structopt(long, parse(from_os_str))]
+    upstream_ca: Option<PathBuf>,
+
+    /// On Ctrl-C, wait this long for in-flight requests to finish before exiting
+    #[structopt(long, default_value = "10000")]
+    shutdown_grace_ms: u64,
+
+    /// Keep numeric and UUID path segments apart in analytics instead of grouping them as {id}
+    #[structopt(long)]
+    no_normalize_paths: bool,
+
+    /// Send paths starting with PREFIX to their own upstream, as PREFIX=UPSTREAM. The longest
+    /// matching prefix wins; other paths use --upstream. Repeatable.
+    #[structopt(long = "route", number_of_values = 1)]
+    routes: Vec<UpstreamRoute>,
+
+    /// Replace text in every proxied text response body, as FIND=REPLACEMENT. Repeatable.
+    #[structopt(long = "replace", number_of_values = 1)]
+    replacements: Vec<BodyReplacement>,
+
+    /// Send X-Forwarded-Proto, X-Forwarded-Host and X-Forwarded-For upstream
+    #[structopt(long)]
+    forwarded_headers: bool,
+
+    /// Attach a matching decision trace to every response (X-DevProxy-Explain)
+    #[structopt(long)]
+    explain: bool,
+
+    /// Keep recordings in this JSONL file so they survive restarts
+    #[structopt(long, parse(from_os_str))]
+    persist_file: Option<PathBuf>,
+
+    /// Keep at most this many recordings, evicting the oldest (0 keeps everything)
+    #[structopt(long, default_value = "10000")]
+    max_recordings: usize,
+
+    /// Seed the random choice between weighted mock responses, for reproducible runs
+    #[structopt(long)]
+    mock_seed: Option<u64>,
 }
 
 fn main() -> Result<()> {
-    env_logger::init();
-
     let opt = Opt::from_args();
+    logger::init(opt.log_format);
 
     let config = ProxyConfig {
         proxy_port: opt.internal_port,
         ui_port: opt.ui_port,
-        upstream_url: opt.upstream,
-        recording_enabled: opt.record,
+        upstream_urls: opt.upstream,
+        recording_enabled: opt.record || opt.auto_mock,
+        verbose: opt.verbose,
+        keep_alive: !opt.no_keepalive,
+        max_requests_per_connection: opt.max_requests_per_connection,
+        header_read_timeout_ms: opt.header_read_timeout_ms,
+        pipeline_flush: opt.pipeline_flush,
+        etag_cache: opt.etag_cache,
+        passthrough: opt.passthrough,
+        correlation_header: opt.correlation_header.map(|h| h.to_ascii_lowercase()),
+        max_inflight: opt.max_inflight,
+        ui_header_read_timeout_ms: opt.ui_header_read_timeout_ms,
+        ui_idle_timeout_ms: opt.ui_idle_timeout_ms,
+        ui_user: opt.ui_user,
+        ui_pass: opt.ui_pass,
+        ui_cors_origins: opt.ui_cors_origins,
+        ui_cors_methods: opt.ui_cors_methods,
+        ui_cors_headers: opt.ui_cors_headers,
+        strip_recorded_headers: opt.strip_recorded_headers,
+        replacements: opt.replacements,
+        forwarded_headers: opt.forwarded_headers,
+        explain: opt.explain,
+        persist_file: opt.persist_file,
+        max_recordings: opt.max_recordings,
+        mock_seed: opt.mock_seed,
+        routes: opt.routes,
+        rules_file: opt.rules_file,
+        max_body_bytes: opt.max_body_bytes,
+        circuit_breaker_failures: opt.circuit_breaker_failures,
+        circuit_breaker_window_ms: opt.circuit_breaker_window_ms,
+        circuit_breaker_cooldown_ms: opt.circuit_breaker_cooldown_ms,
+        circuit_breaker_status: opt.circuit_breaker_status,
+        upstream_timeout_ms: opt.timeout_ms,
+        pool_max_idle_per_host: opt.pool_max_idle_per_host,
+        pool_idle_timeout_ms: opt.pool_idle_timeout_ms,
+        upstream_protocol: opt.upstream_protocol,
+        normalize_paths: !opt.no_normalize_paths,
+        tls_cert: opt.tls_cert,
+        tls_key: opt.tls_key,
+        insecure_upstream: opt.insecure_upstream,
+        upstream_ca: opt.upstream_ca,
+        shutdown_grace_ms: opt.shutdown_grace_ms,
+        log_format: opt.log_format,
+        access_log: opt.access_log,
+        record_filters: opt.record_filters,
+        auto_mock: opt.auto_mock,
+        auto_mock_match_body: opt.auto_mock_match_body,
     };
 
     println!("🚀 Starting Dev Proxy...");
-    println!("   Proxy: http://0.0.0.0:{}", opt.port);
-    println!("   UI:    http://0.0.0.0:{}", config.ui_port);
-    println!("   Upstream: {}", config.upstream_url);
+    println!("   Proxy: {}://0.0.0.0:{}", config.scheme(), opt.port);
+    println!(
+        "   UI:    http://0.0.0.0:{}{}",
+        config.ui_port,
+        if config.ui_credentials().is_some() {
+            " (basic auth)"
+        } else {
+            ""
+        }
+    );
+    println!("   Upstream: {}", config.upstream_urls.join(", "));
+    for route in &config.routes {
+        println!("   Route: {} -> {}", route.prefix, route.upstream);
+    }
+    if let Some(ref path) = config.upstream_ca {
+        println!("   Upstream CA: {}", path.display());
+    }
+    if config.insecure_upstream {
+        println!("   ⚠️  --insecure-upstream: upstream TLS certificates are NOT verified");
+    }
     println!(
         "   Recording: {}",
         if config.recording_enabled {
@@ -61,20 +358,89 @@ fn main() -> Result<()> {
             "disabled"
         }
     );
+    if config.auto_mock {
+        println!(
+            "   Auto-mock: enabled{}",
+            if config.auto_mock_match_body {
+                " (matching bodies)"
+            } else {
+                ""
+            }
+        );
+    }
 
-    let storage = storage::Storage::new();
-    let mock_manager = mock::MockManager::new();
+    let storage = match config.persist_file {
+        Some(ref path) => {
+            let storage = storage::Storage::with_persistence(path.clone())?;
+            println!(
+                "   Persisting recordings to {} ({} loaded)",
+                path.display(),
+                storage.recordings.read().len()
+            );
+            storage
+        }
+        None => storage::Storage::new(),
+    }
+    .with_max_recordings(config.max_recordings)
+    .with_path_normalization(config.normalize_paths);
+    let mut mock_manager = mock::MockManager::new();
+    if let Some(seed) = config.mock_seed {
+        mock_manager = mock_manager.with_seed(seed);
+    }
     let response_modifier = ResponseModifier::new();
     let rate_limiter = RateLimiter::new();
     let latency_injector = LatencyInjector::new();
+    latency_injector.set_global_delay_ms(opt.global_delay_ms);
+    let fault_injector = FaultInjector::new();
+    let recording = Arc::new(AtomicBool::new(config.recording_enabled));
+    let auto_mock = Arc::new(AtomicBool::new(config.auto_mock));
+    let record_filter = RecordFilter::new(config.record_filters.clone());
+    if let Some(ref path) = config.rules_file {
+        let managers = RuleManagers {
+            mock_manager: mock_manager.clone(),
+            response_modifier: response_modifier.clone(),
+            rate_limiter: rate_limiter.clone(),
+            latency_injector: latency_injector.clone(),
+            record_filter: record_filter.clone(),
+        };
+        RulesConfig::load(path)?.apply(&managers);
+        println!("   Rules: {} (SIGHUP reloads)", path.display());
+        rules_config::reload_on_sighup(path.clone(), managers);
+    }
+    let mut logger = RequestLogger::new(config.verbose, config.log_format);
+    if let Some(ref path) = config.access_log {
+        let access_log = AccessLog::open(path.clone())?;
+        println!(
+            "   Access log: {} (SIGHUP reopens)",
+            access_log.path().display()
+        );
+        logger = logger.with_access_log(access_log);
+    }
+    let metrics = Metrics::new();
+    let circuit_breaker = CircuitBreaker::new(&config);
+    let client = config.upstream_client()?;
+    let ws_connector = websocket::upstream_connector(&config)?;
+    let next_upstream = Arc::new(AtomicUsize::new(0));
+    let shutdown = Shutdown::new();
+    shutdown.drain_on_ctrl_c();
 
     // Start UI server in a separate thread with its own runtime
-    let ui_storage = storage.clone();
-    let ui_mock_manager = mock_manager.clone();
-    let ui_modifier = response_modifier.clone();
-    let ui_rate_limiter = rate_limiter.clone();
-    let ui_latency = latency_injector.clone();
-    let ui_port = config.ui_port;
+    let ui_state = UiState {
+        storage: storage.clone(),
+        mock_manager: mock_manager.clone(),
+        response_modifier: response_modifier.clone(),
+        rate_limiter: rate_limiter.clone(),
+        latency_injector: latency_injector.clone(),
+        fault_injector: fault_injector.clone(),
+        snapshots: SnapshotStore::new(),
+        metrics: metrics.clone(),
+        circuit_breaker: circuit_breaker.clone(),
+        recording: recording.clone(),
+        auto_mock: auto_mock.clone(),
+        client: client.clone(),
+    };
+    let ui_config = config.clone();
+    let ui_shutdown = shutdown.clone();
     thread::spawn(move || {
         let rt = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
@@ -82,28 +448,37 @@ fn main() -> Result<()> {
             .unwrap();
 
         rt.block_on(async {
-            if let Err(e) = ui::start_ui_server(
-                ui_port,
-                ui_storage,
-                ui_mock_manager,
-                ui_modifier,
-                ui_rate_limiter,
-                ui_latency,
-            )
-            .await
-            {
+            if let Err(e) = ui::start_ui_server(ui_config, ui_state, ui_shutdown).await {
                 eprintln!("UI server error: {}", e);
             }
         });
     });
 
-    let http_storage = storage.clone();
-    let http_mock_manager = mock_manager.clone();
-    let http_modifier = response_modifier.clone();
-    let http_rate_limiter = rate_limiter.clone();
-    let http_latency = latency_injector.clone();
+    let http_state = HttpLayerState {
+        config: config.clone(),
+        storage: storage.clone(),
+        mock_manager: mock_manager.clone(),
+        response_modifier: response_modifier.clone(),
+        rate_limiter: rate_limiter.clone(),
+        latency_injector: latency_injector.clone(),
+        fault_injector,
+        etag_cache: EtagCache::new(config.max_recordings),
+        metrics: metrics.clone(),
+        inflight: Arc::new(AtomicUsize::new(0)),
+        logger: logger.clone(),
+        client,
+        ws_connector,
+        next_upstream: next_upstream.clone(),
+        shutdown: shutdown.clone(),
+        recording: recording.clone(),
+        record_filter: record_filter.clone(),
+        auto_mock,
+    };
     let http_port = opt.port;
-    let pingora_port = opt.internal_port;
+    let http_storage = storage.clone();
+    let http_logger = logger.clone();
+    let http_shutdown = shutdown.clone();
+    let persist_file = config.persist_file.clone();
     thread::spawn(move || {
         let rt = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
@@ -111,25 +486,45 @@ fn main() -> Result<()> {
             .unwrap();
 
         rt.block_on(async {
-            if let Err(e) = http_layer::start_http_layer(
-                http_port,
-                pingora_port,
-                http_storage,
-                http_mock_manager,
-                http_modifier,
-                http_rate_limiter,
-                http_latency,
-            )
-            .await
-            {
+            if let Err(e) = http_layer::start_http_layer(http_port, http_state).await {
                 eprintln!("HTTP layer error: {}", e);
+                // Nothing left to drain, but Ctrl-C must still stop the proxy
+                http_shutdown.draining().await;
             }
         });
+
+        // The listener is closed and in-flight requests have finished or timed out
+        http_logger.flush();
+        print_shutdown_summary(&http_storage, persist_file.as_deref());
+        http_shutdown.stop();
     });
 
     // Give UI server time to start
     thread::sleep(std::time::Duration::from_millis(200));
 
     // Start proxy server on main thread (Pingora creates its own runtime)
-    proxy::start_proxy_server(config, storage, mock_manager)
+    let recorder = Recorder::new(storage, config.clone(), recording, record_filter);
+    proxy::start_proxy_server(
+        config,
+        recorder,
+        mock_manager,
+        logger,
+        circuit_breaker,
+        next_upstream,
+        shutdown,
+    )
+}
+
+/// Flush `--persist-file` and report what was captured, once draining is done
+fn print_shutdown_summary(storage: &storage::Storage, persist_file: Option<&std::path::Path>) {
+    let info = storage.memory_info();
+    println!("📼 {} recording(s) captured", info.count);
+    match persist_file {
+        Some(path) => match storage.flush() {
+            Ok(()) => println!("   Saved to {}", path.display()),
+            Err(e) => eprintln!("   Failed to flush {}: {}", path.display(), e),
+        },
+        None => println!("   Not persisted; use --persist-file to keep them across restarts"),
+    }
+    println!("👋 Bye");
 }
@@ -0,0 +1,292 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// URL match type shared by mock, modifier, rate limit and latency rules
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchType {
+    Exact,
+    Contains,
+    Regex,
+    StartsWith,
+    EndsWith,
+}
+
+impl MatchType {
+    pub fn matches(&self, pattern: &str, url: &str) -> bool {
+        match self {
+            MatchType::Exact => url == pattern,
+            MatchType::Contains => url.contains(pattern),
+            MatchType::StartsWith => url.starts_with(pattern),
+            MatchType::EndsWith => url.ends_with(pattern),
+            MatchType::Regex => {
+                if let Ok(re) = Regex::new(pattern) {
+                    re.is_match(url)
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Match a request URL; with `match_query` off the query string is left out, so
+    /// `/api/users?page=2` matches an exact `/api/users`
+    pub fn matches_url(&self, pattern: &str, url: &str, match_query: bool) -> bool {
+        let url = if match_query {
+            url
+        } else {
+            url.split_once('?').map_or(url, |(path, _)| path)
+        };
+        self.matches(pattern, url)
+    }
+}
+
+/// Serde default for rules' `match_query`: patterns see the query string unless turned off
+pub fn default_match_query() -> bool {
+    true
+}
+
+/// Parses the names used in rule JSON, e.g. `startswith`
+impl FromStr for MatchType {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "exact" => Ok(MatchType::Exact),
+            "contains" => Ok(MatchType::Contains),
+            "regex" => Ok(MatchType::Regex),
+            "startswith" => Ok(MatchType::StartsWith),
+            "endswith" => Ok(MatchType::EndsWith),
+            _ => Err(format!(
+                "Invalid match type '{}', expected exact, contains, regex, startswith or endswith",
+                value
+            )),
+        }
+    }
+}
+
+/// Path pattern for `--passthrough`: a path prefix, or a regex when written as `regex:<pattern>`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum PassthroughPattern {
+    Prefix(String),
+    Regex(Regex),
+}
+
+impl PassthroughPattern {
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            PassthroughPattern::Prefix(prefix) => path.starts_with(prefix.as_str()),
+            PassthroughPattern::Regex(re) => re.is_match(path),
+        }
+    }
+}
+
+impl FromStr for PassthroughPattern {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.strip_prefix("regex:") {
+            Some(pattern) => Regex::new(pattern)
+                .map(PassthroughPattern::Regex)
+                .map_err(|e| format!("Invalid passthrough regex '{}': {}", pattern, e)),
+            None => Ok(PassthroughPattern::Prefix(value.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for PassthroughPattern {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<PassthroughPattern> for String {
+    fn from(pattern: PassthroughPattern) -> Self {
+        match pattern {
+            PassthroughPattern::Prefix(prefix) => prefix,
+            PassthroughPattern::Regex(re) => format!("regex:{}", re.as_str()),
+        }
+    }
+}
+
+/// Value of a query or header condition that only requires the key to be present
+pub const ANY_VALUE: &str = "*";
+
+/// First `(name, value)` condition the URL's query string doesn't satisfy. Every
+/// condition has to hold; a value of `*` only requires the parameter to be present.
+pub fn unmet_query_condition<'a>(
+    conditions: &'a [(String, String)],
+    url: &str,
+) -> Option<&'a (String, String)> {
+    if conditions.is_empty() {
+        return None;
+    }
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+    let params: Vec<(Cow<'_, str>, Cow<'_, str>)> =
+        url::form_urlencoded::parse(query.as_bytes()).collect();
+    conditions.iter().find(|(name, expected)| {
+        !params
+            .iter()
+            .any(|(key, value)| key == name && (expected == ANY_VALUE || value == expected))
+    })
+}
+
+/// First `(name, value)` condition the request headers don't satisfy. Header names are
+/// compared case-insensitively; a value of `*` only requires the header to be present.
+pub fn unmet_header_condition<'a>(
+    conditions: &'a [(String, String)],
+    headers: &HashMap<String, String>,
+) -> Option<&'a (String, String)> {
+    conditions.iter().find(|(name, expected)| {
+        !headers.iter().any(|(key, value)| {
+            key.eq_ignore_ascii_case(name) && (expected == ANY_VALUE || value == expected)
+        })
+    })
+}
+
+/// Reassign priorities so rules follow the order of `ids`, highest first. Unknown ids
+/// are ignored; unlisted rules keep their relative order below the reordered ones.
+pub fn reorder_priorities<R>(
+    rules: &mut HashMap<String, R>,
+    ids: Vec<String>,
+    priority: impl Fn(&mut R) -> &mut i32,
+) {
+    let mut seen = HashSet::new();
+    let listed: Vec<String> = ids
+        .into_iter()
+        .filter(|id| rules.contains_key(id) && seen.insert(id.clone()))
+        .collect();
+    let mut unlisted: Vec<(i32, String)> = rules
+        .iter_mut()
+        .filter(|(id, _)| !seen.contains(*id))
+        .map(|(id, rule)| (*priority(rule), id.clone()))
+        .collect();
+    unlisted.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+    let count = rules.len() as i32;
+    let ordered = listed.iter().chain(unlisted.iter().map(|(_, id)| id));
+    for (index, id) in ordered.enumerate() {
+        if let Some(rule) = rules.get_mut(id) {
+            *priority(rule) = count - index as i32;
+        }
+    }
+}
+
+/// A path segment that's an id rather than a fixed name: all digits, or a UUID
+pub fn is_dynamic_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && (segment.bytes().all(|b| b.is_ascii_digit()) || uuid::Uuid::parse_str(segment).is_ok())
+}
+
+/// Canonicalize a JSON body (compact, keys sorted) so formatting differences
+/// don't affect body matching. Non-JSON bodies are returned as-is.
+pub fn normalize_json_body(body: &str) -> Cow<'_, str> {
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(value) => Cow::Owned(value.to_string()),
+        Err(_) => Cow::Borrowed(body),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatternTestRequest {
+    pub match_type: MatchType,
+    pub pattern: String,
+    pub samples: Vec<String>,
+    // Treat samples (and the pattern) as JSON bodies and canonicalize them first
+    #[serde(default)]
+    pub normalize_json_body: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatternTestResult {
+    pub url: String,
+    pub matched: bool,
+    // Capture groups (index 0 is the whole match), only for regex patterns
+    pub captures: Option<Vec<Option<String>>>,
+}
+
+/// Evaluate a pattern against sample URLs without creating a rule.
+/// Unlike rule matching, an invalid regex is reported instead of silently not matching.
+pub fn test_pattern<'a>(request: &'a PatternTestRequest) -> Result<Vec<PatternTestResult>, String> {
+    let regex = match request.match_type {
+        MatchType::Regex => {
+            Some(Regex::new(&request.pattern).map_err(|e| format!("Invalid regex: {}", e))?)
+        }
+        _ => None,
+    };
+
+    let normalize = |value: &'a str| -> Cow<'a, str> {
+        if request.normalize_json_body {
+            normalize_json_body(value)
+        } else {
+            Cow::Borrowed(value)
+        }
+    };
+    let pattern = normalize(&request.pattern);
+
+    let results = request
+        .samples
+        .iter()
+        .map(|url| match regex {
+            Some(ref re) => {
+                let captures = re.captures(&normalize(url)).map(|caps| {
+                    caps.iter()
+                        .map(|group| group.map(|m| m.as_str().to_string()))
+                        .collect()
+                });
+                PatternTestResult {
+                    url: url.clone(),
+                    matched: captures.is_some(),
+                    captures,
+                }
+            }
+            None => PatternTestResult {
+                url: url.clone(),
+                matched: request.match_type.matches(&pattern, &normalize(url)),
+                captures: None,
+            },
+        })
+        .collect();
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dynamic_segments() {
+        assert!(is_dynamic_segment("42"));
+        assert!(is_dynamic_segment("4bd5e5b2-1f0a-4c2e-9d61-54b1f5e0a0a1"));
+        assert!(!is_dynamic_segment(""));
+        assert!(!is_dynamic_segment("v2"));
+        assert!(!is_dynamic_segment("users"));
+        assert!(!is_dynamic_segment("4bd5e5b2-nope"));
+    }
+
+    #[test]
+    fn reorder_puts_listed_ids_first() {
+        let mut rules = HashMap::from([
+            ("a".to_string(), 30),
+            ("b".to_string(), 20),
+            ("c".to_string(), 10),
+            ("d".to_string(), 10),
+        ]);
+        let ids = ["c", "missing", "a", "c"].map(String::from).to_vec();
+        reorder_priorities(&mut rules, ids, |priority| priority);
+
+        let mut order: Vec<(&String, &i32)> = rules.iter().collect();
+        order.sort_by(|a, b| b.1.cmp(a.1));
+        let order: Vec<&str> = order.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(order, ["c", "a", "b", "d"]);
+        assert_eq!(rules["c"], 4);
+        assert_eq!(rules["d"], 1);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::latency_injector::LatencyStats;
+use crate::rate_limiter::BucketStats;
+use parking_lot::RwLock;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Upper bounds (seconds) of the request duration histogram buckets, Prometheus defaults
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Request counters for the Prometheus `/metrics` endpoint, updated once per request
+/// by the HTTP layer
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: AtomicU64,
+    by_status: RwLock<BTreeMap<u16, AtomicU64>>,
+    // Per-bucket counts (not cumulative); the last slot is +Inf
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    duration_sum_ms: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Counters::default()),
+        }
+    }
+
+    pub fn record_request(&self, status: u16, duration_ms: u64) {
+        let counters = &self.inner;
+        counters.requests.fetch_add(1, Ordering::Relaxed);
+
+        let counted = counters
+            .by_status
+            .read()
+            .get(&status)
+            .map(|count| count.fetch_add(1, Ordering::Relaxed))
+            .is_some();
+        if !counted {
+            counters
+                .by_status
+                .write()
+                .entry(status)
+                .or_default()
+                .fetch_add(1, Ordering::Relaxed);
+        }
+
+        let seconds = duration_ms as f64 / 1000.0;
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        counters.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        counters
+            .duration_sum_ms
+            .fetch_add(duration_ms, Ordering::Relaxed);
+    }
+
+    /// Render every metric in the Prometheus text exposition format (version 0.0.4)
+    pub fn render(&self, buckets: &BucketStats, latency: &LatencyStats) -> String {
+        let counters = &self.inner;
+        let mut out = String::new();
+
+        let requests = counters.requests.load(Ordering::Relaxed);
+        out.push_str("# HELP devproxy_requests_total Requests handled by the proxy.\n");
+        out.push_str("# TYPE devproxy_requests_total counter\n");
+        let _ = writeln!(out, "devproxy_requests_total {}", requests);
+
+        out.push_str("# HELP devproxy_responses_total Responses sent, by status code.\n");
+        out.push_str("# TYPE devproxy_responses_total counter\n");
+        for (status, count) in counters.by_status.read().iter() {
+            let _ = writeln!(
+                out,
+                "devproxy_responses_total{{status=\"{}\"}} {}",
+                status,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        out.push_str("# HELP devproxy_request_duration_seconds Time to serve a request.\n");
+        out.push_str("# TYPE devproxy_request_duration_seconds histogram\n");
+        let mut cumulative = 0;
+        for (index, count) in counters.duration_buckets.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let le = DURATION_BUCKETS
+                .get(index)
+                .map_or("+Inf".to_string(), |bound| bound.to_string());
+            let _ = writeln!(
+                out,
+                "devproxy_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+                le, cumulative
+            );
+        }
+        let sum_seconds = counters.duration_sum_ms.load(Ordering::Relaxed) as f64 / 1000.0;
+        let _ = writeln!(out, "devproxy_request_duration_seconds_sum {}", sum_seconds);
+        let _ = writeln!(
+            out,
+            "devproxy_request_duration_seconds_count {}",
+            cumulative
+        );
+
+        out.push_str("# HELP devproxy_rate_limit_buckets Rate limit buckets being tracked.\n");
+        out.push_str("# TYPE devproxy_rate_limit_buckets gauge\n");
+        let _ = writeln!(out, "devproxy_rate_limit_buckets {}", buckets.total_buckets);
+        out.push_str(
+            "# HELP devproxy_rate_limit_active_buckets Rate limit buckets with requests in the current window.\n",
+        );
+        out.push_str("# TYPE devproxy_rate_limit_active_buckets gauge\n");
+        let _ = writeln!(
+            out,
+            "devproxy_rate_limit_active_buckets {}",
+            buckets.active_limits
+        );
+
+        out.push_str(
+            "# HELP devproxy_latency_injections_total Delays injected by latency rules.\n",
+        );
+        out.push_str("# TYPE devproxy_latency_injections_total counter\n");
+        let _ = writeln!(
+            out,
+            "devproxy_latency_injections_total {}",
+            latency.total_injections
+        );
+
+        out
+    }
+}
@@ -1,9 +1,21 @@
+use parking_lot::{Mutex, RwLock};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
-use parking_lot::RwLock;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use uuid::Uuid;
-use regex::Regex;
+
+use crate::explain::{RuleCheck, RuleTrace, unmet_conditions};
+use crate::grpc_web::GrpcWebResponse;
+pub use crate::matcher::MatchType;
+use crate::matcher::{
+    default_match_query, normalize_json_body, reorder_priorities, unmet_header_condition,
+    unmet_query_condition,
+};
+use crate::schedule::ActiveWindow;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MockRule {
@@ -14,8 +26,48 @@ pub struct MockRule {
     pub method: Option<String>,
     pub url_pattern: String,
     pub url_match_type: MatchType,
+    // Match url_pattern against the whole URL; false leaves the query string out
+    #[serde(default = "default_match_query")]
+    pub match_query: bool,
+    // Only match requests whose body matches too (Contains when no match type is given)
+    #[serde(default)]
+    pub body_pattern: Option<String>,
+    #[serde(default)]
+    pub body_match_type: Option<MatchType>,
+    // Compare JSON bodies as canonical JSON (compact, keys sorted) instead of byte for byte
+    #[serde(default)]
+    pub normalize_json_body: bool,
+    // `(name, value)` pairs that must all be present on the request; `*` matches any value
+    #[serde(default)]
+    pub query_matches: Vec<(String, String)>,
+    #[serde(default)]
+    pub header_matches: Vec<(String, String)>,
     pub response: MockResponse,
+    #[serde(default)]
+    pub content_responses: Vec<ContentTypeResponse>,
+    // Responses picked deterministically by hashing the `hash_by` request attribute
+    #[serde(default)]
+    pub variants: Vec<MockResponse>,
+    #[serde(default)]
+    pub hash_by: Option<HashBy>,
+    // Picked at random by weight on each request; when empty `response` is always served
+    #[serde(default)]
+    pub responses: Vec<WeightedResponse>,
+    // Served in order on successive calls, wrapping around; takes precedence over the others
+    #[serde(default)]
+    pub sequence: Option<Vec<MockResponse>>,
+    // Served for the first `count` calls, before any of the responses above
+    #[serde(default)]
+    pub fail_first: Option<FailFirst>,
     pub delay_ms: Option<u64>,
+    // Random extra 0..=jitter ms on top of delay_ms, so clients don't retry in lockstep
+    #[serde(default)]
+    pub delay_jitter_ms: Option<u64>,
+    // As the lowest-priority match, deep-merge the JSON bodies of all matching rules into this one
+    #[serde(default)]
+    pub merge_matching: bool,
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
@@ -28,8 +80,48 @@ pub struct CreateMockRule {
     pub method: Option<String>,
     pub url_pattern: String,
     pub url_match_type: MatchType,
+    // Match url_pattern against the whole URL; false leaves the query string out
+    #[serde(default = "default_match_query")]
+    pub match_query: bool,
+    // Only match requests whose body matches too (Contains when no match type is given)
+    #[serde(default)]
+    pub body_pattern: Option<String>,
+    #[serde(default)]
+    pub body_match_type: Option<MatchType>,
+    // Compare JSON bodies as canonical JSON (compact, keys sorted) instead of byte for byte
+    #[serde(default)]
+    pub normalize_json_body: bool,
+    // `(name, value)` pairs that must all be present on the request; `*` matches any value
+    #[serde(default)]
+    pub query_matches: Vec<(String, String)>,
+    #[serde(default)]
+    pub header_matches: Vec<(String, String)>,
     pub response: MockResponse,
+    #[serde(default)]
+    pub content_responses: Vec<ContentTypeResponse>,
+    // Responses picked deterministically by hashing the `hash_by` request attribute
+    #[serde(default)]
+    pub variants: Vec<MockResponse>,
+    #[serde(default)]
+    pub hash_by: Option<HashBy>,
+    // Picked at random by weight on each request; when empty `response` is always served
+    #[serde(default)]
+    pub responses: Vec<WeightedResponse>,
+    // Served in order on successive calls, wrapping around; takes precedence over the others
+    #[serde(default)]
+    pub sequence: Option<Vec<MockResponse>>,
+    // Served for the first `count` calls, before any of the responses above
+    #[serde(default)]
+    pub fail_first: Option<FailFirst>,
     pub delay_ms: Option<u64>,
+    // Random extra 0..=jitter ms on top of delay_ms, so clients don't retry in lockstep
+    #[serde(default)]
+    pub delay_jitter_ms: Option<u64>,
+    // As the lowest-priority match, deep-merge the JSON bodies of all matching rules into this one
+    #[serde(default)]
+    pub merge_matching: bool,
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
 }
 
 // For updating existing rules (with id but created_at is ignored)
@@ -42,55 +134,736 @@ pub struct UpdateMockRule {
     pub method: Option<String>,
     pub url_pattern: String,
     pub url_match_type: MatchType,
+    // Match url_pattern against the whole URL; false leaves the query string out
+    #[serde(default = "default_match_query")]
+    pub match_query: bool,
+    // Only match requests whose body matches too (Contains when no match type is given)
+    #[serde(default)]
+    pub body_pattern: Option<String>,
+    #[serde(default)]
+    pub body_match_type: Option<MatchType>,
+    // Compare JSON bodies as canonical JSON (compact, keys sorted) instead of byte for byte
+    #[serde(default)]
+    pub normalize_json_body: bool,
+    // `(name, value)` pairs that must all be present on the request; `*` matches any value
+    #[serde(default)]
+    pub query_matches: Vec<(String, String)>,
+    #[serde(default)]
+    pub header_matches: Vec<(String, String)>,
     pub response: MockResponse,
+    #[serde(default)]
+    pub content_responses: Vec<ContentTypeResponse>,
+    // Responses picked deterministically by hashing the `hash_by` request attribute
+    #[serde(default)]
+    pub variants: Vec<MockResponse>,
+    #[serde(default)]
+    pub hash_by: Option<HashBy>,
+    // Picked at random by weight on each request; when empty `response` is always served
+    #[serde(default)]
+    pub responses: Vec<WeightedResponse>,
+    // Served in order on successive calls, wrapping around; takes precedence over the others
+    #[serde(default)]
+    pub sequence: Option<Vec<MockResponse>>,
+    // Served for the first `count` calls, before any of the responses above
+    #[serde(default)]
+    pub fail_first: Option<FailFirst>,
+    pub delay_ms: Option<u64>,
+    // Random extra 0..=jitter ms on top of delay_ms, so clients don't retry in lockstep
+    #[serde(default)]
+    pub delay_jitter_ms: Option<u64>,
+    // As the lowest-priority match, deep-merge the JSON bodies of all matching rules into this one
+    #[serde(default)]
+    pub merge_matching: bool,
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
+}
+
+/// Body of `POST /api/mocks/batch`: several rules created in one call with shared defaults.
+/// Rules are kept as raw JSON so each one is validated and reported on its own.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockBatchRequest {
+    #[serde(default)]
+    pub defaults: MockBatchDefaults,
+    pub rules: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MockBatchDefaults {
+    pub priority: i32,
+    pub enabled: bool,
+    pub url_match_type: MatchType,
     pub delay_ms: Option<u64>,
 }
 
+impl Default for MockBatchDefaults {
+    fn default() -> Self {
+        Self {
+            priority: 0,
+            enabled: true,
+            url_match_type: MatchType::Exact,
+            delay_ms: None,
+        }
+    }
+}
+
+// One entry of a batch; unset fields come from the batch defaults
+#[derive(Debug, Clone, Deserialize)]
+struct MockBatchRule {
+    name: Option<String>,
+    method: Option<String>,
+    url_pattern: String,
+    url_match_type: Option<MatchType>,
+    priority: Option<i32>,
+    enabled: Option<bool>,
+    response: MockResponse,
+    delay_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MockBatchItem {
+    pub index: usize,
+    #[serde(flatten)]
+    pub outcome: MockBatchOutcome,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum MockBatchOutcome {
+    Created { id: String },
+    Invalid { error: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MockBatchResult {
+    pub created: usize,
+    pub invalid: usize,
+    pub results: Vec<MockBatchItem>,
+}
+
+impl MockBatchRule {
+    fn into_create_rule(self, defaults: &MockBatchDefaults) -> Result<CreateMockRule, String> {
+        let method = self
+            .method
+            .map(|method| {
+                http::Method::from_bytes(method.to_uppercase().as_bytes())
+                    .map(|m| m.to_string())
+                    .map_err(|_| format!("Invalid method '{}'", method))
+            })
+            .transpose()?;
+
+        let url_match_type = self
+            .url_match_type
+            .unwrap_or_else(|| defaults.url_match_type.clone());
+        if matches!(url_match_type, MatchType::Regex) {
+            regex::Regex::new(&self.url_pattern).map_err(|e| format!("Invalid regex: {}", e))?;
+        }
+
+        let name = self.name.unwrap_or_else(|| {
+            format!(
+                "{} {}",
+                method.as_deref().unwrap_or("ANY"),
+                self.url_pattern
+            )
+        });
+
+        Ok(CreateMockRule {
+            name,
+            enabled: self.enabled.unwrap_or(defaults.enabled),
+            priority: self.priority.unwrap_or(defaults.priority),
+            method,
+            url_pattern: self.url_pattern,
+            url_match_type,
+            match_query: true,
+            body_pattern: None,
+            body_match_type: None,
+            normalize_json_body: false,
+            query_matches: Vec::new(),
+            header_matches: Vec::new(),
+            response: self.response,
+            content_responses: Vec::new(),
+            variants: Vec::new(),
+            hash_by: None,
+            responses: Vec::new(),
+            sequence: None,
+            fail_first: None,
+            delay_ms: self.delay_ms.or(defaults.delay_ms),
+            delay_jitter_ms: None,
+            merge_matching: false,
+            schedule: ActiveWindow::default(),
+        })
+    }
+}
+
+// Fallback response served when no other rule matches
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum MatchType {
-    Exact,
-    Contains,
-    Regex,
-    StartsWith,
-    EndsWith,
+pub struct SetDefaultMockRule {
+    pub response: MockResponse,
+    pub delay_ms: Option<u64>,
 }
 
+pub const DEFAULT_RULE_ID: &str = "default";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MockResponse {
     pub status: u16,
     pub headers: HashMap<String, String>,
     pub body: String,
+    // Send `body` as a framed gRPC-Web message with trailers
+    #[serde(default)]
+    pub grpc_web: Option<GrpcWebResponse>,
+    // Fetch the body from this URL on first use instead of using `body`
+    #[serde(default)]
+    pub body_url: Option<String>,
+    // Send the body in chunks without a Content-Length
+    #[serde(default)]
+    pub chunked: Option<ChunkedDelivery>,
+    // Expand `{{request.path}}`-style placeholders in `body`, see `render`
+    #[serde(default)]
+    pub templated: bool,
 }
 
-#[derive(Clone)]
-pub struct MockManager {
-    rules: Arc<RwLock<HashMap<String, MockRule>>>,
+/// Chunked delivery of a mock body. Also used with default settings when the mock's
+/// headers declare `Transfer-Encoding: chunked`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChunkedDelivery {
+    pub chunk_size: usize,
+    pub chunk_delay_ms: u64,
 }
 
-impl MockManager {
-    pub fn new() -> Self {
+impl Default for ChunkedDelivery {
+    fn default() -> Self {
         Self {
-            rules: Arc::new(RwLock::new(HashMap::new())),
+            chunk_size: 1024,
+            chunk_delay_ms: 0,
         }
     }
+}
 
-    pub fn add_rule(&self, create_rule: CreateMockRule) -> String {
-        let id = Uuid::new_v4().to_string();
+impl MockResponse {
+    /// How to chunk this response, if it should be chunked at all
+    pub fn chunking(&self) -> Option<ChunkedDelivery> {
+        self.chunked.clone().or_else(|| {
+            self.headers
+                .iter()
+                .any(|(name, value)| {
+                    name.eq_ignore_ascii_case("transfer-encoding")
+                        && value.to_ascii_lowercase().contains("chunked")
+                })
+                .then(ChunkedDelivery::default)
+        })
+    }
 
-        let rule = MockRule {
-            id: id.clone(),
+    /// Content-Length and Transfer-Encoding follow from how the body is sent, so the
+    /// values declared on the mock are never copied to the response
+    pub fn is_framing_header(name: &str) -> bool {
+        name.eq_ignore_ascii_case("content-length")
+            || name.eq_ignore_ascii_case("transfer-encoding")
+    }
+
+    /// Expand the body's placeholders against the request when the response is templated
+    pub fn render(mut self, request: &MockRequest) -> Self {
+        if self.templated {
+            self.body = render_template(&self.body, request);
+        }
+        self
+    }
+
+    /// The response with the bytes to send for it, framed when it's a gRPC-Web mock. A
+    /// body that can't be framed turns into a 500 describing the error, so the client
+    /// sees why the mock failed.
+    pub fn encode(self) -> (Self, Vec<u8>) {
+        let Some(ref grpc_web) = self.grpc_web else {
+            let body = self.body.clone().into_bytes();
+            return (self, body);
+        };
+
+        match grpc_web.encode(&self.body) {
+            Ok(body) => (self, body),
+            Err(e) => {
+                let response = MockResponse {
+                    status: 500,
+                    headers: HashMap::from([(
+                        "content-type".to_string(),
+                        "text/plain".to_string(),
+                    )]),
+                    body: format!("Mock gRPC-Web response: {}", e),
+                    grpc_web: None,
+                    body_url: None,
+                    chunked: None,
+                    templated: false,
+                };
+                let body = response.body.clone().into_bytes();
+                (response, body)
+            }
+        }
+    }
+}
+
+// Fails the first `count` calls with `failure`, e.g. to exercise client retries; reset
+// along with the sequence
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailFirst {
+    pub count: u32,
+    pub failure: MockResponse,
+}
+
+// One of a rule's `responses`, served with probability weight / total weight
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightedResponse {
+    pub weight: u32,
+    pub response: MockResponse,
+}
+
+// Alternative response served when the request's Accept header prefers `content_type`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentTypeResponse {
+    pub content_type: String,
+    pub response: MockResponse,
+}
+
+impl MockRule {
+    /// Delay to apply for one request: `delay_ms` plus a fresh random jitter
+    pub fn effective_delay_ms(&self) -> Option<u64> {
+        match (self.delay_ms, self.delay_jitter_ms) {
+            (None, None) => None,
+            (delay, jitter) => {
+                let jitter = jitter
+                    .filter(|&j| j > 0)
+                    .map_or(0, |j| rand::thread_rng().gen_range(0..=j));
+                Some(delay.unwrap_or(0) + jitter)
+            }
+        }
+    }
+
+    /// Pick the response for a request. With `variants` and `hash_by`, the hashed request
+    /// attribute chooses the variant, so the same client always gets the same one; requests
+    /// without the attribute fall through. Next, `responses` are drawn from at random by
+    /// weight. Otherwise rules without `content_responses` serve `response`, and with them
+    /// the best match for the Accept header by quality wins, defaulting to the first entry
+    /// when nothing matches.
+    pub fn select_response(&self, request: &MockRequest, rng: &mut impl Rng) -> MockResponse {
+        let variant = self
+            .hash_by
+            .as_ref()
+            .filter(|_| !self.variants.is_empty())
+            .and_then(|hash_by| hash_by.value(request))
+            .map(|value| {
+                &self.variants[(stable_hash(&value) % self.variants.len() as u64) as usize]
+            });
+
+        if let Some(response) = variant {
+            return with_grpc_content_type(response.clone());
+        }
+
+        if let Some(response) = pick_weighted(&self.responses, rng) {
+            return with_grpc_content_type(response.clone());
+        }
+
+        let Some(first) = self.content_responses.first() else {
+            return with_grpc_content_type(self.response.clone());
+        };
+
+        let accept = request.headers.get("accept").map(String::as_str);
+
+        let selected = accept
+            .and_then(|accept| {
+                parse_accept(accept).into_iter().find_map(|media_range| {
+                    self.content_responses
+                        .iter()
+                        .find(|candidate| media_type_matches(&media_range, &candidate.content_type))
+                })
+            })
+            .unwrap_or(first);
+
+        let mut response = selected.response.clone();
+        let content_type = match response.grpc_web {
+            Some(ref grpc_web) => grpc_web.content_type().to_string(),
+            None => selected.content_type.clone(),
+        };
+        response
+            .headers
+            .entry("content-type".to_string())
+            .or_insert(content_type);
+        response
+    }
+}
+
+/// Request details the mock serving paths pass in when picking a response
+pub struct MockRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    // Header names are lowercase
+    pub headers: &'a HashMap<String, String>,
+    pub query: Option<&'a str>,
+    pub client_ip: Option<&'a str>,
+}
+
+/// Request attribute hashed to pick a mock variant, e.g. `{"header": "x-user-id"}`,
+/// `{"query": "user"}` or `"ip"`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashBy {
+    Header(String),
+    Query(String),
+    Ip,
+}
+
+impl HashBy {
+    fn value(&self, request: &MockRequest) -> Option<String> {
+        match self {
+            HashBy::Header(name) => request.headers.get(&name.to_ascii_lowercase()).cloned(),
+            HashBy::Query(name) => request.query.and_then(|query| {
+                url::form_urlencoded::parse(query.as_bytes())
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.into_owned())
+            }),
+            HashBy::Ip => request.client_ip.map(str::to_string),
+        }
+    }
+}
+
+/// Whether the request body satisfies the rule's `body_pattern`. With
+/// `normalize_json_body` both sides are canonicalized as JSON first (the pattern only for
+/// non-regex matches), so key order and whitespace in JSON payloads don't matter.
+fn body_matches(rule: &MockRule, body: Option<&[u8]>) -> bool {
+    let Some(ref pattern) = rule.body_pattern else {
+        return true;
+    };
+    let Some(body) = body else {
+        return false;
+    };
+
+    let body = String::from_utf8_lossy(body);
+    let match_type = rule
+        .body_match_type
+        .as_ref()
+        .unwrap_or(&MatchType::Contains);
+    if !rule.normalize_json_body {
+        return match_type.matches(pattern, &body);
+    }
+
+    let body = normalize_json_body(&body);
+    let pattern = match match_type {
+        MatchType::Regex => Cow::Borrowed(pattern.as_str()),
+        _ => normalize_json_body(pattern),
+    };
+    match_type.matches(&pattern, &body)
+}
+
+/// Expand the placeholders of a templated body: `{{request.method}}`, `{{request.path}}`,
+/// `{{query.<name>}}` and `{{header.<name>}}`. A missing query parameter or header
+/// expands to nothing; unknown placeholders are left as written. Values are inserted
+/// as-is, without JSON escaping.
+fn render_template(template: &str, request: &MockRequest) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(len) = rest.find("}}") else {
+            break;
+        };
+        let placeholder = &rest[..len + 2];
+        let name = placeholder[2..placeholder.len() - 2].trim();
+        match template_value(name, request) {
+            Some(value) => rendered.push_str(&value),
+            None => {
+                log::debug!("Unknown mock template placeholder {}", placeholder);
+                rendered.push_str(placeholder);
+            }
+        }
+        rest = &rest[len + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+fn template_value(name: &str, request: &MockRequest) -> Option<String> {
+    if let Some(param) = name.strip_prefix("query.") {
+        return Some(
+            HashBy::Query(param.to_string())
+                .value(request)
+                .unwrap_or_default(),
+        );
+    }
+    if let Some(header) = name.strip_prefix("header.") {
+        return Some(
+            HashBy::Header(header.to_string())
+                .value(request)
+                .unwrap_or_default(),
+        );
+    }
+    match name {
+        "request.method" => Some(request.method.to_string()),
+        "request.path" => Some(request.path.to_string()),
+        _ => None,
+    }
+}
+
+/// Weighted random pick; all-zero weights fall back to the first response
+fn pick_weighted<'a>(
+    responses: &'a [WeightedResponse],
+    rng: &mut impl Rng,
+) -> Option<&'a MockResponse> {
+    let first = responses.first()?;
+    let total: u64 = responses.iter().map(|r| u64::from(r.weight)).sum();
+    if total == 0 {
+        return Some(&first.response);
+    }
+
+    let mut roll = rng.gen_range(0..total);
+    for weighted in responses {
+        let weight = u64::from(weighted.weight);
+        if roll < weight {
+            return Some(&weighted.response);
+        }
+        roll -= weight;
+    }
+    Some(&first.response)
+}
+
+/// FNV-1a, stable across runs and builds unlike the std hasher
+fn stable_hash(value: &str) -> u64 {
+    value.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn with_grpc_content_type(mut response: MockResponse) -> MockResponse {
+    if let Some(ref grpc_web) = response.grpc_web {
+        response
+            .headers
+            .entry("content-type".to_string())
+            .or_insert_with(|| grpc_web.content_type().to_string());
+    }
+    response
+}
+
+/// Media ranges from an Accept header, most preferred first (q=0 entries dropped)
+fn parse_accept(accept: &str) -> Vec<String> {
+    let mut ranges: Vec<(String, f32)> = accept
+        .split(',')
+        .filter_map(|part| {
+            let mut params = part.split(';').map(str::trim);
+            let media_range = params.next()?.to_lowercase();
+            if media_range.is_empty() {
+                return None;
+            }
+            let quality = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((media_range, quality))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+
+    // Stable sort keeps header order for equal quality
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges.into_iter().map(|(range, _)| range).collect()
+}
+
+fn media_type_matches(media_range: &str, content_type: &str) -> bool {
+    let content_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+
+    match media_range.split_once('/') {
+        Some(("*", "*")) => true,
+        Some((range_type, "*")) => content_type
+            .split_once('/')
+            .is_some_and(|(ty, _)| ty == range_type),
+        _ => media_range == content_type,
+    }
+}
+
+impl From<CreateMockRule> for MockRule {
+    /// A new rule with a fresh id
+    fn from(create_rule: CreateMockRule) -> Self {
+        MockRule {
+            id: Uuid::new_v4().to_string(),
             name: create_rule.name,
             enabled: create_rule.enabled,
             priority: create_rule.priority,
             method: create_rule.method,
             url_pattern: create_rule.url_pattern,
             url_match_type: create_rule.url_match_type,
+            match_query: create_rule.match_query,
+            body_pattern: create_rule.body_pattern,
+            body_match_type: create_rule.body_match_type,
+            normalize_json_body: create_rule.normalize_json_body,
+            query_matches: create_rule.query_matches,
+            header_matches: create_rule.header_matches,
             response: create_rule.response,
+            content_responses: create_rule.content_responses,
+            variants: create_rule.variants,
+            hash_by: create_rule.hash_by,
+            responses: create_rule.responses,
+            sequence: create_rule.sequence,
+            fail_first: create_rule.fail_first,
             delay_ms: create_rule.delay_ms,
+            delay_jitter_ms: create_rule.delay_jitter_ms,
+            merge_matching: create_rule.merge_matching,
+            schedule: create_rule.schedule.resolve(),
             created_at: chrono::Utc::now(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MockManager {
+    rules: Arc<RwLock<HashMap<String, MockRule>>>,
+    default_rule: Arc<RwLock<Option<MockRule>>>,
+    // Bodies fetched for `body_url`, keyed by URL
+    fixtures: Arc<RwLock<HashMap<String, String>>>,
+    client: reqwest::Client,
+    // Draws weighted `responses`; seeded with --mock-seed for reproducible runs
+    rng: Arc<Mutex<StdRng>>,
+    // Next `sequence` position of each rule that has served one
+    sequence_positions: Arc<RwLock<HashMap<String, Arc<AtomicUsize>>>>,
+    // `fail_first` failures served so far by each rule that has one
+    fail_first_calls: Arc<RwLock<HashMap<String, Arc<AtomicUsize>>>>,
+}
+
+impl MockManager {
+    pub fn new() -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(HashMap::new())),
+            default_rule: Arc::new(RwLock::new(None)),
+            fixtures: Arc::new(RwLock::new(HashMap::new())),
+            client: reqwest::Client::new(),
+            rng: Arc::new(Mutex::new(StdRng::from_entropy())),
+            sequence_positions: Arc::new(RwLock::new(HashMap::new())),
+            fail_first_calls: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Make weighted response selection deterministic
+    pub fn with_seed(self, seed: u64) -> Self {
+        *self.rng.lock() = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Pick the response `rule` serves for this request. A rule with `fail_first` serves
+    /// its failure until it has done so `count` times. A rule with a `sequence` serves
+    /// its next entry and advances its counter; only the rule that actually serves the
+    /// request advances, so a sequence rule shadowed by a higher-priority match keeps its
+    /// place. Other rules go through `MockRule::select_response`.
+    pub fn select_response(&self, rule: &MockRule, request: &MockRequest) -> MockResponse {
+        if let Some(ref fail_first) = rule.fail_first {
+            let calls = counter(&self.fail_first_calls, &rule.id);
+            // Stop counting at `count` so the failures never come back around
+            let count = fail_first.count as usize;
+            if calls
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                    (n < count).then_some(n + 1)
+                })
+                .is_ok()
+            {
+                return with_grpc_content_type(fail_first.failure.clone());
+            }
+        }
+        if let Some(sequence) = rule.sequence.as_ref().filter(|s| !s.is_empty()) {
+            let position = counter(&self.sequence_positions, &rule.id);
+            let index = position.fetch_add(1, Ordering::Relaxed) % sequence.len();
+            return with_grpc_content_type(sequence[index].clone());
+        }
+        rule.select_response(request, &mut *self.rng.lock())
+    }
+
+    /// Start a rule's sequence from its first response, and its `fail_first` failures
+    /// from zero, again; false for an unknown rule
+    pub fn reset_sequence(&self, id: &str) -> bool {
+        if !self.rules.read().contains_key(id) {
+            return false;
+        }
+        self.forget_counters(id);
+        true
+    }
+
+    fn forget_counters(&self, id: &str) {
+        self.sequence_positions.write().remove(id);
+        self.fail_first_calls.write().remove(id);
+    }
+
+    /// Fill in the body of a `body_url` response, fetching it on first use. A failed fetch
+    /// turns into a 502 describing the error so the broken fixture is obvious.
+    pub async fn resolve_body(&self, mut response: MockResponse) -> MockResponse {
+        let Some(url) = response.body_url.clone() else {
+            return response;
         };
 
+        let cached = self.fixtures.read().get(&url).cloned();
+        let body = match cached {
+            Some(body) => Ok(body),
+            None => self.fetch_fixture(&url).await,
+        };
+
+        match body {
+            Ok(body) => {
+                response.body = body;
+                response
+            }
+            Err(e) => MockResponse {
+                status: 502,
+                headers: HashMap::from([("content-type".to_string(), "text/plain".to_string())]),
+                body: format!("Failed to fetch mock body from {}: {}", url, e),
+                grpc_web: None,
+                body_url: None,
+                chunked: None,
+                templated: false,
+            },
+        }
+    }
+
+    /// Re-fetch every `body_url` of a rule, in any response slot `select_response` can
+    /// pick. Returns None when the rule doesn't exist.
+    pub async fn refresh_fixtures(&self, id: &str) -> Option<Result<usize, String>> {
+        let rule = self.get_rule(id)?;
+        let mut urls: Vec<String> = std::iter::once(&rule.response)
+            .chain(rule.content_responses.iter().map(|c| &c.response))
+            .chain(rule.variants.iter())
+            .chain(rule.responses.iter().map(|r| &r.response))
+            .chain(rule.sequence.iter().flatten())
+            .chain(rule.fail_first.iter().map(|f| &f.failure))
+            .filter_map(|response| response.body_url.clone())
+            .collect();
+        urls.sort();
+        urls.dedup();
+
+        for url in &urls {
+            if let Err(e) = self.fetch_fixture(url).await {
+                return Some(Err(format!("{}: {}", url, e)));
+            }
+        }
+        Some(Ok(urls.len()))
+    }
+
+    async fn fetch_fixture(&self, url: &str) -> Result<String, String> {
+        let body = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?
+            .text()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        self.fixtures.write().insert(url.to_string(), body.clone());
+        Ok(body)
+    }
+
+    pub fn add_rule(&self, create_rule: CreateMockRule) -> String {
+        let rule = MockRule::from(create_rule);
+        let id = rule.id.clone();
+
         let mut rules = self.rules.write();
         rules.insert(id.clone(), rule);
 
@@ -108,10 +881,27 @@ impl MockManager {
                 method: update_rule.method,
                 url_pattern: update_rule.url_pattern,
                 url_match_type: update_rule.url_match_type,
+                match_query: update_rule.match_query,
+                body_pattern: update_rule.body_pattern,
+                body_match_type: update_rule.body_match_type,
+                normalize_json_body: update_rule.normalize_json_body,
+                query_matches: update_rule.query_matches,
+                header_matches: update_rule.header_matches,
                 response: update_rule.response,
+                content_responses: update_rule.content_responses,
+                variants: update_rule.variants,
+                hash_by: update_rule.hash_by,
+                responses: update_rule.responses,
+                sequence: update_rule.sequence,
+                fail_first: update_rule.fail_first,
                 delay_ms: update_rule.delay_ms,
+                delay_jitter_ms: update_rule.delay_jitter_ms,
+                merge_matching: update_rule.merge_matching,
+                schedule: update_rule.schedule.resolve(),
                 created_at: existing.created_at, // Keep original creation time
             };
+            // The sequence or failure count may have changed, so start them over
+            self.forget_counters(&update_rule.id);
             rules.insert(update_rule.id, rule);
             true
         } else {
@@ -119,8 +909,39 @@ impl MockManager {
         }
     }
 
+    /// Create every valid rule of a batch; invalid ones are reported and skipped
+    pub fn add_batch(&self, batch: MockBatchRequest) -> MockBatchResult {
+        let mut results = Vec::with_capacity(batch.rules.len());
+
+        for (index, value) in batch.rules.into_iter().enumerate() {
+            let outcome = serde_json::from_value::<MockBatchRule>(value)
+                .map_err(|e| format!("Invalid rule: {}", e))
+                .and_then(|rule| rule.into_create_rule(&batch.defaults));
+
+            let outcome = match outcome {
+                Ok(create_rule) => MockBatchOutcome::Created {
+                    id: self.add_rule(create_rule),
+                },
+                Err(error) => MockBatchOutcome::Invalid { error },
+            };
+            results.push(MockBatchItem { index, outcome });
+        }
+
+        let created = results
+            .iter()
+            .filter(|item| matches!(item.outcome, MockBatchOutcome::Created { .. }))
+            .count();
+
+        MockBatchResult {
+            created,
+            invalid: results.len() - created,
+            results,
+        }
+    }
+
     pub fn delete_rule(&self, id: &str) -> bool {
         let mut rules = self.rules.write();
+        self.forget_counters(id);
         rules.remove(id).is_some()
     }
 
@@ -136,21 +957,150 @@ impl MockManager {
         all_rules
     }
 
-    pub fn find_matching_rule(&self, method: &str, url: &str) -> Option<MockRule> {
+    /// How every rule was evaluated against a request, highest priority first
+    pub fn explain(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &HashMap<String, String>,
+        body: Option<&[u8]>,
+    ) -> Vec<RuleTrace> {
+        let rules = self.rules.read();
+        let mut all_rules: Vec<&MockRule> = rules.values().collect();
+        all_rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+        all_rules
+            .into_iter()
+            .map(|rule| {
+                let mut trace = RuleCheck {
+                    id: &rule.id,
+                    name: &rule.name,
+                    priority: rule.priority,
+                    enabled: rule.enabled,
+                    schedule: &rule.schedule,
+                    method: rule.method.as_deref(),
+                    url_pattern: &rule.url_pattern,
+                    url_match_type: &rule.url_match_type,
+                    match_query: rule.match_query,
+                }
+                .trace(method, url);
+                if let Some(reason) =
+                    unmet_conditions(&rule.query_matches, &rule.header_matches, url, headers)
+                        .filter(|_| trace.matched)
+                {
+                    trace.matched = false;
+                    trace.reason = reason;
+                }
+                if trace.matched && !body_matches(rule, body) {
+                    trace.matched = false;
+                    trace.reason = format!(
+                        "body doesn't match pattern {}",
+                        rule.body_pattern.as_deref().unwrap_or_default()
+                    );
+                }
+                trace
+            })
+            .collect()
+    }
+
+    /// Reassign priorities so rules follow the order of `ids`, see `reorder_priorities`
+    pub fn reorder(&self, ids: Vec<String>) {
+        reorder_priorities(&mut self.rules.write(), ids, |rule| &mut rule.priority);
+    }
+
+    /// All enabled, active rules matching the request, highest priority first. `body` is
+    /// None when the body isn't available, in which case rules with a `body_pattern` are
+    /// left out.
+    pub fn find_matching_rules(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &HashMap<String, String>,
+        body: Option<&[u8]>,
+    ) -> Vec<MockRule> {
         let rules = self.rules.read();
         let mut matching_rules: Vec<_> = rules
             .values()
-            .filter(|rule| rule.enabled && self.matches(rule, method, url))
+            .filter(|rule| {
+                rule.enabled
+                    && rule.schedule.is_active()
+                    && self.matches(rule, method, url, headers)
+                    && body_matches(rule, body)
+            })
             .cloned()
             .collect();
 
         // Sort by priority (higher first)
         matching_rules.sort_by(|a, b| b.priority.cmp(&a.priority));
+        matching_rules
+    }
+
+    pub fn find_matching_rule(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &HashMap<String, String>,
+        body: Option<&[u8]>,
+    ) -> Option<MockRule> {
+        let matching_rules = self.find_matching_rules(method, url, headers, body);
+
+        if let Some(merged) = merge_rules(&matching_rules) {
+            return Some(merged);
+        }
+
+        // Fall back to the default rule, if one is set
+        matching_rules
+            .into_iter()
+            .next()
+            .or_else(|| self.default_rule.read().clone())
+    }
+
+    pub fn set_default_rule(&self, default_rule: SetDefaultMockRule) {
+        let rule = MockRule {
+            id: DEFAULT_RULE_ID.to_string(),
+            name: "Default response".to_string(),
+            enabled: true,
+            priority: i32::MIN,
+            method: None,
+            url_pattern: String::new(),
+            url_match_type: MatchType::Contains,
+            match_query: true,
+            body_pattern: None,
+            body_match_type: None,
+            normalize_json_body: false,
+            query_matches: Vec::new(),
+            header_matches: Vec::new(),
+            response: default_rule.response,
+            content_responses: Vec::new(),
+            variants: Vec::new(),
+            hash_by: None,
+            responses: Vec::new(),
+            sequence: None,
+            fail_first: None,
+            delay_ms: default_rule.delay_ms,
+            delay_jitter_ms: None,
+            merge_matching: false,
+            schedule: ActiveWindow::default(),
+            created_at: chrono::Utc::now(),
+        };
+
+        *self.default_rule.write() = Some(rule);
+    }
 
-        matching_rules.into_iter().next()
+    pub fn get_default_rule(&self) -> Option<MockRule> {
+        self.default_rule.read().clone()
     }
 
-    fn matches(&self, rule: &MockRule, method: &str, url: &str) -> bool {
+    pub fn clear_default_rule(&self) -> bool {
+        self.default_rule.write().take().is_some()
+    }
+
+    fn matches(
+        &self,
+        rule: &MockRule,
+        method: &str,
+        url: &str,
+        headers: &HashMap<String, String>,
+    ) -> bool {
         // Check method
         if let Some(ref rule_method) = rule.method {
             if rule_method != method {
@@ -158,20 +1108,11 @@ impl MockManager {
             }
         }
 
-        // Check URL pattern
-        match rule.url_match_type {
-            MatchType::Exact => url == rule.url_pattern,
-            MatchType::Contains => url.contains(&rule.url_pattern),
-            MatchType::StartsWith => url.starts_with(&rule.url_pattern),
-            MatchType::EndsWith => url.ends_with(&rule.url_pattern),
-            MatchType::Regex => {
-                if let Ok(re) = Regex::new(&rule.url_pattern) {
-                    re.is_match(url)
-                } else {
-                    false
-                }
-            }
-        }
+        // Check URL pattern, then any query and header conditions
+        rule.url_match_type
+            .matches_url(&rule.url_pattern, url, rule.match_query)
+            && unmet_query_condition(&rule.query_matches, url).is_none()
+            && unmet_header_condition(&rule.header_matches, headers).is_none()
     }
 
     pub fn toggle_rule(&self, id: &str) -> bool {
@@ -187,5 +1128,188 @@ impl MockManager {
     pub fn clear_all(&self) {
         let mut rules = self.rules.write();
         rules.clear();
+        self.sequence_positions.write().clear();
+        self.fail_first_calls.write().clear();
+        *self.default_rule.write() = None;
+    }
+
+    /// Replace every rule, including the default, e.g. when restoring a snapshot
+    pub fn replace_all(&self, new_rules: Vec<MockRule>, default_rule: Option<MockRule>) {
+        let mut rules = self.rules.write();
+        *rules = new_rules
+            .into_iter()
+            .map(|rule| (rule.id.clone(), rule))
+            .collect();
+        self.sequence_positions.write().clear();
+        self.fail_first_calls.write().clear();
+        *self.default_rule.write() = default_rule;
+    }
+}
+
+/// Per-rule counter from `counters`, created at zero on first use
+fn counter(counters: &RwLock<HashMap<String, Arc<AtomicUsize>>>, id: &str) -> Arc<AtomicUsize> {
+    if let Some(counter) = counters.read().get(id) {
+        return counter.clone();
+    }
+    counters.write().entry(id.to_string()).or_default().clone()
+}
+
+/// Deep-merge the responses of `rules` (highest priority first) when the lowest-priority one
+/// opted in with `merge_matching`. Status and delays come from that base rule; headers and
+/// JSON bodies are layered on top of it in priority order, so higher priority wins conflicts.
+/// Returns None to use the single winning rule when there's nothing to merge or a body isn't
+/// plain JSON.
+fn merge_rules(rules: &[MockRule]) -> Option<MockRule> {
+    let base = rules
+        .last()
+        .filter(|base| base.merge_matching && rules.len() > 1)?;
+
+    let mut merged = base.clone();
+    let mut body = serde_json::Value::Null;
+    for rule in rules.iter().rev() {
+        let response = &rule.response;
+        if !rule.content_responses.is_empty()
+            || !rule.variants.is_empty()
+            || !rule.responses.is_empty()
+            || rule.sequence.is_some()
+            || rule.fail_first.is_some()
+            || response.grpc_web.is_some()
+            || response.body_url.is_some()
+        {
+            return None;
+        }
+
+        let fragment = serde_json::from_str(&response.body).ok()?;
+        deep_merge(&mut body, fragment);
+        merged.response.headers.extend(response.headers.clone());
+    }
+
+    merged.response.body = body.to_string();
+    Some(merged)
+}
+
+/// Merge `overlay` into `target`: objects are merged key by key, anything else is replaced
+fn deep_merge(target: &mut serde_json::Value, overlay: serde_json::Value) {
+    match (target, overlay) {
+        (serde_json::Value::Object(target), serde_json::Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                deep_merge(target.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+        (target, overlay) => *target = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn body_rule(pattern: &str, match_type: &str, normalize: bool) -> MockRule {
+        serde_json::from_value(json!({
+            "id": "rule",
+            "name": "rule",
+            "enabled": true,
+            "priority": 0,
+            "method": "POST",
+            "url_pattern": "/graphql",
+            "url_match_type": "exact",
+            "body_pattern": pattern,
+            "body_match_type": match_type,
+            "normalize_json_body": normalize,
+            "response": { "status": 200, "headers": {}, "body": "" },
+            "delay_ms": null,
+            "created_at": "2024-01-01T00:00:00Z",
+        }))
+        .unwrap()
+    }
+
+    const PRETTY: &[u8] = b"{\n  \"b\": 2,\n  \"a\": 1\n}";
+
+    #[test]
+    fn bodies_are_compared_as_sent_by_default() {
+        let rule = body_rule("{\"a\":1,\"b\":2}", "exact", false);
+        assert!(!body_matches(&rule, Some(PRETTY)));
+        assert!(body_matches(&rule, Some(b"{\"a\":1,\"b\":2}")));
+    }
+
+    #[test]
+    fn normalized_json_ignores_formatting_and_key_order() {
+        let rule = body_rule("{\"a\": 1, \"b\": 2}", "exact", true);
+        assert!(body_matches(&rule, Some(PRETTY)));
+
+        let rule = body_rule("\"a\":1", "contains", true);
+        assert!(body_matches(&rule, Some(PRETTY)));
+    }
+
+    #[test]
+    fn normalization_leaves_non_json_bodies_alone() {
+        let rule = body_rule("query { user }", "contains", true);
+        assert!(body_matches(&rule, Some(b"query { user }")));
+        assert!(!body_matches(&rule, Some(b"query {user}")));
+    }
+
+    #[test]
+    fn unframeable_grpc_web_body_is_a_500() {
+        let response = |body: &str| MockResponse {
+            status: 200,
+            headers: HashMap::new(),
+            body: body.to_string(),
+            grpc_web: Some(GrpcWebResponse {
+                format: crate::grpc_web::GrpcWebFormat::Proto,
+                grpc_status: 0,
+                grpc_message: None,
+            }),
+            body_url: None,
+            chunked: None,
+            templated: false,
+        };
+
+        let (framed, body) = response("AAE=").encode();
+        assert_eq!(framed.status, 200);
+        assert_eq!(&body[..7], &[0, 0, 0, 0, 2, 0, 1]);
+
+        let (failed, body) = response("not base64!").encode();
+        assert_eq!(failed.status, 500);
+        assert!(failed.grpc_web.is_none());
+        assert!(String::from_utf8(body).unwrap().contains("Invalid base64"));
+    }
+
+    #[tokio::test]
+    async fn refresh_fixtures_covers_variant_bodies() {
+        // Answers every request with how many it has served so far
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            for served in 1.. {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 1024];
+                let _ = socket.read(&mut request).await;
+                let body = served.to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let mut rule = body_rule("", "contains", false);
+        rule.variants = vec![MockResponse {
+            body_url: Some(format!("http://{}/fixture.json", addr)),
+            ..rule.response.clone()
+        }];
+        rule.hash_by = Some(HashBy::Ip);
+        let manager = MockManager::new();
+        manager.rules.write().insert(rule.id.clone(), rule.clone());
+
+        let variant = rule.variants[0].clone();
+        assert_eq!(manager.resolve_body(variant.clone()).await.body, "1");
+        assert_eq!(manager.resolve_body(variant.clone()).await.body, "1");
+
+        assert_eq!(manager.refresh_fixtures(&rule.id).await, Some(Ok(1)));
+        assert_eq!(manager.resolve_body(variant).await.body, "2");
     }
 }
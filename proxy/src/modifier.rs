@@ -5,6 +5,13 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use uuid::Uuid;
 
+use crate::explain::{RuleCheck, RuleTrace, unmet_conditions};
+pub use crate::matcher::MatchType;
+use crate::matcher::{
+    default_match_query, reorder_priorities, unmet_header_condition, unmet_query_condition,
+};
+use crate::schedule::ActiveWindow;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModifierRule {
     pub id: String,
@@ -13,6 +20,12 @@ pub struct ModifierRule {
     pub priority: i32,
     pub match_request: RequestMatch,
     pub modifications: Vec<Modification>,
+    #[serde(default)]
+    pub apply_to: ModifierTarget,
+    #[serde(default)]
+    pub condition: Option<ModifierCondition>,
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
@@ -23,6 +36,12 @@ pub struct CreateModifierRule {
     pub priority: i32,
     pub match_request: RequestMatch,
     pub modifications: Vec<Modification>,
+    #[serde(default)]
+    pub apply_to: ModifierTarget,
+    #[serde(default)]
+    pub condition: Option<ModifierCondition>,
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,6 +52,46 @@ pub struct UpdateModifierRule {
     pub priority: i32,
     pub match_request: RequestMatch,
     pub modifications: Vec<Modification>,
+    #[serde(default)]
+    pub apply_to: ModifierTarget,
+    #[serde(default)]
+    pub condition: Option<ModifierCondition>,
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
+}
+
+/// Whether a rule's modifications apply to the upstream response (the default) or to the
+/// request before it's proxied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModifierTarget {
+    Request,
+    #[default]
+    Response,
+}
+
+/// Gate on the body a rule's modifications would see, checked after `match_request`
+/// (status codes included) has matched; both must pass
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModifierCondition {
+    BodyContains(String),
+    BodyMatchesRegex(String),
+}
+
+impl ModifierCondition {
+    /// Bodies that aren't UTF-8 never pass
+    pub fn matches(&self, body: &[u8]) -> bool {
+        let Ok(body) = std::str::from_utf8(body) else {
+            return false;
+        };
+        match self {
+            ModifierCondition::BodyContains(text) => body.contains(text.as_str()),
+            ModifierCondition::BodyMatchesRegex(pattern) => {
+                Regex::new(pattern).is_ok_and(|re| re.is_match(body))
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,17 +99,15 @@ pub struct RequestMatch {
     pub method: Option<String>,
     pub url_pattern: String,
     pub url_match_type: MatchType,
+    // Match url_pattern against the whole URL; false leaves the query string out
+    #[serde(default = "default_match_query")]
+    pub match_query: bool,
     pub status_codes: Option<Vec<u16>>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum MatchType {
-    Exact,
-    Contains,
-    Regex,
-    StartsWith,
-    EndsWith,
+    // `(name, value)` pairs that must all be present on the request; `*` matches any value
+    #[serde(default)]
+    pub query_matches: Vec<(String, String)>,
+    #[serde(default)]
+    pub header_matches: Vec<(String, String)>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -80,6 +137,96 @@ pub enum Modification {
         path: String,
         value: serde_json::Value,
     },
+
+    #[serde(rename = "coerce_json")]
+    CoerceJson { path: String, to_type: JsonType },
+
+    #[serde(rename = "delete_json")]
+    DeleteJson { path: String },
+
+    /// Push `value` onto the array at `path`; anything else at the path is left as is
+    #[serde(rename = "append_json")]
+    AppendJson {
+        path: String,
+        value: serde_json::Value,
+    },
+
+    /// Regex replace on the request path before it goes upstream. `$1`-style references
+    /// in the replacement pick up capture groups; the query string is left alone.
+    #[serde(rename = "rewrite_url")]
+    RewriteUrl {
+        pattern: String,
+        replacement: String,
+    },
+
+    /// The canonical fix for mislabeled responses. Applied after every other modification
+    /// of every matching rule, replacing any `content-type` header regardless of its case.
+    #[serde(rename = "set_content_type")]
+    SetContentType { value: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonType {
+    String,
+    Number,
+    Boolean,
+}
+
+impl JsonType {
+    /// Convert an existing JSON value to this type, or None when it can't be converted
+    fn coerce(&self, value: &serde_json::Value) -> Option<serde_json::Value> {
+        use serde_json::Value;
+
+        match (self, value) {
+            (JsonType::String, Value::String(_))
+            | (JsonType::Number, Value::Number(_))
+            | (JsonType::Boolean, Value::Bool(_)) => Some(value.clone()),
+
+            (JsonType::String, Value::Number(n)) => Some(Value::String(n.to_string())),
+            (JsonType::String, Value::Bool(b)) => Some(Value::String(b.to_string())),
+
+            (JsonType::Number, Value::String(s)) => {
+                let s = s.trim();
+                if let Ok(n) = s.parse::<i64>() {
+                    Some(Value::from(n))
+                } else {
+                    s.parse::<f64>()
+                        .ok()
+                        .and_then(serde_json::Number::from_f64)
+                        .map(Value::Number)
+                }
+            }
+            (JsonType::Number, Value::Bool(b)) => Some(Value::from(*b as i64)),
+
+            (JsonType::Boolean, Value::String(s)) => match s.trim().to_lowercase().as_str() {
+                "true" | "1" => Some(Value::Bool(true)),
+                "false" | "0" => Some(Value::Bool(false)),
+                _ => None,
+            },
+            (JsonType::Boolean, Value::Number(n)) => n.as_f64().map(|n| Value::Bool(n != 0.0)),
+
+            _ => None,
+        }
+    }
+}
+
+impl From<CreateModifierRule> for ModifierRule {
+    /// A new rule with a fresh id
+    fn from(create_rule: CreateModifierRule) -> Self {
+        ModifierRule {
+            id: Uuid::new_v4().to_string(),
+            name: create_rule.name,
+            enabled: create_rule.enabled,
+            priority: create_rule.priority,
+            match_request: create_rule.match_request,
+            modifications: create_rule.modifications,
+            apply_to: create_rule.apply_to,
+            condition: create_rule.condition,
+            schedule: create_rule.schedule.resolve(),
+            created_at: chrono::Utc::now(),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -95,17 +242,8 @@ impl ResponseModifier {
     }
 
     pub fn add_rule(&self, create_rule: CreateModifierRule) -> String {
-        let id = Uuid::new_v4().to_string();
-
-        let rule = ModifierRule {
-            id: id.clone(),
-            name: create_rule.name,
-            enabled: create_rule.enabled,
-            priority: create_rule.priority,
-            match_request: create_rule.match_request,
-            modifications: create_rule.modifications,
-            created_at: chrono::Utc::now(),
-        };
+        let rule = ModifierRule::from(create_rule);
+        let id = rule.id.clone();
 
         let mut rules = self.rules.write();
         rules.insert(id.clone(), rule);
@@ -123,6 +261,9 @@ impl ResponseModifier {
                 priority: update_rule.priority,
                 match_request: update_rule.match_request,
                 modifications: update_rule.modifications,
+                apply_to: update_rule.apply_to,
+                condition: update_rule.condition,
+                schedule: update_rule.schedule.resolve(),
                 created_at: existing.created_at,
             };
             rules.insert(update_rule.id, rule);
@@ -149,16 +290,85 @@ impl ResponseModifier {
         all_rules
     }
 
+    /// How every rule was evaluated against a request, highest priority first
+    pub fn explain(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &HashMap<String, String>,
+        status: Option<u16>,
+    ) -> Vec<RuleTrace> {
+        let rules = self.rules.read();
+        let mut all_rules: Vec<&ModifierRule> = rules.values().collect();
+        all_rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+        all_rules
+            .into_iter()
+            .map(|rule| {
+                let mut trace = RuleCheck {
+                    id: &rule.id,
+                    name: &rule.name,
+                    priority: rule.priority,
+                    enabled: rule.enabled,
+                    schedule: &rule.schedule,
+                    method: rule.match_request.method.as_deref(),
+                    url_pattern: &rule.match_request.url_pattern,
+                    url_match_type: &rule.match_request.url_match_type,
+                    match_query: rule.match_request.match_query,
+                }
+                .trace(method, url);
+                if let Some(reason) = unmet_conditions(
+                    &rule.match_request.query_matches,
+                    &rule.match_request.header_matches,
+                    url,
+                    headers,
+                )
+                .filter(|_| trace.matched)
+                {
+                    trace.matched = false;
+                    trace.reason = reason;
+                }
+                // Status-scoped rules only match once the response status is known
+                let status_matches = match (&rule.match_request.status_codes, status) {
+                    (Some(codes), Some(status)) => codes.contains(&status),
+                    (Some(_), None) => false,
+                    (None, _) => true,
+                };
+                if trace.matched && !status_matches {
+                    trace.matched = false;
+                    trace.reason = format!(
+                        "status {} is not in {:?}",
+                        status.map_or("unknown".to_string(), |s| s.to_string()),
+                        rule.match_request
+                            .status_codes
+                            .as_deref()
+                            .unwrap_or_default()
+                    );
+                }
+                trace
+            })
+            .collect()
+    }
+
+    /// Reassign priorities so rules follow the order of `ids`, see `reorder_priorities`
+    pub fn reorder(&self, ids: Vec<String>) {
+        reorder_priorities(&mut self.rules.write(), ids, |rule| &mut rule.priority);
+    }
+
     pub fn find_matching_rules(
         &self,
         method: &str,
         url: &str,
+        headers: &HashMap<String, String>,
         status: Option<u16>,
     ) -> Vec<ModifierRule> {
         let rules = self.rules.read();
         let mut matching_rules: Vec<_> = rules
             .values()
-            .filter(|rule| rule.enabled && self.matches(rule, method, url, status))
+            .filter(|rule| {
+                rule.enabled
+                    && rule.schedule.is_active()
+                    && self.matches(rule, method, url, headers, status)
+            })
             .cloned()
             .collect();
 
@@ -166,7 +376,14 @@ impl ResponseModifier {
         matching_rules
     }
 
-    fn matches(&self, rule: &ModifierRule, method: &str, url: &str, status: Option<u16>) -> bool {
+    fn matches(
+        &self,
+        rule: &ModifierRule,
+        method: &str,
+        url: &str,
+        headers: &HashMap<String, String>,
+        status: Option<u16>,
+    ) -> bool {
         // Check method
         if let Some(ref rule_method) = rule.match_request.method {
             if rule_method != method {
@@ -174,29 +391,21 @@ impl ResponseModifier {
             }
         }
 
-        // Check status codes
+        // Status-scoped rules need a known status in the set; they never match
+        // when the status isn't known yet (request phase)
         if let Some(ref status_codes) = rule.match_request.status_codes {
-            if let Some(status_code) = status {
-                if !status_codes.contains(&status_code) {
-                    return false;
-                }
+            if !status.is_some_and(|status_code| status_codes.contains(&status_code)) {
+                return false;
             }
         }
 
-        // Check URL pattern
-        match rule.match_request.url_match_type {
-            MatchType::Exact => url == rule.match_request.url_pattern,
-            MatchType::Contains => url.contains(&rule.match_request.url_pattern),
-            MatchType::StartsWith => url.starts_with(&rule.match_request.url_pattern),
-            MatchType::EndsWith => url.ends_with(&rule.match_request.url_pattern),
-            MatchType::Regex => {
-                if let Ok(re) = Regex::new(&rule.match_request.url_pattern) {
-                    re.is_match(url)
-                } else {
-                    false
-                }
-            }
-        }
+        // Check URL pattern, then any query and header conditions
+        rule.match_request.url_match_type.matches_url(
+            &rule.match_request.url_pattern,
+            url,
+            rule.match_request.match_query,
+        ) && unmet_query_condition(&rule.match_request.query_matches, url).is_none()
+            && unmet_header_condition(&rule.match_request.header_matches, headers).is_none()
     }
 
     pub fn toggle_rule(&self, id: &str) -> bool {
@@ -209,43 +418,183 @@ impl ResponseModifier {
         }
     }
 
+    /// Replace every rule, e.g. when restoring a snapshot
+    pub fn replace_all(&self, new_rules: Vec<ModifierRule>) {
+        let mut rules = self.rules.write();
+        *rules = new_rules
+            .into_iter()
+            .map(|rule| (rule.id.clone(), rule))
+            .collect();
+    }
+
     pub fn clear_all(&self) {
         let mut rules = self.rules.write();
         rules.clear();
     }
 
+    /// Whether any response rule would touch this response, so its body has to be buffered
+    pub fn has_response_rules(
+        &self,
+        method: &str,
+        url: &str,
+        request_headers: &HashMap<String, String>,
+        status: u16,
+    ) -> bool {
+        self.find_matching_rules(method, url, request_headers, Some(status))
+            .iter()
+            .any(|rule| rule.apply_to == ModifierTarget::Response)
+    }
+
+    /// Apply the `rewrite_url` modifications of matching `apply_to: request` rules whose
+    /// `condition` passes on the request body to the request path, in priority order.
+    /// Returns the new path and query when the path changed.
+    pub fn rewrite_url(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &HashMap<String, String>,
+        body: &[u8],
+    ) -> Option<String> {
+        let (path, query) = match url.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (url, None),
+        };
+
+        let rules = self
+            .find_matching_rules(method, url, headers, None)
+            .into_iter()
+            .filter(|rule| rule.apply_to == ModifierTarget::Request)
+            .filter(|rule| {
+                rule.condition
+                    .as_ref()
+                    .is_none_or(|condition| condition.matches(body))
+            });
+        let mut rewritten = path.to_string();
+        for rule in rules {
+            for modification in &rule.modifications {
+                let Modification::RewriteUrl {
+                    pattern,
+                    replacement,
+                } = modification
+                else {
+                    continue;
+                };
+                if let Ok(re) = Regex::new(pattern) {
+                    rewritten = re.replace_all(&rewritten, replacement.as_str()).to_string();
+                }
+            }
+        }
+
+        if rewritten == path {
+            return None;
+        }
+        Some(match query {
+            Some(query) => format!("{}?{}", rewritten, query),
+            None => rewritten,
+        })
+    }
+
+    /// Apply the header and body modifications of matching `apply_to: request` rules to
+    /// a request before it goes upstream. The Host header is left alone, since the HTTP
+    /// layer sets it for the internal hop. Returns whether the body changed.
+    pub fn apply_request_modifications(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &mut http::HeaderMap,
+        body: &mut Vec<u8>,
+    ) -> bool {
+        let request_headers: HashMap<String, String> = headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let original_body = body.clone();
+
+        let rules = self
+            .find_matching_rules(method, url, &request_headers, None)
+            .into_iter()
+            .filter(|rule| rule.apply_to == ModifierTarget::Request);
+        for rule in rules {
+            // Checked against the body as earlier rules left it
+            if rule
+                .condition
+                .as_ref()
+                .is_some_and(|condition| !condition.matches(body))
+            {
+                continue;
+            }
+
+            for modification in &rule.modifications {
+                match modification {
+                    Modification::AddHeader { name, value } => {
+                        if name.eq_ignore_ascii_case("host") {
+                            log::debug!("Ignoring request modification of the Host header");
+                            continue;
+                        }
+                        if let (Ok(name), Ok(value)) = (
+                            http::header::HeaderName::try_from(name.as_str()),
+                            http::HeaderValue::try_from(value.as_str()),
+                        ) {
+                            headers.insert(name, value);
+                        }
+                    }
+
+                    Modification::RemoveHeader { name } => {
+                        if name.eq_ignore_ascii_case("host") {
+                            log::debug!("Ignoring request modification of the Host header");
+                            continue;
+                        }
+                        headers.remove(name.as_str());
+                    }
+
+                    Modification::ReplaceBody {
+                        pattern,
+                        replacement,
+                        use_regex,
+                    } => replace_body(body, pattern, replacement, *use_regex),
+
+                    // Response-only, and rewrite_url runs separately
+                    _ => {}
+                }
+            }
+        }
+
+        *body != original_body
+    }
+
     pub async fn apply_modifications(
         &self,
         method: &str,
         url: &str,
+        request_headers: &HashMap<String, String>,
         status: u16,
-        headers: &mut std::collections::HashMap<String, String>,
+        headers: &mut HashMap<String, String>,
         body: &mut Vec<u8>,
     ) -> u16 {
-        let rules = self.find_matching_rules(method, url, Some(status));
+        let rules = self
+            .find_matching_rules(method, url, request_headers, Some(status))
+            .into_iter()
+            .filter(|rule| rule.apply_to == ModifierTarget::Response);
         let mut final_status = status;
+        let mut content_type = None;
 
         for rule in rules {
+            // Checked against the body as earlier rules left it
+            if rule
+                .condition
+                .as_ref()
+                .is_some_and(|condition| !condition.matches(body))
+            {
+                continue;
+            }
+
             for modification in &rule.modifications {
                 match modification {
                     Modification::ReplaceBody {
                         pattern,
                         replacement,
                         use_regex,
-                    } => {
-                        if let Ok(body_str) = String::from_utf8(body.clone()) {
-                            let modified = if *use_regex {
-                                if let Ok(re) = Regex::new(pattern) {
-                                    re.replace_all(&body_str, replacement.as_str()).to_string()
-                                } else {
-                                    body_str
-                                }
-                            } else {
-                                body_str.replace(pattern, replacement)
-                            };
-                            *body = modified.into_bytes();
-                        }
-                    }
+                    } => replace_body(body, pattern, replacement, *use_regex),
 
                     Modification::AddHeader { name, value } => {
                         headers.insert(name.clone(), value.clone());
@@ -274,32 +623,6 @@ impl ResponseModifier {
                                     continue;
                                 }
 
-                                // Helper function to set nested value
-                                fn set_nested_value(
-                                    current: &mut serde_json::Value,
-                                    keys: &[&str],
-                                    value: &serde_json::Value,
-                                ) -> bool {
-                                    if keys.is_empty() {
-                                        return false;
-                                    }
-
-                                    if keys.len() == 1 {
-                                        if let Some(obj) = current.as_object_mut() {
-                                            obj.insert(keys[0].to_string(), value.clone());
-                                            return true;
-                                        }
-                                        return false;
-                                    }
-
-                                    // Navigate deeper
-                                    if let Some(next) = current.get_mut(keys[0]) {
-                                        set_nested_value(next, &keys[1..], value)
-                                    } else {
-                                        false
-                                    }
-                                }
-
                                 set_nested_value(&mut json, &path_parts, value);
 
                                 if let Ok(modified_json) = serde_json::to_string(&json) {
@@ -308,10 +631,359 @@ impl ResponseModifier {
                             }
                         }
                     }
+
+                    Modification::CoerceJson { path, to_type } => {
+                        // Leave the body untouched unless the field exists and converts cleanly
+                        let Ok(mut json) = serde_json::from_slice::<serde_json::Value>(body) else {
+                            continue;
+                        };
+
+                        let keys: Vec<&str> = path.split('.').collect();
+                        let Some(target) = json_path_mut(&mut json, &keys) else {
+                            continue;
+                        };
+
+                        if let Some(coerced) = to_type.coerce(target) {
+                            *target = coerced;
+                            if let Ok(modified_json) = serde_json::to_string(&json) {
+                                *body = modified_json.into_bytes();
+                            }
+                        }
+                    }
+
+                    Modification::DeleteJson { path } => {
+                        let Ok(mut json) = serde_json::from_slice::<serde_json::Value>(body) else {
+                            continue;
+                        };
+
+                        let keys: Vec<&str> = path.split('.').collect();
+                        if !delete_nested_value(&mut json, &keys) {
+                            continue;
+                        }
+                        if let Ok(modified_json) = serde_json::to_string(&json) {
+                            *body = modified_json.into_bytes();
+                        }
+                    }
+
+                    Modification::AppendJson { path, value } => {
+                        let Ok(mut json) = serde_json::from_slice::<serde_json::Value>(body) else {
+                            continue;
+                        };
+
+                        let keys: Vec<&str> = path.split('.').collect();
+                        if !append_nested_value(&mut json, &keys, value) {
+                            log::debug!("append_json: no array at {}", path);
+                            continue;
+                        }
+                        if let Ok(modified_json) = serde_json::to_string(&json) {
+                            *body = modified_json.into_bytes();
+                        }
+                    }
+
+                    Modification::SetContentType { value } => {
+                        content_type = Some(value.clone());
+                    }
+
+                    // Applied to the request by `rewrite_url`
+                    Modification::RewriteUrl { .. } => {}
                 }
             }
         }
 
+        if let Some(value) = content_type {
+            headers.retain(|name, _| !name.eq_ignore_ascii_case("content-type"));
+            headers.insert("content-type".to_string(), value);
+        }
+
         final_status
     }
 }
+
+/// Follow a dotted path (`data.users.2.email`) into a JSON value
+fn json_path_mut<'a>(
+    json: &'a mut serde_json::Value,
+    keys: &[&str],
+) -> Option<&'a mut serde_json::Value> {
+    keys.iter()
+        .try_fold(json, |current, key| json_child_mut(current, key))
+}
+
+/// A path segment that's all digits, read as an array index
+fn array_index(key: &str) -> Option<usize> {
+    key.bytes()
+        .all(|b| b.is_ascii_digit())
+        .then(|| key.parse().ok())
+        .flatten()
+}
+
+/// Step one path segment into a JSON value: arrays by index, objects by key
+fn json_child_mut<'a>(
+    current: &'a mut serde_json::Value,
+    key: &str,
+) -> Option<&'a mut serde_json::Value> {
+    match (current, array_index(key)) {
+        (serde_json::Value::Array(items), Some(index)) => {
+            let len = items.len();
+            let child = items.get_mut(index);
+            if child.is_none() {
+                log::debug!("JSON path index {} out of bounds (length {})", index, len);
+            }
+            child
+        }
+        (current, _) => current.get_mut(key),
+    }
+}
+
+/// Set the value at a dotted path. The last segment may add a new object key, but an
+/// array index has to exist already.
+fn set_nested_value(
+    current: &mut serde_json::Value,
+    keys: &[&str],
+    value: &serde_json::Value,
+) -> bool {
+    let Some((last, parents)) = keys.split_last() else {
+        return false;
+    };
+    let Some(parent) = json_path_mut(current, parents) else {
+        return false;
+    };
+
+    match (parent, array_index(last)) {
+        (serde_json::Value::Array(items), Some(index)) => {
+            let len = items.len();
+            match items.get_mut(index) {
+                Some(item) => {
+                    *item = value.clone();
+                    true
+                }
+                None => {
+                    log::debug!("JSON path index {} out of bounds (length {})", index, len);
+                    false
+                }
+            }
+        }
+        (serde_json::Value::Object(obj), _) => {
+            obj.insert(last.to_string(), value.clone());
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Remove the object key or array element at a dotted path; false if it isn't there
+fn delete_nested_value(json: &mut serde_json::Value, keys: &[&str]) -> bool {
+    let Some((last, parents)) = keys.split_last() else {
+        return false;
+    };
+    match (json_path_mut(json, parents), array_index(last)) {
+        (Some(serde_json::Value::Array(items)), Some(index)) if index < items.len() => {
+            items.remove(index);
+            true
+        }
+        (Some(serde_json::Value::Object(obj)), _) => obj.remove(*last).is_some(),
+        _ => false,
+    }
+}
+
+/// Push onto the array at a dotted path; false if there's no array there
+fn append_nested_value(
+    json: &mut serde_json::Value,
+    keys: &[&str],
+    value: &serde_json::Value,
+) -> bool {
+    match json_path_mut(json, keys).and_then(|target| target.as_array_mut()) {
+        Some(array) => {
+            array.push(value.clone());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Replace text in a UTF-8 body; bodies that aren't UTF-8 are left alone
+fn replace_body(body: &mut Vec<u8>, pattern: &str, replacement: &str, use_regex: bool) {
+    if let Ok(body_str) = String::from_utf8(body.clone()) {
+        let modified = if use_regex {
+            if let Ok(re) = Regex::new(pattern) {
+                re.replace_all(&body_str, replacement).to_string()
+            } else {
+                body_str
+            }
+        } else {
+            body_str.replace(pattern, replacement)
+        };
+        *body = modified.into_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn keys(path: &str) -> Vec<&str> {
+        path.split('.').collect()
+    }
+
+    #[test]
+    fn deletes_nested_keys() {
+        let mut body = json!({ "data": { "user": { "name": "a", "email": "b" } } });
+        assert!(delete_nested_value(&mut body, &keys("data.user.email")));
+        assert_eq!(body, json!({ "data": { "user": { "name": "a" } } }));
+    }
+
+    #[test]
+    fn delete_with_missing_intermediate_key_is_a_no_op() {
+        let mut body = json!({ "data": { "user": { "name": "a" } } });
+        let original = body.clone();
+        assert!(!delete_nested_value(&mut body, &keys("data.account.id")));
+        assert!(!delete_nested_value(&mut body, &keys("data.user.email")));
+        assert_eq!(body, original);
+    }
+
+    #[test]
+    fn deletes_array_elements_by_index() {
+        let mut body = json!({ "items": [1, 2, 3] });
+        assert!(delete_nested_value(&mut body, &keys("items.1")));
+        assert_eq!(body, json!({ "items": [1, 3] }));
+        assert!(!delete_nested_value(&mut body, &keys("items.5")));
+    }
+
+    #[test]
+    fn appends_to_nested_arrays() {
+        let mut body = json!({ "data": { "tags": ["a"] } });
+        assert!(append_nested_value(
+            &mut body,
+            &keys("data.tags"),
+            &json!("b")
+        ));
+        assert_eq!(body, json!({ "data": { "tags": ["a", "b"] } }));
+    }
+
+    #[test]
+    fn append_to_a_non_array_or_missing_path_is_a_no_op() {
+        let mut body = json!({ "data": { "tags": "a" } });
+        let original = body.clone();
+        assert!(!append_nested_value(
+            &mut body,
+            &keys("data.tags"),
+            &json!("b")
+        ));
+        assert!(!append_nested_value(
+            &mut body,
+            &keys("data.missing.tags"),
+            &json!("b")
+        ));
+        assert_eq!(body, original);
+    }
+
+    #[test]
+    fn sets_values_inside_arrays_by_index() {
+        let mut body = json!({ "data": { "users": [
+            { "email": "a@x" }, { "email": "b@x" }, { "email": "c@x" }
+        ] } });
+        assert!(set_nested_value(
+            &mut body,
+            &keys("data.users.2.email"),
+            &json!("new@x")
+        ));
+        assert_eq!(body["data"]["users"][2]["email"], "new@x");
+        assert_eq!(body["data"]["users"][0]["email"], "a@x");
+    }
+
+    #[test]
+    fn mixes_object_keys_and_array_indices() {
+        let mut body = json!({ "orders": [{ "lines": [{ "qty": 1 }, { "qty": 2 }] }] });
+        assert!(set_nested_value(
+            &mut body,
+            &keys("orders.0.lines.1.qty"),
+            &json!(5)
+        ));
+        assert_eq!(
+            body,
+            json!({ "orders": [{ "lines": [{ "qty": 1 }, { "qty": 5 }] }] })
+        );
+
+        assert!(set_nested_value(
+            &mut body,
+            &keys("orders.0"),
+            &json!("replaced")
+        ));
+        assert_eq!(body, json!({ "orders": ["replaced"] }));
+    }
+
+    #[test]
+    fn out_of_bounds_index_is_a_no_op() {
+        let mut body = json!({ "items": [{ "name": "a" }] });
+        let original = body.clone();
+        assert!(!set_nested_value(
+            &mut body,
+            &keys("items.3.name"),
+            &json!("b")
+        ));
+        assert!(!set_nested_value(&mut body, &keys("items.3"), &json!("b")));
+        assert_eq!(body, original);
+    }
+
+    #[test]
+    fn digit_keys_on_objects_stay_keys() {
+        let mut body = json!({ "codes": { "404": "missing" } });
+        assert!(set_nested_value(
+            &mut body,
+            &keys("codes.404"),
+            &json!("not found")
+        ));
+        assert!(set_nested_value(
+            &mut body,
+            &keys("codes.500"),
+            &json!("error")
+        ));
+        assert_eq!(
+            body,
+            json!({ "codes": { "404": "not found", "500": "error" } })
+        );
+    }
+
+    #[test]
+    fn rewrite_url_honours_apply_to_and_condition() {
+        let modifier = ResponseModifier::new();
+        let rule = |apply_to: &str, condition: serde_json::Value| -> CreateModifierRule {
+            serde_json::from_value(json!({
+                "name": "strip api",
+                "enabled": true,
+                "priority": 0,
+                "match_request": {
+                    "method": null,
+                    "url_pattern": "/api/",
+                    "url_match_type": "startswith",
+                    "status_codes": null
+                },
+                "modifications": [
+                    { "type": "rewrite_url", "pattern": "^/api/(.*)$", "replacement": "/$1" }
+                ],
+                "apply_to": apply_to,
+                "condition": condition
+            }))
+            .unwrap()
+        };
+        let headers = HashMap::new();
+
+        // Rules default to the response and never touch the request path
+        let response_rule = modifier.add_rule(rule("response", json!(null)));
+        assert_eq!(
+            modifier.rewrite_url("GET", "/api/users", &headers, b""),
+            None
+        );
+        modifier.delete_rule(&response_rule);
+
+        modifier.add_rule(rule("request", json!({ "body_contains": "tenant" })));
+        assert_eq!(
+            modifier.rewrite_url("POST", "/api/users?page=2", &headers, b"{}"),
+            None
+        );
+        assert_eq!(
+            modifier.rewrite_url("POST", "/api/users?page=2", &headers, b"{\"tenant\":1}"),
+            Some("/users?page=2".to_string())
+        );
+    }
+}
@@ -0,0 +1,266 @@
+use crate::matcher::is_dynamic_segment;
+use crate::schema::json_type_name;
+use crate::storage::{RecordedRequest, extract_endpoint};
+use serde_json::{Map, Value, json};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Everything seen for one method on one path template
+#[derive(Default)]
+struct Operation {
+    path_params: Vec<String>,
+    query_params: BTreeSet<String>,
+    request_example: Option<(String, Value)>,
+    // Status code to how often it was seen and the first body example
+    responses: BTreeMap<u16, (usize, Option<(String, Value)>)>,
+}
+
+/// Build a minimal OpenAPI 3.0 document from recorded traffic. Recordings are grouped by
+/// path and method; numeric and UUID path segments become parameters, so
+/// `/api/users/42` and `/api/users/7` both land on `/api/users/{userId}`. Each operation
+/// lists every status code observed, with the newest body as its example.
+pub fn to_openapi(recordings: &[RecordedRequest]) -> Value {
+    let mut recordings: Vec<&RecordedRequest> = recordings.iter().collect();
+    recordings.sort_by_key(|recording| std::cmp::Reverse(recording.timestamp));
+
+    let mut paths: BTreeMap<String, BTreeMap<String, Operation>> = BTreeMap::new();
+    for recording in &recordings {
+        let (template, path_params) = path_template(&path_of(&recording.url));
+        let operation = paths
+            .entry(template)
+            .or_default()
+            .entry(recording.method.to_lowercase())
+            .or_default();
+        operation.path_params = path_params;
+
+        if let Some((_, query)) = recording.url.split_once('?') {
+            operation.query_params.extend(
+                query
+                    .split('&')
+                    .filter_map(|pair| pair.split('=').next())
+                    .filter(|name| !name.is_empty())
+                    .map(|name| urlencoding::decode(name).unwrap_or_default().to_string()),
+            );
+        }
+        if operation.request_example.is_none() {
+            operation.request_example = recording
+                .body
+                .as_deref()
+                .and_then(|body| example(&recording.headers, body));
+        }
+        if let Some(ref response) = recording.response {
+            let (count, response_example) = operation.responses.entry(response.status).or_default();
+            *count += 1;
+            if response_example.is_none() {
+                *response_example = response
+                    .body
+                    .as_deref()
+                    .and_then(|body| example(&response.headers, body));
+            }
+        }
+    }
+
+    let paths: Map<String, Value> = paths
+        .into_iter()
+        .map(|(template, operations)| {
+            let operations: Map<String, Value> = operations
+                .into_iter()
+                .map(|(method, operation)| {
+                    let summary = format!("{} {}", method.to_uppercase(), template);
+                    (method, operation_json(summary, operation))
+                })
+                .collect();
+            (template, Value::Object(operations))
+        })
+        .collect();
+
+    let description = format!(
+        "Inferred by dev-proxy from {} recorded requests",
+        recordings.len()
+    );
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Recorded API",
+            "description": description,
+            "version": crate::version::build_info().version,
+        },
+        "paths": paths,
+    })
+}
+
+fn operation_json(summary: String, operation: Operation) -> Value {
+    let mut parameters: Vec<Value> = operation
+        .path_params
+        .iter()
+        .map(|name| {
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" },
+            })
+        })
+        .collect();
+    parameters.extend(operation.query_params.iter().map(|name| {
+        json!({
+            "name": name,
+            "in": "query",
+            "required": false,
+            "schema": { "type": "string" },
+        })
+    }));
+
+    let responses: Map<String, Value> = operation
+        .responses
+        .into_iter()
+        .map(|(status, (count, response_example))| {
+            let plural = if count == 1 { "" } else { "s" };
+            let mut response = json!({
+                "description": format!("Observed {} time{}", count, plural),
+            });
+            if let Some(example) = response_example {
+                response["content"] = content(example);
+            }
+            (status.to_string(), response)
+        })
+        .collect();
+
+    let mut operation_json = json!({
+        "summary": summary,
+        "responses": responses,
+    });
+    if !parameters.is_empty() {
+        operation_json["parameters"] = json!(parameters);
+    }
+    if let Some(example) = operation.request_example {
+        operation_json["requestBody"] = json!({ "content": content(example) });
+    }
+    operation_json
+}
+
+fn content((content_type, value): (String, Value)) -> Value {
+    let media_type = json!({
+        "schema": schema_for(&value),
+        "example": value,
+    });
+    Value::Object(Map::from_iter([(content_type, media_type)]))
+}
+
+/// Path of a recorded URL, which is usually just the path and query but may be absolute
+fn path_of(url: &str) -> String {
+    let endpoint = extract_endpoint(url);
+    match url::Url::parse(&endpoint) {
+        Ok(parsed) => parsed.path().to_string(),
+        Err(_) => endpoint,
+    }
+}
+
+/// Replace numeric and UUID segments with `{<singular previous segment>Id}` parameters;
+/// returns the template and the parameter names in order
+fn path_template(path: &str) -> (String, Vec<String>) {
+    let mut params: Vec<String> = Vec::new();
+    // Literal segment before the current one, empty after a parameter
+    let mut previous = "";
+    let segments: Vec<String> = path
+        .split('/')
+        .map(|segment| {
+            let is_param = is_dynamic_segment(segment);
+            let templated = if is_param {
+                let base = singular(previous);
+                let mut name = if base.is_empty() {
+                    "id".to_string()
+                } else {
+                    format!("{}Id", base)
+                };
+                if params.contains(&name) {
+                    name = format!("{}{}", name, params.len() + 1);
+                }
+                params.push(name.clone());
+                format!("{{{}}}", name)
+            } else {
+                segment.to_string()
+            };
+            previous = if is_param { "" } else { segment };
+            templated
+        })
+        .collect();
+    (segments.join("/"), params)
+}
+
+/// `users` -> `user`; words that only look plural (`status`, `address`) stay as they are
+fn singular(word: &str) -> &str {
+    if word.ends_with("ss") || word.ends_with("us") {
+        return word;
+    }
+    word.strip_suffix('s').unwrap_or(word)
+}
+
+/// Media type and example value of a recorded body: parsed JSON for JSON bodies, the text
+/// otherwise; binary bodies give no example
+fn example(headers: &HashMap<String, String>, body: &[u8]) -> Option<(String, Value)> {
+    if body.is_empty() {
+        return None;
+    }
+    let content_type = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        .and_then(|(_, value)| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase());
+
+    if let Ok(json) = serde_json::from_slice::<Value>(body) {
+        let content_type = content_type
+            .filter(|content_type| content_type.contains("json"))
+            .unwrap_or_else(|| "application/json".to_string());
+        return Some((content_type, json));
+    }
+    let text = std::str::from_utf8(body).ok()?;
+    Some((
+        content_type.unwrap_or_else(|| "text/plain".to_string()),
+        Value::String(text.to_string()),
+    ))
+}
+
+/// JSON Schema of an example value; arrays take their items' schema from the first item
+fn schema_for(value: &Value) -> Value {
+    match value {
+        Value::Null => json!({ "nullable": true }),
+        Value::Object(map) => {
+            let properties: Map<String, Value> = map
+                .iter()
+                .map(|(key, child)| (key.clone(), schema_for(child)))
+                .collect();
+            json!({ "type": "object", "properties": properties })
+        }
+        Value::Array(items) => json!({
+            "type": "array",
+            "items": items.first().map(schema_for).unwrap_or_else(|| json!({})),
+        }),
+        other => json!({ "type": json_type_name(other) }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_parameters_after_the_singular_segment() {
+        let (template, params) = path_template("/api/users/42/orders/7");
+        assert_eq!(template, "/api/users/{userId}/orders/{orderId}");
+        assert_eq!(params, ["userId", "orderId"]);
+    }
+
+    #[test]
+    fn keeps_words_that_only_look_plural() {
+        let (template, params) = path_template("/status/5/address/9");
+        assert_eq!(template, "/status/{statusId}/address/{addressId}");
+        assert_eq!(params, ["statusId", "addressId"]);
+    }
+
+    #[test]
+    fn parameters_without_a_literal_before_them() {
+        let (template, params) = path_template("/9/4bd5e5b2-1f0a-4c2e-9d61-54b1f5e0a0a1");
+        assert_eq!(template, "/{id}/{id2}");
+        assert_eq!(params, ["id", "id2"]);
+    }
+}
@@ -1,26 +1,47 @@
-use crate::config::ProxyConfig;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::config::{ProxyConfig, UpstreamProtocol};
+use crate::logger::RequestLogger;
 use crate::mock::MockManager;
-use crate::recorder::Recorder;
-use crate::storage::Storage;
+use crate::recorder::{REQUEST_ID_HEADER, Recorder};
+use crate::shutdown::Shutdown;
+use crate::storage::CapturedBody;
 use anyhow::Result;
 use async_trait::async_trait;
 use bytes::Bytes;
 use pingora::prelude::*;
-use pingora_core::upstreams::peer::HttpPeer;
+use pingora_core::server::{RunArgs, ShutdownSignal, ShutdownSignalWatch};
+use pingora_core::tls::x509::X509;
+use pingora_core::upstreams::peer::{ALPN, HttpPeer};
 use pingora_proxy::{ProxyHttp, Session, http_proxy_service};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 
 pub struct DevProxy {
-    upstream_url: String,
+    config: ProxyConfig,
     recorder: Arc<Recorder>,
     mock_manager: Arc<MockManager>,
+    logger: RequestLogger,
+    // Round-robin position in `config.upstream_urls`, shared with WebSocket upgrades
+    next_upstream: Arc<AtomicUsize>,
+    circuit_breaker: CircuitBreaker,
+    // Roots from --upstream-ca, parsed once and shared by every HTTPS peer
+    upstream_ca: Option<Arc<Box<[X509]>>>,
 }
 
 pub struct ProxyCtx {
+    correlation_id: String,
     request_id: Option<(String, Instant)>,
-    request_body: Vec<u8>,
-    response_body: Vec<u8>,
+    // Upstream picked for this request and how many have refused the connection so far
+    upstream: Option<usize>,
+    failed_upstreams: usize,
+    // Sent to a --route upstream, which has no fallback
+    routed: bool,
+    // Upstream of the current attempt, whose outcome the circuit breaker is told about
+    peer_upstream: Option<String>,
+    // Recorded copies, capped at --max-body-bytes; the bodies themselves stream through
+    request_body: CapturedBody,
+    response_body: CapturedBody,
 }
 
 #[async_trait]
@@ -29,9 +50,14 @@ impl ProxyHttp for DevProxy {
 
     fn new_ctx(&self) -> Self::CTX {
         ProxyCtx {
+            correlation_id: String::new(),
             request_id: None,
-            request_body: Vec::new(),
-            response_body: Vec::new(),
+            upstream: None,
+            failed_upstreams: 0,
+            routed: false,
+            peer_upstream: None,
+            request_body: CapturedBody::default(),
+            response_body: CapturedBody::default(),
         }
     }
 
@@ -43,19 +69,66 @@ impl ProxyHttp for DevProxy {
         // Extract method and URI first (before any mutable operations)
         let method = session.req_header().method.as_str().to_string();
         let uri = session.req_header().uri.to_string();
+        ctx.correlation_id = session
+            .req_header()
+            .headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
 
         // Record request
         ctx.request_id = self
             .recorder
             .record_request(&method, &uri, session.req_header(), None);
 
-        // Check for mock rule
-        if let Some(mock_rule) = self.mock_manager.find_matching_rule(&method, &uri) {
+        // Check for mock rule, unless the path is passthrough. The body hasn't been read
+        // yet, so rules matching on it are left to the HTTP layer.
+        let passthrough = self.config.is_passthrough(session.req_header().uri.path());
+        let req_header = session.req_header();
+        let mut headers = std::collections::HashMap::new();
+        for (name, value) in req_header.headers.iter() {
+            if let Ok(value) = value.to_str() {
+                headers.insert(name.to_string(), value.to_string());
+            }
+        }
+        if let Some(mock_rule) = self
+            .mock_manager
+            .find_matching_rule(&method, &uri, &headers, None)
+            .filter(|_| !passthrough)
+        {
+            let client_ip = headers
+                .get("x-forwarded-for")
+                .and_then(|xff| xff.split(',').next())
+                .map(|ip| ip.trim().to_string())
+                .or_else(|| {
+                    // Hash the address without its port so it's stable across connections
+                    let addr = session.client_addr()?.to_string();
+                    Some(match addr.parse::<std::net::SocketAddr>() {
+                        Ok(socket_addr) => socket_addr.ip().to_string(),
+                        Err(_) => addr,
+                    })
+                });
+            let mock_request = crate::mock::MockRequest {
+                method: &method,
+                path: req_header.uri.path(),
+                headers: &headers,
+                query: req_header.uri.query(),
+                client_ip: client_ip.as_deref(),
+            };
+            let response = self.mock_manager.select_response(&mock_rule, &mock_request);
+            let (response, body) = self
+                .mock_manager
+                .resolve_body(response)
+                .await
+                .render(&mock_request)
+                .encode();
+
             // Clone everything we need from mock_rule
-            let status = mock_rule.response.status;
-            let headers = mock_rule.response.headers.clone();
-            let body = mock_rule.response.body.clone();
-            let delay = mock_rule.delay_ms;
+            let status = response.status;
+            let chunking = response.chunking();
+            let headers = response.headers;
+            let delay = mock_rule.effective_delay_ms();
 
             // Add delay if specified
             if let Some(delay_ms) = delay {
@@ -72,6 +145,9 @@ impl ProxyHttp for DevProxy {
 
             // Add headers
             for (k, v) in &headers {
+                if crate::mock::MockResponse::is_framing_header(k) {
+                    continue;
+                }
                 if let (Ok(name), Ok(value)) = (
                     http::header::HeaderName::try_from(k.as_str()),
                     http::HeaderValue::try_from(v.as_str()),
@@ -84,10 +160,25 @@ impl ProxyHttp for DevProxy {
                 let _ = header.insert_header(http::header::CONTENT_TYPE, "application/json");
             }
 
+            if chunking.is_some() {
+                let _ = header.insert_header(http::header::TRANSFER_ENCODING, "chunked");
+            }
+
             // Now we can mutate session
             let _ = session.write_response_header(Box::new(header), false).await;
 
-            if !body.is_empty() {
+            if let Some(chunking) = chunking {
+                let delay = std::time::Duration::from_millis(chunking.chunk_delay_ms);
+                for (i, chunk) in body.chunks(chunking.chunk_size.max(1)).enumerate() {
+                    if i > 0 && !delay.is_zero() {
+                        tokio::time::sleep(delay).await;
+                    }
+                    let _ = session
+                        .write_response_body(Some(Bytes::copy_from_slice(chunk)), false)
+                        .await;
+                }
+                let _ = session.write_response_body(None, true).await;
+            } else if !body.is_empty() {
                 let _ = session
                     .write_response_body(Some(Bytes::from(body.clone())), true)
                     .await;
@@ -98,32 +189,90 @@ impl ProxyHttp for DevProxy {
             // Record it
             if let Some((ref id, start)) = ctx.request_id {
                 let dur = start.elapsed().as_millis() as u64;
+                let mut headers = headers;
+                self.config.strip_recorded(&mut headers);
                 self.recorder.storage.update_response(
                     id,
                     crate::storage::RecordedResponse {
                         status,
                         headers,
-                        body: Some(body.into_bytes()),
+                        body_size: body.len(),
+                        body: Some(body),
+                        original_charset: None,
+                        body_truncated: false,
                     },
                     dur,
                 );
             }
 
-            println!("{} {} - {} [MOCKED]", method, uri, status);
+            self.logger.detail(
+                &ctx.correlation_id,
+                &format!("proxy {} {} - {} [MOCKED]", method, uri, status),
+            );
 
             // Return true to skip upstream
             return Ok(true);
         }
 
+        // Pick the upstream now, so an open circuit can be answered without a connect
+        let path = session.req_header().uri.path().to_string();
+        let allowed = match self.config.route_for(&path) {
+            Some(upstream) => {
+                ctx.routed = true;
+                self.circuit_breaker.allow(upstream)
+            }
+            None => {
+                let upstreams = &self.config.upstream_urls;
+                let first = self.next_upstream.fetch_add(1, Ordering::Relaxed);
+                ctx.upstream = (0..upstreams.len())
+                    .map(|offset| (first + offset) % upstreams.len())
+                    .find(|&index| self.circuit_breaker.allow(&upstreams[index]));
+                ctx.upstream.is_some()
+            }
+        };
+        if !allowed {
+            self.reject_open_circuit(session, ctx, &method, &uri, &path)
+                .await?;
+            return Ok(true);
+        }
+
         Ok(false)
     }
 
     async fn upstream_peer(
         &self,
-        _session: &mut Session,
-        _ctx: &mut Self::CTX,
+        session: &mut Session,
+        ctx: &mut Self::CTX,
     ) -> Result<Box<HttpPeer>, Box<pingora::Error>> {
-        let url = url::Url::parse(&self.upstream_url).map_err(|e| {
+        let routed = self.config.route_for(session.req_header().uri.path());
+        ctx.routed = routed.is_some();
+        let upstream = match routed {
+            Some(upstream) => upstream,
+            None => {
+                // Take the next upstream in turn; after a failed connect, the next one whose
+                // circuit lets requests through (request_filter already checked the first)
+                let upstreams = &self.config.upstream_urls;
+                let first = *ctx
+                    .upstream
+                    .get_or_insert_with(|| self.next_upstream.fetch_add(1, Ordering::Relaxed));
+                if ctx.failed_upstreams > 0 {
+                    let Some(offset) = (ctx.failed_upstreams..upstreams.len()).find(|&offset| {
+                        self.circuit_breaker
+                            .allow(&upstreams[(first + offset) % upstreams.len()])
+                    }) else {
+                        return Err(pingora::Error::explain(
+                            pingora::ErrorType::ConnectError,
+                            "every other upstream's circuit is open",
+                        ));
+                    };
+                    ctx.failed_upstreams = offset;
+                }
+                &upstreams[(first + ctx.failed_upstreams) % upstreams.len()]
+            }
+        };
+        ctx.peer_upstream = Some(upstream.to_string());
+
+        let url = url::Url::parse(upstream).map_err(|e| {
             pingora::Error::explain(
                 pingora::ErrorType::ConnectError,
                 format!("Invalid URL: {}", e),
@@ -139,11 +288,47 @@ impl ProxyHttp for DevProxy {
             .unwrap_or(if url.scheme() == "https" { 443 } else { 80 });
         let use_tls = url.scheme() == "https";
 
-        Ok(Box::new(HttpPeer::new(
-            (host, port),
-            use_tls,
-            host.to_string(),
-        )))
+        let mut peer = HttpPeer::new((host, port), use_tls, host.to_string());
+        peer.options.alpn = match self.config.upstream_protocol {
+            UpstreamProtocol::Http1 => ALPN::H1,
+            UpstreamProtocol::Http2 => ALPN::H2,
+            UpstreamProtocol::Auto => ALPN::H2H1,
+        };
+        if use_tls {
+            peer.options.verify_cert = !self.config.insecure_upstream;
+            peer.options.verify_hostname = !self.config.insecure_upstream;
+            peer.options.ca = self.upstream_ca.clone();
+        }
+        Ok(Box::new(peer))
+    }
+
+    fn fail_to_connect(
+        &self,
+        _session: &mut Session,
+        _peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        mut e: Box<pingora::Error>,
+    ) -> Box<pingora::Error> {
+        if let Some(upstream) = ctx.peer_upstream.take() {
+            self.circuit_breaker.record_failure(&upstream);
+        }
+
+        // Fall through to the next upstream until each has been tried once
+        ctx.failed_upstreams += 1;
+        if !ctx.routed && ctx.failed_upstreams < self.config.upstream_urls.len() {
+            e.set_retry(true);
+        }
+        e
+    }
+
+    async fn upstream_request_filter(
+        &self,
+        _session: &mut Session,
+        upstream_request: &mut pingora_http::RequestHeader,
+        _ctx: &mut Self::CTX,
+    ) -> Result<(), Box<pingora::Error>> {
+        upstream_request.remove_header(REQUEST_ID_HEADER);
+        Ok(())
     }
 
     async fn response_filter(
@@ -163,6 +348,14 @@ impl ProxyHttp for DevProxy {
                     header_map.insert(name.to_string(), value_str.to_string());
                 }
             }
+            // Announced size until the body has streamed through
+            let body_size = upstream_response
+                .headers
+                .get(http::header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0);
+            self.config.strip_recorded(&mut header_map);
 
             self.recorder.storage.update_response(
                 id,
@@ -170,6 +363,9 @@ impl ProxyHttp for DevProxy {
                     status,
                     headers: header_map,
                     body: None,
+                    original_charset: None,
+                    body_truncated: false,
+                    body_size,
                 },
                 duration_ms,
             );
@@ -186,7 +382,7 @@ impl ProxyHttp for DevProxy {
         ctx: &mut Self::CTX,
     ) -> Result<Option<Duration>, Box<pingora::Error>> {
         if let Some(data) = body {
-            ctx.response_body.extend_from_slice(data);
+            ctx.response_body.append(data, self.config.max_body_bytes);
         }
         Ok(None)
     }
@@ -199,7 +395,7 @@ impl ProxyHttp for DevProxy {
         ctx: &mut Self::CTX,
     ) -> Result<(), Box<pingora::Error>> {
         if let Some(data) = body {
-            ctx.request_body.extend_from_slice(data);
+            ctx.request_body.append(data, self.config.max_body_bytes);
         }
         Ok(())
     }
@@ -207,7 +403,7 @@ impl ProxyHttp for DevProxy {
     async fn logging(
         &self,
         session: &mut Session,
-        _e: Option<&pingora::Error>,
+        e: Option<&pingora::Error>,
         ctx: &mut Self::CTX,
     ) {
         let method = session.req_header().method.as_str();
@@ -217,53 +413,170 @@ impl ProxyHttp for DevProxy {
             .map(|h| h.status.as_u16())
             .unwrap_or(0);
 
-        if let Some((ref id, _)) = ctx.request_id {
-            if let Some(recording) = self.recorder.storage.get_by_id(id) {
-                let mut updated = recording.clone();
-
-                if !ctx.request_body.is_empty() {
-                    updated.body = Some(ctx.request_body.clone());
-                }
-
-                if let Some(ref mut response) = updated.response {
-                    if !ctx.response_body.is_empty() {
-                        response.body = Some(ctx.response_body.clone());
-                    }
-                }
+        if let Some(upstream) = ctx.peer_upstream.take() {
+            if e.is_some() || status >= 500 {
+                self.circuit_breaker.record_failure(&upstream);
+            } else {
+                self.circuit_breaker.record_success(&upstream);
+            }
+        }
 
-                self.recorder
-                    .storage
-                    .recordings
-                    .write()
-                    .insert(id.clone(), updated);
+        // Update in place so fields written concurrently by the HTTP layer survive
+        if let Some((ref id, _)) = ctx.request_id {
+            if let Some(e) = e {
+                self.recorder.storage.set_origin(
+                    id,
+                    crate::storage::ResponseOrigin::UpstreamError {
+                        error: e.to_string(),
+                    },
+                );
             }
+            let request_body =
+                (!ctx.request_body.data.is_empty()).then(|| std::mem::take(&mut ctx.request_body));
+            let response_body = (!ctx.response_body.data.is_empty())
+                .then(|| std::mem::take(&mut ctx.response_body));
+            self.recorder
+                .storage
+                .update_bodies(id, request_body, response_body);
         }
 
+        // The HTTP layer logs the summary line; this hop is only shown in verbose mode
         if status > 0 {
-            println!("{} {} - {}", method, uri, status);
+            self.logger.detail(
+                &ctx.correlation_id,
+                &format!("proxy {} {} - {}", method, uri, status),
+            );
         }
     }
 }
 
+impl DevProxy {
+    /// Answer right away because every upstream this request could go to has an open
+    /// circuit
+    async fn reject_open_circuit(
+        &self,
+        session: &mut Session,
+        ctx: &mut ProxyCtx,
+        method: &str,
+        uri: &str,
+        path: &str,
+    ) -> Result<(), Box<pingora::Error>> {
+        let status = self.circuit_breaker.open_status();
+        let retry_after_secs = match self.config.route_for(path) {
+            Some(upstream) => self.circuit_breaker.retry_in_ms(upstream),
+            None => self
+                .config
+                .upstream_urls
+                .iter()
+                .map(|upstream| self.circuit_breaker.retry_in_ms(upstream))
+                .min()
+                .unwrap_or(0),
+        }
+        .div_ceil(1000);
+        let body = b"Upstream circuit open, retry later".to_vec();
+
+        let status_code =
+            http::StatusCode::from_u16(status).unwrap_or(http::StatusCode::SERVICE_UNAVAILABLE);
+        let mut header = pingora_http::ResponseHeader::build(status_code, None).map_err(|e| {
+            pingora::Error::because(pingora::ErrorType::HTTPStatus(500), "build header", e)
+        })?;
+        let _ = header.insert_header(http::header::CONTENT_TYPE, "text/plain");
+        let _ = header.insert_header(http::header::RETRY_AFTER, retry_after_secs.to_string());
+        let _ = header.insert_header(http::header::CONTENT_LENGTH, body.len().to_string());
+        let _ = session.write_response_header(Box::new(header), false).await;
+        let _ = session
+            .write_response_body(Some(Bytes::from(body.clone())), true)
+            .await;
+
+        if let Some((ref id, start)) = ctx.request_id {
+            self.recorder.storage.update_response(
+                id,
+                crate::storage::RecordedResponse {
+                    status,
+                    headers: std::collections::HashMap::from([(
+                        "retry-after".to_string(),
+                        retry_after_secs.to_string(),
+                    )]),
+                    body_size: body.len(),
+                    body: Some(body),
+                    original_charset: None,
+                    body_truncated: false,
+                },
+                start.elapsed().as_millis() as u64,
+            );
+            self.recorder
+                .storage
+                .set_origin(id, crate::storage::ResponseOrigin::CircuitOpen);
+        }
+
+        self.logger.detail(
+            &ctx.correlation_id,
+            &format!("proxy {} {} - {} [CIRCUIT OPEN]", method, uri, status),
+        );
+        Ok(())
+    }
+}
+
+/// `--upstream-ca` plus the system roots. A peer's CA list replaces Pingora's default trust
+/// store, so without the system roots public upstreams would stop verifying; reqwest adds
+/// the CA on top of them the same way.
+fn upstream_trust_store(path: &std::path::Path) -> Result<Box<[X509]>> {
+    let native = rustls_native_certs::load_native_certs();
+    for e in &native.errors {
+        eprintln!("Skipping system root certificates: {}", e);
+    }
+    let mut roots: Vec<X509> = native
+        .certs
+        .iter()
+        .filter_map(|der| X509::from_der(der).ok())
+        .collect();
+    let pem = std::fs::read(path)?;
+    roots.extend(X509::stack_from_pem(&pem)?);
+    Ok(roots.into_boxed_slice())
+}
+
 pub fn start_proxy_server(
     config: ProxyConfig,
-    storage: Storage,
+    recorder: Recorder,
     mock_manager: MockManager,
+    logger: RequestLogger,
+    circuit_breaker: CircuitBreaker,
+    next_upstream: Arc<AtomicUsize>,
+    shutdown: Shutdown,
 ) -> Result<()> {
     let mut server = Server::new(None).unwrap();
     server.bootstrap();
 
-    let recorder = Arc::new(Recorder::new(storage, config.recording_enabled));
+    let upstream_ca = match config.upstream_ca {
+        Some(ref path) => Some(Arc::new(upstream_trust_store(path)?)),
+        None => None,
+    };
 
     let proxy_service = DevProxy {
-        upstream_url: config.upstream_url.clone(),
-        recorder,
+        config: config.clone(),
+        recorder: Arc::new(recorder),
         mock_manager: Arc::new(mock_manager),
+        logger,
+        next_upstream,
+        circuit_breaker,
+        upstream_ca,
     };
 
     let mut proxy_service_http = http_proxy_service(&server.configuration, proxy_service);
     proxy_service_http.add_tcp(&format!("0.0.0.0:{}", config.proxy_port));
 
     server.add_service(proxy_service_http);
-    server.run_forever();
+    // Pingora stops only after the HTTP layer has drained, since it forwards through us
+    server.run(RunArgs {
+        shutdown_signal: Box::new(shutdown),
+    });
+    std::process::exit(0)
+}
+
+#[async_trait]
+impl ShutdownSignalWatch for Shutdown {
+    async fn recv(&self) -> ShutdownSignal {
+        self.stopped().await;
+        ShutdownSignal::FastShutdown
+    }
 }
@@ -1,11 +1,15 @@
 use parking_lot::RwLock;
-use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+use crate::explain::{RuleCheck, RuleTrace};
+pub use crate::matcher::MatchType;
+use crate::matcher::{default_match_query, reorder_priorities};
+use crate::schedule::ActiveWindow;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RateLimitRule {
     pub id: String,
@@ -15,6 +19,11 @@ pub struct RateLimitRule {
     pub match_request: RateLimitMatch,
     pub limit: RateLimit,
     pub response: RateLimitResponse,
+    // Escalating responses for clients that keep getting rejected, see `RateLimitTier`
+    #[serde(default)]
+    pub tiers: Vec<RateLimitTier>,
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
@@ -26,6 +35,11 @@ pub struct CreateRateLimitRule {
     pub match_request: RateLimitMatch,
     pub limit: RateLimit,
     pub response: RateLimitResponse,
+    // Escalating responses for clients that keep getting rejected, see `RateLimitTier`
+    #[serde(default)]
+    pub tiers: Vec<RateLimitTier>,
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,6 +51,11 @@ pub struct UpdateRateLimitRule {
     pub match_request: RateLimitMatch,
     pub limit: RateLimit,
     pub response: RateLimitResponse,
+    // Escalating responses for clients that keep getting rejected, see `RateLimitTier`
+    #[serde(default)]
+    pub tiers: Vec<RateLimitTier>,
+    #[serde(flatten)]
+    pub schedule: ActiveWindow,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,19 +63,12 @@ pub struct RateLimitMatch {
     pub method: Option<String>,
     pub url_pattern: String,
     pub url_match_type: MatchType,
+    // Match url_pattern against the whole URL; false leaves the query string out
+    #[serde(default = "default_match_query")]
+    pub match_query: bool,
     pub key_type: KeyType,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum MatchType {
-    Exact,
-    Contains,
-    Regex,
-    StartsWith,
-    EndsWith,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum KeyType {
@@ -69,6 +81,47 @@ pub enum KeyType {
     Custom {
         pattern: String,
     },
+    // One bucket per combination of the parts' values, e.g. per (API key, IP) pair
+    Composite {
+        parts: Vec<KeyType>,
+    },
+}
+
+impl KeyType {
+    /// Composite keys take at least one part and can't be nested
+    pub fn validate(&self) -> Result<(), String> {
+        let KeyType::Composite { parts } = self else {
+            return Ok(());
+        };
+        if parts.is_empty() {
+            return Err("Composite key_type needs at least one part".to_string());
+        }
+        if parts
+            .iter()
+            .any(|part| matches!(part, KeyType::Composite { .. }))
+        {
+            return Err("Composite key_type parts can't be composite themselves".to_string());
+        }
+        Ok(())
+    }
+
+    /// Bucket key segment for a request: the key type and the resolved value
+    fn resolve(&self, client_key: &str, headers: &HashMap<String, String>) -> String {
+        match self {
+            KeyType::Global => "global".to_string(),
+            KeyType::IpAddress => format!("ip:{}", client_key),
+            KeyType::Header { name } => {
+                let header_value = headers.get(name).map(|v| v.as_str()).unwrap_or("unknown");
+                format!("header:{}:{}", name, header_value)
+            }
+            KeyType::Custom { pattern } => format!("custom:{}", pattern),
+            KeyType::Composite { parts } => parts
+                .iter()
+                .map(|part| part.resolve(client_key, headers))
+                .collect::<Vec<_>>()
+                .join("|"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -76,6 +129,10 @@ pub struct RateLimit {
     pub max_requests: u32,
     pub window_seconds: u32,
     pub burst_size: Option<u32>,
+    // When set, only responses with these statuses count towards the limit (e.g. 401/403
+    // for lockouts); the limit is still enforced before the request is handled
+    #[serde(default)]
+    pub count_statuses: Option<Vec<u16>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -86,10 +143,33 @@ pub struct RateLimitResponse {
     pub delay_ms: Option<u64>,
 }
 
+/// Response used once a client has been rejected this many times in a row, e.g. a
+/// short-lived 429 at first and a 403 after 20 rejections. The tier with the highest
+/// `after_rejections` the client has reached wins; below every tier `response` is used.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitTier {
+    pub after_rejections: u32,
+    pub response: RateLimitResponse,
+}
+
+impl RateLimitRule {
+    /// Response for a client on its `rejections`-th consecutive rejection
+    fn response_for(&self, rejections: u32) -> &RateLimitResponse {
+        self.tiers
+            .iter()
+            .filter(|tier| tier.after_rejections <= rejections)
+            .max_by_key(|tier| tier.after_rejections)
+            .map(|tier| &tier.response)
+            .unwrap_or(&self.response)
+    }
+}
+
 #[derive(Debug, Clone)]
 struct RateLimitBucket {
     requests: Vec<Instant>,
     burst_used: u32,
+    // Rejections since the last allowed request
+    consecutive_rejections: u32,
 }
 
 impl RateLimitBucket {
@@ -97,38 +177,28 @@ impl RateLimitBucket {
         Self {
             requests: Vec::new(),
             burst_used: 0,
+            consecutive_rejections: 0,
         }
     }
 
     fn is_allowed(&mut self, limit: &RateLimit, now: Instant) -> (bool, RateLimitInfo) {
-        let window = Duration::from_secs(limit.window_seconds as u64);
+        let (allowed, info) = self.peek(limit, now);
+        if allowed {
+            self.record(limit, now);
+        }
+        (allowed, info)
+    }
 
-        // Clean old requests outside the window
-        self.requests
-            .retain(|&req_time| now.duration_since(req_time) < window);
+    /// Decide whether another request fits without counting it
+    fn peek(&mut self, limit: &RateLimit, now: Instant) -> (bool, RateLimitInfo) {
+        self.prune(limit, now);
 
         let current_count = self.requests.len() as u32;
         let remaining = limit.max_requests.saturating_sub(current_count);
-
-        // Check if burst is available
-        let burst_size = limit.burst_size.unwrap_or(0);
-        let can_use_burst = self.burst_used < burst_size && current_count >= limit.max_requests;
-
-        let allowed = current_count < limit.max_requests || can_use_burst;
-
-        if allowed {
-            self.requests.push(now);
-            if can_use_burst {
-                self.burst_used += 1;
-            }
-
-            // Reset burst counter if we're back under the limit
-            if current_count < limit.max_requests {
-                self.burst_used = 0;
-            }
-        }
+        let allowed = current_count < limit.max_requests || self.can_use_burst(limit);
 
         // Calculate reset time
+        let window = Duration::from_secs(limit.window_seconds as u64);
         let oldest = self.requests.first().copied();
         let reset_in_seconds = oldest
             .map(|t| {
@@ -149,9 +219,40 @@ impl RateLimitBucket {
                 } else {
                     None
                 },
+                consecutive_rejections: self.consecutive_rejections,
             },
         )
     }
+
+    /// Count a request against the window
+    fn record(&mut self, limit: &RateLimit, now: Instant) {
+        self.prune(limit, now);
+
+        let current_count = self.requests.len() as u32;
+        let can_use_burst = self.can_use_burst(limit);
+
+        self.requests.push(now);
+        if can_use_burst {
+            self.burst_used += 1;
+        }
+
+        // Reset burst counter if we're back under the limit
+        if current_count < limit.max_requests {
+            self.burst_used = 0;
+        }
+    }
+
+    fn can_use_burst(&self, limit: &RateLimit) -> bool {
+        let burst_size = limit.burst_size.unwrap_or(0);
+        self.burst_used < burst_size && self.requests.len() as u32 >= limit.max_requests
+    }
+
+    // Clean old requests outside the window
+    fn prune(&mut self, limit: &RateLimit, now: Instant) {
+        let window = Duration::from_secs(limit.window_seconds as u64);
+        self.requests
+            .retain(|&req_time| now.duration_since(req_time) < window);
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -160,6 +261,25 @@ pub struct RateLimitInfo {
     pub remaining: u32,
     pub reset_in_seconds: u64,
     pub retry_after: Option<u64>,
+    pub consecutive_rejections: u32,
+}
+
+impl From<CreateRateLimitRule> for RateLimitRule {
+    /// A new rule with a fresh id
+    fn from(create_rule: CreateRateLimitRule) -> Self {
+        RateLimitRule {
+            id: Uuid::new_v4().to_string(),
+            name: create_rule.name,
+            enabled: create_rule.enabled,
+            priority: create_rule.priority,
+            match_request: create_rule.match_request,
+            limit: create_rule.limit,
+            response: create_rule.response,
+            tiers: create_rule.tiers,
+            schedule: create_rule.schedule.resolve(),
+            created_at: chrono::Utc::now(),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -177,18 +297,8 @@ impl RateLimiter {
     }
 
     pub fn add_rule(&self, create_rule: CreateRateLimitRule) -> String {
-        let id = Uuid::new_v4().to_string();
-
-        let rule = RateLimitRule {
-            id: id.clone(),
-            name: create_rule.name,
-            enabled: create_rule.enabled,
-            priority: create_rule.priority,
-            match_request: create_rule.match_request,
-            limit: create_rule.limit,
-            response: create_rule.response,
-            created_at: chrono::Utc::now(),
-        };
+        let rule = RateLimitRule::from(create_rule);
+        let id = rule.id.clone();
 
         let mut rules = self.rules.write();
         rules.insert(id.clone(), rule);
@@ -207,6 +317,8 @@ impl RateLimiter {
                 match_request: update_rule.match_request,
                 limit: update_rule.limit,
                 response: update_rule.response,
+                tiers: update_rule.tiers,
+                schedule: update_rule.schedule.resolve(),
                 created_at: existing.created_at,
             };
             rules.insert(update_rule.id, rule);
@@ -233,6 +345,35 @@ impl RateLimiter {
         all_rules
     }
 
+    /// How every rule was evaluated against a request, highest priority first
+    pub fn explain(&self, method: &str, url: &str) -> Vec<RuleTrace> {
+        let rules = self.rules.read();
+        let mut all_rules: Vec<&RateLimitRule> = rules.values().collect();
+        all_rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+        all_rules
+            .into_iter()
+            .map(|rule| {
+                RuleCheck {
+                    id: &rule.id,
+                    name: &rule.name,
+                    priority: rule.priority,
+                    enabled: rule.enabled,
+                    schedule: &rule.schedule,
+                    method: rule.match_request.method.as_deref(),
+                    url_pattern: &rule.match_request.url_pattern,
+                    url_match_type: &rule.match_request.url_match_type,
+                    match_query: rule.match_request.match_query,
+                }
+                .trace(method, url)
+            })
+            .collect()
+    }
+
+    /// Reassign priorities so rules follow the order of `ids`, see `reorder_priorities`
+    pub fn reorder(&self, ids: Vec<String>) {
+        reorder_priorities(&mut self.rules.write(), ids, |rule| &mut rule.priority);
+    }
+
     pub fn toggle_rule(&self, id: &str) -> bool {
         let mut rules = self.rules.write();
         if let Some(rule) = rules.get_mut(id) {
@@ -243,6 +384,17 @@ impl RateLimiter {
         }
     }
 
+    /// Replace every rule and start with empty buckets, e.g. when restoring a snapshot
+    pub fn replace_all(&self, new_rules: Vec<RateLimitRule>) {
+        let mut rules = self.rules.write();
+        let mut buckets = self.buckets.write();
+        *rules = new_rules
+            .into_iter()
+            .map(|rule| (rule.id.clone(), rule))
+            .collect();
+        buckets.clear();
+    }
+
     pub fn clear_all(&self) {
         let mut rules = self.rules.write();
         let mut buckets = self.buckets.write();
@@ -256,12 +408,9 @@ impl RateLimiter {
         url: &str,
         client_key: &str,
         headers: &HashMap<String, String>,
-    ) -> Option<(RateLimitRule, RateLimitInfo)> {
+    ) -> Option<(RateLimitResponse, RateLimitInfo)> {
         let rules = self.rules.read();
-        let matching_rule = rules
-            .values()
-            .filter(|rule| rule.enabled && self.matches(rule, method, url))
-            .max_by_key(|rule| rule.priority)?;
+        let matching_rule = self.find_matching_rule(&rules, method, url)?;
 
         let bucket_key = self.generate_bucket_key(matching_rule, client_key, headers);
 
@@ -271,15 +420,70 @@ impl RateLimiter {
             .or_insert_with(RateLimitBucket::new);
 
         let now = Instant::now();
-        let (allowed, info) = bucket.is_allowed(&matching_rule.limit, now);
+        // Status-counted rules are only charged once the response is known, see `record_response`
+        let (allowed, mut info) = if matching_rule.limit.count_statuses.is_some() {
+            bucket.peek(&matching_rule.limit, now)
+        } else {
+            bucket.is_allowed(&matching_rule.limit, now)
+        };
 
         if !allowed {
-            Some((matching_rule.clone(), info))
+            bucket.consecutive_rejections = bucket.consecutive_rejections.saturating_add(1);
+            info.consecutive_rejections = bucket.consecutive_rejections;
+            let response = matching_rule.response_for(bucket.consecutive_rejections);
+            Some((response.clone(), info))
         } else {
+            bucket.consecutive_rejections = 0;
             None
         }
     }
 
+    /// Post-response half of status-counted rules: charge the bucket when the
+    /// response status is one the rule counts
+    pub fn record_response(
+        &self,
+        method: &str,
+        url: &str,
+        client_key: &str,
+        headers: &HashMap<String, String>,
+        status: u16,
+    ) {
+        let rules = self.rules.read();
+        let Some(matching_rule) = self.find_matching_rule(&rules, method, url) else {
+            return;
+        };
+
+        let counted = matching_rule
+            .limit
+            .count_statuses
+            .as_ref()
+            .is_some_and(|statuses| statuses.contains(&status));
+        if !counted {
+            return;
+        }
+
+        let bucket_key = self.generate_bucket_key(matching_rule, client_key, headers);
+        let mut buckets = self.buckets.write();
+        buckets
+            .entry(bucket_key)
+            .or_insert_with(RateLimitBucket::new)
+            .record(&matching_rule.limit, Instant::now());
+    }
+
+    fn find_matching_rule<'a>(
+        &self,
+        rules: &'a HashMap<String, RateLimitRule>,
+        method: &str,
+        url: &str,
+    ) -> Option<&'a RateLimitRule> {
+        rules
+            .values()
+            .filter(|rule| {
+                rule.enabled && rule.schedule.is_active() && self.matches(rule, method, url)
+            })
+            .max_by_key(|rule| rule.priority)
+    }
+
     fn matches(&self, rule: &RateLimitRule, method: &str, url: &str) -> bool {
         // Check method
         if let Some(ref rule_method) = rule.match_request.method {
@@ -289,19 +493,11 @@ impl RateLimiter {
         }
 
         // Check URL pattern
-        match rule.match_request.url_match_type {
-            MatchType::Exact => url == rule.match_request.url_pattern,
-            MatchType::Contains => url.contains(&rule.match_request.url_pattern),
-            MatchType::StartsWith => url.starts_with(&rule.match_request.url_pattern),
-            MatchType::EndsWith => url.ends_with(&rule.match_request.url_pattern),
-            MatchType::Regex => {
-                if let Ok(re) = Regex::new(&rule.match_request.url_pattern) {
-                    re.is_match(url)
-                } else {
-                    false
-                }
-            }
-        }
+        rule.match_request.url_match_type.matches_url(
+            &rule.match_request.url_pattern,
+            url,
+            rule.match_request.match_query,
+        )
     }
 
     fn generate_bucket_key(
@@ -310,15 +506,11 @@ impl RateLimiter {
         client_key: &str,
         headers: &HashMap<String, String>,
     ) -> String {
-        match &rule.match_request.key_type {
-            KeyType::Global => format!("{}:global", rule.id),
-            KeyType::IpAddress => format!("{}:ip:{}", rule.id, client_key),
-            KeyType::Header { name } => {
-                let header_value = headers.get(name).map(|v| v.as_str()).unwrap_or("unknown");
-                format!("{}:header:{}:{}", rule.id, name, header_value)
-            }
-            KeyType::Custom { pattern } => format!("{}:custom:{}", rule.id, pattern),
-        }
+        format!(
+            "{}:{}",
+            rule.id,
+            rule.match_request.key_type.resolve(client_key, headers)
+        )
     }
 
     pub fn reset_bucket(&self, rule_id: &str) {
@@ -1,17 +1,125 @@
+use crate::config::ProxyConfig;
+use crate::matcher::MatchType;
 use crate::storage::{RecordedRequest, Storage};
 use bytes::Bytes;
 use chrono::Utc;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Instant;
 
+/// Internal header the HTTP layer uses to hand its request id to the proxy,
+/// so both layers refer to the same recording. Never forwarded upstream.
+pub const REQUEST_ID_HEADER: &str = "x-dev-proxy-request-id";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordFilterAction {
+    Include,
+    Exclude,
+}
+
+/// One `--record-filter` entry or `record_filter` item of the rules file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordFilterRule {
+    pub action: RecordFilterAction,
+    pub match_type: MatchType,
+    pub pattern: String,
+}
+
+/// `ACTION:MATCH_TYPE:PATTERN`, e.g. `include:startswith:/api/` or
+/// `exclude:regex:^/api/health`. Without a match type the pattern is a prefix.
+impl FromStr for RecordFilterRule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (action, rest) = value
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid record filter '{}', expected ACTION:PATTERN", value))?;
+        let action = match action.to_ascii_lowercase().as_str() {
+            "include" => RecordFilterAction::Include,
+            "exclude" => RecordFilterAction::Exclude,
+            _ => {
+                return Err(format!(
+                    "Invalid record filter action '{}', expected include or exclude",
+                    action
+                ));
+            }
+        };
+        let (match_type, pattern) = rest
+            .split_once(':')
+            .and_then(|(match_type, pattern)| Some((match_type.parse().ok()?, pattern)))
+            .unwrap_or((MatchType::StartsWith, rest));
+        if let MatchType::Regex = match_type {
+            regex::Regex::new(pattern)
+                .map_err(|e| format!("Invalid record filter regex '{}': {}", pattern, e))?;
+        }
+        Ok(Self {
+            action,
+            match_type,
+            pattern: pattern.to_string(),
+        })
+    }
+}
+
+/// Which requests get recorded. A URL is recorded when it matches an include rule, or
+/// there are none, and matches no exclude rule; excludes win over includes. The rules
+/// file can replace the rules while running.
+#[derive(Clone, Default)]
+pub struct RecordFilter {
+    rules: Arc<RwLock<Vec<RecordFilterRule>>>,
+}
+
+impl RecordFilter {
+    pub fn new(rules: Vec<RecordFilterRule>) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(rules)),
+        }
+    }
+
+    pub fn replace_all(&self, rules: Vec<RecordFilterRule>) {
+        *self.rules.write() = rules;
+    }
+
+    pub fn allows(&self, url: &str) -> bool {
+        let rules = self.rules.read();
+        let matches = |rule: &RecordFilterRule| rule.match_type.matches(&rule.pattern, url);
+        let mut includes = rules
+            .iter()
+            .filter(|rule| rule.action == RecordFilterAction::Include)
+            .peekable();
+        let included = includes.peek().is_none() || includes.any(matches);
+        included
+            && !rules
+                .iter()
+                .any(|rule| rule.action == RecordFilterAction::Exclude && matches(rule))
+    }
+}
+
 pub struct Recorder {
     pub storage: Storage,
-    enabled: bool,
+    config: ProxyConfig,
+    // Starts as --record; the UI can switch it while running
+    enabled: Arc<AtomicBool>,
+    filter: RecordFilter,
 }
 
 impl Recorder {
-    pub fn new(storage: Storage, enabled: bool) -> Self {
-        Self { storage, enabled }
+    pub fn new(
+        storage: Storage,
+        config: ProxyConfig,
+        enabled: Arc<AtomicBool>,
+        filter: RecordFilter,
+    ) -> Self {
+        Self {
+            storage,
+            config,
+            enabled,
+            filter,
+        }
     }
 
     pub fn record_request(
@@ -21,19 +129,31 @@ impl Recorder {
         headers: &pingora_http::RequestHeader,
         body: Option<&Bytes>,
     ) -> Option<(String, Instant)> {
-        if !self.enabled {
+        if !self.enabled.load(Ordering::Relaxed) || !self.filter.allows(url) {
             return None;
         }
 
+        let mut id = String::new();
         let mut header_map = HashMap::new();
         for (name, value) in headers.headers.iter() {
+            if name.as_str() == REQUEST_ID_HEADER {
+                id = value.to_str().unwrap_or_default().to_string();
+                continue;
+            }
             if let Ok(value_str) = value.to_str() {
                 header_map.insert(name.to_string(), value_str.to_string());
             }
         }
 
+        let correlation_id = self
+            .config
+            .correlation_header
+            .as_ref()
+            .and_then(|name| header_map.get(name).cloned());
+        self.config.strip_recorded(&mut header_map);
+
         let recorded_request = RecordedRequest {
-            id: String::new(),
+            id,
             timestamp: Utc::now(),
             method: method.to_string(),
             url: url.to_string(),
@@ -41,9 +161,75 @@ impl Recorder {
             body: body.map(|b| b.to_vec()),
             response: None,
             duration_ms: None,
+            original_charset: None,
+            body_truncated: false,
+            injected_latency_ms: None,
+            cache_hit: false,
+            replay: None,
+            correlation_id,
+            explanation: None,
+            origin: None,
         };
 
         let id = self.storage.store_request(recorded_request);
         Some((id, Instant::now()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(rules: &[&str]) -> RecordFilter {
+        RecordFilter::new(rules.iter().map(|rule| rule.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn parses_rules_with_and_without_match_type() {
+        let rule: RecordFilterRule = "exclude:regex:^/api/health".parse().unwrap();
+        assert_eq!(rule.action, RecordFilterAction::Exclude);
+        assert!(matches!(rule.match_type, MatchType::Regex));
+        assert_eq!(rule.pattern, "^/api/health");
+
+        let rule: RecordFilterRule = "Include:/api/".parse().unwrap();
+        assert_eq!(rule.action, RecordFilterAction::Include);
+        assert!(matches!(rule.match_type, MatchType::StartsWith));
+        assert_eq!(rule.pattern, "/api/");
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        assert!("/api/".parse::<RecordFilterRule>().is_err());
+        assert!("keep:/api/".parse::<RecordFilterRule>().is_err());
+        assert!("include:regex:(".parse::<RecordFilterRule>().is_err());
+    }
+
+    #[test]
+    fn records_everything_without_rules() {
+        assert!(filter(&[]).allows("/anything"));
+    }
+
+    #[test]
+    fn includes_limit_what_is_recorded() {
+        let filter = filter(&["include:startswith:/api/"]);
+        assert!(filter.allows("/api/users"));
+        assert!(!filter.allows("/static/app.js"));
+    }
+
+    #[test]
+    fn excludes_win_over_includes() {
+        let filter = filter(&["include:startswith:/api/", "exclude:contains:/health"]);
+        assert!(filter.allows("/api/users"));
+        assert!(!filter.allows("/api/health"));
+        assert!(!filter.allows("/static/health"));
+    }
+
+    #[test]
+    fn replace_all_swaps_the_rules() {
+        let filter = filter(&["exclude:startswith:/api/"]);
+        assert!(!filter.allows("/api/users"));
+        filter.replace_all(vec!["exclude:startswith:/static/".parse().unwrap()]);
+        assert!(filter.allows("/api/users"));
+        assert!(!filter.allows("/static/app.js"));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::latency_injector::{CreateLatencyRule, LatencyInjector, LatencyRule};
+use crate::matcher::MatchType;
+use crate::mock::{CreateMockRule, MockManager, MockRule};
+use crate::modifier::{CreateModifierRule, ModifierCondition, ModifierRule, ResponseModifier};
+use crate::rate_limiter::{CreateRateLimitRule, RateLimitRule, RateLimiter};
+use crate::recorder::{RecordFilter, RecordFilterRule};
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Rules loaded from `--rules-file` (YAML, or JSON since it's a subset). Every listed
+/// section replaces that rule set wholesale; a section left out keeps whatever rules
+/// are already in memory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RulesConfig {
+    pub mocks: Option<Vec<CreateMockRule>>,
+    pub modifiers: Option<Vec<CreateModifierRule>>,
+    pub rate_limits: Option<Vec<CreateRateLimitRule>>,
+    pub latency_rules: Option<Vec<CreateLatencyRule>>,
+    pub record_filter: Option<Vec<RecordFilterRule>>,
+}
+
+/// The rule managers a rules file is applied to
+#[derive(Clone)]
+pub struct RuleManagers {
+    pub mock_manager: MockManager,
+    pub response_modifier: ResponseModifier,
+    pub rate_limiter: RateLimiter,
+    pub latency_injector: LatencyInjector,
+    pub record_filter: RecordFilter,
+}
+
+impl RulesConfig {
+    /// Read, parse and validate a rules file
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read rules file {}", path.display()))?;
+        let rules: RulesConfig = serde_yaml::from_str(&contents)
+            .with_context(|| format!("Invalid rules file {}", path.display()))?;
+        rules
+            .validate()
+            .map_err(|e| anyhow!("Invalid rules file {}: {}", path.display(), e))?;
+        Ok(rules)
+    }
+
+    /// Catch what serde can't, so a broken rule never replaces a working ruleset
+    fn validate(&self) -> Result<(), String> {
+        for rule in self.mocks.iter().flatten() {
+            check_pattern("mock", &rule.name, &rule.url_match_type, &rule.url_pattern)?;
+            if let (Some(MatchType::Regex), Some(pattern)) =
+                (&rule.body_match_type, &rule.body_pattern)
+            {
+                check_pattern("mock", &rule.name, &MatchType::Regex, pattern)?;
+            }
+        }
+        for rule in self.modifiers.iter().flatten() {
+            let request = &rule.match_request;
+            check_pattern(
+                "modifier",
+                &rule.name,
+                &request.url_match_type,
+                &request.url_pattern,
+            )?;
+            if let Some(ModifierCondition::BodyMatchesRegex(pattern)) = &rule.condition {
+                check_pattern("modifier", &rule.name, &MatchType::Regex, pattern)?;
+            }
+        }
+        for rule in self.rate_limits.iter().flatten() {
+            let request = &rule.match_request;
+            check_pattern(
+                "rate limit",
+                &rule.name,
+                &request.url_match_type,
+                &request.url_pattern,
+            )?;
+            request
+                .key_type
+                .validate()
+                .map_err(|e| format!("rate limit '{}': {}", rule.name, e))?;
+        }
+        for rule in self.latency_rules.iter().flatten() {
+            let request = &rule.match_request;
+            check_pattern(
+                "latency rule",
+                &rule.name,
+                &request.url_match_type,
+                &request.url_pattern,
+            )?;
+        }
+        for rule in self.record_filter.iter().flatten() {
+            check_pattern(
+                "record filter",
+                &rule.pattern,
+                &rule.match_type,
+                &rule.pattern,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Swap the listed rule sets into the managers. Rules get fresh ids on every load.
+    pub fn apply(self, managers: &RuleManagers) {
+        if let Some(mocks) = self.mocks {
+            let rules = mocks.into_iter().map(MockRule::from).collect();
+            let default_rule = managers.mock_manager.get_default_rule();
+            managers.mock_manager.replace_all(rules, default_rule);
+        }
+        if let Some(modifiers) = self.modifiers {
+            let rules = modifiers.into_iter().map(ModifierRule::from).collect();
+            managers.response_modifier.replace_all(rules);
+        }
+        if let Some(rate_limits) = self.rate_limits {
+            let rules = rate_limits.into_iter().map(RateLimitRule::from).collect();
+            managers.rate_limiter.replace_all(rules);
+        }
+        if let Some(latency_rules) = self.latency_rules {
+            let rules = latency_rules.into_iter().map(LatencyRule::from).collect();
+            let stats = managers.latency_injector.get_stats();
+            managers.latency_injector.replace_all(rules, stats);
+        }
+        if let Some(record_filter) = self.record_filter {
+            managers.record_filter.replace_all(record_filter);
+        }
+    }
+}
+
+fn check_pattern(
+    kind: &str,
+    name: &str,
+    match_type: &MatchType,
+    pattern: &str,
+) -> Result<(), String> {
+    match match_type {
+        MatchType::Regex => regex::Regex::new(pattern)
+            .map(|_| ())
+            .map_err(|e| format!("{} '{}': invalid regex: {}", kind, name, e)),
+        _ => Ok(()),
+    }
+}
+
+/// Re-read the rules file whenever the process gets SIGHUP. A file that fails to load
+/// is reported and the rules already in memory stay in place.
+#[cfg(unix)]
+pub fn reload_on_sighup(path: PathBuf, managers: RuleManagers) {
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        rt.block_on(async {
+            use tokio::signal::unix::{SignalKind, signal};
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(e) => {
+                    eprintln!("Can't listen for SIGHUP, rules won't reload: {}", e);
+                    return;
+                }
+            };
+            while hangups.recv().await.is_some() {
+                match RulesConfig::load(&path) {
+                    Ok(rules) => {
+                        rules.apply(&managers);
+                        println!("🔄 Reloaded rules from {}", path.display());
+                    }
+                    Err(e) => eprintln!("Keeping the current rules: {:#}", e),
+                }
+            }
+        });
+    });
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_sighup(_path: PathBuf, _managers: RuleManagers) {}
@@ -0,0 +1,40 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Optional time window during which a rule is active, shared by all rule types.
+/// Outside the window the rule behaves as if it were disabled.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ActiveWindow {
+    pub active_from: Option<DateTime<Utc>>,
+    pub active_until: Option<DateTime<Utc>>,
+    // Relative alternative to `active_until`, counted from `active_from` or from when the rule is saved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_for_seconds: Option<u64>,
+}
+
+impl ActiveWindow {
+    /// Turn `active_for_seconds` into an absolute `active_until` so the window
+    /// doesn't move when the rule is read back later
+    pub fn resolve(self) -> Self {
+        let active_until = match (self.active_until, self.active_for_seconds) {
+            (Some(until), _) => Some(until),
+            (None, Some(seconds)) => {
+                let start = self.active_from.unwrap_or_else(Utc::now);
+                Some(start + Duration::seconds(seconds as i64))
+            }
+            (None, None) => None,
+        };
+
+        Self {
+            active_from: self.active_from,
+            active_until,
+            active_for_seconds: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        let now = Utc::now();
+        self.active_from.is_none_or(|from| now >= from)
+            && self.active_until.is_none_or(|until| now < until)
+    }
+}
@@ -0,0 +1,118 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Elements of an array that are inspected when inferring its item shape
+const ARRAY_SAMPLE_SIZE: usize = 20;
+
+/// Inferred shape of a JSON document: every field path with the types seen there.
+/// Object fields are joined with `.`, array items are written as `[]`, e.g.
+/// `users[].address.city`. The root value has the path `$`.
+pub type JsonShape = BTreeMap<String, BTreeSet<&'static str>>;
+
+pub fn json_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Infer the shape of a JSON value. Arrays are sampled, and the shapes of the sampled
+/// items are merged, so a field that only some items have is still reported.
+pub fn infer_shape(value: &Value) -> JsonShape {
+    let mut shape = JsonShape::new();
+    collect_shape(value, "$", &mut shape);
+    shape
+}
+
+fn collect_shape(value: &Value, path: &str, shape: &mut JsonShape) {
+    shape
+        .entry(path.to_string())
+        .or_default()
+        .insert(json_type_name(value));
+
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map {
+                let child_path = if path == "$" {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                collect_shape(child, &child_path, shape);
+            }
+        }
+        Value::Array(items) => {
+            let item_path = if path == "$" {
+                "[]".to_string()
+            } else {
+                format!("{}[]", path)
+            };
+            for item in items.iter().take(ARRAY_SAMPLE_SIZE) {
+                collect_shape(item, &item_path, shape);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// A field whose presence or type differed between responses
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldDrift {
+    pub path: String,
+    pub present_in: usize,
+    pub missing_in: usize,
+    /// Number of responses each type was seen in
+    pub types: BTreeMap<&'static str, usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SchemaDrift {
+    pub endpoint: String,
+    pub responses_analyzed: usize,
+    pub stable_fields: usize,
+    pub drifting_fields: Vec<FieldDrift>,
+}
+
+/// Compare the shapes of several responses. A field is stable when every response has it
+/// with the same single type; anything else is reported as drift.
+pub fn compare_shapes(endpoint: &str, shapes: &[JsonShape]) -> SchemaDrift {
+    let mut fields: BTreeMap<&str, (usize, BTreeMap<&'static str, usize>)> = BTreeMap::new();
+    for shape in shapes {
+        for (path, types) in shape {
+            let (present_in, type_counts) = fields.entry(path.as_str()).or_default();
+            *present_in += 1;
+            for type_name in types {
+                *type_counts.entry(type_name).or_default() += 1;
+            }
+        }
+    }
+
+    let total = shapes.len();
+    let mut stable_fields = 0;
+    let mut drifting_fields = Vec::new();
+    for (path, (present_in, types)) in fields {
+        if present_in == total && types.len() == 1 {
+            stable_fields += 1;
+            continue;
+        }
+        drifting_fields.push(FieldDrift {
+            path: path.to_string(),
+            present_in,
+            missing_in: total - present_in,
+            types,
+        });
+    }
+
+    SchemaDrift {
+        endpoint: endpoint.to_string(),
+        responses_analyzed: total,
+        stable_fields,
+        drifting_fields,
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Phase {
+    Running,
+    // Listeners have stopped accepting; in-flight requests are finishing
+    Draining,
+    // Drained and flushed; Pingora may stop
+    Stopped,
+}
+
+/// Graceful shutdown on Ctrl-C, shared by the HTTP layer, the UI server and Pingora,
+/// which each run on their own runtime. The HTTP layer drains its in-flight requests
+/// before Pingora, which they are forwarded through, is stopped.
+#[derive(Clone)]
+pub struct Shutdown {
+    phase: Arc<watch::Sender<Phase>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            phase: Arc::new(watch::channel(Phase::Running).0),
+        }
+    }
+
+    /// Start draining on the first Ctrl-C; a second one exits right away
+    pub fn drain_on_ctrl_c(&self) {
+        let shutdown = self.clone();
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+
+            rt.block_on(async {
+                if let Err(e) = tokio::signal::ctrl_c().await {
+                    eprintln!("Can't listen for Ctrl-C, shutdown won't be graceful: {}", e);
+                    return;
+                }
+                println!("\n🛑 Shutting down (Ctrl-C again to exit immediately)...");
+                shutdown.phase.send_replace(Phase::Draining);
+
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    eprintln!("Exiting without waiting for in-flight requests");
+                    std::process::exit(130);
+                }
+            });
+        });
+    }
+
+    /// Resolves once Ctrl-C has been pressed
+    pub async fn draining(&self) {
+        self.wait_for(Phase::Draining).await;
+    }
+
+    /// Mark in-flight requests drained and recordings flushed
+    pub fn stop(&self) {
+        self.phase.send_replace(Phase::Stopped);
+    }
+
+    /// Resolves once `stop` has been called
+    pub async fn stopped(&self) {
+        self.wait_for(Phase::Stopped).await;
+    }
+
+    async fn wait_for(&self, phase: Phase) {
+        let mut receiver = self.phase.subscribe();
+        // The sender lives in `self`, so the channel can't close while we wait
+        let _ = receiver.wait_for(|current| *current >= phase).await;
+    }
+}
@@ -0,0 +1,123 @@
+use crate::latency_injector::{LatencyInjector, LatencyRule, LatencyStats};
+use crate::mock::{MockManager, MockRule};
+use crate::modifier::{ModifierRule, ResponseModifier};
+use crate::rate_limiter::{RateLimitRule, RateLimiter};
+use crate::storage::{RecordedRequest, Storage};
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Full runtime state captured under a name: recordings, every rule set and latency stats.
+/// Rate limit buckets aren't captured; they start empty after a restore.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub recordings: Vec<RecordedRequest>,
+    pub mock_rules: Vec<MockRule>,
+    pub default_mock_rule: Option<MockRule>,
+    pub modifier_rules: Vec<ModifierRule>,
+    pub rate_limit_rules: Vec<RateLimitRule>,
+    pub latency_rules: Vec<LatencyRule>,
+    pub latency_stats: LatencyStats,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub recordings: usize,
+    pub mock_rules: usize,
+    pub modifier_rules: usize,
+    pub rate_limit_rules: usize,
+    pub latency_rules: usize,
+}
+
+impl Snapshot {
+    pub fn capture(
+        name: &str,
+        storage: &Storage,
+        mock_manager: &MockManager,
+        response_modifier: &ResponseModifier,
+        rate_limiter: &RateLimiter,
+        latency_injector: &LatencyInjector,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            created_at: Utc::now(),
+            recordings: storage.get_all(),
+            mock_rules: mock_manager.get_all_rules(),
+            default_mock_rule: mock_manager.get_default_rule(),
+            modifier_rules: response_modifier.get_all_rules(),
+            rate_limit_rules: rate_limiter.get_all_rules(),
+            latency_rules: latency_injector.get_all_rules(),
+            latency_stats: latency_injector.get_stats(),
+        }
+    }
+
+    pub fn restore(
+        &self,
+        storage: &Storage,
+        mock_manager: &MockManager,
+        response_modifier: &ResponseModifier,
+        rate_limiter: &RateLimiter,
+        latency_injector: &LatencyInjector,
+    ) {
+        storage.replace_all(self.recordings.clone());
+        mock_manager.replace_all(self.mock_rules.clone(), self.default_mock_rule.clone());
+        response_modifier.replace_all(self.modifier_rules.clone());
+        rate_limiter.replace_all(self.rate_limit_rules.clone());
+        latency_injector.replace_all(self.latency_rules.clone(), self.latency_stats.clone());
+    }
+
+    pub fn info(&self) -> SnapshotInfo {
+        SnapshotInfo {
+            name: self.name.clone(),
+            created_at: self.created_at,
+            recordings: self.recordings.len(),
+            mock_rules: self.mock_rules.len(),
+            modifier_rules: self.modifier_rules.len(),
+            rate_limit_rules: self.rate_limit_rules.len(),
+            latency_rules: self.latency_rules.len(),
+        }
+    }
+}
+
+/// In-memory named snapshots; saving under an existing name replaces it
+#[derive(Clone)]
+pub struct SnapshotStore {
+    snapshots: Arc<RwLock<HashMap<String, Snapshot>>>,
+}
+
+impl SnapshotStore {
+    pub fn new() -> Self {
+        Self {
+            snapshots: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub fn save(&self, snapshot: Snapshot) -> SnapshotInfo {
+        let info = snapshot.info();
+        self.snapshots
+            .write()
+            .insert(snapshot.name.clone(), snapshot);
+        info
+    }
+
+    pub fn get(&self, name: &str) -> Option<Snapshot> {
+        self.snapshots.read().get(name).cloned()
+    }
+
+    pub fn list(&self) -> Vec<SnapshotInfo> {
+        let snapshots = self.snapshots.read();
+        let mut infos: Vec<_> = snapshots.values().map(Snapshot::info).collect();
+        infos.sort_by_key(|info| info.created_at);
+        infos
+    }
+
+    pub fn delete(&self, name: &str) -> bool {
+        self.snapshots.write().remove(name).is_some()
+    }
+}
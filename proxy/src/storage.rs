@@ -1,8 +1,18 @@
+use crate::explain::Explanation;
+use crate::matcher::is_dynamic_segment;
+use crate::schema::{SchemaDrift, compare_shapes, infer_shape};
+use base64::Engine;
 use chrono::{DateTime, Duration, Utc};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use tokio::sync::broadcast;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,6 +25,159 @@ pub struct RecordedRequest {
     pub body: Option<Vec<u8>>,
     pub response: Option<RecordedResponse>,
     pub duration_ms: Option<u64>,
+    /// Charset the body was transcoded from when it wasn't UTF-8
+    #[serde(default)]
+    pub original_charset: Option<String>,
+    /// Only the first `--max-body-bytes` of the body were kept
+    #[serde(default)]
+    pub body_truncated: bool,
+    /// Artificial delay added by latency rules, already included in `duration_ms`
+    #[serde(default)]
+    pub injected_latency_ms: Option<u64>,
+    /// Answered with 304 from the ETag cache without contacting upstream
+    #[serde(default)]
+    pub cache_hit: bool,
+    /// Attempts made when this recording is the result of a replay
+    #[serde(default)]
+    pub replay: Option<ReplayInfo>,
+    /// Value of the configured correlation header, shared by requests of one flow
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    /// Matching decision trace, when the request asked for one
+    #[serde(default)]
+    pub explanation: Option<Explanation>,
+    /// Where the response came from when it isn't an upstream answer or a rule mock
+    #[serde(default)]
+    pub origin: Option<ResponseOrigin>,
+}
+
+/// Responses the proxy produced or cut short itself; auto-mock never serves these again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ResponseOrigin {
+    // Answered without contacting upstream because its circuit was open
+    CircuitOpen,
+    // Upstream failed partway, so the recorded response is incomplete
+    UpstreamError { error: String },
+    // Served by `--auto-mock` from an earlier recording
+    AutoMock { recording_id: String },
+}
+
+/// Upper bounds (exclusive) of the endpoint latency histogram buckets, in ms: 0-10, 10-50,
+/// 50-100, 100-500, 500-1000, plus a final 1000+ bucket
+pub const ENDPOINT_HISTOGRAM_BOUNDS_MS: [u64; 5] = [10, 50, 100, 500, 1000];
+
+/// Completed recordings buffered per live subscriber before it starts lagging
+const RECORDING_EVENT_CAPACITY: usize = 256;
+
+/// Frames kept per WebSocket session; later frames are only counted
+pub const MAX_WS_FRAMES_PER_SESSION: usize = 1000;
+/// Payload bytes kept per WebSocket frame; the rest is cut off
+pub const MAX_WS_FRAME_BYTES: usize = 16 * 1024;
+/// WebSocket sessions kept; the oldest is dropped when a new one starts
+pub const MAX_WS_SESSIONS: usize = 100;
+
+/// A proxied WebSocket connection and the frames exchanged over it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsSession {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub url: String,
+    pub headers: HashMap<String, String>,
+    pub closed_at: Option<DateTime<Utc>>,
+    pub frames: Vec<WsFrame>,
+    /// Frames not kept because the session hit MAX_WS_FRAMES_PER_SESSION
+    pub dropped_frames: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsFrame {
+    pub timestamp: DateTime<Utc>,
+    pub direction: WsDirection,
+    pub kind: WsFrameKind,
+    /// Text as-is, binary as base64, both cut to MAX_WS_FRAME_BYTES
+    pub data: String,
+    pub size: usize,
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WsDirection {
+    ClientToServer,
+    ServerToClient,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WsFrameKind {
+    Text,
+    Binary,
+}
+
+/// WebSocket session without its frames, for the list endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct WsSessionSummary {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub url: String,
+    pub closed_at: Option<DateTime<Utc>>,
+    pub frame_count: usize,
+    pub dropped_frames: usize,
+}
+
+impl WsFrame {
+    fn new(direction: WsDirection, kind: WsFrameKind, payload: &[u8]) -> Self {
+        let truncated = payload.len() > MAX_WS_FRAME_BYTES;
+        let kept = &payload[..payload.len().min(MAX_WS_FRAME_BYTES)];
+        let data = match kind {
+            WsFrameKind::Text => String::from_utf8_lossy(kept).into_owned(),
+            WsFrameKind::Binary => base64::engine::general_purpose::STANDARD.encode(kept),
+        };
+
+        Self {
+            timestamp: Utc::now(),
+            direction,
+            kind,
+            data,
+            size: payload.len(),
+            truncated,
+        }
+    }
+}
+
+/// Recording as returned by the list endpoint. Bodies are dropped unless requested,
+/// their sizes are always reported so the list can show them without the payload.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingSummary {
+    #[serde(flatten)]
+    pub recording: RecordedRequest,
+    pub request_body_size: usize,
+    pub response_body_size: usize,
+}
+
+impl RecordingSummary {
+    pub fn new(mut recording: RecordedRequest, include_bodies: bool) -> Self {
+        let request_body_size = recording.body.as_ref().map_or(0, Vec::len);
+        let response_body_size = recording
+            .response
+            .as_ref()
+            .and_then(|r| r.body.as_ref())
+            .map_or(0, Vec::len);
+
+        if !include_bodies {
+            recording.body = None;
+            if let Some(ref mut response) = recording.response {
+                response.body = None;
+            }
+        }
+
+        Self {
+            recording,
+            request_body_size,
+            response_body_size,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,9 +185,84 @@ pub struct RecordedResponse {
     pub status: u16,
     pub headers: HashMap<String, String>,
     pub body: Option<Vec<u8>>,
+    #[serde(default)]
+    pub original_charset: Option<String>,
+    /// Only the first `--max-body-bytes` of the body were kept
+    #[serde(default)]
+    pub body_truncated: bool,
+    /// Bytes the body had on the wire, even when it was truncated or not kept
+    #[serde(default)]
+    pub body_size: usize,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Body bytes collected for a recording while the body streams through, up to a cap
+#[derive(Debug, Default)]
+pub struct CapturedBody {
+    pub data: Vec<u8>,
+    pub truncated: bool,
+    // Every byte seen, including those past the cap
+    pub size: usize,
+}
+
+impl CapturedBody {
+    /// Keep as much of `chunk` as fits in `max_bytes` (0 means no cap)
+    pub fn append(&mut self, chunk: &[u8], max_bytes: usize) {
+        let room = match max_bytes {
+            0 => chunk.len(),
+            max_bytes => max_bytes.saturating_sub(self.data.len()),
+        };
+        if chunk.len() > room {
+            self.truncated = true;
+        }
+        self.size += chunk.len();
+        self.data.extend_from_slice(&chunk[..chunk.len().min(room)]);
+    }
+}
+
+/// Field the recordings list is ordered by
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingSort {
+    #[default]
+    Timestamp,
+    DurationMs,
+    Status,
+}
+
+impl FromStr for RecordingSort {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "timestamp" => Ok(RecordingSort::Timestamp),
+            "duration_ms" => Ok(RecordingSort::DurationMs),
+            "status" => Ok(RecordingSort::Status),
+            _ => Err(format!(
+                "Invalid sort '{}', expected timestamp, duration_ms or status",
+                value
+            )),
+        }
+    }
+}
+
+/// Which slice of the filtered recordings to return, and in what order
+#[derive(Debug, Clone, Default)]
+pub struct PageOptions {
+    pub sort: RecordingSort,
+    // Largest first unless set, so the default is newest first
+    pub ascending: bool,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+/// One page of recordings; `total` counts every recording matching the filters
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingPage<T> {
+    pub total: usize,
+    pub items: Vec<T>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FilterOptions {
     pub search: Option<String>,
     pub method: Option<String>,
@@ -33,6 +271,158 @@ pub struct FilterOptions {
     pub max_duration: Option<u64>,
     pub from_time: Option<DateTime<Utc>>,
     pub to_time: Option<DateTime<Utc>>,
+    // Set by operators in `search`, see `apply_search_query`
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub min_status: Option<u16>,
+    #[serde(default)]
+    pub max_status: Option<u16>,
+    #[serde(default)]
+    pub min_size: Option<usize>,
+    #[serde(default)]
+    pub max_size: Option<usize>,
+    // Request or response header by case-insensitive name; no value means any value
+    #[serde(default)]
+    pub header_name: Option<String>,
+    #[serde(default)]
+    pub header_value: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+// Longer operators first, so `>=` isn't read as `>` followed by `=`
+const COMPARISONS: &[(&str, Comparison)] = &[
+    (">=", Comparison::Ge),
+    ("<=", Comparison::Le),
+    (">", Comparison::Gt),
+    ("<", Comparison::Lt),
+    (":", Comparison::Eq),
+    ("=", Comparison::Eq),
+];
+
+impl FilterOptions {
+    /// Fold field operators in a search string into the filters, e.g.
+    /// `status:500 method:POST url:/api/users duration>200 size>=10000`. `status:5xx`
+    /// matches a whole class. Tokens that aren't operators, and "quoted phrases", stay
+    /// free text searched in the URL, method and bodies.
+    pub fn apply_search_query(&mut self, query: &str) {
+        let mut free_text = Vec::new();
+        for (token, quoted) in search_tokens(query) {
+            if quoted || !self.apply_search_operator(&token) {
+                free_text.push(token);
+            }
+        }
+        self.search = (!free_text.is_empty()).then(|| free_text.join(" "));
+    }
+
+    /// Whether `token` was a recognised `field<op>value`
+    fn apply_search_operator(&mut self, token: &str) -> bool {
+        let Some(end) = token.find([':', '=', '<', '>']) else {
+            return false;
+        };
+        let (field, rest) = token.split_at(end);
+        let Some((comparison, value)) = COMPARISONS
+            .iter()
+            .find_map(|(op, comparison)| rest.strip_prefix(op).map(|value| (*comparison, value)))
+            .filter(|(_, value)| !value.is_empty())
+        else {
+            return false;
+        };
+
+        match (field.to_ascii_lowercase().as_str(), comparison) {
+            ("method", Comparison::Eq) => self.method = Some(value.to_uppercase()),
+            ("url", Comparison::Eq) => self.url = Some(value.to_string()),
+            ("status", Comparison::Eq) if value.len() == 3 && value.ends_with("xx") => {
+                let Some(class) = value[..1]
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|c| (1..=5).contains(c))
+                else {
+                    return false;
+                };
+                self.min_status = Some(class * 100);
+                self.max_status = Some(class * 100 + 99);
+            }
+            ("status", Comparison::Eq) => match value.parse() {
+                Ok(status) => self.status = Some(status),
+                Err(_) => return false,
+            },
+            ("status", comparison) => {
+                let Some((min, max)) = bounds(comparison, value) else {
+                    return false;
+                };
+                let to_status = |bound: Option<u64>| bound.map(|b| b.min(u16::MAX as u64) as u16);
+                self.min_status = to_status(min).or(self.min_status);
+                self.max_status = to_status(max).or(self.max_status);
+            }
+            ("duration", comparison) => {
+                let Some((min, max)) = bounds(comparison, value.trim_end_matches("ms")) else {
+                    return false;
+                };
+                self.min_duration = min.or(self.min_duration);
+                self.max_duration = max.or(self.max_duration);
+            }
+            ("size", comparison) => {
+                let Some((min, max)) = bounds(comparison, value) else {
+                    return false;
+                };
+                self.min_size = min.map(|b| b as usize).or(self.min_size);
+                self.max_size = max.map(|b| b as usize).or(self.max_size);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Inclusive (min, max) a numeric comparison allows, None if the value isn't a number or
+/// nothing can satisfy it
+fn bounds(comparison: Comparison, value: &str) -> Option<(Option<u64>, Option<u64>)> {
+    let value: u64 = value.parse().ok()?;
+    Some(match comparison {
+        Comparison::Eq => (Some(value), Some(value)),
+        Comparison::Gt => (Some(value.checked_add(1)?), None),
+        Comparison::Ge => (Some(value), None),
+        Comparison::Lt => (None, Some(value.checked_sub(1)?)),
+        Comparison::Le => (None, Some(value)),
+    })
+}
+
+/// Split a search string on whitespace, keeping double-quoted runs together. The flag is
+/// set for tokens that start with a quote, which are taken literally.
+fn search_tokens(query: &str) -> Vec<(String, bool)> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+
+    for c in query.chars() {
+        match c {
+            '"' => {
+                quoted |= current.is_empty() && !in_quotes;
+                in_quotes = !in_quotes;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    tokens.push((std::mem::take(&mut current), quoted));
+                }
+                quoted = false;
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push((current, quoted));
+    }
+    tokens
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,26 +431,576 @@ pub struct ReplayRequest {
     pub url: String,
     pub headers: HashMap<String, String>,
     pub body: Option<Vec<u8>>,
+    pub correlation_id: Option<String>,
+}
+
+/// Optional body of `POST /api/recordings/{id}/replay`, applied to the recorded request
+/// before it's sent
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplayOverrides {
+    // Replaces the header of the same name, case-insensitively; an empty value removes it
+    #[serde(default)]
+    pub header_overrides: HashMap<String, String>,
+    #[serde(default)]
+    pub body: Option<String>,
+}
+
+impl ReplayRequest {
+    pub fn apply_overrides(&mut self, overrides: ReplayOverrides) {
+        for (name, value) in overrides.header_overrides {
+            self.headers
+                .retain(|existing, _| !existing.eq_ignore_ascii_case(&name));
+            if !value.is_empty() {
+                self.headers.insert(name, value);
+            }
+        }
+
+        if let Some(body) = overrides.body {
+            // The recorded length no longer fits; the client sets the right one
+            self.headers
+                .retain(|name, _| !name.eq_ignore_ascii_case("content-length"));
+            self.body = Some(body.into_bytes());
+        }
+    }
+}
+
+/// How replay reacts to throttling responses (429/503 with `Retry-After`)
+#[derive(Debug, Clone, Default)]
+pub struct ReplayRetryPolicy {
+    pub honor_retry_after: bool,
+    pub max_retries: u32,
+}
+
+impl ReplayRetryPolicy {
+    pub const DEFAULT_MAX_RETRIES: u32 = 3;
+    pub const MAX_RETRIES_CAP: u32 = 10;
+    // Longest Retry-After we'll actually wait for
+    pub const MAX_WAIT_MS: u64 = 60_000;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayInfo {
+    pub attempts: u32,
+    pub history: Vec<ReplayAttempt>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayAttempt {
+    pub status: u16,
+    pub duration_ms: u64,
+    // Wait before the next attempt, when the upstream asked for one and we retried
+    pub retry_after_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkReplayRequest {
+    pub ids: Vec<String>,
+    pub upstream: Option<String>,
+    pub concurrency: Option<usize>,
+}
+
+/// Body of `POST /api/recordings/replay-batch`: recordings picked by id or by filter,
+/// replayed oldest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchReplayRequest {
+    #[serde(default)]
+    pub ids: Vec<String>,
+    #[serde(default)]
+    pub filter: Option<FilterOptions>,
+    pub upstream: Option<String>,
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkReplayItem {
+    pub id: String,
+    #[serde(flatten)]
+    pub outcome: BulkReplayOutcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum BulkReplayOutcome {
+    Replayed { response: Box<RecordedRequest> },
+    Failed { error: String },
+    NotFound,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkReplayResult {
+    pub total: usize,
+    pub replayed: usize,
+    pub failed: usize,
+    pub not_found: usize,
+    pub avg_duration_ms: u64,
+    pub results: Vec<BulkReplayItem>,
+}
+
+impl BulkReplayResult {
+    pub fn from_items(results: Vec<BulkReplayItem>) -> Self {
+        let mut replayed = 0;
+        let mut failed = 0;
+        let mut not_found = 0;
+        let mut total_duration = 0u64;
+
+        for item in &results {
+            match &item.outcome {
+                BulkReplayOutcome::Replayed { response } => {
+                    replayed += 1;
+                    total_duration += response.duration_ms.unwrap_or(0);
+                }
+                BulkReplayOutcome::Failed { .. } => failed += 1,
+                BulkReplayOutcome::NotFound => not_found += 1,
+            }
+        }
+
+        Self {
+            total: results.len(),
+            replayed,
+            failed,
+            not_found,
+            avg_duration_ms: if replayed > 0 {
+                total_duration / replayed as u64
+            } else {
+                0
+            },
+            results,
+        }
+    }
+}
+
+/// (timestamp, id) of every recording, oldest first
+type RecordingOrder = BTreeSet<(DateTime<Utc>, String)>;
+
+/// Newest complete recording per request, so auto-mock lookups don't scan every
+/// recording. Entries may go stale when a recording changes; lookups re-check them.
+#[derive(Default)]
+struct CompletedIndex {
+    // (method, url) -> (timestamp, id)
+    by_request: HashMap<(String, String), (DateTime<Utc>, String)>,
+    // (method, url, request body hash) -> (timestamp, id)
+    by_body: HashMap<(String, String, u64), (DateTime<Utc>, String)>,
+}
+
+impl CompletedIndex {
+    fn rebuild(recordings: &HashMap<String, RecordedRequest>) -> Self {
+        let mut index = Self::default();
+        for request in recordings.values() {
+            index.insert(request);
+        }
+        index
+    }
+
+    fn insert(&mut self, request: &RecordedRequest) {
+        if !is_complete(request) {
+            return;
+        }
+        let entry = (request.timestamp, request.id.clone());
+        let (method, url) = (request.method.clone(), request.url.clone());
+        if !request.body_truncated {
+            let hash = body_hash(request.body.as_deref().unwrap_or_default());
+            keep_newest(
+                self.by_body.entry((method.clone(), url.clone(), hash)),
+                entry.clone(),
+            );
+        }
+        keep_newest(self.by_request.entry((method, url)), entry);
+    }
+
+    /// Forget `request` under its current method, url and body
+    fn remove(&mut self, request: &RecordedRequest) {
+        let key = (request.method.clone(), request.url.clone());
+        let hash = body_hash(request.body.as_deref().unwrap_or_default());
+        let body_key = (key.0.clone(), key.1.clone(), hash);
+        if self
+            .by_request
+            .get(&key)
+            .is_some_and(|(_, id)| *id == request.id)
+        {
+            self.by_request.remove(&key);
+        }
+        if self
+            .by_body
+            .get(&body_key)
+            .is_some_and(|(_, id)| *id == request.id)
+        {
+            self.by_body.remove(&body_key);
+        }
+    }
+
+    fn get(&self, method: &str, url: &str, body: Option<&[u8]>) -> Option<&str> {
+        let (method, url) = (method.to_string(), url.to_string());
+        let entry = match body {
+            Some(body) => self.by_body.get(&(method, url, body_hash(body))),
+            None => self.by_request.get(&(method, url)),
+        };
+        entry.map(|(_, id)| id.as_str())
+    }
+}
+
+fn keep_newest<K>(
+    slot: std::collections::hash_map::Entry<'_, K, (DateTime<Utc>, String)>,
+    entry: (DateTime<Utc>, String),
+) {
+    match slot {
+        std::collections::hash_map::Entry::Occupied(mut slot) => {
+            if slot.get().0 <= entry.0 {
+                slot.insert(entry);
+            }
+        }
+        std::collections::hash_map::Entry::Vacant(slot) => {
+            slot.insert(entry);
+        }
+    }
+}
+
+fn body_hash(body: &[u8]) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Has an upstream response that was captured whole, so it can be served again
+fn is_complete(request: &RecordedRequest) -> bool {
+    !request.cache_hit
+        && request.origin.is_none()
+        && request
+            .response
+            .as_ref()
+            .is_some_and(|response| !response.body_truncated)
 }
 
 #[derive(Clone)]
 pub struct Storage {
     pub recordings: Arc<RwLock<HashMap<String, RecordedRequest>>>,
+    ws_sessions: Arc<RwLock<HashMap<String, WsSession>>>,
+    // Completed recordings, for live streams
+    events: broadcast::Sender<RecordedRequest>,
+    // Requests rejected by load shedding; they never produce a recording
+    shed_requests: Arc<AtomicU64>,
+    // JSONL file every recording change is appended to, with --persist-file
+    persist: Option<PersistLog>,
+    // Recordings ordered by timestamp, so the oldest can be evicted without a scan
+    order: Arc<Mutex<RecordingOrder>>,
+    // Newest complete recording per request, for auto-mock
+    completed: Arc<Mutex<CompletedIndex>>,
+    // Recordings kept in memory; 0 keeps everything
+    max_recordings: usize,
+    // Analytics group `/users/1` and `/users/2` as `/users/{id}`
+    normalize_paths: bool,
+}
+
+/// Lines the persist file may hold beyond two per live recording before it's compacted
+const PERSIST_COMPACT_SLACK: usize = 1000;
+
+/// Work for the `--persist-file` writer thread
+enum PersistOp {
+    Append(Box<RecordedRequest>),
+    Rewrite(Vec<RecordedRequest>),
+    Sync(std::sync::mpsc::Sender<std::io::Result<()>>),
+}
+
+/// Append-only JSONL log of recordings. Each change appends the whole recording again,
+/// so on load the last line for an id wins. Writes happen on a background thread; ops
+/// are sent while the recordings lock is held, so the file sees them in map order.
+#[derive(Clone)]
+struct PersistLog {
+    ops: std::sync::mpsc::Sender<PersistOp>,
+    // Lines in the file, live or superseded, to decide when to compact
+    lines: Arc<AtomicUsize>,
+}
+
+impl PersistLog {
+    fn open(path: PathBuf) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let (ops, queue) = std::sync::mpsc::channel();
+        std::thread::spawn(move || write_persist_ops(PersistFile::new(path, file), queue));
+        Ok(Self {
+            ops,
+            lines: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    fn send(&self, op: PersistOp) {
+        if self.ops.send(op).is_err() {
+            eprintln!("Recording persistence stopped; changes are only kept in memory");
+        }
+    }
+
+    /// Append a recording; true when the file has grown enough to be compacted
+    fn append(&self, request: &RecordedRequest, live: usize) -> bool {
+        self.send(PersistOp::Append(Box::new(request.clone())));
+        let lines = self.lines.fetch_add(1, Ordering::Relaxed) + 1;
+        lines > live * 2 + PERSIST_COMPACT_SLACK
+    }
+
+    fn rewrite<'a>(&self, requests: impl Iterator<Item = &'a RecordedRequest>) {
+        let requests: Vec<_> = requests.cloned().collect();
+        self.lines.store(requests.len(), Ordering::Relaxed);
+        self.send(PersistOp::Rewrite(requests));
+    }
+
+    /// Wait until every op sent so far has reached the disk
+    fn sync(&self) -> std::io::Result<()> {
+        let (done, result) = std::sync::mpsc::channel();
+        self.send(PersistOp::Sync(done));
+        result
+            .recv()
+            .unwrap_or_else(|_| Err(std::io::Error::other("recording persistence stopped")))
+    }
+}
+
+/// Run persist ops until every `PersistLog` is dropped, flushing whenever the queue is
+/// drained
+fn write_persist_ops(mut file: PersistFile, queue: std::sync::mpsc::Receiver<PersistOp>) {
+    while let Ok(op) = queue.recv() {
+        file.apply(op);
+        while let Ok(op) = queue.try_recv() {
+            file.apply(op);
+        }
+        file.flush();
+    }
+}
+
+struct PersistFile {
+    path: PathBuf,
+    writer: std::io::BufWriter<File>,
+}
+
+impl PersistFile {
+    fn new(path: PathBuf, file: File) -> Self {
+        Self {
+            path,
+            writer: std::io::BufWriter::new(file),
+        }
+    }
+
+    fn apply(&mut self, op: PersistOp) {
+        match op {
+            PersistOp::Append(request) => self.append(&request),
+            PersistOp::Rewrite(requests) => self.rewrite(&requests),
+            PersistOp::Sync(done) => {
+                let result = self
+                    .writer
+                    .flush()
+                    .and_then(|_| self.writer.get_ref().sync_data());
+                let _ = done.send(result);
+            }
+        }
+    }
+
+    fn append(&mut self, request: &RecordedRequest) {
+        let result = serde_json::to_writer(&mut self.writer, request)
+            .map_err(std::io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"));
+        if let Err(e) = result {
+            eprintln!(
+                "Failed to persist recording to {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    /// Replace the file's contents with one line per recording
+    fn rewrite(&mut self, requests: &[RecordedRequest]) {
+        let result = self
+            .writer
+            .flush()
+            .and_then(|_| self.writer.get_ref().set_len(0))
+            .and_then(|_| {
+                for request in requests {
+                    serde_json::to_writer(&mut self.writer, request)?;
+                    self.writer.write_all(b"\n")?;
+                }
+                self.writer.flush()
+            });
+        if let Err(e) = result {
+            eprintln!("Failed to rewrite {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            eprintln!("Failed to write {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Read recordings back from a JSONL file. Lines that don't parse, like one cut off by a
+/// crash mid-write, are skipped with a warning.
+fn load_recordings(path: &Path) -> anyhow::Result<HashMap<String, RecordedRequest>> {
+    let mut recordings = HashMap::new();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(recordings),
+        Err(e) => return Err(e.into()),
+    };
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<RecordedRequest>(&line) {
+            Ok(request) => {
+                recordings.insert(request.id.clone(), request);
+            }
+            Err(e) => eprintln!(
+                "Skipping unreadable line {} of {}: {}",
+                index + 1,
+                path.display(),
+                e
+            ),
+        }
+    }
+    Ok(recordings)
 }
 
 impl Storage {
     pub fn new() -> Self {
         Self {
             recordings: Arc::new(RwLock::new(HashMap::new())),
+            ws_sessions: Arc::new(RwLock::new(HashMap::new())),
+            events: broadcast::channel(RECORDING_EVENT_CAPACITY).0,
+            shed_requests: Arc::new(AtomicU64::new(0)),
+            persist: None,
+            order: Arc::new(Mutex::new(BTreeSet::new())),
+            completed: Arc::new(Mutex::new(CompletedIndex::default())),
+            max_recordings: 0,
+            normalize_paths: false,
+        }
+    }
+
+    /// Keep at most `max` recordings, evicting the oldest by timestamp (0 keeps everything)
+    pub fn with_max_recordings(mut self, max: usize) -> Self {
+        self.max_recordings = max;
+        let mut recordings = self.recordings.write();
+        if self.evict_oldest(&mut recordings) {
+            self.persist_all(&recordings);
+        }
+        drop(recordings);
+        self
+    }
+
+    /// Group numeric and UUID path segments in analytics; recordings keep their real URLs
+    pub fn with_path_normalization(mut self, normalize_paths: bool) -> Self {
+        self.normalize_paths = normalize_paths;
+        self
+    }
+
+    /// Key a recording is grouped under in analytics
+    fn endpoint_key(&self, url: &str) -> String {
+        let endpoint = extract_endpoint(url);
+        if self.normalize_paths {
+            normalize_path(&endpoint)
+        } else {
+            endpoint
+        }
+    }
+
+    /// Insert or replace a recording, keeping the order index and the cap in step
+    fn insert_recording(
+        &self,
+        recordings: &mut HashMap<String, RecordedRequest>,
+        request: RecordedRequest,
+    ) {
+        let mut order = self.order.lock();
+        let mut completed = self.completed.lock();
+        if let Some(previous) = recordings.get(&request.id) {
+            order.remove(&(previous.timestamp, previous.id.clone()));
+            completed.remove(previous);
+        }
+        order.insert((request.timestamp, request.id.clone()));
+        completed.insert(&request);
+        recordings.insert(request.id.clone(), request);
+        drop(completed);
+        drop(order);
+        self.evict_oldest(recordings);
+    }
+
+    /// Drop the oldest recordings past `max_recordings`; true when any were dropped
+    fn evict_oldest(&self, recordings: &mut HashMap<String, RecordedRequest>) -> bool {
+        if self.max_recordings == 0 {
+            return false;
+        }
+        let mut order = self.order.lock();
+        let mut evicted = false;
+        while recordings.len() > self.max_recordings {
+            let Some((_, id)) = order.pop_first() else {
+                break;
+            };
+            if let Some(request) = recordings.remove(&id) {
+                self.completed.lock().remove(&request);
+            }
+            evicted = true;
+        }
+        evicted
+    }
+
+    /// Rebuild the order and auto-mock indexes after the whole map was replaced
+    fn reindex(&self, recordings: &HashMap<String, RecordedRequest>) {
+        *self.order.lock() = recordings
+            .values()
+            .map(|request| (request.timestamp, request.id.clone()))
+            .collect();
+        *self.completed.lock() = CompletedIndex::rebuild(recordings);
+    }
+
+    /// Storage backed by a JSONL file: recordings already in it are loaded, and every
+    /// change is appended. The file is compacted on load, which also drops any
+    /// partially written trailing line, and again once superseded and evicted lines
+    /// outnumber the live ones.
+    pub fn with_persistence(path: PathBuf) -> anyhow::Result<Self> {
+        let recordings = load_recordings(&path)?;
+        let persist = PersistLog::open(path)?;
+        persist.rewrite(recordings.values());
+
+        let storage = Self {
+            persist: Some(persist),
+            ..Self::new()
+        };
+        storage.reindex(&recordings);
+        *storage.recordings.write() = recordings;
+        Ok(storage)
+    }
+
+    /// Append the current state of recording `id`, compacting the file when it's due.
+    /// Call with the recordings lock held so ops reach the writer in order.
+    fn persist(&self, recordings: &HashMap<String, RecordedRequest>, id: &str) {
+        let (Some(persist), Some(request)) = (&self.persist, recordings.get(id)) else {
+            return;
+        };
+        if persist.append(request, recordings.len()) {
+            persist.rewrite(recordings.values());
+        }
+    }
+
+    fn persist_all(&self, recordings: &HashMap<String, RecordedRequest>) {
+        if let Some(ref persist) = self.persist {
+            persist.rewrite(recordings.values());
+        }
+    }
+
+    /// Make sure every change to `--persist-file` so far has reached the disk
+    pub fn flush(&self) -> std::io::Result<()> {
+        match self.persist {
+            Some(ref persist) => persist.sync(),
+            None => Ok(()),
         }
     }
 
     pub fn store_request(&self, mut request: RecordedRequest) -> String {
-        let id = Uuid::new_v4().to_string();
-        request.id = id.clone();
+        // Keep a caller-provided id so other layers can correlate with this recording
+        if request.id.is_empty() {
+            request.id = Uuid::new_v4().to_string();
+        }
+        let id = request.id.clone();
 
         let mut recordings = self.recordings.write();
-        recordings.insert(id.clone(), request);
+        self.insert_recording(&mut recordings, request);
+        self.persist(&recordings, &id);
 
         id
     }
@@ -71,6 +1011,95 @@ impl Storage {
             request.response = Some(response);
             request.duration_ms = Some(duration_ms);
         }
+        self.persist(&recordings, id);
+    }
+
+    /// Attribute injected latency to a recording. The delay happened outside the
+    /// window measured by the proxy, so it is added to `duration_ms` as well.
+    pub fn record_injected_latency(&self, id: &str, delay_ms: u64) {
+        let mut recordings = self.recordings.write();
+        if let Some(request) = recordings.get_mut(id) {
+            request.injected_latency_ms = Some(request.injected_latency_ms.unwrap_or(0) + delay_ms);
+            request.duration_ms = request.duration_ms.map(|duration| duration + delay_ms);
+        }
+        self.persist(&recordings, id);
+    }
+
+    pub fn set_explanation(&self, id: &str, explanation: Explanation) {
+        let mut recordings = self.recordings.write();
+        if let Some(request) = recordings.get_mut(id) {
+            request.explanation = Some(explanation);
+        }
+        self.persist(&recordings, id);
+    }
+
+    /// Mark a recording's response as produced or cut short by the proxy
+    pub fn set_origin(&self, id: &str, origin: ResponseOrigin) {
+        let mut recordings = self.recordings.write();
+        if let Some(request) = recordings.get_mut(id) {
+            self.completed.lock().remove(request);
+            request.origin = Some(origin);
+        }
+        self.persist(&recordings, id);
+    }
+
+    pub fn update_bodies(
+        &self,
+        id: &str,
+        request_body: Option<CapturedBody>,
+        response_body: Option<CapturedBody>,
+    ) {
+        // Stored copies are transcoded to UTF-8 so they can be searched and displayed
+        let mut recordings = self.recordings.write();
+        if let Some(request) = recordings.get_mut(id) {
+            let mut completed = self.completed.lock();
+            completed.remove(request);
+            if let Some(body) = request_body {
+                let (data, charset) = transcode_to_utf8(&request.headers, body.data);
+                request.body = Some(data);
+                request.original_charset = charset;
+                request.body_truncated = body.truncated;
+            }
+            if let Some(ref mut response) = request.response
+                && let Some(body) = response_body
+            {
+                let (data, charset) = transcode_to_utf8(&response.headers, body.data);
+                response.body = Some(data);
+                response.original_charset = charset;
+                response.body_truncated = body.truncated;
+                response.body_size = body.size;
+            }
+            completed.insert(request);
+        }
+        self.persist(&recordings, id);
+        drop(recordings);
+
+        // Bodies arrive last, so the recording is complete now
+        self.publish(id);
+    }
+
+    /// Store a recording that already has its response, e.g. a served mock
+    pub fn insert_completed(&self, request: RecordedRequest) {
+        let id = request.id.clone();
+        let mut recordings = self.recordings.write();
+        self.insert_recording(&mut recordings, request);
+        self.persist(&recordings, &id);
+        drop(recordings);
+        self.publish(&id);
+    }
+
+    /// Live feed of completed recordings
+    pub fn subscribe(&self) -> broadcast::Receiver<RecordedRequest> {
+        self.events.subscribe()
+    }
+
+    fn publish(&self, id: &str) {
+        if self.events.receiver_count() == 0 {
+            return;
+        }
+        if let Some(request) = self.get_by_id(id) {
+            let _ = self.events.send(request);
+        }
     }
 
     pub fn get_all(&self) -> Vec<RecordedRequest> {
@@ -80,19 +1109,47 @@ impl Storage {
         requests
     }
 
-    pub fn get_filtered(&self, filters: &FilterOptions) -> Vec<RecordedRequest> {
+    pub fn get_filtered(
+        &self,
+        filters: &FilterOptions,
+        page: &PageOptions,
+    ) -> RecordingPage<RecordedRequest> {
         let recordings = self.recordings.read();
-        let mut requests: Vec<_> = recordings
+        let mut requests: Vec<&RecordedRequest> = recordings
             .values()
             .filter(|req| self.matches_filters(req, filters))
-            .cloned()
             .collect();
 
-        requests.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
-        requests
+        // Ties (and recordings still missing the sort field) fall back to newest first
+        requests.sort_by(|a, b| {
+            let order = match page.sort {
+                RecordingSort::Timestamp => a.timestamp.cmp(&b.timestamp),
+                RecordingSort::DurationMs => a.duration_ms.cmp(&b.duration_ms),
+                RecordingSort::Status => {
+                    let status = |r: &RecordedRequest| r.response.as_ref().map(|r| r.status);
+                    status(a).cmp(&status(b))
+                }
+            };
+            let order = if page.ascending {
+                order
+            } else {
+                order.reverse()
+            };
+            order.then_with(|| b.timestamp.cmp(&a.timestamp))
+        });
+
+        RecordingPage {
+            total: requests.len(),
+            items: requests
+                .into_iter()
+                .skip(page.offset)
+                .take(page.limit.unwrap_or(usize::MAX))
+                .cloned()
+                .collect(),
+        }
     }
 
-    fn matches_filters(&self, request: &RecordedRequest, filters: &FilterOptions) -> bool {
+    pub fn matches_filters(&self, request: &RecordedRequest, filters: &FilterOptions) -> bool {
         // Search filter
         if let Some(ref search) = filters.search {
             let search_lower = search.to_lowercase();
@@ -135,6 +1192,15 @@ impl Storage {
             }
         }
 
+        // URL filter
+        if filters
+            .url
+            .as_ref()
+            .is_some_and(|url| !request.url.to_lowercase().contains(&url.to_lowercase()))
+        {
+            return false;
+        }
+
         // Status code filter
         if let Some(status) = filters.status {
             match &request.response {
@@ -143,6 +1209,50 @@ impl Storage {
             }
         }
 
+        let status = request.response.as_ref().map(|response| response.status);
+        if filters
+            .min_status
+            .is_some_and(|min| status.is_none_or(|status| status < min))
+            || filters
+                .max_status
+                .is_some_and(|max| status.is_none_or(|status| status > max))
+        {
+            return false;
+        }
+
+        // Header filter, on either side of the exchange
+        if let Some(ref header_name) = filters.header_name {
+            let matches = |headers: &HashMap<String, String>| {
+                headers.iter().any(|(name, value)| {
+                    name.eq_ignore_ascii_case(header_name)
+                        && filters
+                            .header_value
+                            .as_ref()
+                            .is_none_or(|wanted| value == wanted)
+                })
+            };
+            if !matches(&request.headers)
+                && !request
+                    .response
+                    .as_ref()
+                    .is_some_and(|response| matches(&response.headers))
+            {
+                return false;
+            }
+        }
+
+        // Response size filters
+        let size = request.response.as_ref().map(|response| response.body_size);
+        if filters
+            .min_size
+            .is_some_and(|min| size.is_none_or(|size| size < min))
+            || filters
+                .max_size
+                .is_some_and(|max| size.is_none_or(|size| size > max))
+        {
+            return false;
+        }
+
         // Duration filters
         if let Some(min_duration) = filters.min_duration {
             match request.duration_ms {
@@ -179,9 +1289,159 @@ impl Storage {
         recordings.get(id).cloned()
     }
 
+    /// Every recording carrying this correlation id, oldest first
+    pub fn get_by_correlation(&self, correlation_id: &str) -> Vec<RecordedRequest> {
+        let recordings = self.recordings.read();
+        let mut requests: Vec<_> = recordings
+            .values()
+            .filter(|req| req.correlation_id.as_deref() == Some(correlation_id))
+            .cloned()
+            .collect();
+        requests.sort_by_key(|req| req.timestamp);
+        requests
+    }
+
+    /// Newest recording of `method` and `url` with a complete response, for auto-mock.
+    /// With `body`, the recorded request body has to be identical too.
+    pub fn find_completed(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<&[u8]>,
+    ) -> Option<RecordedRequest> {
+        let recordings = self.recordings.read();
+        let id = self.completed.lock().get(method, url, body)?.to_string();
+        // The index may point at a recording that changed since, so check it again
+        recordings
+            .get(&id)
+            .filter(|req| req.method == method && req.url == url && is_complete(req))
+            .filter(|req| {
+                body.is_none_or(|body| {
+                    !req.body_truncated && req.body.as_deref().unwrap_or_default() == body
+                })
+            })
+            .cloned()
+    }
+
     pub fn clear(&self) {
         let mut recordings = self.recordings.write();
         recordings.clear();
+        self.order.lock().clear();
+        *self.completed.lock() = CompletedIndex::default();
+        self.persist_all(&recordings);
+        self.ws_sessions.write().clear();
+    }
+
+    /// Remove every recording matching `filters` in one pass under the write lock;
+    /// returns how many were removed
+    pub fn delete_filtered(&self, filters: &FilterOptions) -> usize {
+        let mut recordings = self.recordings.write();
+        let before = recordings.len();
+        let mut order = self.order.lock();
+        recordings.retain(|id, request| {
+            let keep = !self.matches_filters(request, filters);
+            if !keep {
+                order.remove(&(request.timestamp, id.clone()));
+            }
+            keep
+        });
+        drop(order);
+
+        let deleted = before - recordings.len();
+        if deleted > 0 {
+            // An older recording may now be the newest for a request
+            *self.completed.lock() = CompletedIndex::rebuild(&recordings);
+            self.persist_all(&recordings);
+        }
+        deleted
+    }
+
+    pub fn start_ws_session(&self, url: &str, headers: HashMap<String, String>) -> String {
+        let id = Uuid::new_v4().to_string();
+        let mut sessions = self.ws_sessions.write();
+
+        if sessions.len() >= MAX_WS_SESSIONS {
+            let oldest = sessions
+                .values()
+                .min_by_key(|session| session.timestamp)
+                .map(|session| session.id.clone());
+            if let Some(oldest) = oldest {
+                sessions.remove(&oldest);
+            }
+        }
+
+        sessions.insert(
+            id.clone(),
+            WsSession {
+                id: id.clone(),
+                timestamp: Utc::now(),
+                url: url.to_string(),
+                headers,
+                closed_at: None,
+                frames: Vec::new(),
+                dropped_frames: 0,
+            },
+        );
+        id
+    }
+
+    pub fn record_ws_frame(
+        &self,
+        session_id: &str,
+        direction: WsDirection,
+        kind: WsFrameKind,
+        payload: &[u8],
+    ) {
+        let mut sessions = self.ws_sessions.write();
+        let Some(session) = sessions.get_mut(session_id) else {
+            return;
+        };
+
+        if session.frames.len() >= MAX_WS_FRAMES_PER_SESSION {
+            session.dropped_frames += 1;
+        } else {
+            session.frames.push(WsFrame::new(direction, kind, payload));
+        }
+    }
+
+    pub fn close_ws_session(&self, session_id: &str) {
+        if let Some(session) = self.ws_sessions.write().get_mut(session_id) {
+            session.closed_at = Some(Utc::now());
+        }
+    }
+
+    /// All WebSocket sessions, newest first, without their frames
+    pub fn get_ws_sessions(&self) -> Vec<WsSessionSummary> {
+        let sessions = self.ws_sessions.read();
+        let mut summaries: Vec<_> = sessions
+            .values()
+            .map(|session| WsSessionSummary {
+                id: session.id.clone(),
+                timestamp: session.timestamp,
+                url: session.url.clone(),
+                closed_at: session.closed_at,
+                frame_count: session.frames.len() + session.dropped_frames,
+                dropped_frames: session.dropped_frames,
+            })
+            .collect();
+        summaries.sort_by_key(|summary| std::cmp::Reverse(summary.timestamp));
+        summaries
+    }
+
+    pub fn get_ws_session(&self, id: &str) -> Option<WsSession> {
+        self.ws_sessions.read().get(id).cloned()
+    }
+
+    /// Replace all recordings, e.g. when restoring a snapshot
+    pub fn replace_all(&self, new_recordings: Vec<RecordedRequest>) {
+        let mut recordings = self.recordings.write();
+        *recordings = new_recordings
+            .into_iter()
+            .map(|req| (req.id.clone(), req))
+            .collect();
+        self.reindex(&recordings);
+        self.evict_oldest(&mut recordings);
+        self.persist_all(&recordings);
     }
 
     pub fn get_stats(&self) -> RecordingStats {
@@ -195,25 +1455,36 @@ impl Storage {
         let mut total_duration = 0u64;
         let mut min_duration = u64::MAX;
         let mut max_duration = 0u64;
+        let mut total_injected = 0u64;
+        let mut total_bytes = 0u64;
+        let mut responses = 0u64;
+        let mut durations = Vec::new();
+        let mut adjusted_durations = Vec::new();
 
         for req in recordings.values() {
+            match req.response.as_ref().and_then(|r| status_class(r.status)) {
+                Some(StatusClass::Success) => success += 1,
+                Some(StatusClass::Redirect) => redirects += 1,
+                Some(StatusClass::ClientError) => client_errors += 1,
+                Some(StatusClass::ServerError) => server_errors += 1,
+                None => {}
+            }
+
             if let Some(ref response) = req.response {
-                let status = response.status;
-                if status >= 200 && status < 300 {
-                    success += 1;
-                } else if status >= 300 && status < 400 {
-                    redirects += 1;
-                } else if status >= 400 && status < 500 {
-                    client_errors += 1;
-                } else if status >= 500 {
-                    server_errors += 1;
-                }
+                total_bytes += response.body_size as u64;
+                responses += 1;
             }
 
             if let Some(duration) = req.duration_ms {
                 total_duration += duration;
                 min_duration = min_duration.min(duration);
                 max_duration = max_duration.max(duration);
+
+                // Upstream-only duration, excluding latency added by injection rules
+                let injected = req.injected_latency_ms.unwrap_or(0);
+                total_injected += injected;
+                durations.push(duration);
+                adjusted_durations.push(duration.saturating_sub(injected));
             }
         }
 
@@ -223,6 +1494,10 @@ impl Storage {
             0
         };
 
+        durations.sort_unstable();
+        adjusted_durations.sort_unstable();
+        let adjusted_total: u64 = adjusted_durations.iter().sum();
+
         RecordingStats {
             total,
             success,
@@ -236,20 +1511,54 @@ impl Storage {
                 min_duration
             },
             max_duration_ms: max_duration,
+            p50_ms: percentile(&durations, 50.0),
+            p95_ms: percentile(&durations, 95.0),
+            p99_ms: percentile(&durations, 99.0),
+            total_injected_latency_ms: total_injected,
+            adjusted_avg_duration_ms: if total > 0 {
+                adjusted_total / total as u64
+            } else {
+                0
+            },
+            adjusted_min_duration_ms: adjusted_durations.first().copied().unwrap_or(0),
+            adjusted_max_duration_ms: adjusted_durations.last().copied().unwrap_or(0),
+            adjusted_p50_ms: percentile(&adjusted_durations, 50.0),
+            adjusted_p95_ms: percentile(&adjusted_durations, 95.0),
+            adjusted_p99_ms: percentile(&adjusted_durations, 99.0),
+            shed_requests: self.shed_requests.load(Ordering::Relaxed),
+            total_bytes,
+            avg_response_bytes: total_bytes.checked_div(responses).unwrap_or(0),
         }
     }
 
-    pub fn get_analytics(&self) -> Analytics {
+    pub fn record_shed_request(&self) {
+        self.shed_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Analytics over every recording; the timelines cover the last `window_seconds`
+    pub fn get_analytics(&self, window_seconds: i64) -> Analytics {
         let recordings = self.recordings.read();
 
         let mut method_counts: HashMap<String, usize> = HashMap::new();
         let mut status_counts: HashMap<u16, usize> = HashMap::new();
         let mut endpoint_stats: HashMap<String, EndpointStats> = HashMap::new();
-        let mut timeline: Vec<TimelinePoint> = Vec::new();
+        let mut endpoint_durations: HashMap<String, Vec<u64>> = HashMap::new();
 
-        // Group by time intervals (last hour, by minute)
+        // Group by time intervals (the window, by a bucket size that keeps the series short)
+        let window_seconds = window_seconds.clamp(1, MAX_ANALYTICS_WINDOW_SECONDS);
         let now = Utc::now();
-        let one_hour_ago = now - Duration::hours(1);
+        let window_start = now - Duration::seconds(window_seconds);
+        let bucket_seconds = timeline_bucket_seconds(window_seconds);
+        let first_bucket = window_start.timestamp().div_euclid(bucket_seconds) * bucket_seconds;
+        let last_bucket = now.timestamp().div_euclid(bucket_seconds) * bucket_seconds;
+        let bucket_count = (last_bucket - first_bucket) / bucket_seconds + 1;
+        let mut timeline: Vec<TimelinePoint> = (0..bucket_count)
+            .map(|index| TimelinePoint::empty(first_bucket + index * bucket_seconds))
+            .collect();
+        let mut timeline_durations = vec![0u64; timeline.len()];
+        let mut error_rate_timeline: Vec<ErrorRateBucket> = (0..bucket_count)
+            .map(|index| ErrorRateBucket::empty(first_bucket + index * bucket_seconds))
+            .collect();
 
         for req in recordings.values() {
             // Method distribution
@@ -261,7 +1570,7 @@ impl Storage {
             }
 
             // Endpoint statistics
-            let endpoint = extract_endpoint(&req.url);
+            let endpoint = self.endpoint_key(&req.url);
             let entry = endpoint_stats
                 .entry(endpoint.clone())
                 .or_insert(EndpointStats {
@@ -270,26 +1579,62 @@ impl Storage {
                     avg_duration: 0,
                     errors: 0,
                     total_duration: 0,
+                    p50_ms: 0,
+                    p95_ms: 0,
+                    p99_ms: 0,
+                    histogram: vec![0; ENDPOINT_HISTOGRAM_BOUNDS_MS.len() + 1],
+                    total_bytes: 0,
                 });
 
             entry.count += 1;
             if let Some(duration) = req.duration_ms {
                 entry.total_duration += duration;
+                let bucket = ENDPOINT_HISTOGRAM_BOUNDS_MS
+                    .iter()
+                    .position(|bound| duration < *bound)
+                    .unwrap_or(ENDPOINT_HISTOGRAM_BOUNDS_MS.len());
+                entry.histogram[bucket] += 1;
+                endpoint_durations
+                    .entry(endpoint)
+                    .or_default()
+                    .push(duration);
             }
             if let Some(ref response) = req.response {
                 if response.status >= 400 {
                     entry.errors += 1;
                 }
+                entry.total_bytes += response.body_size as u64;
             }
 
-            // Timeline (last hour)
-            if req.timestamp >= one_hour_ago {
-                timeline.push(TimelinePoint {
-                    timestamp: req.timestamp,
-                    duration_ms: req.duration_ms.unwrap_or(0),
-                    status: req.response.as_ref().map(|r| r.status).unwrap_or(0),
-                    method: req.method.clone(),
-                });
+            // Timelines (within the window), bucketed alike
+            if req.timestamp >= window_start {
+                let index =
+                    (req.timestamp.timestamp() - first_bucket).div_euclid(bucket_seconds) as usize;
+                if let Some(point) = timeline.get_mut(index) {
+                    point.count += 1;
+                    timeline_durations[index] += req.duration_ms.unwrap_or(0);
+                    if let Some(ref response) = req.response {
+                        point.status = point.status.max(response.status);
+                    }
+                }
+
+                if let (Some(response), Some(bucket)) =
+                    (&req.response, error_rate_timeline.get_mut(index))
+                {
+                    bucket.total += 1;
+                    bucket.errors += usize::from(response.status >= 400);
+                }
+            }
+        }
+
+        for (point, total_duration) in timeline.iter_mut().zip(timeline_durations) {
+            if point.count > 0 {
+                point.duration_ms = total_duration / point.count as u64;
+            }
+        }
+        for bucket in &mut error_rate_timeline {
+            if bucket.total > 0 {
+                bucket.error_rate = bucket.errors as f64 / bucket.total as f64;
             }
         }
 
@@ -305,15 +1650,169 @@ impl Storage {
         top_endpoints.sort_by(|a, b| b.count.cmp(&a.count));
         top_endpoints.truncate(10); // Top 10
 
-        // Sort timeline
-        timeline.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        // Percentiles only for the endpoints shown, one sort each
+        for stats in &mut top_endpoints {
+            if let Some(durations) = endpoint_durations.get_mut(&stats.endpoint) {
+                durations.sort_unstable();
+                stats.p50_ms = percentile(durations, 50.0);
+                stats.p95_ms = percentile(durations, 95.0);
+                stats.p99_ms = percentile(durations, 99.0);
+            }
+        }
 
         Analytics {
             method_distribution: method_counts,
             status_distribution: status_counts,
             top_endpoints,
+            histogram_bounds_ms: ENDPOINT_HISTOGRAM_BOUNDS_MS.to_vec(),
             timeline,
+            window_seconds,
+            bucket_seconds,
+            error_rate_timeline,
+        }
+    }
+
+    /// How the JSON response shape of one endpoint (path without query) varied across
+    /// its recordings. Responses that aren't JSON are ignored.
+    pub fn get_schema_drift(&self, endpoint: &str) -> SchemaDrift {
+        let recordings = self.recordings.read();
+        let shapes: Vec<_> = recordings
+            .values()
+            .filter(|req| {
+                extract_endpoint(&req.url) == endpoint || self.endpoint_key(&req.url) == endpoint
+            })
+            .filter_map(|req| req.response.as_ref())
+            .filter(|response| {
+                response.headers.iter().any(|(name, value)| {
+                    name.eq_ignore_ascii_case("content-type") && value.contains("json")
+                })
+            })
+            .filter_map(|response| response.body.as_deref())
+            .filter_map(|body| serde_json::from_slice::<serde_json::Value>(body).ok())
+            .map(|json| infer_shape(&json))
+            .collect();
+
+        compare_shapes(endpoint, &shapes)
+    }
+
+    /// Status-class counts per time bucket, from the first to the last recording.
+    /// Empty buckets in between are included so the series can be charted directly.
+    /// Buckets are widened to a multiple of `bucket_seconds` when the span would need more
+    /// than `MAX_STATUS_TIMELINE_BUCKETS`.
+    pub fn get_status_timeline(&self, bucket_seconds: i64) -> Vec<StatusTimelineBucket> {
+        let recordings = self.recordings.read();
+        let timestamps = || {
+            recordings
+                .values()
+                .filter(|req| {
+                    req.response
+                        .as_ref()
+                        .and_then(|r| status_class(r.status))
+                        .is_some()
+                })
+                .map(|req| req.timestamp.timestamp())
+        };
+        let (Some(oldest), Some(newest)) = (timestamps().min(), timestamps().max()) else {
+            return Vec::new();
+        };
+        let bucket_seconds = bucket_seconds.max(1);
+        // One extra bucket covers the first and last not being aligned
+        let needed = (newest - oldest) / bucket_seconds + 2;
+        let bucket_seconds = bucket_seconds
+            * ((needed + MAX_STATUS_TIMELINE_BUCKETS - 1) / MAX_STATUS_TIMELINE_BUCKETS);
+
+        let mut buckets: BTreeMap<i64, StatusTimelineBucket> = BTreeMap::new();
+        for req in recordings.values() {
+            let Some(class) = req.response.as_ref().and_then(|r| status_class(r.status)) else {
+                continue;
+            };
+
+            let start = req.timestamp.timestamp().div_euclid(bucket_seconds) * bucket_seconds;
+            let bucket = buckets
+                .entry(start)
+                .or_insert_with(|| StatusTimelineBucket::empty(start));
+            match class {
+                StatusClass::Success => bucket.success += 1,
+                StatusClass::Redirect => bucket.redirects += 1,
+                StatusClass::ClientError => bucket.client_errors += 1,
+                StatusClass::ServerError => bucket.server_errors += 1,
+            }
+        }
+
+        let (Some(&first), Some(&last)) = (buckets.keys().next(), buckets.keys().next_back())
+        else {
+            return Vec::new();
+        };
+
+        (first..=last)
+            .step_by(bucket_seconds as usize)
+            .map(|start| {
+                buckets
+                    .remove(&start)
+                    .unwrap_or_else(|| StatusTimelineBucket::empty(start))
+            })
+            .collect()
+    }
+
+    /// How many recordings are held and roughly how much memory they take, from one read
+    /// pass over the map
+    pub fn memory_info(&self) -> StorageInfo {
+        let recordings = self.recordings.read();
+        let headers_size = |headers: &HashMap<String, String>| -> usize {
+            headers
+                .iter()
+                .map(|(name, value)| name.len() + value.len())
+                .sum()
+        };
+
+        let mut info = StorageInfo {
+            count: recordings.len(),
+            approx_bytes: 0,
+            oldest_timestamp: None,
+            newest_timestamp: None,
+        };
+        for req in recordings.values() {
+            info.approx_bytes += req.url.len()
+                + req.method.len()
+                + headers_size(&req.headers)
+                + req.body.as_ref().map_or(0, Vec::len);
+            if let Some(ref response) = req.response {
+                info.approx_bytes +=
+                    headers_size(&response.headers) + response.body.as_ref().map_or(0, Vec::len);
+            }
+            info.oldest_timestamp = Some(
+                info.oldest_timestamp
+                    .map_or(req.timestamp, |oldest| oldest.min(req.timestamp)),
+            );
+            info.newest_timestamp = Some(
+                info.newest_timestamp
+                    .map_or(req.timestamp, |newest| newest.max(req.timestamp)),
+            );
+        }
+        info
+    }
+
+    /// These recordings' ids, oldest first and without duplicates; the unknown ids
+    /// instead when there are any
+    pub fn ids_by_timestamp(&self, ids: &[String]) -> Result<Vec<String>, Vec<String>> {
+        let recordings = self.recordings.read();
+        let unknown: Vec<String> = ids
+            .iter()
+            .filter(|id| !recordings.contains_key(*id))
+            .cloned()
+            .collect();
+        if !unknown.is_empty() {
+            return Err(unknown);
         }
+
+        let mut found: Vec<_> = ids
+            .iter()
+            .filter_map(|id| recordings.get(id))
+            .map(|request| (request.timestamp, request.id.clone()))
+            .collect();
+        found.sort();
+        found.dedup();
+        Ok(found.into_iter().map(|(_, id)| id).collect())
     }
 
     pub fn get_for_replay(&self, id: &str) -> Option<ReplayRequest> {
@@ -323,11 +1822,85 @@ impl Storage {
             url: req.url.clone(),
             headers: req.headers.clone(),
             body: req.body.clone(),
+            correlation_id: req.correlation_id.clone(),
         })
     }
 }
 
-fn extract_endpoint(url: &str) -> String {
+/// Decode a body declared with a non-UTF-8 charset in its `content-type` header.
+/// Returns the UTF-8 bytes and the original charset name when transcoding happened.
+fn transcode_to_utf8(
+    headers: &HashMap<String, String>,
+    body: Vec<u8>,
+) -> (Vec<u8>, Option<String>) {
+    let charset = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        .and_then(|(_, value)| {
+            value.split(';').skip(1).find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                if key.trim().eq_ignore_ascii_case("charset") {
+                    Some(value.trim().trim_matches('"').to_string())
+                } else {
+                    None
+                }
+            })
+        });
+
+    let encoding = match charset.and_then(|c| encoding_rs::Encoding::for_label(c.as_bytes())) {
+        Some(encoding) if encoding != encoding_rs::UTF_8 => encoding,
+        _ => return (body, None),
+    };
+
+    let (decoded, _) = encoding.decode_without_bom_handling(&body);
+    (
+        decoded.into_owned().into_bytes(),
+        Some(encoding.name().to_string()),
+    )
+}
+
+/// Nearest-rank percentile over an already sorted slice
+fn percentile(sorted: &[u64], pct: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = ((pct / 100.0) * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank.min(sorted.len() - 1)]
+}
+
+// Most buckets a status timeline returns, however long the recordings span
+const MAX_STATUS_TIMELINE_BUCKETS: i64 = 1000;
+
+/// Analytics window used when none is asked for
+pub const DEFAULT_ANALYTICS_WINDOW_SECONDS: i64 = 3600;
+// Longest window analytics accept, a year
+const MAX_ANALYTICS_WINDOW_SECONDS: i64 = 366 * 86400;
+
+/// Smallest of minute, 5 minute, 15 minute, hour and day buckets that splits the window
+/// into at most 120, so a one hour window is charted per minute and a day per 15 minutes
+fn timeline_bucket_seconds(window_seconds: i64) -> i64 {
+    const BUCKETS: [i64; 5] = [60, 300, 900, 3600, 86400];
+    BUCKETS
+        .into_iter()
+        .find(|bucket| window_seconds / bucket <= 120)
+        .unwrap_or(86400)
+}
+
+/// Replace numeric and UUID-looking path segments with `{id}`
+fn normalize_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| {
+            if is_dynamic_segment(segment) {
+                "{id}"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn extract_endpoint(url: &str) -> String {
     // Extract path without query parameters
     if let Some(path_end) = url.find('?') {
         url[..path_end].to_string()
@@ -346,6 +1919,36 @@ pub struct RecordingStats {
     pub avg_duration_ms: u64,
     pub min_duration_ms: u64,
     pub max_duration_ms: u64,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    pub p99_ms: u64,
+    // Same figures with injected latency subtracted, i.e. real upstream time
+    pub total_injected_latency_ms: u64,
+    pub adjusted_avg_duration_ms: u64,
+    pub adjusted_min_duration_ms: u64,
+    pub adjusted_max_duration_ms: u64,
+    pub adjusted_p50_ms: u64,
+    pub adjusted_p95_ms: u64,
+    pub adjusted_p99_ms: u64,
+    // Requests answered 503 because --max-inflight was reached
+    #[serde(default)]
+    pub shed_requests: u64,
+    // Response body bytes across all recordings, and per recorded response
+    #[serde(default)]
+    pub total_bytes: u64,
+    #[serde(default)]
+    pub avg_response_bytes: u64,
+}
+
+/// Body of `GET /api/storage/info`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageInfo {
+    pub count: usize,
+    // Rough estimate: URL, method, header and body bytes summed over every recording.
+    // Struct overhead, map capacity and allocator slack aren't counted.
+    pub approx_bytes: usize,
+    pub oldest_timestamp: Option<DateTime<Utc>>,
+    pub newest_timestamp: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -353,7 +1956,38 @@ pub struct Analytics {
     pub method_distribution: HashMap<String, usize>,
     pub status_distribution: HashMap<u16, usize>,
     pub top_endpoints: Vec<EndpointStats>,
+    // Bucket bounds of every endpoint's `histogram`, for labelling
+    pub histogram_bounds_ms: Vec<u64>,
     pub timeline: Vec<TimelinePoint>,
+    // Span the timelines cover, and the size of each of their buckets
+    #[serde(default)]
+    pub window_seconds: i64,
+    #[serde(default)]
+    pub bucket_seconds: i64,
+    // Share of responses with status >= 400 per bucket over the window, empty buckets
+    // included
+    #[serde(default)]
+    pub error_rate_timeline: Vec<ErrorRateBucket>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorRateBucket {
+    pub bucket_start: DateTime<Utc>,
+    pub total: usize,
+    pub errors: usize,
+    // errors / total, 0 for an empty bucket
+    pub error_rate: f64,
+}
+
+impl ErrorRateBucket {
+    fn empty(start: i64) -> Self {
+        Self {
+            bucket_start: DateTime::from_timestamp(start, 0).unwrap_or_default(),
+            total: 0,
+            errors: 0,
+            error_rate: 0.0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -363,12 +1997,457 @@ pub struct EndpointStats {
     pub avg_duration: u64,
     pub errors: usize,
     pub total_duration: u64,
+    #[serde(default)]
+    pub p50_ms: u64,
+    #[serde(default)]
+    pub p95_ms: u64,
+    #[serde(default)]
+    pub p99_ms: u64,
+    // Requests per `ENDPOINT_HISTOGRAM_BOUNDS_MS` bucket; all zero without timing data
+    #[serde(default)]
+    pub histogram: Vec<u64>,
+    // Response body bytes served for this endpoint
+    #[serde(default)]
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusTimelineBucket {
+    pub timestamp: DateTime<Utc>,
+    pub success: usize,
+    pub redirects: usize,
+    pub client_errors: usize,
+    pub server_errors: usize,
+}
+
+impl StatusTimelineBucket {
+    fn empty(start: i64) -> Self {
+        Self {
+            timestamp: DateTime::from_timestamp(start, 0).unwrap_or_default(),
+            success: 0,
+            redirects: 0,
+            client_errors: 0,
+            server_errors: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StatusClass {
+    Success,
+    Redirect,
+    ClientError,
+    ServerError,
+}
+
+fn status_class(status: u16) -> Option<StatusClass> {
+    match status {
+        200..=299 => Some(StatusClass::Success),
+        300..=399 => Some(StatusClass::Redirect),
+        400..=499 => Some(StatusClass::ClientError),
+        500.. => Some(StatusClass::ServerError),
+        _ => None,
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TimelinePoint {
+    // Start of the bucket; buckets match `error_rate_timeline`
     pub timestamp: DateTime<Utc>,
+    pub count: usize,
+    // Mean duration of the bucket's requests
     pub duration_ms: u64,
+    // Highest response status in the bucket, 0 when none has completed
     pub status: u16,
-    pub method: String,
+}
+
+impl TimelinePoint {
+    fn empty(start: i64) -> Self {
+        Self {
+            timestamp: DateTime::from_timestamp(start, 0).unwrap_or_default(),
+            count: 0,
+            duration_ms: 0,
+            status: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording(id: &str, url: &str, status: u16, duration_ms: u64) -> RecordedRequest {
+        RecordedRequest {
+            id: id.to_string(),
+            timestamp: Utc::now(),
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: HashMap::new(),
+            body: None,
+            response: Some(RecordedResponse {
+                status,
+                headers: HashMap::new(),
+                body: None,
+                original_charset: None,
+                body_truncated: false,
+                body_size: 0,
+            }),
+            duration_ms: Some(duration_ms),
+            original_charset: None,
+            body_truncated: false,
+            injected_latency_ms: None,
+            cache_hit: false,
+            replay: None,
+            correlation_id: None,
+            explanation: None,
+            origin: None,
+        }
+    }
+
+    /// `count` recordings one second apart, oldest first, with ids `r0`, `r1`, ...
+    fn storage_with(count: usize) -> Storage {
+        let storage = Storage::new();
+        let start = Utc::now() - Duration::seconds(count as i64);
+        for i in 0..count {
+            let mut request = recording(&format!("r{}", i), "/api/items", 200, i as u64);
+            request.timestamp = start + Duration::seconds(i as i64);
+            storage.insert_completed(request);
+        }
+        storage
+    }
+
+    fn ids(page: &RecordingPage<RecordedRequest>) -> Vec<&str> {
+        page.items.iter().map(|r| r.id.as_str()).collect()
+    }
+
+    #[test]
+    fn offset_past_the_end_is_empty_with_total() {
+        let storage = storage_with(5);
+        let page = storage.get_filtered(
+            &FilterOptions::default(),
+            &PageOptions {
+                offset: 10,
+                limit: Some(3),
+                ..PageOptions::default()
+            },
+        );
+        assert_eq!(page.total, 5);
+        assert!(page.items.is_empty());
+    }
+
+    #[test]
+    fn pages_newest_first_by_default() {
+        let storage = storage_with(5);
+        let page = storage.get_filtered(
+            &FilterOptions::default(),
+            &PageOptions {
+                offset: 1,
+                limit: Some(2),
+                ..PageOptions::default()
+            },
+        );
+        assert_eq!(page.total, 5);
+        assert_eq!(ids(&page), ["r3", "r2"]);
+    }
+
+    #[test]
+    fn sorts_by_duration_ascending() {
+        let storage = storage_with(3);
+        let page = storage.get_filtered(
+            &FilterOptions::default(),
+            &PageOptions {
+                sort: RecordingSort::DurationMs,
+                ascending: true,
+                ..PageOptions::default()
+            },
+        );
+        assert_eq!(ids(&page), ["r0", "r1", "r2"]);
+    }
+
+    #[test]
+    fn total_counts_only_filtered_recordings() {
+        let storage = storage_with(3);
+        storage.insert_completed(recording("missing", "/api/missing", 404, 1));
+        let filters = FilterOptions {
+            status: Some(404),
+            ..FilterOptions::default()
+        };
+        let page = storage.get_filtered(&filters, &PageOptions::default());
+        assert_eq!(page.total, 1);
+        assert_eq!(ids(&page), ["missing"]);
+    }
+
+    fn search(query: &str) -> FilterOptions {
+        let mut filters = FilterOptions::default();
+        filters.apply_search_query(query);
+        filters
+    }
+
+    #[test]
+    fn combines_search_operators() {
+        let filters = search("status:500 method:post url:/api/users duration>200 size<=1000");
+        assert_eq!(filters.status, Some(500));
+        assert_eq!(filters.method.as_deref(), Some("POST"));
+        assert_eq!(filters.url.as_deref(), Some("/api/users"));
+        assert_eq!(filters.min_duration, Some(201));
+        assert_eq!(filters.max_duration, None);
+        assert_eq!(filters.max_size, Some(1000));
+        assert_eq!(filters.search, None);
+    }
+
+    #[test]
+    fn status_class_and_ranges() {
+        let filters = search("status:4xx");
+        assert_eq!(
+            (filters.min_status, filters.max_status),
+            (Some(400), Some(499))
+        );
+
+        let filters = search("status>=500 status<600");
+        assert_eq!(
+            (filters.min_status, filters.max_status),
+            (Some(500), Some(599))
+        );
+    }
+
+    #[test]
+    fn quoted_phrases_stay_free_text() {
+        let filters = search("\"status:500 in body\" method:GET");
+        assert_eq!(filters.search.as_deref(), Some("status:500 in body"));
+        assert_eq!(filters.method.as_deref(), Some("GET"));
+        assert_eq!(filters.status, None);
+    }
+
+    #[test]
+    fn unrecognized_tokens_fall_back_to_free_text() {
+        let filters = search("timeout status:abc foo:bar duration>");
+        assert_eq!(
+            filters.search.as_deref(),
+            Some("timeout status:abc foo:bar duration>")
+        );
+        assert_eq!(filters.status, None);
+        assert_eq!(filters.min_duration, None);
+    }
+
+    #[test]
+    fn search_operators_filter_recordings() {
+        let storage = Storage::new();
+        storage.insert_completed(recording("slow-error", "/api/users", 500, 900));
+        storage.insert_completed(recording("fast-error", "/api/users", 500, 10));
+        storage.insert_completed(recording("slow-ok", "/api/users", 200, 900));
+        storage.insert_completed(recording("other", "/api/orders", 500, 900));
+
+        let page = storage.get_filtered(
+            &search("status:5xx url:/api/users duration>200"),
+            &PageOptions::default(),
+        );
+        assert_eq!(ids(&page), ["slow-error"]);
+    }
+
+    #[test]
+    fn normalizes_integer_segments() {
+        assert_eq!(normalize_path("/api/users/1"), "/api/users/{id}");
+        assert_eq!(
+            normalize_path("/api/users/42/posts/7"),
+            "/api/users/{id}/posts/{id}"
+        );
+    }
+
+    #[test]
+    fn normalizes_uuid_segments() {
+        assert_eq!(
+            normalize_path("/api/orders/4bd5e5b2-1f0a-4c2e-9d61-54b1f5e0a0a1"),
+            "/api/orders/{id}"
+        );
+    }
+
+    #[test]
+    fn keeps_mixed_and_named_segments() {
+        assert_eq!(
+            normalize_path("/api/v2/users/abc123"),
+            "/api/v2/users/abc123"
+        );
+        assert_eq!(
+            normalize_path("/api/users/7/4bd5e5b2-1f0a-4c2e-9d61-54b1f5e0a0a1/avatar"),
+            "/api/users/{id}/{id}/avatar"
+        );
+        assert_eq!(normalize_path("/"), "/");
+    }
+
+    #[test]
+    fn persist_file_is_compacted_and_reloads() {
+        let path = std::env::temp_dir().join(format!("dev-proxy-{}.jsonl", Uuid::new_v4()));
+        let storage = Storage::with_persistence(path.clone())
+            .unwrap()
+            .with_max_recordings(10);
+        let start = Utc::now();
+        for i in 0..(PERSIST_COMPACT_SLACK + 100) {
+            let mut request = recording(&format!("r{}", i), "/api/items", 200, 0);
+            request.timestamp = start + Duration::seconds(i as i64);
+            storage.insert_completed(request);
+        }
+        storage.update_response(
+            "r1099",
+            RecordedResponse {
+                status: 201,
+                headers: HashMap::new(),
+                body: None,
+                original_charset: None,
+                body_truncated: false,
+                body_size: 0,
+            },
+            5,
+        );
+        storage.flush().unwrap();
+
+        let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+        assert!(lines <= 10 * 2 + PERSIST_COMPACT_SLACK, "{} lines", lines);
+
+        let reloaded = Storage::with_persistence(path.clone())
+            .unwrap()
+            .with_max_recordings(10);
+        assert_eq!(reloaded.recordings.read().len(), 10);
+        let last = reloaded.get_by_id("r1099").unwrap();
+        assert_eq!(last.response.unwrap().status, 201);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn timeline_shares_error_rate_buckets() {
+        let storage = Storage::new();
+        let now = Utc::now();
+        for (id, seconds_ago, status, duration) in
+            [("a", 10, 200, 10), ("b", 10, 500, 30), ("c", 1800, 404, 5)]
+        {
+            let mut request = recording(id, "/api/items", status, duration);
+            request.timestamp = now - Duration::seconds(seconds_ago);
+            storage.insert_completed(request);
+        }
+
+        let analytics = storage.get_analytics(3600);
+        assert_eq!(analytics.bucket_seconds, 60);
+        assert_eq!(
+            analytics.timeline.len(),
+            analytics.error_rate_timeline.len()
+        );
+        for (point, bucket) in analytics
+            .timeline
+            .iter()
+            .zip(&analytics.error_rate_timeline)
+        {
+            assert_eq!(point.timestamp, bucket.bucket_start);
+            assert_eq!(point.count, bucket.total);
+        }
+
+        let busy = analytics.timeline.iter().find(|p| p.count == 2).unwrap();
+        assert_eq!(busy.duration_ms, 20);
+        assert_eq!(busy.status, 500);
+        let total: usize = analytics.timeline.iter().map(|p| p.count).sum();
+        assert_eq!(total, 3);
+    }
+
+    #[test]
+    fn batch_replay_ids_are_ordered_oldest_first() {
+        let storage = storage_with(3);
+        let list = |ids: &[&str]| -> Vec<String> { ids.iter().map(|id| id.to_string()).collect() };
+
+        assert_eq!(
+            storage.ids_by_timestamp(&list(&["r2", "r0", "r1", "r0"])),
+            Ok(list(&["r0", "r1", "r2"]))
+        );
+        assert_eq!(
+            storage.ids_by_timestamp(&list(&["r2", "missing"])),
+            Err(list(&["missing"]))
+        );
+    }
+
+    #[test]
+    fn find_completed_uses_newest_and_forgets_evicted() {
+        let storage = Storage::new().with_max_recordings(3);
+        let start = Utc::now();
+        for (i, body) in ["a", "b"].into_iter().enumerate() {
+            let mut request = recording(&format!("r{}", i), "/api/items?page=1", 200, 0);
+            request.method = "POST".to_string();
+            request.body = Some(body.as_bytes().to_vec());
+            request.timestamp = start + Duration::seconds(i as i64);
+            storage.insert_completed(request);
+        }
+
+        let newest = storage.find_completed("POST", "/api/items?page=1", None);
+        assert_eq!(newest.unwrap().id, "r1");
+        let by_body = storage.find_completed("POST", "/api/items?page=1", Some(b"a"));
+        assert_eq!(by_body.unwrap().id, "r0");
+        assert!(
+            storage
+                .find_completed("POST", "/api/items?page=1", Some(b"c"))
+                .is_none()
+        );
+        assert!(
+            storage
+                .find_completed("GET", "/api/items?page=1", None)
+                .is_none()
+        );
+
+        // Pushes both out past the cap
+        for i in 2..5 {
+            let mut request = recording(&format!("r{}", i), "/api/other", 200, 0);
+            request.timestamp = start + Duration::seconds(i as i64);
+            storage.insert_completed(request);
+        }
+        assert!(
+            storage
+                .find_completed("POST", "/api/items?page=1", None)
+                .is_none()
+        );
+        assert_eq!(storage.completed.lock().by_request.len(), 1);
+    }
+
+    #[test]
+    fn find_completed_waits_for_bodies_and_skips_proxy_answers() {
+        let storage = Storage::new();
+        let mut pending = recording("r0", "/api/items", 200, 0);
+        let response = pending.response.take().unwrap();
+        storage.store_request(pending);
+
+        storage.update_response("r0", response, 5);
+        assert!(storage.find_completed("GET", "/api/items", None).is_none());
+
+        let body = CapturedBody {
+            data: b"[1]".to_vec(),
+            truncated: false,
+            size: 3,
+        };
+        storage.update_bodies("r0", None, Some(body));
+        let found = storage.find_completed("GET", "/api/items", None).unwrap();
+        assert_eq!(found.response.unwrap().body.unwrap(), b"[1]");
+
+        let mut cached = recording("r1", "/api/cached", 304, 0);
+        cached.cache_hit = true;
+        storage.insert_completed(cached);
+        assert!(storage.find_completed("GET", "/api/cached", None).is_none());
+
+        storage.insert_completed(recording("r2", "/api/open", 503, 0));
+        storage.set_origin("r2", ResponseOrigin::CircuitOpen);
+        assert!(storage.find_completed("GET", "/api/open", None).is_none());
+    }
+
+    #[test]
+    fn status_timeline_widens_buckets_over_long_spans() {
+        let storage = storage_with(3);
+        assert_eq!(storage.get_status_timeline(1).len(), 3);
+
+        // Per second over ten days would be 864,000 buckets
+        let mut old = recording("old", "/api/items", 500, 0);
+        old.timestamp = Utc::now() - Duration::days(10);
+        storage.insert_completed(old);
+        let timeline = storage.get_status_timeline(1);
+        assert!(timeline.len() <= MAX_STATUS_TIMELINE_BUCKETS as usize);
+        let width = timeline[1].timestamp - timeline[0].timestamp;
+        assert!(width >= Duration::seconds(864));
+        let total: usize = timeline
+            .iter()
+            .map(|bucket| bucket.success + bucket.server_errors)
+            .sum();
+        assert_eq!(total, 4);
+    }
 }
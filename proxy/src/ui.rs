@@ -1,129 +1,278 @@
-use crate::latency_injector::{CreateLatencyRule, LatencyInjector, UpdateLatencyRule};
+use crate::circuit_breaker::CircuitBreaker;
+use crate::config::ProxyConfig;
+use crate::cors::CorsPolicy;
+use crate::fault_injector::{CreateFaultRule, FaultInjector, UpdateFaultRule};
+use crate::http_layer::{ResponseBody, full};
+use crate::latency_injector::{CreateLatencyRule, GlobalDelay, LatencyInjector, UpdateLatencyRule};
+use crate::matcher::PatternTestRequest;
+use crate::metrics::Metrics;
 use crate::mock::MockManager;
 use crate::modifier::{CreateModifierRule, ResponseModifier, UpdateModifierRule};
 use crate::rate_limiter::{CreateRateLimitRule, RateLimiter, UpdateRateLimitRule};
-use crate::storage::{FilterOptions, Storage};
+use crate::shutdown::Shutdown;
+use crate::snapshot::{Snapshot, SnapshotStore};
+use crate::storage::{
+    BatchReplayRequest, BulkReplayItem, BulkReplayOutcome, BulkReplayRequest, BulkReplayResult,
+    DEFAULT_ANALYTICS_WINDOW_SECONDS, FilterOptions, PageOptions, RecordedRequest, RecordingPage,
+    RecordingSummary, ReplayOverrides, ReplayRetryPolicy, Storage,
+};
 use anyhow::Result;
+use base64::Engine;
 use bytes::Bytes;
 use http::{Method, StatusCode, header};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
 use mime_guess::from_path;
 use rust_embed::RustEmbed;
 use serde_json::json;
 use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use tokio::net::TcpListener;
+use tokio::sync::broadcast;
 
 #[derive(RustEmbed)]
 #[folder = "../ui/build"]
 struct Assets;
 
+/// Shared state handed to every request served by the UI server
+pub struct UiState {
+    pub storage: Storage,
+    pub mock_manager: MockManager,
+    pub response_modifier: ResponseModifier,
+    pub rate_limiter: RateLimiter,
+    pub latency_injector: LatencyInjector,
+    pub fault_injector: FaultInjector,
+    pub snapshots: SnapshotStore,
+    pub metrics: Metrics,
+    pub circuit_breaker: CircuitBreaker,
+    // Whether new requests are recorded, shared with the proxy
+    pub recording: Arc<AtomicBool>,
+    // Whether repeated requests are answered from their recordings
+    pub auto_mock: Arc<AtomicBool>,
+    // Sends replays; the same pooled client the HTTP layer forwards with
+    pub client: reqwest::Client,
+}
+
 pub async fn start_ui_server(
-    port: u16,
-    storage: Storage,
-    mock_manager: MockManager,
-    response_modifier: ResponseModifier,
-    rate_limiter: RateLimiter,
-    latency_injector: LatencyInjector,
+    config: ProxyConfig,
+    state: UiState,
+    shutdown: Shutdown,
 ) -> Result<()> {
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let addr = SocketAddr::from(([0, 0, 0, 0], config.ui_port));
     let listener = TcpListener::bind(addr).await?;
 
     println!("UI server listening on {}", addr);
 
-    let storage = Arc::new(storage);
-    let mock_manager = Arc::new(mock_manager);
-    let response_modifier = Arc::new(response_modifier);
-    let rate_limiter = Arc::new(rate_limiter);
-    let latency_injector = Arc::new(latency_injector);
+    let state = Arc::new(state);
+    let access = Arc::new(UiAccess {
+        authorization: config
+            .ui_credentials()
+            .map(|(user, pass)| basic_authorization(user, pass)),
+        cors: CorsPolicy::from_config(&config)?,
+    });
+    let config = Arc::new(config);
 
     loop {
-        let (stream, _) = listener.accept().await?;
-        let storage = storage.clone();
-        let mock_manager = mock_manager.clone();
-        let response_modifier = response_modifier.clone();
-        let rate_limiter = rate_limiter.clone();
-        let latency_injector = latency_injector.clone();
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.draining() => return Ok(()),
+        };
+        let state = state.clone();
+        let config = config.clone();
+        let access = access.clone();
 
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(
-                stream,
-                storage,
-                mock_manager,
-                response_modifier,
-                rate_limiter,
-                latency_injector,
-            )
-            .await
-            {
+            if let Err(e) = handle_connection(stream, state, &config, access).await {
                 eprintln!("Error handling connection: {}", e);
             }
         });
     }
 }
 
+/// Who may use the UI server: Basic Auth credentials and cross-origin API access
+struct UiAccess {
+    // The `Authorization` header value every request must carry when --ui-user is set
+    authorization: Option<String>,
+    cors: CorsPolicy,
+}
+
+/// Request activity on one UI connection, used to drop it once it has been idle too long
+struct ConnectionActivity {
+    opened: Instant,
+    // Milliseconds after `opened` at which the last request started or finished
+    last_ms: AtomicU64,
+    in_flight: AtomicUsize,
+}
+
+impl ConnectionActivity {
+    fn new() -> Self {
+        Self {
+            opened: Instant::now(),
+            last_ms: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        self.last_ms
+            .store(self.opened.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Mark a request in progress until the returned guard is dropped
+    fn begin(self: &Arc<Self>) -> ActivityGuard {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+        ActivityGuard(self.clone())
+    }
+
+    /// How long the connection has had no request in progress, None while one is
+    fn idle_for(&self) -> Option<Duration> {
+        if self.in_flight.load(Ordering::Relaxed) > 0 {
+            return None;
+        }
+        let last = Duration::from_millis(self.last_ms.load(Ordering::Relaxed));
+        Some(self.opened.elapsed().saturating_sub(last))
+    }
+}
+
+struct ActivityGuard(Arc<ConnectionActivity>);
+
+impl Drop for ActivityGuard {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.0.touch();
+    }
+}
+
 async fn handle_connection(
     stream: tokio::net::TcpStream,
-    storage: Arc<Storage>,
-    mock_manager: Arc<MockManager>,
-    response_modifier: Arc<ResponseModifier>,
-    rate_limiter: Arc<RateLimiter>,
-    latency_injector: Arc<LatencyInjector>,
+    state: Arc<UiState>,
+    config: &ProxyConfig,
+    access: Arc<UiAccess>,
 ) -> Result<()> {
     let io = hyper_util::rt::TokioIo::new(stream);
+    let activity = Arc::new(ConnectionActivity::new());
 
+    let service_activity = activity.clone();
     let service = hyper::service::service_fn(move |req| {
-        let storage = storage.clone();
-        let mock_manager = mock_manager.clone();
-        let response_modifier = response_modifier.clone();
-        let rate_limiter = rate_limiter.clone();
-        let latency_injector = latency_injector.clone();
+        let state = state.clone();
+        let activity = service_activity.clone();
+        let access = access.clone();
         async move {
-            handle_request(
-                req,
-                storage,
-                mock_manager,
-                response_modifier,
-                rate_limiter,
-                latency_injector,
-            )
-            .await
+            // The request stays in progress until its body is fully sent, which matters
+            // for streaming responses
+            let guard = activity.begin();
+            let response = handle_request(req, state, &access).await?;
+            Ok::<_, Infallible>(response.map(|body| {
+                body.map_frame(move |frame| {
+                    let _ = &guard;
+                    frame
+                })
+                .boxed_unsync()
+            }))
         }
     });
 
-    hyper::server::conn::http1::Builder::new()
-        .serve_connection(io, service)
-        .await?;
+    let mut builder = hyper::server::conn::http1::Builder::new();
+    if config.ui_header_read_timeout_ms > 0 {
+        builder
+            .timer(hyper_util::rt::TokioTimer::new())
+            .header_read_timeout(Duration::from_millis(config.ui_header_read_timeout_ms));
+    }
+    let connection = builder.serve_connection(io, service);
+    tokio::pin!(connection);
+
+    if config.ui_idle_timeout_ms == 0 {
+        connection.await?;
+        return Ok(());
+    }
+
+    // Drop the connection once no request has been in progress for the idle timeout
+    let idle_timeout = Duration::from_millis(config.ui_idle_timeout_ms);
+    loop {
+        let wait = activity
+            .idle_for()
+            .map_or(idle_timeout, |idle| idle_timeout.saturating_sub(idle));
+        if wait.is_zero() {
+            return Ok(());
+        }
 
-    Ok(())
+        tokio::select! {
+            result = connection.as_mut() => {
+                result?;
+                return Ok(());
+            }
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
 }
 
 async fn handle_request(
     req: hyper::Request<hyper::body::Incoming>,
-    storage: Arc<Storage>,
-    mock_manager: Arc<MockManager>,
-    response_modifier: Arc<ResponseModifier>,
-    rate_limiter: Arc<RateLimiter>,
-    latency_injector: Arc<LatencyInjector>,
-) -> Result<hyper::Response<http_body_util::Full<Bytes>>, Infallible> {
+    state: Arc<UiState>,
+    access: &UiAccess,
+) -> Result<hyper::Response<ResponseBody>, Infallible> {
+    let is_api = req.uri().path().starts_with("/api/");
+    let origin = req.headers().get(header::ORIGIN).cloned();
+    let mut response = route_request(req, state, access).await?;
+    if is_api {
+        access.cors.apply(origin.as_ref(), response.headers_mut());
+    }
+    Ok(response)
+}
+
+async fn route_request(
+    req: hyper::Request<hyper::body::Incoming>,
+    state: Arc<UiState>,
+    access: &UiAccess,
+) -> Result<hyper::Response<ResponseBody>, Infallible> {
     let path = req.uri().path().to_string();
     let method = req.method().clone();
     let query = req.uri().query().map(|q| q.to_string());
 
+    if method == Method::GET && path == "/healthz" {
+        let health = json!({
+            "status": "ok",
+            "version": crate::version::build_info(),
+        });
+        return Ok(json_response(health.to_string()));
+    }
+
+    // Browsers send preflights without credentials
+    if method == Method::OPTIONS && path.starts_with("/api/") {
+        return Ok(access.cors.preflight(req.headers()));
+    }
+
+    // Everything but the health check needs credentials when --ui-user is set
+    if let Some(ref expected) = access.authorization {
+        let presented = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .map(|value| value.as_bytes())
+            .unwrap_or_default();
+        if !constant_time_eq(presented, expected.as_bytes()) {
+            return Ok(unauthorized_response());
+        }
+    }
+
+    if method == Method::GET && path == "/metrics" {
+        let metrics = state.metrics.render(
+            &state.rate_limiter.get_bucket_stats(),
+            &state.latency_injector.get_stats(),
+        );
+        return Ok(hyper::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(full(Bytes::from(metrics)))
+            .unwrap());
+    }
+
     // API routes
     if path.starts_with("/api/") {
-        return handle_api_request(
-            method,
-            path,
-            query,
-            req,
-            storage,
-            mock_manager,
-            response_modifier,
-            rate_limiter,
-            latency_injector,
-        )
-        .await;
+        return handle_api_request(method, path, query, req, &state).await;
     }
 
     // Serve static files
@@ -135,49 +284,270 @@ async fn handle_api_request(
     path: String,
     query: Option<String>,
     req: hyper::Request<hyper::body::Incoming>,
-    storage: Arc<Storage>,
-    mock_manager: Arc<MockManager>,
-    response_modifier: Arc<ResponseModifier>,
-    rate_limiter: Arc<RateLimiter>,
-    latency_injector: Arc<LatencyInjector>,
-) -> Result<hyper::Response<http_body_util::Full<Bytes>>, Infallible> {
+    state: &UiState,
+) -> Result<hyper::Response<ResponseBody>, Infallible> {
+    let UiState {
+        storage,
+        mock_manager,
+        response_modifier,
+        rate_limiter,
+        latency_injector,
+        fault_injector,
+        snapshots,
+        metrics: _,
+        circuit_breaker,
+        recording,
+        auto_mock,
+        client,
+    } = state;
+
     match (method.as_str(), path.as_str()) {
         // Existing endpoints
         ("GET", "/api/recordings") => {
             let filters = parse_filter_options(query.as_deref());
-            let recordings = if has_filters(&filters) {
-                storage.get_filtered(&filters)
-            } else {
-                storage.get_all()
+            let page = match parse_page_options(query.as_deref()) {
+                Ok(page) => page,
+                Err(e) => return Ok(api_error(ApiErrorKind::Validation, &e)),
             };
-            let json = serde_json::to_string(&recordings).unwrap();
-            Ok(json_response(json))
+
+            // Bodies are fetched per recording on demand unless explicitly requested
+            let include_bodies =
+                query_param(query.as_deref(), "include_bodies").is_some_and(|v| v == "true");
+            let summarize = |recordings: Vec<RecordedRequest>| -> Vec<RecordingSummary> {
+                recordings
+                    .into_iter()
+                    .map(|recording| RecordingSummary::new(recording, include_bodies))
+                    .collect()
+            };
+
+            // Without pagination params the whole list is returned as a plain array
+            let json = match page {
+                Some(page) => {
+                    let page = storage.get_filtered(&filters, &page);
+                    serde_json::to_string(&RecordingPage {
+                        total: page.total,
+                        items: summarize(page.items),
+                    })
+                }
+                None if has_filters(&filters) => serde_json::to_string(&summarize(
+                    storage
+                        .get_filtered(&filters, &PageOptions::default())
+                        .items,
+                )),
+                None => serde_json::to_string(&summarize(storage.get_all())),
+            };
+            Ok(json_response(json.unwrap()))
         }
         ("GET", "/api/stats") => {
             let stats = storage.get_stats();
             let json = serde_json::to_string(&stats).unwrap();
             Ok(json_response(json))
         }
+        ("GET", "/api/recording/status") => {
+            let enabled = recording.load(Ordering::Relaxed);
+            Ok(json_response(json!({ "enabled": enabled }).to_string()))
+        }
+        ("POST", "/api/recording/toggle") => {
+            let enabled = !recording.fetch_xor(true, Ordering::Relaxed);
+            println!(
+                "⏺️  Recording {}",
+                if enabled { "enabled" } else { "disabled" }
+            );
+            Ok(json_response(json!({ "enabled": enabled }).to_string()))
+        }
+        ("GET", "/api/auto-mock/status") => {
+            let enabled = auto_mock.load(Ordering::Relaxed);
+            Ok(json_response(json!({ "enabled": enabled }).to_string()))
+        }
+        ("POST", "/api/auto-mock/toggle") => {
+            let enabled = !auto_mock.fetch_xor(true, Ordering::Relaxed);
+            if enabled && !recording.load(Ordering::Relaxed) {
+                println!(
+                    "⚠️  Auto-mock is on but recording is off, so new requests won't be captured"
+                );
+            }
+            println!(
+                "🎭 Auto-mock {}",
+                if enabled { "enabled" } else { "disabled" }
+            );
+            Ok(json_response(json!({ "enabled": enabled }).to_string()))
+        }
+        ("GET", "/api/storage/info") => {
+            let info = storage.memory_info();
+            Ok(json_response(serde_json::to_string(&info).unwrap()))
+        }
         ("GET", "/api/analytics") => {
-            let analytics = storage.get_analytics();
+            let window_seconds = match query_param(query.as_deref(), "window_seconds") {
+                None => DEFAULT_ANALYTICS_WINDOW_SECONDS,
+                Some(value) => match value.parse::<i64>() {
+                    Ok(seconds) if seconds > 0 => seconds,
+                    _ => {
+                        return Ok(api_error(
+                            ApiErrorKind::Validation,
+                            &format!(
+                                "Invalid window_seconds '{}', expected a positive number",
+                                value
+                            ),
+                        ));
+                    }
+                },
+            };
+            let analytics = storage.get_analytics(window_seconds);
             let json = serde_json::to_string(&analytics).unwrap();
             Ok(json_response(json))
         }
+        ("GET", "/api/analytics/schema-drift") => match query_param(query.as_deref(), "endpoint") {
+            Some(endpoint) => {
+                let drift = storage.get_schema_drift(&endpoint);
+                Ok(json_response(serde_json::to_string(&drift).unwrap()))
+            }
+            None => Ok(error_response("Missing 'endpoint' query parameter")),
+        },
+        ("GET", "/api/analytics/status-timeline") => {
+            let bucket = query_param(query.as_deref(), "bucket").unwrap_or_else(|| "1m".into());
+            match parse_bucket_seconds(&bucket) {
+                Some(bucket_seconds) => {
+                    let timeline = storage.get_status_timeline(bucket_seconds);
+                    let json = serde_json::to_string(&timeline).unwrap();
+                    Ok(json_response(json))
+                }
+                None => Ok(error_response(&format!(
+                    "Invalid bucket '{}', expected e.g. 30s, 1m, 1h",
+                    bucket
+                ))),
+            }
+        }
+        ("GET", "/api/ws-sessions") => {
+            let sessions = storage.get_ws_sessions();
+            let json = serde_json::to_string(&sessions).unwrap();
+            Ok(json_response(json))
+        }
+        ("GET", p) if p.starts_with("/api/ws-sessions/") => {
+            let id = p.trim_start_matches("/api/ws-sessions/");
+            match storage.get_ws_session(id) {
+                Some(session) => Ok(json_response(serde_json::to_string(&session).unwrap())),
+                None => Ok(api_error(ApiErrorKind::NotFound, "Not found")),
+            }
+        }
+        ("GET", "/api/recordings/tail") => {
+            let filters = parse_filter_options(query.as_deref());
+            let include_bodies =
+                query_param(query.as_deref(), "include_bodies").is_some_and(|v| v == "true");
+            Ok(tail_response(storage.clone(), filters, include_bodies))
+        }
+        ("GET", "/api/stream") => Ok(stream_response(storage.clone())),
+        ("GET", "/api/recordings/har") => {
+            let har = crate::har::to_har(&storage.get_all());
+            Ok(hyper::Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"dev-proxy.har\"",
+                )
+                .body(full(Bytes::from(har.to_string())))
+                .unwrap())
+        }
+        ("GET", "/api/recordings/openapi") => {
+            let document = crate::openapi::to_openapi(&storage.get_all());
+            Ok(json_response(document.to_string()))
+        }
+        ("GET", p) if p.starts_with("/api/recordings/by-correlation/") => {
+            let correlation_id =
+                urlencoding::decode(p.trim_start_matches("/api/recordings/by-correlation/"))
+                    .map(|id| id.into_owned())
+                    .unwrap_or_default();
+            let recordings = storage.get_by_correlation(&correlation_id);
+            let json = serde_json::to_string(&recordings).unwrap();
+            Ok(json_response(json))
+        }
         ("GET", p) if p.starts_with("/api/recordings/") => {
             let id = p.trim_start_matches("/api/recordings/");
             if let Some(recording) = storage.get_by_id(id) {
                 let json = serde_json::to_string(&recording).unwrap();
                 Ok(json_response(json))
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
+        ("POST", "/api/recordings/replay") => {
+            match read_body_json::<BulkReplayRequest>(req).await {
+                Ok(bulk) => {
+                    let result = replay_bulk(client, storage, bulk).await;
+                    let json = serde_json::to_string(&result).unwrap();
+                    Ok(json_response(json))
+                }
+                Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
+            }
+        }
+        ("POST", "/api/recordings/replay-batch") => {
+            let batch = match read_body_json::<BatchReplayRequest>(req).await {
+                Ok(batch) => batch,
+                Err(e) => return Ok(error_response(&format!("Invalid request: {}", e))),
+            };
+            let ids = match (batch.ids.is_empty(), batch.filter) {
+                (false, None) => match storage.ids_by_timestamp(&batch.ids) {
+                    Ok(ids) => ids,
+                    Err(unknown) => {
+                        return Ok(api_error(
+                            ApiErrorKind::NotFound,
+                            &format!("Unknown recordings: {}", unknown.join(", ")),
+                        ));
+                    }
+                },
+                (true, Some(mut filter)) => {
+                    if let Some(search) = filter.search.take() {
+                        filter.apply_search_query(&search);
+                    }
+                    let oldest_first = PageOptions {
+                        ascending: true,
+                        ..PageOptions::default()
+                    };
+                    storage
+                        .get_filtered(&filter, &oldest_first)
+                        .items
+                        .into_iter()
+                        .map(|recording| recording.id)
+                        .collect()
+                }
+                _ => {
+                    return Ok(api_error(
+                        ApiErrorKind::Validation,
+                        "Expected either ids or filter",
+                    ));
+                }
+            };
+
+            let result = replay_bulk(
+                client,
+                storage,
+                BulkReplayRequest {
+                    ids,
+                    upstream: batch.upstream,
+                    concurrency: batch.concurrency,
+                },
+            )
+            .await;
+            let json = serde_json::to_string(&batch_replay_recordings(storage, result)).unwrap();
+            Ok(json_response(json))
+        }
         ("POST", p) if p.starts_with("/api/recordings/") && p.ends_with("/replay") => {
             let id = p
                 .trim_start_matches("/api/recordings/")
                 .trim_end_matches("/replay");
 
-            if let Some(replay_req) = storage.get_for_replay(id) {
+            if let Some(mut replay_req) = storage.get_for_replay(id) {
+                // The returned recording shows the request as sent, overrides included
+                match read_optional_body_json::<ReplayOverrides>(req).await {
+                    Ok(overrides) => replay_req.apply_overrides(overrides),
+                    Err(e) => {
+                        return Ok(api_error(
+                            ApiErrorKind::Validation,
+                            &format!("Invalid overrides: {}", e),
+                        ));
+                    }
+                }
+
                 // Get the upstream URL from query params or use default
                 let upstream_url = query
                     .as_deref()
@@ -192,18 +562,47 @@ async fn handle_api_request(
                     })
                     .unwrap_or_else(|| "http://localhost:8000".to_string());
 
-                match replay_request(&replay_req, &upstream_url).await {
+                let retry = ReplayRetryPolicy {
+                    honor_retry_after: query_param(query.as_deref(), "honor_retry_after")
+                        .is_some_and(|v| v == "true"),
+                    max_retries: query_param(query.as_deref(), "max_retries")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(ReplayRetryPolicy::DEFAULT_MAX_RETRIES)
+                        .min(ReplayRetryPolicy::MAX_RETRIES_CAP),
+                };
+
+                match replay_request(client, &replay_req, &upstream_url, &retry).await {
                     Ok(response) => {
                         let json = serde_json::to_string(&response).unwrap();
                         Ok(json_response(json))
                     }
-                    Err(e) => Ok(error_response(&format!("Replay failed: {}", e))),
+                    Err(e) => Ok(api_error(
+                        ApiErrorKind::Internal,
+                        &format!("Replay failed: {}", e),
+                    )),
                 }
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
         ("DELETE", "/api/recordings") => {
+            // With the list's filter params only the matching recordings go, and without
+            // any everything does. Filters this can't understand are an error rather than
+            // a reason to delete it all.
+            if let Some(key) = unknown_param(query.as_deref(), DELETE_RECORDINGS_PARAMS) {
+                return Ok(api_error(
+                    ApiErrorKind::Validation,
+                    &format!("Unknown filter parameter '{}'", key),
+                ));
+            }
+            let filters = match parse_strict_filter_options(query.as_deref()) {
+                Ok(filters) => filters,
+                Err(e) => return Ok(api_error(ApiErrorKind::Validation, &e)),
+            };
+            if has_filters(&filters) {
+                let deleted = storage.delete_filtered(&filters);
+                return Ok(json_response(json!({ "deleted": deleted }).to_string()));
+            }
             storage.clear();
             Ok(json_response(json!({"success": true}).to_string()))
         }
@@ -221,13 +620,67 @@ async fn handle_api_request(
             }
             Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
         },
+        ("POST", "/api/mocks/batch") => {
+            match read_body_json::<crate::mock::MockBatchRequest>(req).await {
+                Ok(batch) => {
+                    let result = mock_manager.add_batch(batch);
+                    Ok(json_response(serde_json::to_string(&result).unwrap()))
+                }
+                Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
+            }
+        }
+        ("POST", "/api/mocks/reorder") => match read_body_json::<Vec<String>>(req).await {
+            Ok(ids) => {
+                mock_manager.reorder(ids);
+                let rules = mock_manager.get_all_rules();
+                Ok(json_response(serde_json::to_string(&rules).unwrap()))
+            }
+            Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
+        },
+        ("POST", "/api/mocks/import-har") => match read_body_json::<serde_json::Value>(req).await {
+            Ok(har) => match crate::har::to_mock_rules(&har) {
+                Ok(rules) => {
+                    let ids: Vec<String> = rules
+                        .into_iter()
+                        .map(|rule| mock_manager.add_rule(rule))
+                        .collect();
+                    Ok(json_response(json!({"ids": ids}).to_string()))
+                }
+                Err(e) => Ok(api_error(ApiErrorKind::Validation, &e)),
+            },
+            Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
+        },
+        ("GET", "/api/mocks/default") => {
+            if let Some(rule) = mock_manager.get_default_rule() {
+                let json = serde_json::to_string(&rule).unwrap();
+                Ok(json_response(json))
+            } else {
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
+            }
+        }
+        ("PUT", "/api/mocks/default") => {
+            match read_body_json::<crate::mock::SetDefaultMockRule>(req).await {
+                Ok(rule) => {
+                    mock_manager.set_default_rule(rule);
+                    Ok(json_response(json!({"success": true}).to_string()))
+                }
+                Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
+            }
+        }
+        ("DELETE", "/api/mocks/default") => {
+            if mock_manager.clear_default_rule() {
+                Ok(json_response(json!({"success": true}).to_string()))
+            } else {
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
+            }
+        }
         ("GET", p) if p.starts_with("/api/mocks/") && !p.ends_with("/toggle") => {
             let id = p.trim_start_matches("/api/mocks/");
             if let Some(rule) = mock_manager.get_rule(id) {
                 let json = serde_json::to_string(&rule).unwrap();
                 Ok(json_response(json))
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
         ("PUT", p) if p.starts_with("/api/mocks/") => {
@@ -236,12 +689,27 @@ async fn handle_api_request(
                     if mock_manager.update_rule(rule) {
                         Ok(json_response(json!({"success": true}).to_string()))
                     } else {
-                        Ok(not_found_response())
+                        Ok(api_error(ApiErrorKind::NotFound, "Not found"))
                     }
                 }
                 Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
             }
         }
+        ("POST", p) if p.starts_with("/api/mocks/") && p.ends_with("/refresh-body") => {
+            let id = p
+                .trim_start_matches("/api/mocks/")
+                .trim_end_matches("/refresh-body");
+            match mock_manager.refresh_fixtures(id).await {
+                Some(Ok(refreshed)) => Ok(json_response(
+                    json!({"success": true, "refreshed": refreshed}).to_string(),
+                )),
+                Some(Err(e)) => Ok(api_error(
+                    ApiErrorKind::Internal,
+                    &format!("Failed to fetch mock body from {}", e),
+                )),
+                None => Ok(api_error(ApiErrorKind::NotFound, "Not found")),
+            }
+        }
         ("POST", p) if p.starts_with("/api/mocks/") && p.ends_with("/toggle") => {
             let id = p
                 .trim_start_matches("/api/mocks/")
@@ -249,7 +717,17 @@ async fn handle_api_request(
             if mock_manager.toggle_rule(id) {
                 Ok(json_response(json!({"success": true}).to_string()))
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
+            }
+        }
+        ("POST", p) if p.starts_with("/api/mocks/") && p.ends_with("/reset-sequence") => {
+            let id = p
+                .trim_start_matches("/api/mocks/")
+                .trim_end_matches("/reset-sequence");
+            if mock_manager.reset_sequence(id) {
+                Ok(json_response(json!({"success": true}).to_string()))
+            } else {
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
         ("DELETE", p) if p.starts_with("/api/mocks/") => {
@@ -257,7 +735,7 @@ async fn handle_api_request(
             if mock_manager.delete_rule(id) {
                 Ok(json_response(json!({"success": true}).to_string()))
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
         ("DELETE", "/api/mocks") => {
@@ -277,13 +755,21 @@ async fn handle_api_request(
             }
             Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
         },
+        ("POST", "/api/modifiers/reorder") => match read_body_json::<Vec<String>>(req).await {
+            Ok(ids) => {
+                response_modifier.reorder(ids);
+                let rules = response_modifier.get_all_rules();
+                Ok(json_response(serde_json::to_string(&rules).unwrap()))
+            }
+            Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
+        },
         ("GET", p) if p.starts_with("/api/modifiers/") && !p.ends_with("/toggle") => {
             let id = p.trim_start_matches("/api/modifiers/");
             if let Some(rule) = response_modifier.get_rule(id) {
                 let json = serde_json::to_string(&rule).unwrap();
                 Ok(json_response(json))
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
         ("PUT", p) if p.starts_with("/api/modifiers/") => {
@@ -292,7 +778,7 @@ async fn handle_api_request(
                     if response_modifier.update_rule(rule) {
                         Ok(json_response(json!({"success": true}).to_string()))
                     } else {
-                        Ok(not_found_response())
+                        Ok(api_error(ApiErrorKind::NotFound, "Not found"))
                     }
                 }
                 Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
@@ -305,10 +791,10 @@ async fn handle_api_request(
                 if response_modifier.toggle_rule(id) {
                     Ok(json_response(json!({"success": true}).to_string()))
                 } else {
-                    Ok(not_found_response())
+                    Ok(api_error(ApiErrorKind::NotFound, "Not found"))
                 }
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
         ("DELETE", p) if p.starts_with("/api/modifiers/") => {
@@ -316,7 +802,7 @@ async fn handle_api_request(
             if response_modifier.delete_rule(id) {
                 Ok(json_response(json!({"success": true}).to_string()))
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
         ("DELETE", "/api/modifiers") => {
@@ -330,10 +816,13 @@ async fn handle_api_request(
             Ok(json_response(json))
         }
         ("POST", "/api/rate-limits") => match read_body_json::<CreateRateLimitRule>(req).await {
-            Ok(rule) => {
-                let id = rate_limiter.add_rule(rule);
-                Ok(json_response(json!({"id": id}).to_string()))
-            }
+            Ok(rule) => match rule.match_request.key_type.validate() {
+                Ok(()) => {
+                    let id = rate_limiter.add_rule(rule);
+                    Ok(json_response(json!({"id": id}).to_string()))
+                }
+                Err(e) => Ok(api_error(ApiErrorKind::Validation, &e)),
+            },
             Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
         },
         ("GET", p)
@@ -346,16 +835,18 @@ async fn handle_api_request(
                 let json = serde_json::to_string(&rule).unwrap();
                 Ok(json_response(json))
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
         ("PUT", p) if p.starts_with("/api/rate-limits/") => {
             match read_body_json::<UpdateRateLimitRule>(req).await {
                 Ok(rule) => {
-                    if rate_limiter.update_rule(rule) {
+                    if let Err(e) = rule.match_request.key_type.validate() {
+                        Ok(api_error(ApiErrorKind::Validation, &e))
+                    } else if rate_limiter.update_rule(rule) {
                         Ok(json_response(json!({"success": true}).to_string()))
                     } else {
-                        Ok(not_found_response())
+                        Ok(api_error(ApiErrorKind::NotFound, "Not found"))
                     }
                 }
                 Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
@@ -368,10 +859,10 @@ async fn handle_api_request(
                 if rate_limiter.toggle_rule(id) {
                     Ok(json_response(json!({"success": true}).to_string()))
                 } else {
-                    Ok(not_found_response())
+                    Ok(api_error(ApiErrorKind::NotFound, "Not found"))
                 }
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
         ("POST", p) if p.starts_with("/api/rate-limits/") && p.ends_with("/reset") => {
@@ -381,7 +872,7 @@ async fn handle_api_request(
                 rate_limiter.reset_bucket(id);
                 Ok(json_response(json!({"success": true}).to_string()))
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
         ("DELETE", p) if p.starts_with("/api/rate-limits/") => {
@@ -389,13 +880,21 @@ async fn handle_api_request(
             if rate_limiter.delete_rule(id) {
                 Ok(json_response(json!({"success": true}).to_string()))
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
         ("DELETE", "/api/rate-limits") => {
             rate_limiter.clear_all();
             Ok(json_response(json!({"success": true}).to_string()))
         }
+        ("POST", "/api/rate-limits/reorder") => match read_body_json::<Vec<String>>(req).await {
+            Ok(ids) => {
+                rate_limiter.reorder(ids);
+                let rules = rate_limiter.get_all_rules();
+                Ok(json_response(serde_json::to_string(&rules).unwrap()))
+            }
+            Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
+        },
         ("GET", "/api/rate-limits/stats") => {
             let stats = rate_limiter.get_bucket_stats();
             let json = serde_json::to_string(&stats).unwrap();
@@ -403,6 +902,19 @@ async fn handle_api_request(
         }
 
         // Latency injection endpoints
+        ("GET", "/api/global-delay") => {
+            let delay = GlobalDelay {
+                delay_ms: latency_injector.global_delay_ms(),
+            };
+            Ok(json_response(serde_json::to_string(&delay).unwrap()))
+        }
+        ("PUT", "/api/global-delay") => match read_body_json::<GlobalDelay>(req).await {
+            Ok(delay) => {
+                latency_injector.set_global_delay_ms(delay.delay_ms);
+                Ok(json_response(serde_json::to_string(&delay).unwrap()))
+            }
+            Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
+        },
         ("GET", "/api/latency-rules") => {
             let rules = latency_injector.get_all_rules();
             let json = serde_json::to_string(&rules).unwrap();
@@ -415,6 +927,14 @@ async fn handle_api_request(
             }
             Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
         },
+        ("POST", "/api/latency-rules/reorder") => match read_body_json::<Vec<String>>(req).await {
+            Ok(ids) => {
+                latency_injector.reorder(ids);
+                let rules = latency_injector.get_all_rules();
+                Ok(json_response(serde_json::to_string(&rules).unwrap()))
+            }
+            Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
+        },
         ("GET", p)
             if p.starts_with("/api/latency-rules/")
                 && !p.ends_with("/toggle")
@@ -425,7 +945,7 @@ async fn handle_api_request(
                 let json = serde_json::to_string(&rule).unwrap();
                 Ok(json_response(json))
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
         ("PUT", p) if p.starts_with("/api/latency-rules/") => {
@@ -434,7 +954,7 @@ async fn handle_api_request(
                     if latency_injector.update_rule(rule) {
                         Ok(json_response(json!({"success": true}).to_string()))
                     } else {
-                        Ok(not_found_response())
+                        Ok(api_error(ApiErrorKind::NotFound, "Not found"))
                     }
                 }
                 Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
@@ -447,10 +967,10 @@ async fn handle_api_request(
                 if latency_injector.toggle_rule(id) {
                     Ok(json_response(json!({"success": true}).to_string()))
                 } else {
-                    Ok(not_found_response())
+                    Ok(api_error(ApiErrorKind::NotFound, "Not found"))
                 }
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
         ("DELETE", p) if p.starts_with("/api/latency-rules/") => {
@@ -458,7 +978,7 @@ async fn handle_api_request(
             if latency_injector.delete_rule(id) {
                 Ok(json_response(json!({"success": true}).to_string()))
             } else {
-                Ok(not_found_response())
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
             }
         }
         ("DELETE", "/api/latency-rules") => {
@@ -466,7 +986,7 @@ async fn handle_api_request(
             Ok(json_response(json!({"success": true}).to_string()))
         }
         ("GET", "/api/latency-stats") => {
-            let stats = latency_injector.get_stats();
+            let stats = latency_injector.live_stats();
             let json = serde_json::to_string(&stats).unwrap();
             Ok(json_response(json))
         }
@@ -475,16 +995,159 @@ async fn handle_api_request(
             Ok(json_response(json!({"success": true}).to_string()))
         }
 
-        _ => Ok(not_found_response()),
+        ("GET", "/api/faults") => {
+            let rules = fault_injector.get_all_rules();
+            let json = serde_json::to_string(&rules).unwrap();
+            Ok(json_response(json))
+        }
+        ("POST", "/api/faults") => match read_body_json::<CreateFaultRule>(req).await {
+            Ok(rule) => {
+                let id = fault_injector.add_rule(rule);
+                Ok(json_response(json!({"id": id}).to_string()))
+            }
+            Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
+        },
+        ("GET", p) if p.starts_with("/api/faults/") && !p.ends_with("/toggle") => {
+            let id = p.trim_start_matches("/api/faults/");
+            if let Some(rule) = fault_injector.get_rule(id) {
+                let json = serde_json::to_string(&rule).unwrap();
+                Ok(json_response(json))
+            } else {
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
+            }
+        }
+        ("PUT", p) if p.starts_with("/api/faults/") => {
+            match read_body_json::<UpdateFaultRule>(req).await {
+                Ok(rule) => {
+                    if fault_injector.update_rule(rule) {
+                        Ok(json_response(json!({"success": true}).to_string()))
+                    } else {
+                        Ok(api_error(ApiErrorKind::NotFound, "Not found"))
+                    }
+                }
+                Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
+            }
+        }
+        ("POST", p) if p.starts_with("/api/faults/") && p.ends_with("/toggle") => {
+            let id = p
+                .trim_start_matches("/api/faults/")
+                .trim_end_matches("/toggle");
+            if fault_injector.toggle_rule(id) {
+                Ok(json_response(json!({"success": true}).to_string()))
+            } else {
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
+            }
+        }
+        ("DELETE", p) if p.starts_with("/api/faults/") => {
+            let id = p.trim_start_matches("/api/faults/");
+            if fault_injector.delete_rule(id) {
+                Ok(json_response(json!({"success": true}).to_string()))
+            } else {
+                Ok(api_error(ApiErrorKind::NotFound, "Not found"))
+            }
+        }
+        ("DELETE", "/api/faults") => {
+            fault_injector.clear_all();
+            Ok(json_response(json!({"success": true}).to_string()))
+        }
+        ("GET", "/api/fault-stats") => {
+            let stats = fault_injector.get_stats();
+            let json = serde_json::to_string(&stats).unwrap();
+            Ok(json_response(json))
+        }
+        ("POST", "/api/fault-stats/reset") => {
+            fault_injector.reset_stats();
+            Ok(json_response(json!({"success": true}).to_string()))
+        }
+
+        ("GET", "/api/circuit-breaker") => {
+            let json = serde_json::to_string(&circuit_breaker.status()).unwrap();
+            Ok(json_response(json))
+        }
+
+        ("GET", "/api/version") => {
+            let json = serde_json::to_string(&crate::version::build_info()).unwrap();
+            Ok(json_response(json))
+        }
+
+        // Snapshots
+        ("GET", "/api/snapshots") => {
+            let json = serde_json::to_string(&snapshots.list()).unwrap();
+            Ok(json_response(json))
+        }
+        ("POST", p) if p.starts_with("/api/snapshots/") && p.ends_with("/restore") => {
+            let name = snapshot_name(
+                p.trim_start_matches("/api/snapshots/")
+                    .trim_end_matches("/restore"),
+            );
+            match snapshots.get(&name) {
+                Some(snapshot) => {
+                    snapshot.restore(
+                        storage,
+                        mock_manager,
+                        response_modifier,
+                        rate_limiter,
+                        latency_injector,
+                    );
+                    let json = serde_json::to_string(&snapshot.info()).unwrap();
+                    Ok(json_response(json))
+                }
+                None => Ok(api_error(ApiErrorKind::NotFound, "Snapshot not found")),
+            }
+        }
+        ("POST", p) if p.starts_with("/api/snapshots/") => {
+            let name = snapshot_name(p.trim_start_matches("/api/snapshots/"));
+            if name.is_empty() || name.contains('/') {
+                return Ok(error_response(
+                    "Snapshot name must be non-empty without '/'",
+                ));
+            }
+
+            let snapshot = Snapshot::capture(
+                &name,
+                storage,
+                mock_manager,
+                response_modifier,
+                rate_limiter,
+                latency_injector,
+            );
+            let json = serde_json::to_string(&snapshots.save(snapshot)).unwrap();
+            Ok(json_response(json))
+        }
+        ("DELETE", p) if p.starts_with("/api/snapshots/") => {
+            let name = snapshot_name(p.trim_start_matches("/api/snapshots/"));
+            if snapshots.delete(&name) {
+                Ok(json_response(json!({"success": true}).to_string()))
+            } else {
+                Ok(api_error(ApiErrorKind::NotFound, "Snapshot not found"))
+            }
+        }
+
+        // Pattern debugging
+        ("POST", "/api/match-pattern/test") => {
+            match read_body_json::<PatternTestRequest>(req).await {
+                Ok(test) => match crate::matcher::test_pattern(&test) {
+                    Ok(results) => {
+                        let json = serde_json::to_string(&results).unwrap();
+                        Ok(json_response(json))
+                    }
+                    Err(e) => Ok(error_response(&e)),
+                },
+                Err(e) => Ok(error_response(&format!("Invalid request: {}", e))),
+            }
+        }
+
+        _ => Ok(api_error(ApiErrorKind::NotFound, "Not found")),
     }
 }
 
 async fn replay_request(
+    client: &reqwest::Client,
     replay_req: &crate::storage::ReplayRequest,
     upstream_url: &str,
+    retry: &ReplayRetryPolicy,
 ) -> Result<crate::storage::RecordedRequest, String> {
     let start = std::time::Instant::now();
-    let client = reqwest::Client::new();
 
     // Parse method
     let method = reqwest::Method::from_bytes(replay_req.method.as_bytes())
@@ -497,41 +1160,66 @@ async fn replay_request(
         format!("{}{}", upstream_url, replay_req.url)
     };
 
-    // Build request
-    let mut request = client.request(method, &full_url);
+    let mut history = Vec::new();
 
-    // Add headers (skip host header)
-    for (key, value) in &replay_req.headers {
-        if key.to_lowercase() != "host" {
-            request = request.header(key, value);
+    let (status, response_headers, response_body) = loop {
+        let attempt_start = std::time::Instant::now();
+
+        // Build request
+        let mut request = client.request(method.clone(), &full_url);
+
+        // Add headers (skip host header)
+        for (key, value) in &replay_req.headers {
+            if key.to_lowercase() != "host" {
+                request = request.header(key, value);
+            }
         }
-    }
 
-    // Add body if present
-    if let Some(ref body) = replay_req.body {
-        request = request.body(body.clone());
-    }
+        // Add body if present
+        if let Some(ref body) = replay_req.body {
+            request = request.body(body.clone());
+        }
 
-    // Send request
-    let response = request
-        .send()
-        .await
-        .map_err(|e| format!("Request failed: {}", e))?;
+        // Send request
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
 
-    let status = response.status().as_u16();
-    let mut response_headers = std::collections::HashMap::new();
+        let status = response.status().as_u16();
+        let mut response_headers = std::collections::HashMap::new();
 
-    for (name, value) in response.headers().iter() {
-        if let Ok(value_str) = value.to_str() {
-            response_headers.insert(name.to_string(), value_str.to_string());
+        for (name, value) in response.headers().iter() {
+            if let Ok(value_str) = value.to_str() {
+                response_headers.insert(name.to_string(), value_str.to_string());
+            }
         }
-    }
 
-    let response_body = response
-        .bytes()
-        .await
-        .map_err(|e| format!("Failed to read response: {}", e))?
-        .to_vec();
+        let response_body = response
+            .bytes()
+            .await
+            .map_err(|e| format!("Failed to read response: {}", e))?
+            .to_vec();
+
+        // Retry throttled responses while the policy allows it
+        let retries_left = (history.len() as u32) < retry.max_retries;
+        let retry_after_ms =
+            (retry.honor_retry_after && retries_left && matches!(status, 429 | 503))
+                .then(|| response_headers.get("retry-after"))
+                .flatten()
+                .and_then(|value| parse_retry_after_ms(value));
+
+        history.push(crate::storage::ReplayAttempt {
+            status,
+            duration_ms: attempt_start.elapsed().as_millis() as u64,
+            retry_after_ms,
+        });
+
+        match retry_after_ms {
+            Some(wait_ms) => tokio::time::sleep(std::time::Duration::from_millis(wait_ms)).await,
+            None => break (status, response_headers, response_body),
+        }
+    };
 
     let duration_ms = start.elapsed().as_millis() as u64;
 
@@ -545,12 +1233,137 @@ async fn replay_request(
         response: Some(crate::storage::RecordedResponse {
             status,
             headers: response_headers,
+            body_size: response_body.len(),
             body: Some(response_body),
+            original_charset: None,
+            body_truncated: false,
         }),
         duration_ms: Some(duration_ms),
+        original_charset: None,
+        body_truncated: false,
+        injected_latency_ms: None,
+        cache_hit: false,
+        replay: Some(crate::storage::ReplayInfo {
+            attempts: history.len() as u32,
+            history,
+        }),
+        correlation_id: replay_req.correlation_id.clone(),
+        explanation: None,
+        origin: None,
     })
 }
 
+/// Parse a Retry-After value, either delay seconds or an HTTP date, capped at
+/// `ReplayRetryPolicy::MAX_WAIT_MS` so a hostile upstream can't stall a replay
+fn parse_retry_after_ms(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let wait_ms = match value.parse::<u64>() {
+        Ok(seconds) => seconds.saturating_mul(1000),
+        Err(_) => {
+            let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            let wait = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+            wait.num_milliseconds().max(0) as u64
+        }
+    };
+    Some(wait_ms.min(ReplayRetryPolicy::MAX_WAIT_MS))
+}
+
+/// Replay a selected set of recordings, running at most `concurrency` at once. Replays
+/// start in the order of the requested ids, so a concurrency of 1 replays them one after
+/// another; results keep that order and unknown ids are reported as not found.
+async fn replay_bulk(
+    client: &reqwest::Client,
+    storage: &Storage,
+    bulk: BulkReplayRequest,
+) -> BulkReplayResult {
+    let upstream_url = bulk
+        .upstream
+        .unwrap_or_else(|| "http://localhost:8000".to_string());
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(
+        bulk.concurrency.unwrap_or(1).max(1),
+    ));
+
+    let mut tasks = tokio::task::JoinSet::new();
+    let mut items: Vec<Option<BulkReplayItem>> = Vec::with_capacity(bulk.ids.len());
+
+    for (index, id) in bulk.ids.into_iter().enumerate() {
+        match storage.get_for_replay(&id) {
+            Some(replay_req) => {
+                // Taken before spawning, so replays can't start out of order
+                let permit = semaphore.clone().acquire_owned().await;
+                let upstream_url = upstream_url.clone();
+                let client = client.clone();
+                tasks.spawn(async move {
+                    let _permit = permit;
+                    let outcome = match replay_request(
+                        &client,
+                        &replay_req,
+                        &upstream_url,
+                        &ReplayRetryPolicy::default(),
+                    )
+                    .await
+                    {
+                        Ok(response) => BulkReplayOutcome::Replayed {
+                            response: Box::new(response),
+                        },
+                        Err(error) => BulkReplayOutcome::Failed { error },
+                    };
+                    (index, BulkReplayItem { id, outcome })
+                });
+                items.push(None);
+            }
+            None => items.push(Some(BulkReplayItem {
+                id,
+                outcome: BulkReplayOutcome::NotFound,
+            })),
+        }
+    }
+
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((index, item)) = joined {
+            items[index] = Some(item);
+        }
+    }
+
+    BulkReplayResult::from_items(items.into_iter().flatten().collect())
+}
+
+/// `replay-batch` answers with the replayed recordings, in replay order. A replay that
+/// got no response stays in the list as a recording without one, its `origin` carrying
+/// the error.
+fn batch_replay_recordings(storage: &Storage, result: BulkReplayResult) -> Vec<RecordedRequest> {
+    result
+        .results
+        .into_iter()
+        .filter_map(|item| match item.outcome {
+            BulkReplayOutcome::Replayed { response } => Some(*response),
+            BulkReplayOutcome::Failed { error } => {
+                let replay_req = storage.get_for_replay(&item.id)?;
+                Some(RecordedRequest {
+                    id: uuid::Uuid::new_v4().to_string(),
+                    timestamp: chrono::Utc::now(),
+                    method: replay_req.method,
+                    url: replay_req.url,
+                    headers: replay_req.headers,
+                    body: replay_req.body,
+                    response: None,
+                    duration_ms: None,
+                    original_charset: None,
+                    body_truncated: false,
+                    injected_latency_ms: None,
+                    cache_hit: false,
+                    replay: None,
+                    correlation_id: replay_req.correlation_id,
+                    explanation: None,
+                    origin: Some(crate::storage::ResponseOrigin::UpstreamError { error }),
+                })
+            }
+            // Checked before replaying; only gone if deleted meanwhile
+            BulkReplayOutcome::NotFound => None,
+        })
+        .collect()
+}
+
 async fn read_body_json<T: serde::de::DeserializeOwned>(
     req: hyper::Request<hyper::body::Incoming>,
 ) -> Result<T, String> {
@@ -566,7 +1379,83 @@ async fn read_body_json<T: serde::de::DeserializeOwned>(
     serde_json::from_slice(&bytes).map_err(|e| format!("Failed to parse JSON: {}", e))
 }
 
+/// Like `read_body_json`, but an empty body gives the default value
+async fn read_optional_body_json<T: serde::de::DeserializeOwned + Default>(
+    req: hyper::Request<hyper::body::Incoming>,
+) -> Result<T, String> {
+    use http_body_util::BodyExt;
+
+    let bytes = req
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| format!("Failed to read body: {}", e))?
+        .to_bytes();
+    if bytes.iter().all(u8::is_ascii_whitespace) {
+        return Ok(T::default());
+    }
+
+    serde_json::from_slice(&bytes).map_err(|e| format!("Failed to parse JSON: {}", e))
+}
+
+/// Snapshot name from its percent-encoded path segment, so `my%20snap` is `my snap`
+fn snapshot_name(segment: &str) -> String {
+    urlencoding::decode(segment).unwrap_or_default().to_string()
+}
+
+fn query_param(query: Option<&str>, name: &str) -> Option<String> {
+    query?.split('&').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        (key == name).then(|| urlencoding::decode(value).unwrap_or_default().to_string())
+    })
+}
+
+/// Parse a bucket size like `30s`, `5m` or `1h` into seconds
+fn parse_bucket_seconds(bucket: &str) -> Option<i64> {
+    let split = bucket.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = bucket.split_at(split);
+    let amount: i64 = amount.parse().ok().filter(|&n| n > 0)?;
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return None,
+    };
+    amount.checked_mul(multiplier)
+}
+
+/// Query parameters `DELETE /api/recordings` accepts
+const DELETE_RECORDINGS_PARAMS: &[&str] = &[
+    "search",
+    "method",
+    "status",
+    "minDuration",
+    "maxDuration",
+    "headerName",
+    "headerValue",
+];
+
+/// First query parameter that isn't in `allowed`
+fn unknown_param<'a>(query: Option<&'a str>, allowed: &[&str]) -> Option<&'a str> {
+    query?
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| param.split_once('=').map_or(param, |(key, _)| key))
+        .find(|key| !allowed.contains(key))
+}
+
+/// The list's filter params; values that don't parse are ignored
 fn parse_filter_options(query: Option<&str>) -> FilterOptions {
+    filter_options(query, false).unwrap_or_default()
+}
+
+/// Like `parse_filter_options`, but values that don't parse are an error, for deletes
+fn parse_strict_filter_options(query: Option<&str>) -> Result<FilterOptions, String> {
+    filter_options(query, true)
+}
+
+fn filter_options(query: Option<&str>, strict: bool) -> Result<FilterOptions, String> {
     let mut filters = FilterOptions {
         search: None,
         method: None,
@@ -575,6 +1464,13 @@ fn parse_filter_options(query: Option<&str>) -> FilterOptions {
         max_duration: None,
         from_time: None,
         to_time: None,
+        url: None,
+        min_status: None,
+        max_status: None,
+        min_size: None,
+        max_size: None,
+        header_name: None,
+        header_value: None,
     };
 
     if let Some(query_str) = query {
@@ -583,25 +1479,25 @@ fn parse_filter_options(query: Option<&str>) -> FilterOptions {
                 let decoded_value = urlencoding::decode(value).unwrap_or_default();
                 match key {
                     "search" if !decoded_value.is_empty() => {
-                        filters.search = Some(decoded_value.to_string());
+                        filters.apply_search_query(&decoded_value);
                     }
                     "method" if !decoded_value.is_empty() => {
                         filters.method = Some(decoded_value.to_string());
                     }
-                    "status" => {
-                        if let Ok(status) = decoded_value.parse::<u16>() {
-                            filters.status = Some(status);
-                        }
+                    "status" if !decoded_value.is_empty() => {
+                        filters.status = parse_number(key, &decoded_value, strict)?;
+                    }
+                    "minDuration" if !decoded_value.is_empty() => {
+                        filters.min_duration = parse_number(key, &decoded_value, strict)?;
                     }
-                    "minDuration" => {
-                        if let Ok(duration) = decoded_value.parse::<u64>() {
-                            filters.min_duration = Some(duration);
-                        }
+                    "maxDuration" if !decoded_value.is_empty() => {
+                        filters.max_duration = parse_number(key, &decoded_value, strict)?;
                     }
-                    "maxDuration" => {
-                        if let Ok(duration) = decoded_value.parse::<u64>() {
-                            filters.max_duration = Some(duration);
-                        }
+                    "headerName" if !decoded_value.is_empty() => {
+                        filters.header_name = Some(decoded_value.to_string());
+                    }
+                    "headerValue" if !decoded_value.is_empty() => {
+                        filters.header_value = Some(decoded_value.to_string());
                     }
                     _ => {}
                 }
@@ -609,7 +1505,58 @@ fn parse_filter_options(query: Option<&str>) -> FilterOptions {
         }
     }
 
-    filters
+    if strict && filters.header_value.is_some() && filters.header_name.is_none() {
+        return Err("headerValue needs a headerName".to_string());
+    }
+    Ok(filters)
+}
+
+/// A numeric filter value; one that doesn't parse is None, or an error when `strict`
+fn parse_number<T: std::str::FromStr>(
+    key: &str,
+    value: &str,
+    strict: bool,
+) -> Result<Option<T>, String> {
+    match value.parse() {
+        Ok(number) => Ok(Some(number)),
+        Err(_) if strict => Err(format!("Invalid {} '{}', expected a number", key, value)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// `limit`, `offset`, `sort` and `order` (`asc`/`desc`) for the recordings list, or None
+/// when none of them is given
+fn parse_page_options(query: Option<&str>) -> Result<Option<PageOptions>, String> {
+    let [limit, offset, sort, order] =
+        ["limit", "offset", "sort", "order"].map(|name| query_param(query, name));
+    if limit.is_none() && offset.is_none() && sort.is_none() && order.is_none() {
+        return Ok(None);
+    }
+
+    let number = |name: &str, value: Option<String>| -> Result<Option<usize>, String> {
+        value
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| format!("Invalid {} '{}', expected a number", name, value))
+            })
+            .transpose()
+    };
+    let ascending = match order.as_deref() {
+        None | Some("desc") => false,
+        Some("asc") => true,
+        Some(order) => return Err(format!("Invalid order '{}', expected asc or desc", order)),
+    };
+
+    Ok(Some(PageOptions {
+        sort: sort
+            .map(|sort| sort.parse())
+            .transpose()?
+            .unwrap_or_default(),
+        ascending,
+        offset: number("offset", offset)?.unwrap_or(0),
+        limit: number("limit", limit)?,
+    }))
 }
 
 fn has_filters(filters: &FilterOptions) -> bool {
@@ -620,11 +1567,15 @@ fn has_filters(filters: &FilterOptions) -> bool {
         || filters.max_duration.is_some()
         || filters.from_time.is_some()
         || filters.to_time.is_some()
+        || filters.url.is_some()
+        || filters.min_status.is_some()
+        || filters.max_status.is_some()
+        || filters.min_size.is_some()
+        || filters.max_size.is_some()
+        || filters.header_name.is_some()
 }
 
-async fn serve_static_file(
-    path: &str,
-) -> Result<hyper::Response<http_body_util::Full<Bytes>>, Infallible> {
+async fn serve_static_file(path: &str) -> Result<hyper::Response<ResponseBody>, Infallible> {
     let path = path.trim_start_matches('/');
     let path = if path.is_empty() { "index.html" } else { path };
 
@@ -635,7 +1586,7 @@ async fn serve_static_file(
             Ok(hyper::Response::builder()
                 .status(StatusCode::OK)
                 .header(header::CONTENT_TYPE, mime.as_ref())
-                .body(http_body_util::Full::new(bytes))
+                .body(full(bytes))
                 .unwrap())
         }
         None => {
@@ -644,7 +1595,7 @@ async fn serve_static_file(
                 Ok(hyper::Response::builder()
                     .status(StatusCode::OK)
                     .header(header::CONTENT_TYPE, "text/html")
-                    .body(http_body_util::Full::new(bytes))
+                    .body(full(bytes))
                     .unwrap())
             } else {
                 Ok(not_found_response())
@@ -653,27 +1604,267 @@ async fn serve_static_file(
     }
 }
 
-fn json_response(json: String) -> hyper::Response<http_body_util::Full<Bytes>> {
+/// Stream recordings completed from now on that match `filters`, one JSON object per line.
+/// The stream ends when the client disconnects and the body is dropped.
+fn tail_response(
+    storage: Storage,
+    filters: FilterOptions,
+    include_bodies: bool,
+) -> hyper::Response<ResponseBody> {
+    let receiver = storage.subscribe();
+    let lines = futures_util::stream::unfold(receiver, move |mut receiver| {
+        let storage = storage.clone();
+        let filters = filters.clone();
+        async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(recording) if storage.matches_filters(&recording, &filters) => {
+                        let summary = RecordingSummary::new(recording, include_bodies);
+                        let mut line = serde_json::to_vec(&summary).unwrap();
+                        line.push(b'\n');
+                        return Some((Ok(Frame::data(Bytes::from(line))), receiver));
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+
+    hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(StreamBody::new(lines).boxed_unsync())
+        .unwrap()
+}
+
+/// Server-Sent Events feed with the full JSON of each completed recording. Unlike the
+/// tail, a subscriber that falls behind is disconnected instead of silently skipping
+/// recordings; `EventSource` reconnects on its own and the client can refetch.
+fn stream_response(storage: Storage) -> hyper::Response<ResponseBody> {
+    let receiver = storage.subscribe();
+    let events = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(recording) => {
+                let event = format!("data: {}\n\n", serde_json::to_string(&recording).unwrap());
+                Some((Ok(Frame::data(Bytes::from(event))), receiver))
+            }
+            Err(broadcast::error::RecvError::Lagged(_) | broadcast::error::RecvError::Closed) => {
+                None
+            }
+        }
+    });
+
+    hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(StreamBody::new(events).boxed_unsync())
+        .unwrap()
+}
+
+fn json_response(json: String) -> hyper::Response<ResponseBody> {
     hyper::Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/json")
-        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-        .body(http_body_util::Full::new(Bytes::from(json)))
+        .body(full(Bytes::from(json)))
         .unwrap()
 }
 
-fn error_response(message: &str) -> hyper::Response<http_body_util::Full<Bytes>> {
-    let json = json!({"error": message}).to_string();
+/// Error categories reported in the `type` field of API error responses
+#[derive(Debug, Clone, Copy)]
+enum ApiErrorKind {
+    Validation,
+    NotFound,
+    Internal,
+}
+
+impl ApiErrorKind {
+    fn status(self) -> StatusCode {
+        match self {
+            ApiErrorKind::Validation => StatusCode::BAD_REQUEST,
+            ApiErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ApiErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(self) -> &'static str {
+        match self {
+            ApiErrorKind::Validation => "validation_error",
+            ApiErrorKind::NotFound => "not_found",
+            ApiErrorKind::Internal => "internal_error",
+        }
+    }
+}
+
+fn api_error(kind: ApiErrorKind, message: &str) -> hyper::Response<ResponseBody> {
+    let json = json!({"error": message, "type": kind.code()}).to_string();
     hyper::Response::builder()
-        .status(StatusCode::BAD_REQUEST)
+        .status(kind.status())
         .header(header::CONTENT_TYPE, "application/json")
-        .body(http_body_util::Full::new(Bytes::from(json)))
+        .body(full(Bytes::from(json)))
+        .unwrap()
+}
+
+fn error_response(message: &str) -> hyper::Response<ResponseBody> {
+    api_error(ApiErrorKind::Validation, message)
+}
+
+fn unauthorized_response() -> hyper::Response<ResponseBody> {
+    hyper::Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header(
+            header::WWW_AUTHENTICATE,
+            "Basic realm=\"dev-proxy\", charset=\"UTF-8\"",
+        )
+        .body(full(Bytes::from("Unauthorized")))
         .unwrap()
 }
 
-fn not_found_response() -> hyper::Response<http_body_util::Full<Bytes>> {
+/// `Basic` credentials as a client sends them in the `Authorization` header
+fn basic_authorization(user: &str, pass: &str) -> String {
+    let credentials = format!("{}:{}", user, pass);
+    format!(
+        "Basic {}",
+        base64::engine::general_purpose::STANDARD.encode(credentials)
+    )
+}
+
+/// Compare without stopping at the first mismatch, so response timing doesn't reveal how
+/// much of the credentials was right. Only the length can leak.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn not_found_response() -> hyper::Response<ResponseBody> {
     hyper::Response::builder()
         .status(StatusCode::NOT_FOUND)
-        .body(http_body_util::Full::new(Bytes::from("Not Found")))
+        .body(full(Bytes::from("Not Found")))
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_seconds_are_capped_without_overflow() {
+        assert_eq!(parse_retry_after_ms("2"), Some(2000));
+        assert_eq!(
+            parse_retry_after_ms(&u64::MAX.to_string()),
+            Some(ReplayRetryPolicy::MAX_WAIT_MS)
+        );
+    }
+
+    #[test]
+    fn retry_after_dates_are_capped() {
+        let far = (chrono::Utc::now() + chrono::Duration::days(1)).to_rfc2822();
+        assert_eq!(
+            parse_retry_after_ms(&far),
+            Some(ReplayRetryPolicy::MAX_WAIT_MS)
+        );
+        assert_eq!(
+            parse_retry_after_ms("Mon, 01 Jan 2001 00:00:00 +0000"),
+            Some(0)
+        );
+        assert_eq!(parse_retry_after_ms("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn bulk_replay_reports_every_id() {
+        let storage = Storage::new();
+        storage.insert_completed(RecordedRequest {
+            id: "r0".to_string(),
+            timestamp: chrono::Utc::now(),
+            method: "GET".to_string(),
+            url: "/api/items".to_string(),
+            headers: std::collections::HashMap::new(),
+            body: None,
+            response: None,
+            duration_ms: None,
+            original_charset: None,
+            body_truncated: false,
+            injected_latency_ms: None,
+            cache_hit: false,
+            replay: None,
+            correlation_id: None,
+            explanation: None,
+            origin: None,
+        });
+
+        // Nothing listens on port 1, so the replay itself fails
+        let bulk = BulkReplayRequest {
+            ids: vec!["r0".to_string(), "missing".to_string()],
+            upstream: Some("http://127.0.0.1:1".to_string()),
+            concurrency: None,
+        };
+        let result = replay_bulk(&reqwest::Client::new(), &storage, bulk).await;
+
+        assert_eq!((result.total, result.failed, result.not_found), (2, 1, 1));
+        let json = serde_json::to_value(&result.results).unwrap();
+        assert_eq!(json[0]["id"], "r0");
+        assert_eq!(json[0]["outcome"], "failed");
+        assert!(json[0]["error"].is_string());
+        assert_eq!(json[1]["id"], "missing");
+        assert_eq!(json[1]["outcome"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn batch_replay_keeps_failed_replays_as_recordings() {
+        let storage = Storage::new();
+        storage.insert_completed(RecordedRequest {
+            id: "r0".to_string(),
+            timestamp: chrono::Utc::now(),
+            method: "POST".to_string(),
+            url: "/api/items".to_string(),
+            headers: std::collections::HashMap::new(),
+            body: Some(b"{}".to_vec()),
+            response: None,
+            duration_ms: None,
+            original_charset: None,
+            body_truncated: false,
+            injected_latency_ms: None,
+            cache_hit: false,
+            replay: None,
+            correlation_id: None,
+            explanation: None,
+            origin: None,
+        });
+        let bulk = BulkReplayRequest {
+            ids: vec!["r0".to_string()],
+            upstream: Some("http://127.0.0.1:1".to_string()),
+            concurrency: None,
+        };
+        let result = replay_bulk(&reqwest::Client::new(), &storage, bulk).await;
+
+        let replayed = batch_replay_recordings(&storage, result);
+        assert_eq!(replayed.len(), 1);
+        assert_eq!(replayed[0].url, "/api/items");
+        assert_eq!(replayed[0].body.as_deref(), Some(&b"{}"[..]));
+        assert!(replayed[0].response.is_none());
+        assert!(matches!(
+            replayed[0].origin,
+            Some(crate::storage::ResponseOrigin::UpstreamError { .. })
+        ));
+    }
+
+    #[test]
+    fn list_filters_ignore_bad_values_but_deletes_reject_them() {
+        let lenient = parse_filter_options(Some("status=4xx&minDuration=10&headerValue=x"));
+        assert_eq!(lenient.status, None);
+        assert_eq!(lenient.min_duration, Some(10));
+
+        assert!(parse_strict_filter_options(Some("status=4xx")).is_err());
+        assert!(parse_strict_filter_options(Some("headerValue=x")).is_err());
+        let strict = parse_strict_filter_options(Some("status=404&method=GET")).unwrap();
+        assert_eq!(
+            (strict.status, strict.method.as_deref()),
+            (Some(404), Some("GET"))
+        );
+        assert!(!has_filters(&parse_strict_filter_options(None).unwrap()));
+    }
+}
@@ -0,0 +1,30 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Build metadata embedded by the build script
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    pub build_time: Option<DateTime<Utc>>,
+    pub features: Vec<&'static str>,
+}
+
+pub fn build_info() -> BuildInfo {
+    let build_time = option_env!("DEV_PROXY_BUILD_TIMESTAMP")
+        .and_then(|ts| ts.parse::<i64>().ok())
+        .and_then(|ts| DateTime::from_timestamp(ts, 0));
+
+    let features = option_env!("DEV_PROXY_FEATURES")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|f| !f.is_empty())
+        .collect();
+
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: option_env!("DEV_PROXY_GIT_COMMIT").unwrap_or("unknown"),
+        build_time,
+        features,
+    }
+}
@@ -0,0 +1,270 @@
+use crate::config::ProxyConfig;
+use crate::http_layer::{HttpLayerState, ResponseBody, full};
+use crate::logger::RequestLog;
+use crate::storage::{WsDirection, WsFrameKind};
+use anyhow::{Context, Result, anyhow};
+use bytes::Bytes;
+use futures_util::{SinkExt, Stream, StreamExt};
+use hyper::{Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::CryptoProvider;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::{Message, client::IntoClientRequest};
+use tokio_tungstenite::{Connector, WebSocketStream};
+
+// Handshake headers that are generated again for the upstream connection
+const HANDSHAKE_HEADERS: &[&str] = &[
+    "host",
+    "connection",
+    "upgrade",
+    "sec-websocket-key",
+    "sec-websocket-version",
+    "sec-websocket-extensions",
+];
+
+pub fn is_upgrade_request<B>(req: &Request<B>) -> bool {
+    req.headers()
+        .get(http::header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"))
+}
+
+/// Open a WebSocket to upstream, then accept the client's upgrade and relay frames both
+/// ways. When recording is enabled the text and binary frames are stored as a session.
+/// WebSockets bypass the rule engine: mocks, modifiers and latency rules don't apply.
+pub async fn proxy_websocket(
+    mut req: Request<hyper::body::Incoming>,
+    state: Arc<HttpLayerState>,
+    log: &RequestLog,
+) -> Result<Response<ResponseBody>> {
+    let key = req
+        .headers()
+        .get(http::header::SEC_WEBSOCKET_KEY)
+        .context("missing Sec-WebSocket-Key")?
+        .as_bytes()
+        .to_vec();
+
+    // Picked like an HTTP request: the --route upstream, else the next one in turn
+    let upstream = match state.config.route_for(req.uri().path()) {
+        Some(upstream) => upstream,
+        None => {
+            let upstreams = &state.config.upstream_urls;
+            &upstreams[state.next_upstream.fetch_add(1, Ordering::Relaxed) % upstreams.len()]
+        }
+    };
+    let upstream_url = upstream_ws_url(upstream, &log.url)?;
+    let mut upstream_req = upstream_url.as_str().into_client_request()?;
+    let mut headers = HashMap::new();
+    for (name, value) in req.headers() {
+        if HANDSHAKE_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        upstream_req.headers_mut().append(name, value.clone());
+        if let Ok(value) = value.to_str() {
+            headers.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    let (upstream_ws, upstream_response) = tokio_tungstenite::connect_async_tls_with_config(
+        upstream_req,
+        None,
+        false,
+        Some(state.ws_connector.clone()),
+    )
+    .await
+    .context("upstream WebSocket handshake failed")?;
+
+    state.config.strip_recorded(&mut headers);
+    let session_id = state
+        .should_record(&log.url)
+        .then(|| state.storage.start_ws_session(&log.url, headers));
+
+    let upgrade = hyper::upgrade::on(&mut req);
+    tokio::spawn(async move {
+        match upgrade.await {
+            Ok(upgraded) => {
+                let client_ws = WebSocketStream::from_raw_socket(
+                    hyper_util::rt::TokioIo::new(upgraded),
+                    Role::Server,
+                    None,
+                )
+                .await;
+                relay(client_ws, upstream_ws, &state, session_id.as_deref()).await;
+            }
+            Err(e) => eprintln!("WebSocket upgrade failed: {}", e),
+        }
+
+        if let Some(ref id) = session_id {
+            state.storage.close_ws_session(id);
+        }
+    });
+
+    let mut response = Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(http::header::CONNECTION, "Upgrade")
+        .header(http::header::UPGRADE, "websocket")
+        .header(http::header::SEC_WEBSOCKET_ACCEPT, derive_accept_key(&key));
+    if let Some(protocol) = upstream_response
+        .headers()
+        .get(http::header::SEC_WEBSOCKET_PROTOCOL)
+    {
+        response = response.header(http::header::SEC_WEBSOCKET_PROTOCOL, protocol);
+    }
+
+    Ok(response.body(full(Bytes::new()))?)
+}
+
+/// TLS for wss:// upstreams, trusting what the HTTP upstream client does: the system
+/// roots plus `--upstream-ca`, or any certificate with `--insecure-upstream`
+pub fn upstream_connector(config: &ProxyConfig) -> Result<Connector> {
+    // Picked explicitly, as for the TLS listener
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let tls = if config.insecure_upstream {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCert(provider)))
+            .with_no_client_auth()
+    } else {
+        let mut roots = rustls::RootCertStore::empty();
+        let native = rustls_native_certs::load_native_certs();
+        for e in &native.errors {
+            eprintln!("Skipping system root certificates: {}", e);
+        }
+        roots.add_parsable_certificates(native.certs);
+        if let Some(ref path) = config.upstream_ca {
+            let certs = CertificateDer::pem_file_iter(path)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| anyhow!("Failed to read --upstream-ca {}: {}", path.display(), e))?;
+            roots.add_parsable_certificates(certs);
+        }
+        builder.with_root_certificates(roots).with_no_client_auth()
+    };
+    Ok(Connector::Rustls(Arc::new(tls)))
+}
+
+/// `--insecure-upstream`: skips certificate checks but still verifies handshake
+/// signatures, like reqwest's `danger_accept_invalid_certs`
+#[derive(Debug)]
+struct AcceptAnyCert(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Forward frames until either side closes
+async fn relay<C, U>(
+    client: WebSocketStream<C>,
+    upstream: WebSocketStream<U>,
+    state: &HttpLayerState,
+    session_id: Option<&str>,
+) where
+    C: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    U: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let (client_tx, client_rx) = client.split();
+    let (upstream_tx, upstream_rx) = upstream.split();
+
+    tokio::select! {
+        _ = forward(client_rx, upstream_tx, WsDirection::ClientToServer, state, session_id) => {}
+        _ = forward(upstream_rx, client_tx, WsDirection::ServerToClient, state, session_id) => {}
+    }
+}
+
+async fn forward<R, W>(
+    mut rx: R,
+    mut tx: W,
+    direction: WsDirection,
+    state: &HttpLayerState,
+    session_id: Option<&str>,
+) where
+    R: Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    W: futures_util::Sink<Message> + Unpin,
+{
+    while let Some(Ok(message)) = rx.next().await {
+        if let Some(id) = session_id {
+            match message {
+                Message::Text(ref text) => {
+                    state
+                        .storage
+                        .record_ws_frame(id, direction, WsFrameKind::Text, text.as_bytes())
+                }
+                Message::Binary(ref data) => {
+                    state
+                        .storage
+                        .record_ws_frame(id, direction, WsFrameKind::Binary, data)
+                }
+                _ => {}
+            }
+        }
+
+        if tx.send(message).await.is_err() {
+            break;
+        }
+    }
+    let _ = tx.close().await;
+}
+
+/// ws(s) URL on upstream for a request path
+fn upstream_ws_url(upstream: &str, path_and_query: &str) -> Result<String> {
+    let base = if let Some(rest) = upstream.strip_prefix("https://") {
+        format!("wss://{}", rest)
+    } else if let Some(rest) = upstream.strip_prefix("http://") {
+        format!("ws://{}", rest)
+    } else {
+        return Err(anyhow!("unsupported upstream URL {}", upstream));
+    };
+    Ok(format!("{}{}", base.trim_end_matches('/'), path_and_query))
+}
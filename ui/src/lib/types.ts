@@ -7,12 +7,78 @@ export interface RecordedRequest {
   body?: number[];
   response?: RecordedResponse;
   duration_ms?: number;
+  original_charset?: string;
+  body_truncated?: boolean;
+  injected_latency_ms?: number;
+  cache_hit?: boolean;
+  replay?: ReplayInfo | null;
+  correlation_id?: string | null;
+  explanation?: Explanation | null;
+  origin?: ResponseOrigin | null;
+  // Only on list results; bodies there are null unless ?include_bodies=true
+  request_body_size?: number;
+  response_body_size?: number;
+}
+
+// Responses the proxy produced or cut short itself; auto-mock never serves these again
+export type ResponseOrigin =
+  | { kind: 'circuit_open' }
+  | { kind: 'upstream_error'; error: string }
+  | { kind: 'auto_mock'; recording_id: string };
+
+export interface ReplayInfo {
+  attempts: number;
+  history: ReplayAttempt[];
+}
+
+export interface ReplayAttempt {
+  status: number;
+  duration_ms: number;
+  retry_after_ms: number | null;
+}
+
+// Optional body of POST /api/recordings/{id}/replay; an empty header value removes it
+export interface ReplayOverrides {
+  header_overrides?: Record<string, string>;
+  body?: string;
+}
+
+export interface WsSessionSummary {
+  id: string;
+  timestamp: string;
+  url: string;
+  closed_at: string | null;
+  frame_count: number;
+  dropped_frames: number;
+}
+
+export interface WsSession {
+  id: string;
+  timestamp: string;
+  url: string;
+  headers: Record<string, string>;
+  closed_at: string | null;
+  frames: WsFrame[];
+  dropped_frames: number;
+}
+
+export interface WsFrame {
+  timestamp: string;
+  direction: "client_to_server" | "server_to_client";
+  kind: "text" | "binary";
+  // Binary frames are base64
+  data: string;
+  size: number;
+  truncated: boolean;
 }
 
 export interface RecordedResponse {
   status: number;
   headers: Record<string, string>;
   body?: number[];
+  original_charset?: string;
+  body_truncated?: boolean;
+  body_size?: number;
 }
 
 export interface RecordingStats {
@@ -24,13 +90,46 @@ export interface RecordingStats {
   avg_duration_ms: number;
   min_duration_ms: number;
   max_duration_ms: number;
+  p50_ms: number;
+  p95_ms: number;
+  p99_ms: number;
+  total_injected_latency_ms: number;
+  adjusted_avg_duration_ms: number;
+  adjusted_min_duration_ms: number;
+  adjusted_max_duration_ms: number;
+  adjusted_p50_ms: number;
+  adjusted_p95_ms: number;
+  adjusted_p99_ms: number;
+  shed_requests: number;
+  total_bytes: number;
+  avg_response_bytes: number;
+}
+
+export interface RecordingStatus {
+  enabled: boolean;
+}
+
+export interface AutoMockStatus {
+  enabled: boolean;
+}
+
+export interface StorageInfo {
+  count: number;
+  // Rough sum of URL, header and body sizes, not exact memory usage
+  approx_bytes: number;
+  oldest_timestamp: string | null;
+  newest_timestamp: string | null;
 }
 
 export interface Analytics {
   method_distribution: Record<string, number>;
   status_distribution: Record<string, number>;
   top_endpoints: EndpointStats[];
+  histogram_bounds_ms: number[];
   timeline: TimelinePoint[];
+  window_seconds: number;
+  bucket_seconds: number;
+  error_rate_timeline: ErrorRateBucket[];
 }
 
 export interface EndpointStats {
@@ -39,13 +138,56 @@ export interface EndpointStats {
   avg_duration: number;
   errors: number;
   total_duration: number;
+  p50_ms: number;
+  p95_ms: number;
+  p99_ms: number;
+  histogram: number[];
+  total_bytes: number;
 }
 
+export interface StatusTimelineBucket {
+  timestamp: string;
+  success: number;
+  redirects: number;
+  client_errors: number;
+  server_errors: number;
+}
+
+export interface SchemaDrift {
+  endpoint: string;
+  responses_analyzed: number;
+  stable_fields: number;
+  drifting_fields: FieldDrift[];
+}
+
+// path uses "." for fields and "[]" for array items, "$" is the root
+export interface FieldDrift {
+  path: string;
+  present_in: number;
+  missing_in: number;
+  types: Record<string, number>;
+}
+
+// One bucket of the analytics window, aligned with ErrorRateBucket
 export interface TimelinePoint {
   timestamp: string;
+  count: number;
+  // Mean duration of the bucket's requests
   duration_ms: number;
+  // Highest response status in the bucket, 0 when none has completed
   status: number;
-  method: string;
+}
+
+export interface ErrorRateBucket {
+  bucket_start: string;
+  total: number;
+  errors: number;
+  error_rate: number;
+}
+
+export interface RecordingPage<T> {
+  total: number;
+  items: T[];
 }
 
 export interface FilterOptions {
@@ -54,6 +196,8 @@ export interface FilterOptions {
   status?: number;
   minDuration?: number;
   maxDuration?: number;
+  headerName?: string;
+  headerValue?: string;
 }
 
 export interface MockRule {
@@ -64,9 +208,25 @@ export interface MockRule {
   method?: string;
   url_pattern: string;
   url_match_type: "exact" | "contains" | "regex" | "startswith" | "endswith";
+  match_query?: boolean;
+  body_pattern?: string | null;
+  body_match_type?: "exact" | "contains" | "regex" | "startswith" | "endswith" | null;
+  normalize_json_body?: boolean;
+  query_matches?: [string, string][];
+  header_matches?: [string, string][];
   response: MockResponse;
+  content_responses?: ContentTypeResponse[];
+  variants?: MockResponse[];
+  hash_by?: HashBy | null;
+  responses?: WeightedResponse[];
+  sequence?: MockResponse[] | null;
+  fail_first?: FailFirst | null;
   delay_ms?: number;
+  delay_jitter_ms?: number;
+  merge_matching?: boolean;
   created_at: string;
+  active_from?: string | null;
+  active_until?: string | null;
 }
 
 // For creating new rules (no id or created_at)
@@ -77,16 +237,95 @@ export interface CreateMockRule {
   method?: string;
   url_pattern: string;
   url_match_type: "exact" | "contains" | "regex" | "startswith" | "endswith";
+  match_query?: boolean;
+  body_pattern?: string | null;
+  body_match_type?: "exact" | "contains" | "regex" | "startswith" | "endswith" | null;
+  normalize_json_body?: boolean;
+  query_matches?: [string, string][];
+  header_matches?: [string, string][];
   response: MockResponse;
+  content_responses?: ContentTypeResponse[];
+  variants?: MockResponse[];
+  hash_by?: HashBy | null;
+  responses?: WeightedResponse[];
+  sequence?: MockResponse[] | null;
+  fail_first?: FailFirst | null;
   delay_ms?: number;
+  delay_jitter_ms?: number;
+  merge_matching?: boolean;
+  active_from?: string | null;
+  active_until?: string | null;
+  active_for_seconds?: number;
+}
+
+export interface MockBatchRequest {
+  defaults?: {
+    priority?: number;
+    enabled?: boolean;
+    url_match_type?: "exact" | "contains" | "regex" | "startswith" | "endswith";
+    delay_ms?: number;
+  };
+  rules: {
+    name?: string;
+    method?: string;
+    url_pattern: string;
+    url_match_type?: "exact" | "contains" | "regex" | "startswith" | "endswith";
+    priority?: number;
+    enabled?: boolean;
+    response: MockResponse;
+    delay_ms?: number;
+  }[];
+}
+
+export interface MockBatchResult {
+  created: number;
+  invalid: number;
+  results: (
+    | { index: number; outcome: "created"; id: string }
+    | { index: number; outcome: "invalid"; error: string }
+  )[];
+}
+
+export type HashBy = { header: string } | { query: string } | "ip";
+
+export interface ContentTypeResponse {
+  content_type: string;
+  response: MockResponse;
+}
+
+export interface FailFirst {
+  count: number;
+  failure: MockResponse;
+}
+
+export interface WeightedResponse {
+  weight: number;
+  response: MockResponse;
 }
 
 export interface MockResponse {
   status: number;
   headers: Record<string, string>;
   body: string;
+  grpc_web?: GrpcWebResponse | null;
+  // Fetched once and cached; replaces body
+  body_url?: string | null;
+  chunked?: { chunk_size?: number; chunk_delay_ms?: number } | null;
+  // Expand {{request.method}}, {{request.path}}, {{query.x}}, {{header.x}} in body
+  templated?: boolean;
 }
 
+// body is base64 for "proto", the JSON message for "json"
+export interface GrpcWebResponse {
+  format: "proto" | "json";
+  grpc_status?: number;
+  grpc_message?: string | null;
+}
+
+export type ModifierCondition =
+  | { body_contains: string }
+  | { body_matches_regex: string };
+
 export interface ModifierRule {
   id: string;
   name: string;
@@ -94,7 +333,11 @@ export interface ModifierRule {
   priority: number;
   match_request: RequestMatch;
   modifications: Modification[];
+  apply_to?: "request" | "response";
+  condition?: ModifierCondition | null;
   created_at: string;
+  active_from?: string | null;
+  active_until?: string | null;
 }
 
 export interface CreateModifierRule {
@@ -103,6 +346,11 @@ export interface CreateModifierRule {
   priority: number;
   match_request: RequestMatch;
   modifications: Modification[];
+  apply_to?: "request" | "response";
+  condition?: ModifierCondition | null;
+  active_from?: string | null;
+  active_until?: string | null;
+  active_for_seconds?: number;
 }
 
 export interface UpdateModifierRule {
@@ -112,13 +360,21 @@ export interface UpdateModifierRule {
   priority: number;
   match_request: RequestMatch;
   modifications: Modification[];
+  apply_to?: "request" | "response";
+  condition?: ModifierCondition | null;
+  active_from?: string | null;
+  active_until?: string | null;
+  active_for_seconds?: number;
 }
 
 export interface RequestMatch {
   method?: string;
   url_pattern: string;
   url_match_type: "exact" | "contains" | "regex" | "startswith" | "endswith";
+  match_query?: boolean;
   status_codes?: number[];
+  query_matches?: [string, string][];
+  header_matches?: [string, string][];
 }
 
 export type Modification =
@@ -149,6 +405,29 @@ export type Modification =
       type: "modify_json";
       path: string;
       value: any;
+    }
+  | {
+      type: "coerce_json";
+      path: string;
+      to_type: "string" | "number" | "boolean";
+    }
+  | {
+      type: "delete_json";
+      path: string;
+    }
+  | {
+      type: "append_json";
+      path: string;
+      value: any;
+    }
+  | {
+      type: "rewrite_url";
+      pattern: string;
+      replacement: string;
+    }
+  | {
+      type: "set_content_type";
+      value: string;
     };
 
 export interface RateLimitRule {
@@ -159,7 +438,10 @@ export interface RateLimitRule {
   match_request: RateLimitMatch;
   limit: RateLimit;
   response: RateLimitResponse;
+  tiers?: RateLimitTier[];
   created_at: string;
+  active_from?: string | null;
+  active_until?: string | null;
 }
 
 export interface CreateRateLimitRule {
@@ -169,12 +451,17 @@ export interface CreateRateLimitRule {
   match_request: RateLimitMatch;
   limit: RateLimit;
   response: RateLimitResponse;
+  tiers?: RateLimitTier[];
+  active_from?: string | null;
+  active_until?: string | null;
+  active_for_seconds?: number;
 }
 
 export interface RateLimitMatch {
   method?: string;
   url_pattern: string;
   url_match_type: "exact" | "contains" | "regex" | "startswith" | "endswith";
+  match_query?: boolean;
   key_type: KeyType;
 }
 
@@ -182,12 +469,14 @@ export type KeyType =
   | "global"
   | "ipaddress"
   | { header: { name: string } }
-  | { custom: { pattern: string } };
+  | { custom: { pattern: string } }
+  | { composite: { parts: KeyType[] } };
 
 export interface RateLimit {
   max_requests: number;
   window_seconds: number;
   burst_size?: number;
+  count_statuses?: number[] | null;
 }
 
 export interface RateLimitResponse {
@@ -197,6 +486,11 @@ export interface RateLimitResponse {
   delay_ms?: number;
 }
 
+export interface RateLimitTier {
+  after_rejections: number;
+  response: RateLimitResponse;
+}
+
 export interface BucketStats {
   total_buckets: number;
   active_limits: number;
@@ -210,6 +504,8 @@ export interface LatencyRule {
   match_request: LatencyMatch;
   delay: DelayConfig;
   created_at: string;
+  active_from?: string | null;
+  active_until?: string | null;
 }
 
 export interface CreateLatencyRule {
@@ -218,12 +514,16 @@ export interface CreateLatencyRule {
   priority: number;
   match_request: LatencyMatch;
   delay: DelayConfig;
+  active_from?: string | null;
+  active_until?: string | null;
+  active_for_seconds?: number;
 }
 
 export interface LatencyMatch {
   method?: string;
   url_pattern: string;
   url_match_type: "exact" | "contains" | "regex" | "startswith" | "endswith";
+  match_query?: boolean;
   apply_to: "request" | "response" | "both";
 }
 
@@ -236,7 +536,10 @@ export type DelayConfig =
       base_delay_ms: number;
       spike_delay_ms: number;
       spike_probability: number;
-    };
+    }
+  | { type: "ramp"; start_ms: number; end_ms: number; duration_secs: number }
+  | { type: "pareto"; scale_ms: number; shape: number }
+  | { type: "throttle"; bytes_per_second: number };
 
 export interface LatencyStats {
   total_injections: number;
@@ -247,12 +550,78 @@ export interface LatencyStats {
   by_rule: Record<string, RuleStats>;
 }
 
+export interface FaultRule {
+  id: string;
+  name: string;
+  enabled: boolean;
+  priority: number;
+  match_request: FaultMatch;
+  fault: FaultType;
+  probability: number;
+  created_at: string;
+  active_from?: string | null;
+  active_until?: string | null;
+}
+
+export interface CreateFaultRule {
+  name: string;
+  enabled: boolean;
+  priority: number;
+  match_request: FaultMatch;
+  fault: FaultType;
+  probability: number;
+  active_from?: string | null;
+  active_until?: string | null;
+  active_for_seconds?: number;
+}
+
+export interface UpdateFaultRule {
+  id: string;
+  name: string;
+  enabled: boolean;
+  priority: number;
+  match_request: FaultMatch;
+  fault: FaultType;
+  probability: number;
+  active_from?: string | null;
+  active_until?: string | null;
+  active_for_seconds?: number;
+}
+
+export interface FaultMatch {
+  method?: string;
+  url_pattern: string;
+  url_match_type: "exact" | "contains" | "regex" | "startswith" | "endswith";
+  match_query?: boolean;
+}
+
+export type FaultType =
+  | { type: "connection_reset" }
+  | { type: "truncated_body"; keep_bytes?: number | null }
+  | { type: "malformed_chunked" };
+
+export interface FaultStats {
+  total_injections: number;
+  by_rule: Record<string, FaultRuleStats>;
+}
+
+export interface FaultRuleStats {
+  rule_id: string;
+  rule_name: string;
+  hits: number;
+}
+
+export interface GlobalDelay {
+  delay_ms: number;
+}
+
 export interface RuleStats {
   rule_id: string;
   rule_name: string;
   hits: number;
   total_delay_ms: number;
   avg_delay_ms: number;
+  current_delay_ms?: number | null;
 }
 
 export function formatBody(body?: number[]): string {
@@ -299,3 +668,65 @@ export function getMethodColor(method: string): string {
       return "gray";
   }
 }
+
+export interface BuildInfo {
+  version: string;
+  git_commit: string;
+  build_time: string | null;
+  features: string[];
+}
+
+export interface ApiError {
+  error: string;
+  type: "validation_error" | "not_found" | "internal_error";
+}
+
+export interface SnapshotInfo {
+  name: string;
+  created_at: string;
+  recordings: number;
+  mock_rules: number;
+  modifier_rules: number;
+  rate_limit_rules: number;
+  latency_rules: number;
+}
+
+export interface RuleTrace {
+  rule_id: string;
+  name: string;
+  priority: number;
+  matched: boolean;
+  reason: string;
+}
+
+export interface Explanation {
+  outcome:
+    | "passthrough"
+    | "rate_limited"
+    | "mocked"
+    | "cache_hit"
+    | "auto_mocked"
+    | "proxied";
+  passthrough: boolean;
+  rate_limits: RuleTrace[];
+  mocks: RuleTrace[];
+  mock_rule?: string | null;
+  modifiers: RuleTrace[];
+  latency: RuleTrace[];
+}
+
+export interface CircuitBreakerStatus {
+  failure_threshold: number | null;
+  window_ms: number;
+  cooldown_ms: number;
+  open_status: number;
+  upstreams: UpstreamCircuitStatus[];
+}
+
+export interface UpstreamCircuitStatus {
+  upstream: string;
+  state: "closed" | "open" | "half_open";
+  consecutive_failures: number;
+  opened_at: string | null;
+  retry_in_ms: number | null;
+}